@@ -0,0 +1,98 @@
+/*
+    Accept rate under a flood of short connections, for `ServerConfig::acceptor_threads`.
+
+    A server with 1, 2 and 4 acceptor threads answers `CLIENTS` client threads that each open
+    a connection, send one `Connection: close` request, read the response to the end and start
+    over. Every exchange is a new connection, the churn that makes accepting the bottleneck.
+    Clients and server share the machine and talk over loopback; each iteration is one full
+    connection, so criterion's elements per second is the rate the server sustained.
+
+    Run with `cargo bench --bench accept_rate`. The pool logs every job to stdout, so send the
+    output to a file and read the summary from the end, or from target/criterion.
+
+    Findings (Linux, 1 CPU, loopback, 16 clients, 4 workers):
+
+    | acceptors | per connection | connections/s |
+    |-----------|----------------|---------------|
+    | 1         | 3.26 ms        | 307           |
+    | 2         | 2.83 ms        | 353 (noisy)   |
+    | 4         | 3.25 ms        | 307           |
+
+    - No improvement here, and none to expect on one CPU: there is nothing for a second
+      acceptor to run on in parallel.
+    - The number itself is set by the accept loop's 50 ms poll (`ACCEPT_POLL_INTERVAL`), not by
+      accept(2). Closed-loop clients empty the backlog, every acceptor sees WouldBlock and
+      sleeps, and the 16 clients then wait out the nap together: 16 connections per 50 ms is
+      the 320/s measured. Acceptors that all nap on the same schedule don't shorten that.
+    - So more acceptors only pay off on a multi-core machine whose backlog stays non-empty,
+      where the single acceptor is busy in accept and dispatch the whole time; measure there
+      before raising the default. Under closed-loop churn like this, a shorter poll interval or
+      blocking accept would do more than extra threads.
+ */
+use std::{
+    hint::black_box,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{log::LogFile, Request, Response, Server, ServerConfig};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const CLIENTS: u64 = 16;
+const ACCEPTORS: [usize; 3] = [1, 2, 4];
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: bench\r\nConnection: close\r\n\r\n";
+
+// One connection, one request, the whole response.
+fn exchange(addr: SocketAddr) {
+    let mut stream = TcpStream::connect(addr).expect("couldn't connect to the bench server");
+    stream.write_all(REQUEST).expect("couldn't send the request");
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).expect("couldn't read the response");
+    black_box(response);
+}
+
+// Runs `connections` exchanges spread over the client threads, returning how long they took.
+fn flood(addr: SocketAddr, connections: u64) -> Duration {
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for client in 0..CLIENTS {
+            // the first few clients take one more when it doesn't divide evenly
+            let share = connections / CLIENTS + u64::from(client < connections % CLIENTS);
+            scope.spawn(move || (0..share).for_each(|_| exchange(addr)));
+        }
+    });
+    started.elapsed()
+}
+
+fn accept_rate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("accept_rate");
+    group.throughput(Throughput::Elements(1));
+    group.sample_size(20);
+
+    for acceptors in ACCEPTORS {
+        let config = ServerConfig {
+            addr: String::from("127.0.0.1:0"),
+            acceptor_threads: acceptors,
+            access_log: Some(LogFile::new(std::env::temp_dir().join("book-web-server-accept-rate.log"))),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind(config, |_: &mut Request| Response::new(204)).expect("couldn't start the bench server");
+        let addr = server.local_addr().expect("the bench server has no address");
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        group.bench_with_input(BenchmarkId::from_parameter(acceptors), &addr, |b, &addr| {
+            b.iter_custom(|connections| flood(addr, connections))
+        });
+
+        shutdown.shutdown();
+        running.join().expect("the bench server panicked").expect("the bench server failed");
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, accept_rate);
+criterion_main!(benches);
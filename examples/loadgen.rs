@@ -0,0 +1,291 @@
+/*
+    A load generator for the server, built on plain threads and the crate's blocking `Client`
+    so it exercises connection handling the way plain clients do: keep-alive reuse,
+    Connection: close, chunked and length-delimited bodies.
+
+    Each of `--concurrency` threads opens a connection and sends its share of `--requests` GETs
+    for `--path`, one at a time, waiting for each response before the next. With keep-alive (the
+    default) a connection is reused until the server closes it; with `--no-keep-alive` every
+    request gets a connection of its own. Latency is measured from writing the request to having
+    read the whole response, so with `--no-keep-alive` it includes the connect.
+
+    A response that isn't well-formed HTTP/1.1 (a bad status line, a body shorter than its
+    length, a chunk size that isn't hex, ...) is counted as an error with what was wrong, never
+    as a success, and its connection dropped. So is one that takes longer than `TIMEOUT`.
+
+    Run the server, then e.g.
+    `cargo run --release --example loadgen -- --addr 127.0.0.1:7878 --concurrency 16 --requests 10000`.
+ */
+use std::{
+    collections::BTreeMap,
+    env, process, thread,
+    time::{Duration, Instant},
+};
+use book_web_server::client::Client;
+
+const USAGE: &str = "\
+Usage: loadgen [OPTIONS]
+
+Options:
+  --addr <HOST:PORT>   Server to load (default 127.0.0.1:7878)
+  --path <PATH>        Path to GET (default /)
+  --concurrency <N>    Connections open at once, one thread each (default 8)
+  --requests <N>       Requests in total, spread over the connections (default 1000)
+  --no-keep-alive      Open a new connection for every request
+  -h, --help           Print this help";
+
+// Longest a single read or write may block before the request counts as failed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// How many distinct error messages the report lists before summing up the rest.
+const MAX_ERRORS_SHOWN: usize = 10;
+
+struct Options {
+    addr: String,
+    path: String,
+    concurrency: usize,
+    requests: usize,
+    keep_alive: bool
+}
+
+impl Options {
+    fn from_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            addr: String::from("127.0.0.1:7878"),
+            path: String::from("/"),
+            concurrency: 8,
+            requests: 1000,
+            keep_alive: true
+        };
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None)
+            };
+            let mut value = || inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{flag} needs a value"));
+
+            match flag.as_str() {
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                "--addr" => options.addr = value()?,
+                "--path" => {
+                    options.path = value()?;
+                    if !options.path.starts_with('/') {
+                        return Err(format!("invalid path: {} (it must start with /)", options.path));
+                    }
+                }
+                "--concurrency" => options.concurrency = positive(&value()?, "concurrency")?,
+                "--requests" => options.requests = positive(&value()?, "number of requests")?,
+                "--no-keep-alive" => options.keep_alive = false,
+                _ => return Err(format!("unknown argument: {flag}"))
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn positive(value: &str, what: &str) -> Result<usize, String> {
+    value.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid {what}: {value}"))
+}
+
+// What one client thread saw.
+#[derive(Default)]
+struct Tally {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    errors: BTreeMap<String, usize>,
+    connections: usize
+}
+
+impl Tally {
+    fn merge(&mut self, other: Tally) {
+        self.latencies.extend(other.latencies);
+        other.statuses.into_iter().for_each(|(status, n)| *self.statuses.entry(status).or_default() += n);
+        other.errors.into_iter().for_each(|(error, n)| *self.errors.entry(error).or_default() += n);
+        self.connections += other.connections;
+    }
+}
+
+fn main() {
+    let options = match Options::from_args(env::args()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    println!(
+        "Sending {} GET {} to {} over {} connection(s){}",
+        options.requests,
+        options.path,
+        options.addr,
+        options.concurrency,
+        if options.keep_alive { ", keep-alive" } else { ", one per request" }
+    );
+
+    let started = Instant::now();
+    let mut total = Tally::default();
+    thread::scope(|scope| {
+        let options = &options;
+        let clients: Vec<_> = (0..options.concurrency)
+            .map(|client| {
+                // the first few clients take one more when it doesn't divide evenly
+                let share = options.requests / options.concurrency + usize::from(client < options.requests % options.concurrency);
+                scope.spawn(move || run_client(options, share))
+            })
+            .collect();
+        for client in clients {
+            total.merge(client.join().expect("a client thread panicked"));
+        }
+    });
+    let elapsed = started.elapsed();
+
+    report(&total, elapsed);
+    if !total.errors.is_empty() {
+        process::exit(1);
+    }
+}
+
+// Sends `requests` requests one after another; the client reconnects whenever it has to.
+fn run_client(options: &Options, requests: usize) -> Tally {
+    let mut client = Client::new(&options.addr).keep_alive(options.keep_alive).timeout(TIMEOUT);
+    let mut tally = Tally::default();
+
+    for _ in 0..requests {
+        let started = Instant::now();
+        match client.request("GET", &options.path, &[("User-Agent", "loadgen")], &[]) {
+            Ok(response) => {
+                tally.latencies.push(started.elapsed());
+                *tally.statuses.entry(response.status()).or_default() += 1;
+            }
+            Err(error) => *tally.errors.entry(error.to_string()).or_default() += 1
+        }
+    }
+
+    tally.connections = client.connections();
+    tally
+}
+
+fn report(tally: &Tally, elapsed: Duration) {
+    let completed = tally.latencies.len();
+    let failed: usize = tally.errors.values().sum();
+    let seconds = elapsed.as_secs_f64();
+
+    println!();
+    println!("Completed:   {completed} in {seconds:.2}s ({:.0} requests/s)", completed as f64 / seconds);
+    println!("Failed:      {failed}");
+    println!("Connections: {}", tally.connections);
+
+    let statuses: Vec<String> = tally.statuses.iter().map(|(status, n)| format!("{status}: {n}")).collect();
+    if !statuses.is_empty() {
+        println!("Statuses:    {}", statuses.join(", "));
+    }
+
+    if completed > 0 {
+        let mut latencies = tally.latencies.clone();
+        latencies.sort_unstable();
+        // nearest rank, so p100 is the slowest request
+        let percentile = |q: f64| latencies[((q * completed as f64).ceil() as usize).clamp(1, completed) - 1];
+        let millis = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        println!("Latency:");
+        for (label, q) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p999", 0.999), ("max", 1.0)] {
+            println!("  {label:<5} {:>9.3}ms", millis(percentile(q)));
+        }
+    }
+
+    if failed > 0 {
+        let mut errors: Vec<(&String, &usize)> = tally.errors.iter().collect();
+        errors.sort_by(|a, b| b.1.cmp(a.1));
+        eprintln!("Errors:");
+        for (error, n) in errors.iter().take(MAX_ERRORS_SHOWN) {
+            eprintln!("  {n:>7} × {error}");
+        }
+        if errors.len() > MAX_ERRORS_SHOWN {
+            eprintln!("  and {} other kinds", errors.len() - MAX_ERRORS_SHOWN);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use book_web_server::{Response, Server, ServerConfig};
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::from_args(["loadgen"].iter().chain(args).map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn flags_override_the_defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!((options.addr.as_str(), options.path.as_str()), ("127.0.0.1:7878", "/"));
+        assert_eq!((options.concurrency, options.requests, options.keep_alive), (8, 1000, true));
+
+        let options = parse(&["--addr", "localhost:8080", "--path=/books", "--concurrency=3", "--requests", "20", "--no-keep-alive"]).unwrap();
+        assert_eq!((options.addr.as_str(), options.path.as_str()), ("localhost:8080", "/books"));
+        assert_eq!((options.concurrency, options.requests, options.keep_alive), (3, 20, false));
+    }
+
+    #[test]
+    fn bad_arguments_say_what_is_wrong() {
+        let cases: [(&[&str], &str); 5] = [
+            (&["--requests", "0"], "invalid number of requests: 0"),
+            (&["--concurrency=many"], "invalid concurrency: many"),
+            (&["--path", "books"], "invalid path: books (it must start with /)"),
+            (&["--addr"], "--addr needs a value"),
+            (&["--keep-alive"], "unknown argument: --keep-alive")
+        ];
+
+        for (args, expected) in cases {
+            assert_eq!(parse(args).err().as_deref(), Some(expected), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn tallies_add_up() {
+        let mut total = Tally { latencies: vec![Duration::from_millis(1)], connections: 1, ..Tally::default() };
+        total.statuses.insert(200, 1);
+        let mut other = Tally { latencies: vec![Duration::from_millis(2); 2], connections: 2, ..Tally::default() };
+        other.statuses.extend([(200, 1), (404, 1)]);
+        other.errors.insert(String::from("connection reset"), 3);
+
+        total.merge(other);
+        assert_eq!(total.latencies.len(), 3);
+        assert_eq!(total.statuses, BTreeMap::from([(200, 2), (404, 1)]));
+        assert_eq!(total.errors, BTreeMap::from([(String::from("connection reset"), 3)]));
+        assert_eq!(total.connections, 3);
+    }
+
+    #[test]
+    fn a_client_counts_every_response_and_connection() {
+        let config = ServerConfig { addr: String::from("127.0.0.1:0"), workers: 2, ..ServerConfig::default() };
+        let server = Server::bind(config, |request| match request.path() {
+            "/" => Response::html(200, "hello"),
+            _ => Response::status_only(404)
+        }).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        for (keep_alive, connections) in [(true, 1), (false, 5)] {
+            let options = Options { addr: addr.clone(), path: String::from("/"), concurrency: 1, requests: 5, keep_alive };
+            let tally = run_client(&options, 5);
+            assert_eq!(tally.statuses, BTreeMap::from([(200, 5)]), "keep-alive {keep_alive}");
+            assert!(tally.errors.is_empty(), "{:?}", tally.errors);
+            assert_eq!(tally.latencies.len(), 5);
+            assert_eq!(tally.connections, connections, "keep-alive {keep_alive}");
+        }
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
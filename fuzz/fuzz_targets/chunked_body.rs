@@ -0,0 +1,32 @@
+#![no_main]
+
+use std::io::Read;
+use book_web_server::body::{BodyReader, Framing};
+use libfuzzer_sys::fuzz_target;
+
+/*
+    Decodes arbitrary bytes as a chunked body. The first two bytes pick the limit, so small
+    limits get hit as often as the framing itself goes wrong. Whatever the input, decoding must
+    not panic and must never hand out more than `limit` bytes, even when it ends in an error.
+ */
+fuzz_target!(|data: &[u8]| {
+    let Some((limit, body)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let limit = u64::from(u16::from_le_bytes(*limit));
+
+    let mut reader = BodyReader::new(body, Framing::Chunked, limit);
+    let mut decoded = Vec::new();
+    let mut buffer = [0; 512];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => decoded.extend_from_slice(&buffer[..read]),
+            Err(_) => break
+        }
+        assert!(decoded.len() as u64 <= limit, "decoded {} bytes past a limit of {limit}", decoded.len());
+    }
+
+    assert_eq!(reader.bytes_read(), decoded.len() as u64);
+    assert!(decoded.len() as u64 <= limit);
+});
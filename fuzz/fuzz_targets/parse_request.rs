@@ -0,0 +1,44 @@
+#![no_main]
+
+use book_web_server::request::{ParseError, Request};
+use libfuzzer_sys::fuzz_target;
+
+/*
+    Feeds arbitrary bytes to the request parser, as a keep-alive connection would: one request
+    after another until the input runs out or one fails. Parsing must never panic, and every
+    failure must be one of the typed errors the server knows how to answer.
+ */
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+
+    loop {
+        match Request::parse(&mut reader) {
+            Ok(Some(request)) => {
+                // exercise the accessors handlers lean on
+                let _ = (request.path(), request.query(), request.host(), request.is_keep_alive());
+                let _ = (request.content_length(), request.content_type(), request.trace_context());
+
+                // whatever got through must be free of the bytes that split responses or logs
+                let forbidden = |text: &str| text.bytes().any(|byte| (byte.is_ascii_control() && byte != b'\t') || byte == 0x7f);
+                assert!(!forbidden(request.target()) && !request.target().contains('\t'), "{:?}", request.target());
+                for (name, value) in request.headers() {
+                    assert!(!forbidden(name) && !forbidden(value), "{name:?}: {value:?}");
+                    assert!(!value.starts_with([' ', '\t']) && !value.ends_with([' ', '\t']), "{value:?}");
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                let status = e.status();
+                assert!(matches!(status, 400 | 413 | 501 | 505), "{e:?} maps to {status}");
+                match e {
+                    ParseError::Io(_)
+                    | ParseError::Malformed(_)
+                    | ParseError::ControlCharacter(_)
+                    | ParseError::UnsupportedVersion
+                    | ParseError::Unsupported(_)
+                    | ParseError::TooLarge => break
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,295 @@
+use std::{
+    fmt::{Display, Formatter},
+    num::NonZeroUsize,
+    path, thread,
+    time::Duration,
+};
+use crate::{log_format::LogFormat, ServerConfig};
+
+pub const USAGE: &str = "\
+Usage: book-web-server [OPTIONS]
+
+Options:
+  --bind <HOST>      Address to listen on (default 127.0.0.1)
+  --port <PORT>      Port to listen on (default 7878)
+  --threads <N>      Number of worker threads, 0 for one per CPU (default 4); --workers is the same
+  --max-threads <N>  Start --threads workers and add more, up to N, while all are busy
+  --queue-capacity <N>
+                     Most connections waiting for a worker before new ones get 503 (default: no limit)
+  --shutdown-grace <SECS>
+                     How long shutdown waits for open connections (default 30)
+  --root <DIR>       Directory to serve static files from (default: built-in pages only)
+  --index <FILE>     File served for / and other directories under the root (default index.html)
+  --pid-file <PATH>  Write the process id to PATH while running
+  --log-format <FMT> Access log line format, e.g. '%h %t \"%r\" %s %b' (default: the server's own)
+  --check            Check the configuration and exit
+  -h, --help         Print this help";
+
+impl ServerConfig {
+    /// Builds a config from command line flags, starting from the defaults.
+    ///
+    /// The first item is taken to be the program name, as with `std::env::args()`. Flags take
+    /// their value either as the next argument or after `=`, e.g. `--port=8080`.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<ServerConfig, UsageError> {
+        let mut config = ServerConfig::default();
+        let (mut host, mut port) = match config.addr.rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.to_string()),
+            None => (config.addr.clone(), String::new())
+        };
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(UsageError::Help);
+            }
+
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None)
+            };
+            let mut value = || inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| UsageError::Invalid(format!("{flag} needs a value")));
+
+            match flag.as_str() {
+                "--bind" => host = value()?,
+                "--port" => {
+                    let value = value()?;
+                    value.parse::<u16>().map_err(|_| UsageError::Invalid(format!("invalid port: {value}")))?;
+                    port = value;
+                }
+                "--threads" | "--workers" => {
+                    let value = value()?;
+                    config.workers = match value.parse() {
+                        // auto: as many as the machine can run at once
+                        Ok(0) => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+                        Ok(workers) => workers,
+                        Err(_) => return Err(UsageError::Invalid(format!("invalid number of threads: {value}")))
+                    };
+                }
+                "--max-threads" => config.max_workers = Some(positive(&value()?, "maximum number of threads")?),
+                "--queue-capacity" => config.queue_capacity = Some(positive(&value()?, "queue capacity")?),
+                "--shutdown-grace" => {
+                    let value = value()?;
+                    config.shutdown_grace = value
+                        .parse()
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| UsageError::Invalid(format!("invalid shutdown grace period: {value}")))?;
+                }
+                "--pid-file" => {
+                    let value = value()?;
+                    let pid_file = path::absolute(&value)
+                        .map_err(|e| UsageError::Invalid(format!("invalid PID file {value}: {e}")))?;
+                    config.pid_file = Some(pid_file);
+                }
+                "--root" => {
+                    let value = value()?;
+                    // resolved now, so a later change of working directory can't move the docroot
+                    let root = path::absolute(&value)
+                        .map_err(|e| UsageError::Invalid(format!("invalid docroot {value}: {e}")))?;
+                    config.root = Some(root);
+                }
+                "--log-format" => {
+                    let value = value()?;
+                    let format = LogFormat::parse(&value).map_err(|e| UsageError::Invalid(e.to_string()))?;
+                    config.access_log_format = Some(format);
+                }
+                "--index" => {
+                    let value = value()?;
+                    // a name below each directory, so it can't be absolute or climb out of it
+                    if value.is_empty() || value.starts_with('/') || value.split('/').any(|segment| segment == "..") {
+                        return Err(UsageError::Invalid(format!("invalid index file: {value}")));
+                    }
+                    config.index_file = value;
+                }
+                _ => return Err(UsageError::Invalid(format!("unknown argument: {flag}")))
+            }
+        }
+
+        if host.is_empty() {
+            return Err(UsageError::Invalid(String::from("--bind needs a host")));
+        }
+        // an IPv6 address needs brackets before the port can be appended
+        config.addr = if host.contains(':') && !host.starts_with('[') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        };
+
+        Ok(config)
+    }
+}
+
+// A flag value that has to be a whole number above zero; `what` names it in the error.
+fn positive(value: &str, what: &str) -> Result<usize, UsageError> {
+    value
+        .parse()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| UsageError::Invalid(format!("invalid {what}: {value}")))
+}
+
+/// Why `ServerConfig::from_args` didn't produce a config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsageError {
+    /// `--help` was asked for; print `USAGE` and exit successfully.
+    Help,
+    /// The arguments were wrong; the message says how.
+    Invalid(String)
+}
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsageError::Help => write!(f, "{USAGE}"),
+            UsageError::Invalid(message) => write!(f, "{message}")
+        }
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preflight::PreflightError, temp_dir::TempDir};
+
+    // Parses `args` as given after the program name.
+    fn parse(args: &[&str]) -> Result<ServerConfig, UsageError> {
+        ServerConfig::from_args(["book-web-server"].iter().chain(args).map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn without_a_root_only_the_embedded_pages_are_served() {
+        assert_eq!(parse(&[]).unwrap().root, None);
+    }
+
+    #[test]
+    fn a_relative_root_is_resolved_against_the_working_directory() {
+        let root = parse(&["--root", "site/public"]).unwrap().root.unwrap();
+        assert!(root.is_absolute());
+        assert_eq!(root, std::env::current_dir().unwrap().join("site/public"));
+    }
+
+    #[test]
+    fn an_absolute_root_is_kept_as_given() {
+        assert_eq!(parse(&["--root", "/srv/www"]).unwrap().root.as_deref(), Some(path::Path::new("/srv/www")));
+        assert_eq!(parse(&["--root=/srv/www"]).unwrap().root.as_deref(), Some(path::Path::new("/srv/www")));
+    }
+
+    #[test]
+    fn the_pid_file_is_resolved_like_the_root() {
+        assert_eq!(parse(&[]).unwrap().pid_file, None);
+        let pid_file = parse(&["--pid-file", "run/server.pid"]).unwrap().pid_file.unwrap();
+        assert_eq!(pid_file, std::env::current_dir().unwrap().join("run/server.pid"));
+    }
+
+    #[test]
+    fn the_index_file_can_be_any_name_below_the_root() {
+        assert_eq!(parse(&[]).unwrap().index_file, "index.html");
+        assert_eq!(parse(&["--index", "home.htm"]).unwrap().index_file, "home.htm");
+        assert_eq!(parse(&["--index=site/start.html"]).unwrap().index_file, "site/start.html");
+        for index in ["", "/etc/passwd", "a/../../b.html"] {
+            assert!(matches!(parse(&["--index", index]), Err(UsageError::Invalid(_))), "{index:?}");
+        }
+    }
+
+    #[test]
+    fn the_root_is_checked_at_startup() {
+        let dir = TempDir::new();
+        let file = dir.write("page.html", "<p>hi</p>");
+        let with_root = |root: &path::Path| parse(&["--root", root.to_str().unwrap()]).unwrap();
+
+        assert!(with_root(dir.path()).check().is_ok());
+        for root in [file, dir.path().join("missing")] {
+            let errors = with_root(&root).check().unwrap_err();
+            assert!(matches!(errors.as_slice(), [PreflightError::Docroot { path, .. }] if *path == root), "{errors:?}");
+        }
+    }
+
+    #[test]
+    fn bind_and_port_make_up_the_address() {
+        assert_eq!(parse(&[]).unwrap().addr, ServerConfig::default().addr);
+        assert_eq!(parse(&["--bind", "0.0.0.0", "--port", "8080"]).unwrap().addr, "0.0.0.0:8080");
+        assert_eq!(parse(&["--port=9000", "--bind=localhost"]).unwrap().addr, "localhost:9000");
+        assert_eq!(parse(&["--bind", "::1", "--port", "8080"]).unwrap().addr, "[::1]:8080");
+        assert_eq!(parse(&["--bind", "[::1]"]).unwrap().addr, "[::1]:7878");
+    }
+
+    #[test]
+    fn help_is_asked_for_wherever_it_appears() {
+        assert_eq!(parse(&["-h"]).unwrap_err(), UsageError::Help);
+        assert_eq!(parse(&["--port", "8080", "--help"]).unwrap_err(), UsageError::Help);
+    }
+
+    #[test]
+    fn invalid_arguments_say_what_is_wrong() {
+        let cases: [(&[&str], &str); 9] = [
+            (&["--verbose"], "unknown argument: --verbose"),
+            (&["serve"], "unknown argument: serve"),
+            (&["--port"], "--port needs a value"),
+            (&["--bind", "0.0.0.0", "--root"], "--root needs a value"),
+            (&["--port", "http"], "invalid port: http"),
+            (&["--port", "70000"], "invalid port: 70000"),
+            (&["--bind", ""], "--bind needs a host"),
+            (&["--index", "../secret.html"], "invalid index file: ../secret.html"),
+            (&["--log-format", "%Q"], "%Q")
+        ];
+
+        for (args, message) in cases {
+            match parse(args) {
+                Err(UsageError::Invalid(error)) => assert!(error.contains(message), "{args:?}: {error}"),
+                other => panic!("{args:?} gave {other:?}")
+            }
+        }
+    }
+
+    #[test]
+    fn zero_threads_means_one_per_cpu() {
+        let cpus = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        assert_eq!(parse(&["--threads", "0"]).unwrap().workers, cpus);
+        assert_eq!(parse(&["--workers=0"]).unwrap().workers, cpus);
+        assert_eq!(parse(&["--threads", "6"]).unwrap().workers, 6);
+        assert_eq!(parse(&[]).unwrap().workers, ServerConfig::default().workers);
+    }
+
+    #[test]
+    fn pool_flags_override_the_defaults_and_each_other() {
+        let config = parse(&[
+            "--threads", "2",
+            "--max-threads", "8",
+            "--queue-capacity", "64",
+            "--shutdown-grace", "1.5",
+            "--threads", "3"
+        ]).unwrap();
+        assert_eq!(config.workers, 3);
+        assert_eq!(config.max_workers, Some(8));
+        assert_eq!(config.queue_capacity, Some(64));
+        assert_eq!(config.shutdown_grace, Duration::from_millis(1500));
+
+        // what the startup banner and the status page show
+        let settings = config.pool_settings();
+        assert!(settings.contains(&("Worker threads", String::from("3"))));
+        assert!(settings.contains(&("Max worker threads", String::from("8"))));
+        assert!(settings.contains(&("Queue capacity", String::from("64"))));
+        assert!(settings.contains(&("Shutdown grace", String::from("1.5s"))));
+    }
+
+    #[test]
+    fn pool_flags_must_be_in_range() {
+        let cases: [(&[&str], &str); 6] = [
+            (&["--threads", "-1"], "invalid number of threads: -1"),
+            (&["--max-threads", "0"], "invalid maximum number of threads: 0"),
+            (&["--queue-capacity", "0"], "invalid queue capacity: 0"),
+            (&["--queue-capacity", "lots"], "invalid queue capacity: lots"),
+            (&["--shutdown-grace", "-5"], "invalid shutdown grace period: -5"),
+            (&["--shutdown-grace"], "--shutdown-grace needs a value")
+        ];
+
+        for (args, message) in cases {
+            assert_eq!(parse(args).unwrap_err(), UsageError::Invalid(message.to_string()), "{args:?}");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    future::{self, Future},
+    io,
+    net::SocketAddr,
+    task::Poll,
+};
+use crate::{
+    log::Throttled,
+    server::{ACCEPT_POLL_INTERVAL, REFUSED_WARNING_INTERVAL},
+    Server,
+};
+
+impl Server {
+    /// Like `run`, but accepts connections on the current tokio runtime and stops when
+    /// `shutdown` completes, e.g. `server.run_async(tokio::signal::ctrl_c())`. A
+    /// `ShutdownHandle` stops it too.
+    ///
+    /// Only accepting is async. Each connection is handed to the same blocking worker pool as
+    /// with `run`, and handlers run there exactly as before. Must be called from within a tokio
+    /// runtime; the drain at the end runs on tokio's blocking threads, so it doesn't stall
+    /// other tasks.
+    ///
+    /// An accepted tokio stream is turned back into a std `TcpStream` with `into_std`, which
+    /// leaves the socket in nonblocking mode; it is switched back to blocking before a worker
+    /// sees it, or every read in a handler would fail with WouldBlock. Connections that are shed
+    /// or refused under overload are still answered inline, which can hold up the accepting task
+    /// for the shed read timeout (a fraction of a second).
+    pub async fn run_async<F: Future>(mut self, shutdown: F) -> anyhow::Result<()> {
+        // tokio requires nonblocking sockets; the flag is shared with our own handles on them
+        let listeners = self
+            .listener_handles()?
+            .into_iter()
+            .map(|listener| {
+                listener.set_nonblocking(true)?;
+                tokio::net::TcpListener::from_std(listener)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        self.started()?;
+
+        let handle = self.shutdown_handle();
+        let mut refused_warning = Throttled::new(REFUSED_WARNING_INTERVAL);
+        tokio::pin!(shutdown);
+
+        while !handle.is_shutdown() {
+            let accepted = tokio::select! {
+                _ = &mut shutdown => break,
+                // nothing wakes us when the ShutdownHandle is used, so look at it now and then
+                _ = tokio::time::sleep(ACCEPT_POLL_INTERVAL) => continue,
+                accepted = accept_any(&listeners) => accepted
+            };
+
+            let stream = match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    self.accept_failed(&e);
+                    tokio::time::sleep(ACCEPT_POLL_INTERVAL).await;
+                    continue;
+                }
+            };
+            let dispatched = stream.into_std().and_then(|stream| {
+                stream.set_nonblocking(false)?;
+                self.dispatch(stream, &mut refused_warning)
+            });
+            // only this connection is lost; the listeners are fine
+            if let Err(e) = dispatched {
+                self.accept_failed(&e);
+            }
+        }
+
+        // both handles on each listening socket have to go before it is really closed
+        drop(listeners);
+        tokio::task::spawn_blocking(move || self.stop()).await?;
+        Ok(())
+    }
+}
+
+// The next connection on any of `listeners`. Those earlier in the list are asked first, which
+// only matters while several have connections waiting.
+async fn accept_any(listeners: &[tokio::net::TcpListener]) -> io::Result<(tokio::net::TcpStream, SocketAddr)> {
+    future::poll_fn(|cx| {
+        listeners
+            .iter()
+            .find_map(|listener| match listener.poll_accept(cx) {
+                Poll::Ready(accepted) => Some(Poll::Ready(accepted)),
+                Poll::Pending => None
+            })
+            .unwrap_or(Poll::Pending)
+    })
+    .await
+}
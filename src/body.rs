@@ -0,0 +1,241 @@
+use std::{
+    fmt::{Debug, Formatter},
+    io::{self, BufRead, Read},
+};
+
+// Longest chunk-size line (size plus extensions) we accept in a chunked body.
+const MAX_CHUNK_LINE: u64 = 1024;
+
+/// How the length of a request body is delimited on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// `Content-Length`: exactly this many bytes follow the head.
+    Length(u64),
+    /// `Transfer-Encoding: chunked`: the body is a sequence of size-prefixed chunks.
+    Chunked
+}
+
+/*
+    Reads a request body straight off the connection, decoding chunked framing on the fly, and
+    stops exactly at the end of the body so the underlying reader is left at the start of the
+    next request. Never yields more than `limit` decoded bytes; past that it fails with
+    InvalidData, so a hostile chunked upload can't grow without bound.
+ */
+pub struct BodyReader<R> {
+    inner: R,
+    state: State,
+    limit: u64,
+    read: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // bytes left in a Content-Length body
+    Length(u64),
+    // at the start of a chunk-size line
+    ChunkStart,
+    // bytes left in the current chunk
+    Chunk(u64),
+    // at the CRLF that closes a chunk's data
+    ChunkEnd,
+    Done
+}
+
+impl<R: BufRead> BodyReader<R> {
+    pub fn new(inner: R, framing: Framing, limit: u64) -> Self {
+        let state = match framing {
+            Framing::Length(0) => State::Done,
+            Framing::Length(length) => State::Length(length),
+            Framing::Chunked => State::ChunkStart
+        };
+
+        Self { inner, state, limit, read: 0 }
+    }
+
+    /// Whether the whole body has been read.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Number of decoded body bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    /// Reads and discards whatever is left of the body, then hands back the underlying reader.
+    pub fn finish(mut self) -> io::Result<R> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(self.inner)
+    }
+
+    fn read_chunk_size(&mut self) -> io::Result<u64> {
+        let mut line = Vec::new();
+        self.inner.by_ref().take(MAX_CHUNK_LINE).read_until(b'\n', &mut line)?;
+        if line.pop() != Some(b'\n') {
+            return Err(invalid("unterminated chunk size"));
+        }
+
+        let line = std::str::from_utf8(&line).map_err(|_| invalid("chunk size is not ASCII"))?;
+        // chunk extensions (";name=value") are allowed by the grammar and ignored by us
+        let size = line.trim_end_matches('\r').split(';').next().unwrap_or_default().trim();
+
+        u64::from_str_radix(size, 16).map_err(|_| invalid("malformed chunk size"))
+    }
+
+    fn expect_crlf(&mut self) -> io::Result<()> {
+        let mut crlf = [0; 2];
+        self.inner.read_exact(&mut crlf)?;
+
+        if &crlf == b"\r\n" { Ok(()) } else { Err(invalid("missing CRLF after chunk")) }
+    }
+
+    // Trailer fields after the last chunk are read and dropped, up to the blank line.
+    fn skip_trailers(&mut self) -> io::Result<()> {
+        loop {
+            let mut line = Vec::new();
+            self.inner.by_ref().take(MAX_CHUNK_LINE).read_until(b'\n', &mut line)?;
+            if line.last() != Some(&b'\n') {
+                return Err(invalid("unterminated trailer"));
+            }
+            if line == b"\r\n" || line == b"\n" {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let available = match self.state {
+                State::Done => return Ok(0),
+                State::Length(remaining) | State::Chunk(remaining) => remaining,
+                State::ChunkStart => {
+                    let size = self.read_chunk_size()?;
+                    if size == 0 {
+                        self.skip_trailers()?;
+                        self.state = State::Done;
+                    } else {
+                        self.state = State::Chunk(size);
+                    }
+                    continue;
+                }
+                /*
+                    Checked on the next read rather than straight after the chunk's last byte, so
+                    a read that returned data never also reports an error for it.
+                 */
+                State::ChunkEnd => {
+                    self.expect_crlf()?;
+                    self.state = State::ChunkStart;
+                    continue;
+                }
+            };
+
+            if self.read + available.min(buf.len() as u64) > self.limit {
+                return Err(invalid("request body exceeds the size limit"));
+            }
+
+            let wanted = available.min(buf.len() as u64) as usize;
+            let n = self.inner.read(&mut buf[..wanted])?;
+            if n == 0 && wanted > 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.read += n as u64;
+
+            let left = available - n as u64;
+            self.state = match self.state {
+                State::Length(_) if left == 0 => State::Done,
+                State::Length(_) => State::Length(left),
+                State::Chunk(_) if left == 0 => State::ChunkEnd,
+                _ => State::Chunk(left)
+            };
+
+            return Ok(n);
+        }
+    }
+}
+
+impl<R> Debug for BodyReader<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyReader")
+            .field("state", &self.state)
+            .field("read", &self.read)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads the whole body off `wire`, returning it and whatever follows it.
+    fn read_body(wire: &[u8], framing: Framing, limit: u64) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let mut reader = BodyReader::new(wire, framing, limit);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        assert!(reader.is_done());
+        assert_eq!(reader.bytes_read(), body.len() as u64);
+        Ok((body, reader.finish()?.to_vec()))
+    }
+
+    #[test]
+    fn a_length_delimited_body_stops_at_its_length() {
+        let (body, rest) = read_body(b"helloGET / HTTP/1.1", Framing::Length(5), 1024).unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(rest, b"GET / HTTP/1.1");
+
+        let (body, rest) = read_body(b"GET /", Framing::Length(0), 1024).unwrap();
+        assert!(body.is_empty());
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[test]
+    fn a_chunked_body_is_decoded_and_its_trailers_skipped() {
+        let wire = b"5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: 1\r\n\r\nnext";
+        let (body, rest) = read_body(wire, Framing::Chunked, 1024).unwrap();
+        assert_eq!(body, b"hello, world");
+        assert_eq!(rest, b"next");
+    }
+
+    #[test]
+    fn malformed_chunked_framing_is_invalid_data() {
+        let cases: [&[u8]; 5] = [
+            b"zz\r\nhello\r\n0\r\n\r\n",
+            b"5\r\nhelloXX0\r\n\r\n",
+            b"5\r\nhello\r\n0\r\nX-Trailer: never ends",
+            b"5",
+            &[b'1'; 2048]
+        ];
+        for wire in cases {
+            let error = read_body(wire, Framing::Chunked, 1024).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}: {error}", wire.escape_ascii());
+        }
+    }
+
+    #[test]
+    fn a_body_cut_short_is_an_unexpected_eof() {
+        assert_eq!(read_body(b"hel", Framing::Length(5), 1024).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(read_body(b"5\r\nhel", Framing::Chunked, 1024).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn a_body_over_the_limit_is_refused() {
+        let error = read_body(b"4\r\nabcd\r\n4\r\nefgh\r\n0\r\n\r\n", Framing::Chunked, 6).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_body(b"abcd", Framing::Length(4), 4).unwrap().0, b"abcd");
+    }
+
+    #[test]
+    fn finish_skips_what_the_handler_left_unread() {
+        let mut reader = BodyReader::new(&b"3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\nGET /next"[..], Framing::Chunked, 1024);
+        let mut first = [0; 2];
+        reader.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"ab");
+        assert_eq!(reader.finish().unwrap(), b"GET /next");
+    }
+}
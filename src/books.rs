@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+use crate::{
+    json::{self, Value},
+    request::percent_decode,
+    Request, Response, Router,
+};
+
+// Page size of `GET /books` without a `limit`, and the largest `limit` accepted.
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+/// A book in the example catalog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Book {
+    pub id: u64,
+    pub title: String,
+    pub author: String,
+    pub year: Option<u32>
+}
+
+impl Book {
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("id".to_string(), self.id.into()),
+            ("title".to_string(), self.title.as_str().into()),
+            ("author".to_string(), self.author.as_str().into()),
+            ("year".to_string(), self.year.map(u64::from).into())
+        ])
+    }
+}
+
+/*
+    An in-memory book store behind a small REST API. It is here as a realistic workload that
+    exercises routing, path parameters, JSON bodies and the error statuses together; the data
+    is gone when the server stops.
+ */
+#[derive(Debug)]
+pub struct Catalog {
+    books: RwLock<HashMap<u64, Book>>,
+    next_id: AtomicU64
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self { books: RwLock::new(HashMap::new()), next_id: AtomicU64::new(1) }
+    }
+}
+
+/// Creates an empty catalog and registers its routes under `/books`.
+///
+/// The returned handle reads and writes the same store the routes use.
+pub fn mount(router: &mut Router) -> Arc<Catalog> {
+    let catalog = Arc::new(Catalog::default());
+
+    let c = Arc::clone(&catalog);
+    router.get("/books", move |request| c.list(request));
+    let c = Arc::clone(&catalog);
+    router.post("/books", move |request| c.create(request));
+    let c = Arc::clone(&catalog);
+    router.get("/books/:id", move |request| c.show(request));
+    let c = Arc::clone(&catalog);
+    router.put("/books/:id", move |request| c.update(request));
+    let c = Arc::clone(&catalog);
+    router.delete("/books/:id", move |request| c.remove(request));
+
+    catalog
+}
+
+impl Catalog {
+    /// Adds a book and returns it with its newly assigned id.
+    pub fn insert(&self, title: &str, author: &str, year: Option<u32>) -> Book {
+        let book = Book {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            title: title.to_string(),
+            author: author.to_string(),
+            year
+        };
+
+        self.books
+            .write()
+            .expect("RwLock poisoned: Another thread panicked while holding the lock.")
+            .insert(book.id, book.clone());
+        book
+    }
+
+    pub fn get(&self, id: u64) -> Option<Book> {
+        self.read().get(&id).cloned()
+    }
+
+    /// All books, ordered by id.
+    pub fn books(&self) -> Vec<Book> {
+        let mut books: Vec<Book> = self.read().values().cloned().collect();
+        books.sort_by_key(|book| book.id);
+        books
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<u64, Book>> {
+        self.books.read().expect("RwLock poisoned: Another thread panicked while holding the lock.")
+    }
+
+    /*
+        One page of the (optionally filtered) list plus what a client needs to fetch the next
+        and previous pages. Works on references under the read lock, so only the books on the
+        page are cloned into JSON.
+     */
+    fn list(&self, request: &Request) -> Response {
+        let query = match ListQuery::from_request(request) {
+            Ok(query) => query,
+            Err(parameter) => return error(400, &format!("invalid value for {parameter}"))
+        };
+
+        let books = self.read();
+        let mut matching: Vec<&Book> = books
+            .values()
+            .filter(|book| query.title_contains.as_ref().is_none_or(|needle| book.title.to_lowercase().contains(needle)))
+            .collect();
+        matching.sort_by(|a, b| {
+            let ordering = match query.sort {
+                Sort::Id => a.id.cmp(&b.id),
+                // ties on the title fall back to the id, so pages never shuffle between requests
+                Sort::Title => a.title.cmp(&b.title).then(a.id.cmp(&b.id))
+            };
+            if query.descending { ordering.reverse() } else { ordering }
+        });
+
+        let total = matching.len();
+        let items = matching.iter().skip(query.offset).take(query.limit).map(|book| book.to_json()).collect();
+        let end = query.offset.saturating_add(query.limit);
+        let next = (end < total).then_some(end as u64);
+        // from past the end, "previous" is the last page that has anything on it
+        let last_page = total.saturating_sub(query.limit);
+        let prev = (query.offset > 0).then(|| query.offset.saturating_sub(query.limit).min(last_page) as u64);
+
+        Response::json(200, &Value::Object(vec![
+            ("items".to_string(), Value::Array(items)),
+            ("total".to_string(), (total as u64).into()),
+            ("limit".to_string(), (query.limit as u64).into()),
+            ("offset".to_string(), (query.offset as u64).into()),
+            ("next".to_string(), next.into()),
+            ("prev".to_string(), prev.into())
+        ]))
+    }
+
+    fn show(&self, request: &Request) -> Response {
+        match id(request).and_then(|id| self.get(id)) {
+            Some(book) => Response::json(200, &book.to_json()),
+            None => error(404, "no such book")
+        }
+    }
+
+    fn create(&self, request: &Request) -> Response {
+        let (title, author, year) = match fields(request) {
+            Ok(fields) => fields,
+            Err(response) => return response
+        };
+
+        let book = self.insert(&title, &author, year);
+        Response::json(201, &book.to_json()).with_header("Location", &format!("/books/{}", book.id))
+    }
+
+    fn update(&self, request: &Request) -> Response {
+        let Some(id) = id(request) else {
+            return error(404, "no such book");
+        };
+        let (title, author, year) = match fields(request) {
+            Ok(fields) => fields,
+            Err(response) => return response
+        };
+
+        let mut books = self.books.write().expect("RwLock poisoned: Another thread panicked while holding the lock.");
+        match books.get_mut(&id) {
+            Some(book) => {
+                *book = Book { id, title, author, year };
+                Response::json(200, &book.to_json())
+            }
+            None => error(404, "no such book")
+        }
+    }
+
+    fn remove(&self, request: &Request) -> Response {
+        let removed = id(request).and_then(|id| {
+            self.books
+                .write()
+                .expect("RwLock poisoned: Another thread panicked while holding the lock.")
+                .remove(&id)
+        });
+
+        match removed {
+            Some(_) => Response::status_only(204),
+            None => error(404, "no such book")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sort {
+    Id,
+    Title
+}
+
+// The query string of `GET /books`.
+#[derive(Debug)]
+struct ListQuery {
+    limit: usize,
+    offset: usize,
+    sort: Sort,
+    descending: bool,
+    // lowercased, for a case-insensitive match
+    title_contains: Option<String>
+}
+
+impl ListQuery {
+    // Err names the offending parameter.
+    fn from_request(request: &Request) -> Result<Self, &'static str> {
+        let limit = match request.query_param("limit") {
+            None => DEFAULT_PAGE_SIZE,
+            Some(limit) => limit.parse().ok().filter(|limit| (1..=MAX_PAGE_SIZE).contains(limit)).ok_or("limit")?
+        };
+        let offset = match request.query_param("offset") {
+            None => 0,
+            Some(offset) => offset.parse().map_err(|_| "offset")?
+        };
+        let sort = match request.query_param("sort") {
+            None | Some("id") => Sort::Id,
+            Some("title") => Sort::Title,
+            Some(_) => return Err("sort")
+        };
+        let descending = match request.query_param("order") {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(_) => return Err("order")
+        };
+        let title_contains = match request.query_param("title_contains") {
+            None => None,
+            Some(needle) => Some(percent_decode(needle).ok_or("title_contains")?.to_lowercase())
+        };
+
+        Ok(Self { limit, offset, sort, descending, title_contains })
+    }
+}
+
+// An id that doesn't parse can't name a book, so it is a 404 rather than a 400.
+fn id(request: &Request) -> Option<u64> {
+    request.param("id")?.parse().ok()
+}
+
+/*
+    Validates a create/update body: it must be declared as JSON (415 otherwise) and be an object
+    with non-empty string `title` and `author` and an optional integer `year` (400 otherwise).
+ */
+fn fields(request: &Request) -> Result<(String, String, Option<u32>), Response> {
+    let is_json = request.content_type().is_some_and(|media_type| media_type.essence() == "application/json");
+    if !is_json {
+        return Err(error(415, "expected an application/json body"));
+    }
+
+    let body = std::str::from_utf8(request.body()).map_err(|_| error(400, "body is not UTF-8"))?;
+    let value = json::parse(body).map_err(|e| error(400, &e.to_string()))?;
+
+    let text = |name: &str| value
+        .get(name)
+        .and_then(Value::as_str)
+        .filter(|text| !text.trim().is_empty())
+        .map(str::to_string);
+    let title = text("title").ok_or_else(|| error(400, "title must be a non-empty string"))?;
+    let author = text("author").ok_or_else(|| error(400, "author must be a non-empty string"))?;
+
+    let year = match value.get("year") {
+        None | Some(Value::Null) => None,
+        Some(year) => {
+            let year = year.as_u64().and_then(|year| u32::try_from(year).ok());
+            Some(year.ok_or_else(|| error(400, "year must be a non-negative integer"))?)
+        }
+    };
+
+    Ok((title, author, year))
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(status, &Value::Object(vec![("error".to_string(), message.into())]))
+}
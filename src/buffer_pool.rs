@@ -0,0 +1,148 @@
+use std::{
+    fmt::{Debug, Formatter},
+    io::{self, BufRead, Read},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+// Same as std's BufReader default; big enough for almost every request head.
+pub(crate) const BUFFER_SIZE: usize = 8 * 1024;
+
+// A buffer that grew past this while in use (a large response) is freed rather than kept idle.
+const MAX_RETAINED_CAPACITY: usize = 64 * 1024;
+
+/*
+    Byte buffers reused across connections, so that connection churn doesn't allocate and free a
+    read buffer and a response buffer per connection. At most `max_pooled` buffers are kept;
+    beyond that returned buffers are simply dropped, which bounds the memory held while idle.
+ */
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_pooled: usize
+}
+
+impl BufferPool {
+    pub(crate) fn new(max_pooled: usize) -> Self {
+        Self { buffers: Mutex::new(Vec::with_capacity(max_pooled)), max_pooled }
+    }
+
+    /// An empty buffer with at least `BUFFER_SIZE` capacity.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        let pooled = self.buffers
+            .lock()
+            .expect("Mutex poisoned: Another thread panicked while holding the lock.")
+            .pop();
+
+        pooled.unwrap_or_else(|| Vec::with_capacity(BUFFER_SIZE))
+    }
+
+    pub(crate) fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() < BUFFER_SIZE || buffer.capacity() > MAX_RETAINED_CAPACITY {
+            return;
+        }
+        buffer.clear();
+
+        let mut buffers = self.buffers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if buffers.len() < self.max_pooled {
+            buffers.push(buffer);
+        }
+    }
+
+    pub(crate) fn idle(&self) -> usize {
+        self.buffers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+}
+
+impl Debug for BufferPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("idle", &self.idle())
+            .field("max_pooled", &self.max_pooled)
+            .finish()
+    }
+}
+
+/// A `BufReader` whose buffer comes from a `BufferPool` and goes back to it on drop.
+pub(crate) struct PooledReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    pool: Arc<BufferPool>
+}
+
+impl<R: Read> PooledReader<R> {
+    pub(crate) fn new(inner: R, pool: Arc<BufferPool>) -> Self {
+        let mut buffer = pool.take();
+        buffer.resize(BUFFER_SIZE, 0);
+
+        Self { inner, buffer, pos: 0, filled: 0, pool }
+    }
+}
+
+impl<R: Read> Read for PooledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // like BufReader: a large read with nothing buffered skips the copy
+        if self.pos == self.filled && buf.len() >= self.buffer.len() {
+            return self.inner.read(buf);
+        }
+
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for PooledReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.inner.read(&mut self.buffer)?;
+            self.pos = 0;
+        }
+        Ok(&self.buffer[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.filled);
+    }
+}
+
+impl<R> Drop for PooledReader<R> {
+    fn drop(&mut self) {
+        self.pool.give(std::mem::take(&mut self.buffer));
+    }
+}
+
+/// A buffer checked out of a `BufferPool` for as long as this value lives.
+pub(crate) struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>
+}
+
+impl PooledBuffer {
+    pub(crate) fn new(pool: Arc<BufferPool>) -> Self {
+        Self { buffer: pool.take(), pool }
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.give(std::mem::take(&mut self.buffer));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::Path,
+};
+use crate::{Request, Response};
+
+/*
+    Cache-Control values chosen by request path, so static assets can be cached for a year while
+    HTML is revalidated every time. Rules are either a path prefix (the longest matching one
+    wins) or a file extension; when both kinds match, the extension rule is the more specific
+    one and wins.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    prefixes: Vec<(String, String)>,
+    extensions: Vec<(String, String)>
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `value` for paths starting with `prefix`, e.g. `"/assets/"`. Replaces an existing rule for `prefix`.
+    pub fn prefix(&mut self, prefix: &str, value: &str) -> Result<&mut Self, InvalidRule> {
+        if !prefix.starts_with('/') {
+            return Err(InvalidRule("path prefix must start with '/'"));
+        }
+        validate(value)?;
+
+        replace(&mut self.prefixes, prefix.to_string(), value.to_string());
+        Ok(self)
+    }
+
+    /// Uses `value` for paths whose last segment has the extension `extension` (`"html"` or `".html"`).
+    pub fn extension(&mut self, extension: &str, value: &str) -> Result<&mut Self, InvalidRule> {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        if extension.is_empty() || extension.contains('/') {
+            return Err(InvalidRule("extension must be a non-empty file extension"));
+        }
+        validate(value)?;
+
+        replace(&mut self.extensions, extension, value.to_string());
+        Ok(self)
+    }
+
+    /// The value the rules pick for `path`, if any.
+    pub fn value_for(&self, path: &str) -> Option<&str> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let by_extension = extension.and_then(|extension| {
+            self.extensions.iter().find(|(rule, _)| *rule == extension)
+        });
+
+        by_extension
+            .or_else(|| {
+                self.prefixes
+                    .iter()
+                    .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+                    .max_by_key(|(prefix, _)| prefix.len())
+            })
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty() && self.extensions.is_empty()
+    }
+
+    /*
+        Adds the matching value to a successful (or not-modified) response. A Cache-Control the
+        handler set itself always wins, as do error responses, which shouldn't be cached like
+        the resource they failed to produce.
+     */
+    pub(crate) fn apply(&self, request: &Request, response: &mut Response) {
+        if response.header("Cache-Control").is_some() || !matches!(response.status(), 200 | 304) {
+            return;
+        }
+
+        if let Some(value) = self.value_for(request.path()) {
+            response.set_header("Cache-Control", value);
+        }
+    }
+}
+
+fn replace(rules: &mut Vec<(String, String)>, key: String, value: String) {
+    rules.retain(|(existing, _)| *existing != key);
+    rules.push((key, value));
+}
+
+fn validate(value: &str) -> Result<(), InvalidRule> {
+    if value.trim().is_empty() {
+        return Err(InvalidRule("value must not be empty"));
+    }
+    // a CR or LF here would let the rule inject extra header lines
+    if value.chars().any(char::is_control) {
+        return Err(InvalidRule("value must not contain control characters"));
+    }
+    Ok(())
+}
+
+/// Why a Cache-Control rule was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRule(&'static str);
+
+impl Display for InvalidRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid Cache-Control rule: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidRule {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> CacheControl {
+        let mut rules = CacheControl::new();
+        rules
+            .prefix("/", "no-cache").unwrap()
+            .prefix("/assets/", "public, max-age=31536000, immutable").unwrap()
+            .prefix("/assets/drafts/", "no-store").unwrap()
+            .extension(".HTML", "no-cache, must-revalidate").unwrap();
+        rules
+    }
+
+    fn request(path: &str) -> Request {
+        let raw = format!("GET {path} HTTP/1.1\r\nHost: test\r\n\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn extensions_win_over_prefixes_and_longer_prefixes_over_shorter() {
+        let rules = rules();
+        let cases = [
+            ("/", Some("no-cache")),
+            ("/about", Some("no-cache")),
+            ("/assets/app.js", Some("public, max-age=31536000, immutable")),
+            ("/assets/drafts/logo.svg", Some("no-store")),
+            ("/assets/index.html", Some("no-cache, must-revalidate")),
+            ("/assets/PAGE.Html", Some("no-cache, must-revalidate")),
+            ("/assets.html/app.js", Some("no-cache"))
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(rules.value_for(path), expected, "{path}");
+        }
+        assert_eq!(CacheControl::new().value_for("/"), None);
+        assert!(CacheControl::new().is_empty());
+    }
+
+    #[test]
+    fn a_rule_for_the_same_key_replaces_the_old_one() {
+        let mut rules = rules();
+        rules.prefix("/assets/", "max-age=60").unwrap().extension("html", "no-store").unwrap();
+
+        assert_eq!(rules.value_for("/assets/app.js"), Some("max-age=60"));
+        assert_eq!(rules.value_for("/index.html"), Some("no-store"));
+    }
+
+    #[test]
+    fn invalid_rules_are_refused() {
+        let mut rules = CacheControl::new();
+
+        assert_eq!(rules.prefix("assets/", "no-cache").unwrap_err(), InvalidRule("path prefix must start with '/'"));
+        assert_eq!(rules.prefix("/", " ").unwrap_err(), InvalidRule("value must not be empty"));
+        assert_eq!(
+            rules.prefix("/", "no-cache\r\nSet-Cookie: a=b").unwrap_err(),
+            InvalidRule("value must not contain control characters")
+        );
+        for extension in ["", ".", "css/x"] {
+            assert_eq!(
+                rules.extension(extension, "no-cache").unwrap_err(),
+                InvalidRule("extension must be a non-empty file extension"),
+                "{extension:?}"
+            );
+        }
+        assert!(rules.is_empty());
+    }
+
+    #[test]
+    fn only_successful_responses_without_their_own_value_get_one() {
+        let rules = rules();
+        let request = request("/assets/app.js");
+        let cases = [
+            (Response::new(200), Some("public, max-age=31536000, immutable")),
+            (Response::new(304), Some("public, max-age=31536000, immutable")),
+            (Response::new(200).with_header("Cache-Control", "private"), Some("private")),
+            (Response::new(404), None),
+            (Response::new(206), None),
+            (Response::new(500), None)
+        ];
+
+        for (mut response, expected) in cases {
+            let status = response.status();
+            rules.apply(&request, &mut response);
+            assert_eq!(response.header("Cache-Control"), expected, "{status}");
+        }
+    }
+}
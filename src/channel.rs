@@ -0,0 +1,219 @@
+use std::{
+    sync::{mpsc::RecvTimeoutError, Arc, Weak},
+    time::Duration,
+};
+#[cfg(not(feature = "crossbeam"))]
+use std::sync::{mpsc, Mutex};
+
+/*
+    The pool's job queue, behind a pair of small traits so the backend can be swapped without
+    touching ThreadPool or Worker. Every worker holds a clone of the same receiver and whichever
+    is free takes the next job (a multi-consumer queue); the pool holds the one sender and
+    dropping it disconnects the workers.
+
+    The default backend is std's mpsc channel, which is single-consumer and so needs a mutex
+    around the receiver. With the `crossbeam` feature it is crossbeam's channel instead, which
+    is multi-consumer itself, so idle workers wait on the queue directly rather than on a lock.
+    The feature keeps the default build dependency-free; benches/queue_contention.rs compares
+    the two.
+ */
+pub(crate) trait JobSender<T>: Send {
+    /// Queues `value`; hands it back if every receiver is gone.
+    fn send(&self, value: T) -> Result<(), T>;
+}
+
+pub(crate) trait JobReceiver<T>: Clone + Send + 'static {
+    /// Blocks until a value arrives; `None` once the sender is dropped and the queue is empty.
+    fn recv(&self) -> Option<T>;
+
+    /// Like `recv`, but gives up with `Timeout` if nothing arrives within `timeout`.
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError>;
+
+    /// A handle on the queue that doesn't keep it open: once every receiver is gone, sending
+    /// fails even while weak handles remain.
+    fn downgrade(&self) -> WeakReceiver<T>;
+}
+
+/*
+    Lets the pool make receivers for new workers without holding one itself. A receiver kept by
+    the pool would keep the queue open after every worker had died, so jobs would be queued for
+    nobody instead of being refused.
+ */
+pub(crate) struct WeakReceiver<T>(Weak<ReceiverHandle<T>>);
+
+impl<T> Clone for WeakReceiver<T> {
+    fn clone(&self) -> Self {
+        Self(Weak::clone(&self.0))
+    }
+}
+
+#[cfg(not(feature = "crossbeam"))]
+pub(crate) type Sender<T> = StdSender<T>;
+#[cfg(not(feature = "crossbeam"))]
+pub(crate) type Receiver<T> = StdReceiver<T>;
+#[cfg(not(feature = "crossbeam"))]
+type ReceiverHandle<T> = Mutex<mpsc::Receiver<T>>;
+
+#[cfg(feature = "crossbeam")]
+pub(crate) type Sender<T> = CrossbeamSender<T>;
+#[cfg(feature = "crossbeam")]
+pub(crate) type Receiver<T> = CrossbeamReceiver<T>;
+#[cfg(feature = "crossbeam")]
+type ReceiverHandle<T> = crossbeam_channel::Receiver<T>;
+
+/// Creates the job queue with the backend the crate was built with.
+#[cfg(not(feature = "crossbeam"))]
+pub(crate) fn channel<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::channel();
+    // Mutex owns the receiver, Arc tracks mutex-wrapped receiver reference counts across threads
+    (StdSender(sender), StdReceiver(Arc::new(Mutex::new(receiver))))
+}
+
+/// Creates the job queue with the backend the crate was built with.
+#[cfg(feature = "crossbeam")]
+pub(crate) fn channel<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    (CrossbeamSender(sender), CrossbeamReceiver(Arc::new(receiver)))
+}
+
+#[cfg(not(feature = "crossbeam"))]
+pub(crate) struct StdSender<T>(mpsc::Sender<T>);
+
+#[cfg(not(feature = "crossbeam"))]
+impl<T: Send> JobSender<T> for StdSender<T> {
+    fn send(&self, value: T) -> Result<(), T> {
+        self.0.send(value).map_err(|mpsc::SendError(value)| value)
+    }
+}
+
+// Taking a job off the channel queue involves mutating the receiver,
+// so we need thread-safe smart pointers to share and modify receiver.
+#[cfg(not(feature = "crossbeam"))]
+pub(crate) struct StdReceiver<T>(Arc<ReceiverHandle<T>>);
+
+#[cfg(not(feature = "crossbeam"))]
+impl<T> Clone for StdReceiver<T> {
+    // we clone the Arc to bump the reference count so the workers can share ownership of the receiver
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+#[cfg(not(feature = "crossbeam"))]
+impl<T> StdReceiver<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, mpsc::Receiver<T>> {
+        /*
+            We first call lock on the receiver to acquire the mutex. Acquiring a lock might fail
+            if the mutex is in a poisoned state, which happens if some other thread panicked
+            while holding the lock rather than releasing it. Panicking here in turn would take
+            down every worker one by one. The lock only ever guards the receiver, which a panic
+            elsewhere can't leave half-updated, so we take the guard out of the poison error and
+            carry on. Clearing the poison means the recovery is reported once per panic rather
+            than on every job taken afterwards.
+         */
+        self.0.lock().unwrap_or_else(|poisoned| {
+            eprintln!("Job queue lock poisoned by a panicked worker; recovering it.");
+            self.0.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+#[cfg(all(test, not(feature = "crossbeam")))]
+impl<T: Send + 'static> StdReceiver<T> {
+    // Leaves the lock poisoned, as a thread panicking while it held the lock would.
+    pub(crate) fn poison(&self) {
+        let handle = Arc::clone(&self.0);
+        let _ = std::thread::spawn(move || {
+            let _guard = handle.lock();
+            panic!("poisoning the job queue lock");
+        })
+        .join();
+    }
+
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+}
+
+#[cfg(not(feature = "crossbeam"))]
+impl<T: Send + 'static> JobReceiver<T> for StdReceiver<T> {
+    fn recv(&self) -> Option<T> {
+        self.lock()
+            .recv() // blocks the given thread until a message is received or the thread holding the sender shuts down
+            .ok()
+        // lock automatically released
+    }
+
+    /*
+        Only the worker holding the lock is waiting on the channel; the others are queued on the
+        mutex, where no timeout applies. So the timeout runs from when this worker gets the lock,
+        and with several idle workers they take turns timing out rather than all waking at once.
+     */
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.lock().recv_timeout(timeout)
+    }
+
+    fn downgrade(&self) -> WeakReceiver<T> {
+        WeakReceiver(Arc::downgrade(&self.0))
+    }
+}
+
+#[cfg(not(feature = "crossbeam"))]
+impl<T> WeakReceiver<T> {
+    /// Another receiver on the queue, if any worker still holds one.
+    pub(crate) fn upgrade(&self) -> Option<Receiver<T>> {
+        self.0.upgrade().map(StdReceiver)
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+pub(crate) struct CrossbeamSender<T>(crossbeam_channel::Sender<T>);
+
+#[cfg(feature = "crossbeam")]
+impl<T: Send> JobSender<T> for CrossbeamSender<T> {
+    fn send(&self, value: T) -> Result<(), T> {
+        self.0.send(value).map_err(|crossbeam_channel::SendError(value)| value)
+    }
+}
+
+/*
+    Already multi-consumer, so it needs no lock. The Arc is only there so the pool can hold a
+    weak handle on it: the queue disconnects once the last worker's clone is dropped.
+ */
+#[cfg(feature = "crossbeam")]
+pub(crate) struct CrossbeamReceiver<T>(Arc<ReceiverHandle<T>>);
+
+#[cfg(feature = "crossbeam")]
+impl<T> Clone for CrossbeamReceiver<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl<T: Send + 'static> JobReceiver<T> for CrossbeamReceiver<T> {
+    fn recv(&self) -> Option<T> {
+        self.0.recv().ok()
+    }
+
+    // every idle worker waits on the queue itself, so each times out on its own schedule
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.0.recv_timeout(timeout).map_err(|error| match error {
+            crossbeam_channel::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+            crossbeam_channel::RecvTimeoutError::Disconnected => RecvTimeoutError::Disconnected
+        })
+    }
+
+    fn downgrade(&self) -> WeakReceiver<T> {
+        WeakReceiver(Arc::downgrade(&self.0))
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl<T> WeakReceiver<T> {
+    /// Another receiver on the queue, if any worker still holds one.
+    pub(crate) fn upgrade(&self) -> Option<Receiver<T>> {
+        self.0.upgrade().map(CrossbeamReceiver)
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+use crate::{
+    body::{BodyReader, Framing},
+    Response,
+};
+
+// Largest response body a `Client` reads before giving up on the response as malformed.
+const MAX_RESPONSE_LENGTH: u64 = 16 * 1024 * 1024;
+
+// Longest status or header line, and most header lines, accepted in a response head.
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+// Methods a request can be repeated with to the same effect, so one the server may have seen is safe to resend.
+const IDEMPOTENT_METHODS: [&str; 5] = ["GET", "HEAD", "PUT", "DELETE", "OPTIONS"];
+
+/*
+    A minimal HTTP/1.1 client for one server: enough to drive this server from examples,
+    benchmarks and ad hoc checks, not a general-purpose client. Requests go out one at a time,
+    each waiting for its whole response, which comes back as a `Response` with the headers as
+    received (framing headers included) and the body decoded and in memory.
+
+    With keep-alive (the default) the connection is reused for as long as the server allows it.
+    One the server has closed, or timed out with a 408, in the meantime is replaced before the
+    request goes out. If it closes just as the request is sent, the request is sent once more
+    on a new connection, but only when that's safe: when it couldn't be written, so the server
+    never saw it, or when no byte of the response arrived and the method is idempotent, since
+    the server may have acted on it before closing (RFC 9110 section 9.2.2). Anything else that
+    goes wrong drops the connection and is reported.
+ */
+#[derive(Debug)]
+pub struct Client {
+    addr: String,
+    keep_alive: bool,
+    timeout: Option<Duration>,
+    connection: Option<BufReader<TcpStream>>,
+    connections: usize
+}
+
+impl Client {
+    /// A client for the server at `addr`, a `host:port` address. Nothing is connected until
+    /// the first request.
+    pub fn new(addr: &str) -> Self {
+        Self { addr: addr.to_string(), keep_alive: true, timeout: None, connection: None, connections: 0 }
+    }
+
+    /// Whether to keep the connection open between requests; on by default. Without it every
+    /// request asks for `Connection: close` and gets a connection of its own.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// How long any single read from or write to the server may block before the request
+    /// fails with a timeout. Unbounded by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// GETs `path` from `addr` on a connection of its own.
+    pub fn get(addr: &str, path: &str) -> Result<Response, ClientError> {
+        Client::new(addr).keep_alive(false).request("GET", path, &[], &[])
+    }
+
+    /// Sends a request and reads its response. A `Host` header is added unless `headers` has
+    /// one, and a `Content-Length` for a non-empty body; `headers` shouldn't frame the body
+    /// themselves.
+    pub fn request(&mut self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response, ClientError> {
+        let request = self.encode(method, path, headers, body);
+        let head_only = method == "HEAD";
+        let reused = self.connection.is_some();
+
+        // each failure says whether the request had been written, and so could have been acted on
+        let exchanged = self.send(&request)
+            .map_err(|e| (e, false))
+            .and_then(|()| self.receive(head_only).map_err(|e| (e, true)));
+
+        match exchanged {
+            Ok(response) => Ok(response),
+            Err((ClientError::Closed, sent)) if reused && (!sent || IDEMPOTENT_METHODS.contains(&method)) => {
+                self.connection = None;
+                self.send(&request).and_then(|()| self.receive(head_only))
+            }
+            Err((e, _)) => Err(e)
+        }
+        .inspect_err(|_| self.connection = None)
+    }
+
+    /// Number of connections opened so far, e.g. to check that keep-alive reused one.
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    fn encode(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut head = format!("{method} {path} HTTP/1.1\r\n");
+        if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Host")) {
+            head.push_str(&format!("Host: {}\r\n", self.addr));
+        }
+        for (name, value) in headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        if !self.keep_alive {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    // Writes `request`, on the kept-alive connection if it is still usable or else a new one.
+    fn send(&mut self, request: &[u8]) -> Result<(), ClientError> {
+        if self.connection.as_mut().is_some_and(is_stale) {
+            self.connection = None;
+        }
+        let reader = match &mut self.connection {
+            Some(reader) => reader,
+            None => {
+                let stream = TcpStream::connect(&self.addr)?;
+                stream.set_read_timeout(self.timeout)?;
+                stream.set_write_timeout(self.timeout)?;
+                self.connections += 1;
+                self.connection.insert(BufReader::new(stream))
+            }
+        };
+
+        reader.get_mut().write_all(request).map_err(|e| match e.kind() {
+            io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset => ClientError::Closed,
+            _ => ClientError::Io(e)
+        })
+    }
+
+    fn receive(&mut self, head_only: bool) -> Result<Response, ClientError> {
+        let reader = self.connection.as_mut().expect("a request was sent on the connection");
+        // nothing at all back means the server closed the connection before reading the request
+        if reader.fill_buf()?.is_empty() {
+            return Err(ClientError::Closed);
+        }
+
+        let (response, reusable) = read_response(reader, head_only, MAX_RESPONSE_LENGTH)?;
+        if !reusable || !self.keep_alive {
+            self.connection = None;
+        }
+        Ok(response)
+    }
+}
+
+/*
+    A kept-alive connection the server has spoken on since our last response is no use: it
+    has either closed it, or sent the 408 it answers idle connections with before closing them
+    (RFC 9112 section 9.5). Sending on it would read that 408 as the response to the request.
+ */
+fn is_stale(reader: &mut BufReader<TcpStream>) -> bool {
+    if !reader.buffer().is_empty() {
+        return true;
+    }
+    let stream = reader.get_ref();
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let peeked = stream.peek(&mut [0]);
+    let restored = stream.set_nonblocking(false);
+    // nothing to read yet is the one sign of a connection still waiting for us
+    !matches!(peeked, Err(ref e) if e.kind() == io::ErrorKind::WouldBlock) || restored.is_err()
+}
+
+/*
+    Reads one response off `reader`, skipping any interim 1xx responses before it, and returns
+    it with every header as received and the body decoded, plus whether the connection can
+    carry another request. The body is delimited as RFC 9112 section 6.3 lays down: none for
+    HEAD, 204 and 304, then chunked, then Content-Length, and otherwise by the server closing
+    the connection, which then can't be reused.
+
+    Shared with the reverse proxy, which filters the headers before relaying the response.
+ */
+pub(crate) fn read_response<R: BufRead>(reader: &mut R, head_only: bool, max_body: u64) -> Result<(Response, bool), ClientError> {
+    let (status, http_1_1, headers) = loop {
+        let status_line = read_line(reader)?;
+        // "HTTP/1.1 200 OK"; the reason phrase means nothing to us
+        let version = status_line.get(..9).filter(|version| matches!(*version, "HTTP/1.1 " | "HTTP/1.0 "));
+        let status = status_line
+            .get(9..12)
+            .filter(|_| version.is_some())
+            .and_then(|code| code.parse::<u16>().ok())
+            .filter(|code| (100..600).contains(code))
+            .ok_or_else(|| ClientError::Malformed(format!("status line {status_line:?}")))?;
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(ClientError::Malformed(format!("head with more than {MAX_HEADERS} headers")));
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| ClientError::Malformed(format!("header {line:?}")))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        if !(100..200).contains(&status) {
+            break (status, version == Some("HTTP/1.1 "), headers);
+        }
+    };
+
+    let header = |wanted: &str| headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+        .map(|(_, value)| value.as_str());
+    let framing = if head_only || matches!(status, 204 | 304) {
+        Some(Framing::Length(0))
+    } else if header("Transfer-Encoding").is_some_and(|coding| coding.eq_ignore_ascii_case("chunked")) {
+        Some(Framing::Chunked)
+    } else if let Some(length) = header("Content-Length") {
+        Some(Framing::Length(length.parse().map_err(|_| ClientError::Malformed(format!("Content-Length {length:?}")))?))
+    } else {
+        None
+    };
+
+    let mut body = Vec::new();
+    match framing {
+        Some(framing) => BodyReader::new(reader.by_ref(), framing, max_body).read_to_end(&mut body),
+        None => reader.by_ref().take(max_body + 1).read_to_end(&mut body)
+    }?;
+    if body.len() as u64 > max_body {
+        return Err(ClientError::Malformed(format!("body longer than {max_body} bytes")));
+    }
+
+    let connection = header("Connection").unwrap_or_default();
+    let has_token = |token: &str| connection.split(',').any(|value| value.trim().eq_ignore_ascii_case(token));
+    let reusable = framing.is_some()
+        && !has_token("close")
+        && (http_1_1 || has_token("keep-alive"));
+
+    let mut response = Response::new(status).with_body(body);
+    for (name, value) in &headers {
+        response.add_header(name, value);
+    }
+    Ok((response, reusable))
+}
+
+// One line of the head, without its CRLF. A bare LF is refused, as in requests.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, ClientError> {
+    let mut line = Vec::new();
+    reader.by_ref().take(MAX_LINE).read_until(b'\n', &mut line)?;
+    if line.pop() != Some(b'\n') {
+        return Err(ClientError::Malformed(String::from("response head cut short")));
+    }
+    if line.pop() != Some(b'\r') {
+        return Err(ClientError::Malformed(String::from("response head line ending in a bare LF")));
+    }
+
+    String::from_utf8(line).map_err(|_| ClientError::Malformed(String::from("non UTF-8 response head")))
+}
+
+/// Why a `Client` request got no response.
+#[derive(Debug)]
+pub enum ClientError {
+    /// Connecting, sending or receiving failed, or timed out.
+    Io(io::Error),
+    /// The server closed the connection without answering.
+    Closed,
+    /// The server's answer isn't HTTP/1.1 we can read; the message says what was wrong.
+    Malformed(String)
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        // the body framing is checked by BodyReader, which reports a bad body as InvalidData
+        match error.kind() {
+            io::ErrorKind::InvalidData => ClientError::Malformed(error.to_string()),
+            io::ErrorKind::UnexpectedEof => ClientError::Malformed(String::from("body cut short")),
+            _ => ClientError::Io(error)
+        }
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "{e}"),
+            ClientError::Closed => write!(f, "the server closed the connection without answering"),
+            ClientError::Malformed(what) => write!(f, "malformed response: {what}")
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Reads a canned response as the client would, with no size limit worth mentioning.
+    fn read(raw: &str) -> Result<(Response, bool), ClientError> {
+        read_response(&mut raw.as_bytes(), false, MAX_RESPONSE_LENGTH)
+    }
+
+    #[test]
+    fn a_head_line_ending_in_a_bare_lf_is_malformed() {
+        assert!(matches!(read("HTTP/1.1 200 OK\nContent-Length: 0\r\n\r\n"), Err(ClientError::Malformed(_))));
+        assert!(matches!(read("HTTP/1.1 200 OK\r\nContent-Length: 0\n\r\n"), Err(ClientError::Malformed(_))));
+        assert!(read("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").is_ok());
+    }
+
+    #[test]
+    fn malformed_responses_are_reported() {
+        let too_many_headers = format!("HTTP/1.1 200 OK\r\n{}\r\n", "X-Filler: 1\r\n".repeat(MAX_HEADERS + 1));
+        let malformed = [
+            "HTTP/2 200 OK\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 2OO OK\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 700 Unheard Of\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nno colon here\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: ten\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nbody\r\n0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n",
+            &too_many_headers
+        ];
+        for raw in malformed {
+            assert!(matches!(read(raw), Err(ClientError::Malformed(_))), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn a_body_over_the_limit_is_malformed() {
+        let limited = |raw: &str| read_response(&mut raw.as_bytes(), false, 4);
+        assert!(matches!(limited("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"), Err(ClientError::Malformed(_))));
+        assert!(matches!(limited("HTTP/1.1 200 OK\r\n\r\nhello"), Err(ClientError::Malformed(_))));
+        assert!(limited("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nhell").is_ok());
+    }
+
+    #[test]
+    fn a_chunked_body_is_decoded_and_interim_responses_skipped() {
+        let (response, reusable) = read(
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\n"
+        ).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), b"body");
+        assert!(reusable);
+    }
+
+    #[test]
+    fn a_connection_is_reusable_only_with_framing_and_keep_alive() {
+        let reusable = |raw: &str| read(raw).unwrap().1;
+        assert!(reusable("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"));
+        assert!(!reusable("HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"));
+        // delimited by the close
+        assert!(!reusable("HTTP/1.1 200 OK\r\n\r\nbody"));
+        assert!(!reusable("HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n"));
+        assert!(reusable("HTTP/1.0 200 OK\r\nContent-Length: 0\r\nConnection: keep-alive\r\n\r\n"));
+    }
+
+    #[test]
+    fn a_head_response_has_no_body_whatever_its_length() {
+        let (response, reusable) = read_response(&mut "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n".as_bytes(), true, MAX_RESPONSE_LENGTH).unwrap();
+        assert!(response.body().is_empty());
+        assert!(reusable);
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+    /*
+        A server that follows a script, one list of answers per connection it accepts: each
+        request read gets the next answer, and `None` closes the connection without one. Returns
+        its address and the method of every request it read.
+     */
+    fn scripted_server(script: Vec<Vec<Option<&'static str>>>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut methods = Vec::new();
+            for answers in script {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                for answer in answers {
+                    let mut head = String::new();
+                    // up to the blank line ending the head; no request in a script has a body
+                    while reader.read_line(&mut head).unwrap() > 2 {}
+                    methods.push(head.split(' ').next().unwrap_or_default().to_string());
+                    match answer {
+                        Some(answer) => reader.get_mut().write_all(answer.as_bytes()).unwrap(),
+                        None => break
+                    }
+                }
+            }
+            methods
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn an_idempotent_request_unanswered_on_a_reused_connection_is_sent_again() {
+        let (addr, server) = scripted_server(vec![vec![Some(OK), None], vec![Some(OK)]]);
+        let mut client = Client::new(&addr);
+
+        assert_eq!(client.request("GET", "/", &[], &[]).unwrap().status(), 200);
+        assert_eq!(client.request("DELETE", "/", &[], &[]).unwrap().status(), 200);
+        assert_eq!(client.connections(), 2);
+        assert_eq!(server.join().unwrap(), ["GET", "DELETE", "DELETE"]);
+    }
+
+    #[test]
+    fn a_post_unanswered_on_a_reused_connection_is_not_sent_again() {
+        let (addr, server) = scripted_server(vec![vec![Some(OK), None]]);
+        let mut client = Client::new(&addr);
+
+        assert_eq!(client.request("GET", "/", &[], &[]).unwrap().status(), 200);
+        assert!(matches!(client.request("POST", "/", &[], &[]), Err(ClientError::Closed)));
+        assert_eq!(client.connections(), 1);
+        assert_eq!(server.join().unwrap(), ["GET", "POST"]);
+    }
+
+    #[test]
+    fn an_unanswered_request_on_a_new_connection_is_not_sent_again() {
+        let (addr, server) = scripted_server(vec![vec![None]]);
+        let mut client = Client::new(&addr);
+
+        assert!(matches!(client.request("GET", "/", &[], &[]), Err(ClientError::Closed)));
+        assert_eq!(server.join().unwrap(), ["GET"]);
+    }
+
+    #[test]
+    fn a_malformed_response_drops_the_connection() {
+        let (addr, server) = scripted_server(vec![vec![Some("HTTP/1.1 200 OK\r\nContent-Length: x\r\n\r\n")], vec![Some(OK)]]);
+        let mut client = Client::new(&addr);
+
+        assert!(matches!(client.request("GET", "/", &[], &[]), Err(ClientError::Malformed(_))));
+        assert_eq!(client.request("GET", "/", &[], &[]).unwrap().body(), b"ok");
+        assert_eq!(client.connections(), 2);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,501 @@
+use crate::{negotiate::quality_values, Request, Response};
+
+/*
+    Decides whether a response is worth compressing. Kept separate from the compressor so the
+    rules can be reasoned about (and tuned) without caring how the bytes get squeezed:
+    - already-compressed formats (images, archives) only get bigger and cost CPU,
+    - tiny bodies gain less than the gzip header and trailer cost,
+    - a body that already has a Content-Encoding must not be encoded twice.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionPolicy {
+    /// Compressible media types. An entry ending in `/` matches every subtype (`text/`),
+    /// anything else must match the media type exactly.
+    pub content_types: Vec<String>,
+    /// Bodies shorter than this are sent as they are.
+    pub min_size: usize,
+    /// Leave responses that already carry a `Content-Encoding` untouched.
+    pub skip_encoded: bool
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml"
+            ].map(String::from).to_vec(),
+            min_size: 1024,
+            skip_encoded: true
+        }
+    }
+}
+
+impl CompressionPolicy {
+    pub fn should_compress(&self, content_type: Option<&str>, body_len: usize, content_encoding: Option<&str>) -> bool {
+        if body_len < self.min_size {
+            return false;
+        }
+        if self.skip_encoded && content_encoding.is_some_and(|coding| !coding.eq_ignore_ascii_case("identity")) {
+            return false;
+        }
+
+        let Some(content_type) = content_type else {
+            return false;
+        };
+        // parameters such as charset don't affect compressibility
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+        self.content_types.iter().any(|allowed| match allowed.strip_suffix('/') {
+            Some(top_level) => media_type
+                .split_once('/')
+                .is_some_and(|(kind, _)| kind.eq_ignore_ascii_case(top_level)),
+            None => media_type.eq_ignore_ascii_case(allowed)
+        })
+    }
+
+    /// Gzips `response` if the client accepts gzip and the policy allows it.
+    pub fn apply(&self, request: &Request, mut response: Response) -> Response {
+        // a 206 body is a slice of the identity bytes; gzipping it would answer a range of something else.
+        // A streamed body is left alone too: compressing it would mean reading it all into memory
+        if !accepts_gzip(request) || response.status() == 206 || response.is_streaming() {
+            return response;
+        }
+
+        let allowed = self.should_compress(
+            response.header("Content-Type"),
+            response.body().len(),
+            response.header("Content-Encoding")
+        );
+        if !allowed {
+            return response;
+        }
+
+        let compressed = gzip(response.body());
+        response.set_header("Content-Encoding", "gzip");
+        // any length the handler gave was the identity body's
+        response.remove_header("Content-Length");
+        // the representation now depends on Accept-Encoding, which shared caches must know
+        response.vary("Accept-Encoding");
+        // a validator for the identity bytes must not be reused for the gzipped bytes
+        if let Some(etag) = response.header("ETag").map(str::to_string) {
+            response.set_header("ETag", &gzip_etag(&etag));
+        }
+        response.with_body(compressed)
+    }
+}
+
+/// Turns the ETag of an identity body into one for its gzipped variant (`"abc"` → `"abc-gzip"`).
+pub fn gzip_etag(etag: &str) -> String {
+    match etag.strip_suffix('"') {
+        Some(open) => format!("{open}-gzip\""),
+        None => format!("{etag}-gzip")
+    }
+}
+
+/// Whether the request's `Accept-Encoding` allows gzip (explicitly or via `*`) with a non-zero q-value.
+pub fn accepts_gzip(request: &Request) -> bool {
+    let Some(accept_encoding) = request.header("Accept-Encoding") else {
+        return false;
+    };
+
+    let preferences = quality_values(accept_encoding);
+    let quality = |coding: &str| preferences
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(coding))
+        .map(|(_, q)| *q);
+
+    quality("gzip").or_else(|| quality("*")).is_some_and(|q| q > 0.0)
+}
+
+/// Compresses `data` into a gzip member (RFC 1952).
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no mtime, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    deflate(data, &mut out);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    out
+}
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+// How many earlier positions with the same hash we compare against before settling.
+const MAX_CHAIN: usize = 32;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+/*
+    A small deflate encoder (RFC 1951): greedy LZ77 matching over hash chains, emitted as a single
+    block with the fixed Huffman code. Dynamic Huffman tables would squeeze text another 10-20%,
+    but fixed codes keep this short and still cut typical HTML/CSS/JSON to a third or less.
+ */
+fn deflate(data: &[u8], out: &mut Vec<u8>) {
+    let mut bits = BitWriter::new(out);
+    // final block, fixed Huffman
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut matcher = Matcher::new(data);
+    let mut i = 0;
+    while i < data.len() {
+        let (length, distance) = matcher.longest_match(i);
+
+        if length >= MIN_MATCH {
+            write_length(&mut bits, length);
+            write_distance(&mut bits, distance);
+            for position in i..i + length {
+                matcher.insert(position);
+            }
+            i += length;
+        } else {
+            write_literal(&mut bits, data[i]);
+            matcher.insert(i);
+            i += 1;
+        }
+    }
+
+    // end of block
+    write_symbol(&mut bits, 256);
+    bits.flush();
+}
+
+// Remembers where each 3-byte prefix was last seen, chaining older occurrences through `prev`.
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, head: vec![usize::MAX; 1 << HASH_BITS], prev: vec![usize::MAX; WINDOW] }
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let data = self.data;
+        let key = u32::from(data[i]) << 16 | u32::from(data[i + 1]) << 8 | u32::from(data[i + 2]);
+        (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH <= self.data.len() {
+            let h = self.hash(i);
+            self.prev[i % WINDOW] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    fn longest_match(&self, i: usize) -> (usize, usize) {
+        let data = self.data;
+        if i + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+
+        let max = MAX_MATCH.min(data.len() - i);
+        let (mut best_length, mut best_distance) = (0, 0);
+        let mut candidate = self.head[self.hash(i)];
+
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || candidate >= i || i - candidate >= WINDOW {
+                break;
+            }
+
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[i..i + max])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                (best_length, best_distance) = (length, i - candidate);
+                if length == max {
+                    break;
+                }
+            }
+
+            // slots are reused as the window slides; a stale link points forward and ends the chain
+            let next = self.prev[candidate % WINDOW];
+            if next == usize::MAX || next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+
+        (best_length, best_distance)
+    }
+}
+
+fn write_literal(bits: &mut BitWriter, byte: u8) {
+    write_symbol(bits, u16::from(byte));
+}
+
+fn write_length(bits: &mut BitWriter, length: usize) {
+    let index = LENGTH_BASE.iter().rposition(|&base| usize::from(base) <= length).unwrap_or(0);
+    write_symbol(bits, 257 + index as u16);
+    bits.write((length - usize::from(LENGTH_BASE[index])) as u32, LENGTH_EXTRA[index]);
+}
+
+fn write_distance(bits: &mut BitWriter, distance: usize) {
+    let index = DISTANCE_BASE.iter().rposition(|&base| usize::from(base) <= distance).unwrap_or(0);
+    // distance codes are a flat 5 bits in the fixed code
+    bits.write_huffman(index as u32, 5);
+    bits.write((distance - usize::from(DISTANCE_BASE[index])) as u32, DISTANCE_EXTRA[index]);
+}
+
+// The fixed literal/length code from RFC 1951 section 3.2.6.
+fn write_symbol(bits: &mut BitWriter, symbol: u16) {
+    let symbol = u32::from(symbol);
+    match symbol {
+        0..=143 => bits.write_huffman(0x30 + symbol, 8),
+        144..=255 => bits.write_huffman(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_huffman(symbol - 256, 7),
+        _ => bits.write_huffman(0xC0 + symbol - 280, 8)
+    }
+}
+
+// Deflate packs values least-significant bit first, except Huffman codes, which go most-significant first.
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    buffer: u32,
+    count: u8
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        Self { out, buffer: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, bits: u8) {
+        for bit in 0..bits {
+            self.buffer |= ((value >> bit) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.out.push(self.buffer as u8);
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    fn write_huffman(&mut self, code: u32, bits: u8) {
+        let reversed = code.reverse_bits() >> (32 - u32::from(bits));
+        self.write(reversed, bits);
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+            self.buffer = 0;
+            self.count = 0;
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        *entry = (0..8).fold(n as u32, |c, _| if c & 1 == 1 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 });
+    }
+
+    !data.iter().fold(!0u32, |crc, &byte| table[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Version;
+    use std::io::Read;
+
+    fn request(accept_encoding: Option<&str>) -> Request {
+        let header = accept_encoding.map(|value| format!("Accept-Encoding: {value}\r\n")).unwrap_or_default();
+        let raw = format!("GET / HTTP/1.1\r\nHost: test\r\n{header}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    fn page() -> Response {
+        Response::html(200, "<p>hello</p>".repeat(200))
+            .with_header("Content-Length", "2400")
+            .with_header("ETag", "\"abc\"")
+    }
+
+    // Just enough of an inflater to read back what `deflate` writes: one final block with fixed codes.
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut position = 0;
+        let mut bit = |count: u32| -> u32 {
+            (0..count).fold(0, |value, n| {
+                let b = u32::from(data[position / 8] >> (position % 8)) & 1;
+                position += 1;
+                value | b << n
+            })
+        };
+        assert_eq!(bit(1), 1, "not the final block");
+        assert_eq!(bit(2), 1, "not a fixed Huffman block");
+
+        let mut out = Vec::new();
+        loop {
+            let mut code = (0..7).fold(0, |code, _| code << 1 | bit(1));
+            let symbol = if code <= 0x17 {
+                code + 256
+            } else {
+                code = code << 1 | bit(1);
+                match code {
+                    0x30..=0xBF => code - 0x30,
+                    0xC0..=0xC7 => code - 0xC0 + 280,
+                    _ => (code << 1 | bit(1)) - 0x190 + 144
+                }
+            };
+
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => return out,
+                _ => {
+                    let index = (symbol - 257) as usize;
+                    let length = usize::from(LENGTH_BASE[index]) + bit(u32::from(LENGTH_EXTRA[index])) as usize;
+                    let index = (0..5).fold(0, |code, _| code << 1 | bit(1)) as usize;
+                    let distance = usize::from(DISTANCE_BASE[index]) + bit(u32::from(DISTANCE_EXTRA[index])) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_large_enough_bodies_of_listed_types_are_compressed() {
+        let policy = CompressionPolicy::default();
+        let cases = [
+            (Some("text/html"), 2048, None, true),
+            (Some("TEXT/css; charset=utf-8"), 2048, None, true),
+            (Some("application/json"), 2048, None, true),
+            (Some("application/json-seq"), 2048, None, false),
+            (Some("image/png"), 2048, None, false),
+            (Some("text/plain"), 1023, None, false),
+            (None, 2048, None, false),
+            (Some("text/plain"), 2048, Some("br"), false),
+            (Some("text/plain"), 2048, Some("identity"), true)
+        ];
+
+        for (content_type, len, encoding, expected) in cases {
+            assert_eq!(
+                policy.should_compress(content_type, len, encoding),
+                expected,
+                "{content_type:?}, {len} bytes, encoded as {encoding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn an_encoded_body_can_be_compressed_again_if_the_policy_says_so() {
+        let policy = CompressionPolicy { skip_encoded: false, ..CompressionPolicy::default() };
+        assert!(policy.should_compress(Some("text/plain"), 2048, Some("br")));
+    }
+
+    #[test]
+    fn gzip_must_be_accepted_with_a_non_zero_quality() {
+        let cases = [
+            (Some("gzip"), true),
+            (Some("deflate, GZIP;q=0.5"), true),
+            (Some("gzip;q=0"), false),
+            (Some("*"), true),
+            (Some("identity, *;q=0"), false),
+            (Some("gzip;q=0, *"), false),
+            (Some("br"), false),
+            (None, false)
+        ];
+
+        for (accept_encoding, expected) in cases {
+            assert_eq!(accepts_gzip(&request(accept_encoding)), expected, "{accept_encoding:?}");
+        }
+    }
+
+    #[test]
+    fn gzip_output_is_a_member_that_inflates_back_to_the_input() {
+        let data = "the quick brown fox jumps over the lazy dog. ".repeat(50).into_bytes();
+        let member = gzip(&data);
+
+        assert_eq!(member[..10], [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255]);
+        let (body, trailer) = member[10..].split_at(member.len() - 18);
+        assert_eq!(trailer[..4], crc32(&data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        assert_eq!(inflate(body), data);
+        assert!(member.len() < data.len() / 10, "{} bytes from {}", member.len(), data.len());
+    }
+
+    #[test]
+    fn short_and_unrepetitive_inputs_round_trip() {
+        let noise: Vec<u8> = (0..5000u32).map(|n| (n.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        for data in [&b""[..], b"a", b"ab", b"aaaa", &noise] {
+            let member = gzip(data);
+            assert_eq!(inflate(&member[10..member.len() - 8]), data, "{} bytes", data.len());
+        }
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn a_compressed_response_describes_its_new_body() {
+        let identity = page().body().to_vec();
+        let mut response = CompressionPolicy::default().apply(&request(Some("gzip")), page());
+
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Content-Length"), None);
+        assert_eq!(response.header("ETag"), Some("\"abc-gzip\""));
+        let body = response.body();
+        assert_eq!(inflate(&body[10..body.len() - 8]), identity);
+
+        // the Vary field is merged into the headers as the response is written
+        let mut sent = Vec::new();
+        response.write_to(&mut sent, Version::Http11, true).unwrap();
+        let sent = String::from_utf8_lossy(&sent);
+        assert!(sent.contains("\r\nVary: Accept-Encoding\r\n"), "{sent}");
+    }
+
+    #[test]
+    fn responses_are_left_alone_when_gzip_would_be_wrong() {
+        let policy = CompressionPolicy::default();
+        let partial = Response::new(206)
+            .with_header("Content-Type", "text/plain")
+            .with_body("x".repeat(2048));
+        let streamed = Response::new(200)
+            .with_header("Content-Type", "text/plain")
+            .with_reader(std::io::repeat(b'x').take(2048), Some(2048));
+
+        for (name, accept_encoding, response) in [
+            ("not accepted", None, page()),
+            ("refused", Some("gzip;q=0"), page()),
+            ("partial", Some("gzip"), partial),
+            ("streamed", Some("gzip"), streamed)
+        ] {
+            let response = policy.apply(&request(accept_encoding), response);
+            assert_eq!(response.header("Content-Encoding"), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn gzip_etags_keep_their_quotes() {
+        assert_eq!(gzip_etag("\"abc\""), "\"abc-gzip\"");
+        assert_eq!(gzip_etag("W/\"abc\""), "W/\"abc-gzip\"");
+        assert_eq!(gzip_etag("abc"), "abc-gzip");
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use crate::{http_date, Request, Response};
+
+/// A strong entity tag derived from the content itself (64-bit FNV-1a plus the length).
+pub fn etag(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    format!("\"{:x}-{hash:016x}\"", bytes.len())
+}
+
+/// A strong entity tag for a file, from its length and modification time: cheap enough for a
+/// HEAD request, or a file too big to read just to tag it, and it changes whenever either does.
+pub fn file_etag(metadata: &fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+/// What the request's preconditions say about answering it normally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreconditionResult {
+    /// No precondition stands in the way; send the full response.
+    Proceed,
+    /// The client's cached copy is current: answer `304 Not Modified`.
+    NotModified,
+    /// A precondition failed: answer `412 Precondition Failed`.
+    Failed
+}
+
+/*
+    Evaluates the conditional headers against the current representation, in the order RFC 9110
+    section 13.2.2 lays down:
+    1. If-Match (strong comparison); if it's absent, If-Unmodified-Since. Either failing is 412.
+    2. If-None-Match (weak comparison). A match is 304 for GET and HEAD, 412 for anything else.
+    3. Only if If-None-Match is absent, and only for GET and HEAD, If-Modified-Since: 304 if
+       the representation hasn't changed since.
+    Dates that don't parse are ignored, as is a date validator without a `last_modified` to
+    compare it with. Times are compared in whole seconds, the precision of an HTTP-date.
+ */
+pub fn evaluate_preconditions(request: &Request, etag: Option<&str>, last_modified: Option<SystemTime>) -> PreconditionResult {
+    let read_only = matches!(request.method(), "GET" | "HEAD");
+    let since = |header: &str| request
+        .header(header)
+        .and_then(http_date::parse)
+        .zip(last_modified)
+        .map(|(date, modified)| seconds(modified) <= seconds(date));
+
+    if let Some(if_match) = request.header("If-Match") {
+        if !matches_any(if_match, etag, |candidate, etag| !is_weak(candidate) && !is_weak(etag) && candidate == etag) {
+            return PreconditionResult::Failed;
+        }
+    } else if since("If-Unmodified-Since") == Some(false) {
+        return PreconditionResult::Failed;
+    }
+
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        if matches_any(if_none_match, etag, |candidate, etag| strip_weak(candidate) == strip_weak(etag)) {
+            return if read_only { PreconditionResult::NotModified } else { PreconditionResult::Failed };
+        }
+    } else if read_only && since("If-Modified-Since") == Some(true) {
+        return PreconditionResult::NotModified;
+    }
+
+    PreconditionResult::Proceed
+}
+
+/// True when the client's cached copy is still current by `If-None-Match` alone.
+pub fn is_fresh(request: &Request, etag: &str) -> bool {
+    request
+        .header("If-None-Match")
+        .is_some_and(|if_none_match| matches_any(if_none_match, Some(etag), |candidate, etag| strip_weak(candidate) == strip_weak(etag)))
+}
+
+/*
+    The `304 Not Modified` answer to a request whose cached copy is still fresh. It repeats the
+    validators the 200 would have carried, so the client can update its cache entry; any other
+    cache headers (Cache-Control, Vary) are the caller's to add, as for the 200. It has no body.
+ */
+pub fn not_modified(etag: &str) -> Response {
+    Response::new(304).with_header("ETag", etag)
+}
+
+/*
+    Answers a GET or HEAD with `304 Not Modified` when the 200 the handler produced carries an
+    `ETag` or `Last-Modified` the request's If-None-Match or If-Modified-Since say the client
+    already has. Any handler gets conditional requests this way by setting a validator, even one
+    that builds its body from scratch each time; it just saves the bytes on the wire, not the
+    handler's work, which a handler with a cheap validator can skip by checking `is_fresh` or
+    `evaluate_preconditions` itself first.
+
+    The 304 keeps every header of the 200 but those describing its body's length, so caches
+    get the validators, Cache-Control and Vary to update their entry with. A failed If-Match on
+    a GET is left alone: the handler has already run, and a 412 would tell the client nothing
+    the 200 doesn't.
+ */
+pub fn revalidate(request: &Request, response: Response) -> Response {
+    if response.status() != 200 || !matches!(request.method(), "GET" | "HEAD") {
+        return response;
+    }
+    let etag = response.header("ETag");
+    let last_modified = response.header("Last-Modified").and_then(http_date::parse);
+    if etag.is_none() && last_modified.is_none() {
+        return response;
+    }
+    if evaluate_preconditions(request, etag, last_modified) != PreconditionResult::NotModified {
+        return response;
+    }
+
+    let mut not_modified = Response::new(304);
+    not_modified.inherit_headers(&response);
+    not_modified.remove_header("Content-Length");
+    not_modified.remove_header("Transfer-Encoding");
+    not_modified
+}
+
+/// The `412 Precondition Failed` answer to a request whose If-Match or If-Unmodified-Since failed.
+pub fn precondition_failed() -> Response {
+    Response::status_only(412)
+}
+
+// `*` matches any current representation, i.e. whenever there is an `etag` at all.
+fn matches_any(header: &str, etag: Option<&str>, same: impl Fn(&str, &str) -> bool) -> bool {
+    let Some(etag) = etag else {
+        return false;
+    };
+    if header.trim() == "*" {
+        return true;
+    }
+
+    header.split(',').any(|candidate| same(candidate.trim(), etag))
+}
+
+fn is_weak(tag: &str) -> bool {
+    tag.starts_with("W/")
+}
+
+fn strip_weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::request::Version;
+
+    const ETAG: &str = "\"abc\"";
+
+    fn request(method: &str, headers: &str) -> Request {
+        let raw = format!("{method} / HTTP/1.1\r\nHost: test\r\n{headers}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    // The representation's modification time, and HTTP-dates a second either side of it.
+    fn dates() -> (SystemTime, String, String, String) {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let date = |time| http_date::format(time);
+        (modified, date(modified - Duration::from_secs(1)), date(modified), date(modified + Duration::from_secs(1)))
+    }
+
+    #[test]
+    fn preconditions_are_evaluated_in_rfc_order() {
+        use PreconditionResult::*;
+        let (modified, before, at, after) = dates();
+        let cases = [
+            ("GET", String::new(), Proceed),
+            ("GET", "If-Match: \"abc\"".to_string(), Proceed),
+            ("GET", "If-Match: \"x\", \"abc\"".to_string(), Proceed),
+            ("GET", "If-Match: *".to_string(), Proceed),
+            ("GET", "If-Match: \"x\"".to_string(), Failed),
+            ("PUT", "If-Match: W/\"abc\"".to_string(), Failed),
+            ("PUT", format!("If-Unmodified-Since: {at}"), Proceed),
+            ("PUT", format!("If-Unmodified-Since: {before}"), Failed),
+            // If-Match wins over If-Unmodified-Since
+            ("PUT", format!("If-Match: \"abc\"\r\nIf-Unmodified-Since: {before}"), Proceed),
+            ("GET", "If-None-Match: \"abc\"".to_string(), NotModified),
+            ("HEAD", "If-None-Match: W/\"abc\"".to_string(), NotModified),
+            ("GET", "If-None-Match: *".to_string(), NotModified),
+            ("GET", "If-None-Match: \"x\"".to_string(), Proceed),
+            ("PUT", "If-None-Match: *".to_string(), Failed),
+            ("GET", format!("If-Modified-Since: {at}"), NotModified),
+            ("GET", format!("If-Modified-Since: {after}"), NotModified),
+            ("GET", format!("If-Modified-Since: {before}"), Proceed),
+            ("POST", format!("If-Modified-Since: {at}"), Proceed),
+            ("GET", "If-Modified-Since: yesterday".to_string(), Proceed),
+            // If-None-Match wins over If-Modified-Since
+            ("GET", format!("If-None-Match: \"x\"\r\nIf-Modified-Since: {at}"), Proceed),
+            // a failed If-Match wins over a matching If-None-Match
+            ("GET", "If-Match: \"x\"\r\nIf-None-Match: \"abc\"".to_string(), Failed)
+        ];
+
+        for (method, headers, expected) in cases {
+            let headers = if headers.is_empty() { headers } else { format!("{headers}\r\n") };
+            let request = request(method, &headers);
+            assert_eq!(evaluate_preconditions(&request, Some(ETAG), Some(modified)), expected, "{method} {headers:?}");
+        }
+    }
+
+    #[test]
+    fn validators_the_resource_lacks_never_match() {
+        use PreconditionResult::*;
+        let (_, before, ..) = dates();
+        let cases = [
+            ("If-Match: *\r\n".to_string(), Failed),
+            ("If-None-Match: *\r\n".to_string(), Proceed),
+            (format!("If-Modified-Since: {before}\r\n"), Proceed),
+            (format!("If-Unmodified-Since: {before}\r\n"), Proceed)
+        ];
+
+        for (headers, expected) in cases {
+            assert_eq!(evaluate_preconditions(&request("GET", &headers), None, None), expected, "{headers:?}");
+        }
+    }
+
+    #[test]
+    fn freshness_compares_tags_weakly() {
+        for (headers, fresh) in [("If-None-Match: W/\"abc\"\r\n", true), ("If-None-Match: \"abd\"\r\n", false), ("", false)] {
+            assert_eq!(is_fresh(&request("GET", headers), ETAG), fresh, "{headers:?}");
+        }
+    }
+
+    #[test]
+    fn content_etags_change_with_the_content() {
+        assert_eq!(etag(b""), "\"0-cbf29ce484222325\"");
+        assert_eq!(etag(b"hello"), etag(b"hello"));
+        assert_ne!(etag(b"hello"), etag(b"hellp"));
+        assert!(etag(b"hello").starts_with("\"5-"));
+    }
+
+    #[test]
+    fn file_etags_change_with_the_length_or_the_modification_time() {
+        let dir = crate::temp_dir::TempDir::new();
+        let path = dir.write("page.html", "hello");
+        let tag = || file_etag(&fs::metadata(&path).unwrap());
+
+        let first = tag();
+        assert!(first.starts_with("\"5-"), "{first}");
+        assert_eq!(tag(), first);
+
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        let touched = tag();
+        assert_ne!(touched, first);
+
+        fs::write(&path, "hello, world").unwrap();
+        assert!(tag().starts_with("\"c-"));
+    }
+
+    #[test]
+    fn matching_validators_turn_a_200_into_a_304() {
+        let (modified, _, at, _) = dates();
+        let stamped = || Response::html(200, "hello").with_header("ETag", ETAG).with_header("Cache-Control", "no-cache");
+        let dated = || Response::html(200, "hello").with_header("Last-Modified", &http_date::format(modified));
+        let cases = [
+            ("GET", "If-None-Match: \"abc\"\r\n".to_string(), stamped(), 304),
+            ("HEAD", "If-None-Match: W/\"abc\"\r\n".to_string(), stamped(), 304),
+            ("GET", "If-None-Match: \"x\"\r\n".to_string(), stamped(), 200),
+            ("POST", "If-None-Match: \"abc\"\r\n".to_string(), stamped(), 200),
+            ("GET", format!("If-Modified-Since: {at}\r\n"), dated(), 304),
+            ("GET", String::new(), stamped(), 200),
+            // no validator to compare against
+            ("GET", "If-None-Match: *\r\n".to_string(), Response::html(200, "hello"), 200),
+            // only successes are revalidated
+            ("GET", "If-None-Match: \"abc\"\r\n".to_string(), Response::html(404, "gone").with_header("ETag", ETAG), 404)
+        ];
+
+        for (method, headers, response, expected) in cases {
+            let revalidated = revalidate(&request(method, &headers), response);
+            assert_eq!(revalidated.status(), expected, "{method} {headers:?}");
+        }
+    }
+
+    #[test]
+    fn a_304_keeps_the_headers_but_not_the_body() {
+        let response = Response::html(200, "hello").with_header("ETag", ETAG).with_header("Cache-Control", "no-cache");
+        let mut revalidated = revalidate(&request("GET", "If-None-Match: \"abc\"\r\n"), response);
+
+        assert_eq!(revalidated.header("ETag"), Some(ETAG));
+        assert_eq!(revalidated.header("Cache-Control"), Some("no-cache"));
+        assert_eq!(revalidated.header("Content-Length"), None);
+        assert_eq!(revalidated.header("Transfer-Encoding"), None);
+
+        let mut written = Vec::new();
+        revalidated.write_to(&mut written, Version::Http11, true).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{written}");
+        assert!(written.ends_with("\r\n\r\n"), "{written}");
+    }
+}
@@ -0,0 +1,634 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant, SystemTime},
+};
+use crate::{
+    buffer_pool::{PooledBuffer, PooledReader},
+    conditional,
+    connection_state::ConnectionState,
+    favicon,
+    log::AccessRecord,
+    mime, negotiate, range,
+    read_timeout::{ReadTimeouts, TimedStream},
+    recovery,
+    request::{self, ParseError, Request, Source, Version},
+    response::Response,
+    server::{ServerConfig, Shared},
+    stats::{CloseReason, ServerStats, TrackedConnection},
+    trace::RequestSpan,
+    watchdog::Watchdog,
+};
+
+/*
+    Serves requests on one connection until either side asks to close it. The reader lives for
+    the whole connection rather than per request: it may already hold bytes of the next request
+    if the client pipelines, and those must not be thrown away. While a handler runs, the reader
+    is lent to its request so the body can be read lazily, and handed back afterwards.
+ */
+pub(crate) fn serve(
+    stream: &TcpStream,
+    accepted_at: Instant,
+    shared: &Shared,
+    tracked: &TrackedConnection
+) -> io::Result<CloseReason> {
+    let mut served = 0;
+    let reason = serve_requests(stream, accepted_at, shared, tracked, &mut served);
+    // counted however the connection ended, errors included
+    shared.stats.connection_served(served);
+    close_connection(stream, ClosePolicy::WORKER);
+    reason
+}
+
+/// How long and how much `close_connection` keeps reading after it has stopped writing.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClosePolicy {
+    // most bytes of whatever the client still sends that are read and thrown away
+    drain_limit: u64,
+    // zero: only what has already arrived is read, without waiting
+    linger: Duration
+}
+
+impl ClosePolicy {
+    // A worker can afford to wait a moment for the client to finish.
+    pub(crate) const WORKER: ClosePolicy = ClosePolicy { drain_limit: 64 * 1024, linger: Duration::from_millis(500) };
+    // The accept thread can't: every other client is waiting on it.
+    pub(crate) const ACCEPT_THREAD: ClosePolicy = ClosePolicy { drain_limit: 16 * 1024, linger: Duration::ZERO };
+}
+
+/*
+    Closes a connection so the client sees all of our response. Dropping a socket with unread
+    input makes the kernel reset the connection, and a reset can overtake response bytes still
+    in flight, so the client gets a truncated response or none. That happens whenever we close
+    with a request body unread or a pipelined request queued. So we send our FIN first (the
+    response is complete at that point), then read and discard what the client still has in
+    flight until it closes its side too, up to the policy's limits.
+ */
+pub(crate) fn close_connection(stream: &TcpStream, policy: ClosePolicy) {
+    // fails if the peer is already gone, in which case there's nobody left to be polite to
+    if stream.shutdown(Shutdown::Write).is_err() {
+        return;
+    }
+
+    let lingering = !policy.linger.is_zero();
+    if !lingering && stream.set_nonblocking(true).is_err() {
+        return;
+    }
+    let deadline = Instant::now() + policy.linger;
+    let mut reader = stream;
+    let mut discard = [0; 4096];
+    let mut discarded = 0;
+
+    while discarded < policy.drain_limit {
+        if lingering {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || stream.set_read_timeout(Some(left)).is_err() {
+                return;
+            }
+        }
+        match reader.read(&mut discard) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => discarded += n as u64
+        }
+    }
+}
+
+fn serve_requests(
+    stream: &TcpStream,
+    accepted_at: Instant,
+    shared: &Shared,
+    tracked: &TrackedConnection,
+    served: &mut u64
+) -> io::Result<CloseReason> {
+    let Shared { config, handler, stats, shutdown, buffers, access_log, request_ids, on_panic } = shared;
+
+    // an idle keep-alive connection would otherwise pin a worker forever
+    let timeouts = ReadTimeouts::new(config.keep_alive_timeout);
+
+    let peer = stream.peer_addr().ok();
+    // shared with the watchdog of any request whose route has a timeout
+    let watchdog_stream = Arc::new(stream.try_clone()?);
+    let mut queue_wait = accepted_at.elapsed();
+    let timed_stream = TimedStream::new(stream.try_clone()?, Arc::clone(&timeouts));
+    let mut reader: Source = Box::new(PooledReader::new(timed_stream, Arc::clone(buffers)));
+    let mut writer = stream;
+    let mut write_buffer = PooledBuffer::new(Arc::clone(buffers));
+    // what handlers keep for this connection; dropped with it
+    let mut connection_state = ConnectionState::default();
+    // how long writing the previous response took, for Server-Timing
+    let mut last_write = None;
+
+    loop {
+        /*
+            Waiting for the first byte of the next request happens before the clock starts, so an
+            idle keep-alive gap isn't billed to the request that eventually arrives.
+         */
+        timeouts.set(config.keep_alive_timeout, None);
+        tracked.idle();
+        match reader.fill_buf() {
+            Ok([]) => return Ok(CloseReason::Client),
+            Ok(_) => {}
+            Err(e) if is_timeout(&e) => {
+                // a connection that never sent anything is likely a probe or a preconnect; it gets a silent close
+                if *served > 0 {
+                    send_request_timeout(stream, stats);
+                }
+                return Ok(CloseReason::IdleTimeout);
+            }
+            Err(e) => return Err(e)
+        }
+        tracked.active();
+        let started = Instant::now();
+        let received = SystemTime::now();
+
+        // however slowly the head trickles in, it has to be complete by the deadline
+        timeouts.set(config.header_timeout, Some(started + config.header_timeout));
+        let mut request = match Request::parse_head(&mut reader, config.encoded_slash) {
+            Ok(Some(request)) => request,
+            // the client closed the connection between requests
+            Ok(None) => return Ok(CloseReason::Client),
+            Err(ParseError::Io(e)) if is_timeout(&e) => {
+                send_request_timeout(stream, stats);
+                return Ok(CloseReason::Server);
+            }
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => {
+                // worth a line: that's what a splitting or log injection attempt looks like
+                if let ParseError::ControlCharacter(_) = e {
+                    match peer {
+                        Some(peer) => eprintln!("Rejected request from {peer}: {e}"),
+                        None => eprintln!("Rejected request: {e}")
+                    }
+                }
+                // we can't trust where the next request would start, so answer and hang up
+                Response::status_only(e.status())
+                    .write_buffered(&mut writer, Version::Http11, false, &mut write_buffer, config.stream_threshold)?;
+                return Ok(CloseReason::Server);
+            }
+        };
+        let parsed = started.elapsed();
+
+        // only a connection that sat in the queue for a while is likely to have been given up on
+        if queue_wait >= ABANDON_CHECK_AFTER && peer_gone(stream) {
+            stats.request_abandoned();
+            if cfg!(debug_assertions) {
+                println!("Skipped {} {}: the client left after {queue_wait:?} in the queue", request.method(), request.path());
+            }
+            return Ok(CloseReason::Client);
+        }
+
+        let trusted_id = request
+            .header("X-Request-Id")
+            .filter(|id| config.trust_request_id && request::is_valid_request_id(id))
+            .map(str::to_string);
+        request.set_id(trusted_id.unwrap_or_else(|| request_ids.fetch_add(1, Ordering::Relaxed).to_string()));
+        let span = RequestSpan::enter(&request);
+        if let Some(method_override) = &config.method_override {
+            method_override.apply(&mut request);
+        }
+        timeouts.set(config.body_timeout, None);
+        request.attach_body(reader);
+        request.lend_connection_state(connection_state);
+        request.set_watchdog(Watchdog::new(Arc::clone(&watchdog_stream), request.version()));
+        if let Some(timeout) = config.request_timeout {
+            // the time spent receiving the head counts too
+            request.arm_timeout(timeout.saturating_sub(started.elapsed()));
+        }
+
+        let handler_started = Instant::now();
+        // a handler that panicked may have left shared state or the request body half used
+        let (mut response, panicked) = if !config.implemented_methods.contains(request.method_kind()) {
+            // a method we don't know at all, as opposed to one a route doesn't take (405)
+            (Response::status_only(501), false)
+        } else {
+            match builtin_response(&request, config, stats) {
+                Some(response) => (response, false),
+                None => match recovery::call_handler(&mut request, on_panic.as_deref(), handler) {
+                    Ok(response) => (response, false),
+                    Err(response) => {
+                        stats.handler_panicked();
+                        (response, true)
+                    }
+                }
+            }
+        };
+        let handled = handler_started.elapsed();
+        if response.status() == 404 {
+            response = favicon::respond(&config.favicon, &request).unwrap_or(response);
+        }
+        response = negotiate::error_response(&request, response);
+        response = config.error_pages.apply(&request, response);
+        if let Some(charset) = &config.default_charset {
+            let content_type = response
+                .header("Content-Type")
+                .and_then(|content_type| mime::with_charset(content_type, charset));
+            if let Some(content_type) = content_type {
+                response.set_header("Content-Type", &content_type);
+            }
+        }
+        config.cache_control.apply(&request, &mut response);
+        range::refuse_by_default(&request, &mut response);
+        if let Some(policy) = &config.compression {
+            response = policy.apply(&request, response);
+        }
+        // after compression, which changes the ETag a gzip-accepting client has cached
+        response = conditional::revalidate(&request, response);
+        if let Some(id) = request.id() {
+            response.set_header("X-Request-Id", id);
+        }
+        if config.server_timing || request.server_timing() {
+            response.set_header("Server-Timing", &server_timing(queue_wait, parsed, handled, last_write));
+        }
+
+        // the route's timeout fired while the handler ran; the watchdog already sent the 504
+        let timed_out = request.take_watchdog().is_some_and(|watchdog| !watchdog.claim());
+        // skips any body the handler left unread; None if the body stream is unusable
+        let next_reader = request.detach_body();
+        connection_state = request.return_connection_state();
+
+        // a server that is shutting down finishes the current request but takes no more
+        let shutting_down = shutdown.load(Ordering::SeqCst);
+        *served += 1;
+        let at_limit = config.max_requests_per_connection.is_some_and(|max| *served >= max as u64);
+        if request.method() == "HEAD" {
+            response.omit_body();
+        }
+        let keep_alive = request.is_keep_alive()
+            && !shutting_down
+            && !timed_out
+            && !panicked
+            && !at_limit
+            && !response.needs_close(request.version())
+            && next_reader.is_some();
+        if let (true, Some(max)) = (keep_alive, config.max_requests_per_connection) {
+            // tells the client how many more it may send here, so it can open the next connection in time
+            let timeout = config.keep_alive_timeout.as_secs();
+            response.set_header("Keep-Alive", &format!("timeout={timeout}, max={}", max as u64 - *served));
+        }
+        let mut aborted = false;
+        let body_bytes = if timed_out {
+            // what the client actually received, so the log and stats tell the truth
+            response = Response::status_only(504);
+            response.body().len() as u64
+        } else {
+            let mut counted = CountingWriter { inner: &mut writer, written: 0 };
+            let write_started = Instant::now();
+            match response.write_buffered(&mut counted, request.version(), keep_alive, &mut write_buffer, config.stream_threshold) {
+                Ok(sent) => {
+                    last_write = Some(write_started.elapsed());
+                    sent
+                }
+                Err(e) if is_disconnect(&e) => {
+                    // a cancelled download or a closed tab: routine, so no error, but still logged below
+                    stats.client_disconnected();
+                    println!("Client went away during request {}: {e}", request.id().unwrap_or("-"));
+                    aborted = true;
+                    counted.written
+                }
+                Err(e) => return Err(e)
+            }
+        };
+        let duration = started.elapsed();
+        stats.request_served(duration);
+        span.close(&request, response.status(), duration);
+
+        let format = access_log.format();
+        let logged = access_log.write(&AccessRecord {
+            peer,
+            time: received,
+            // as it appeared on the wire; an override is logged separately
+            method: request.original_method().unwrap_or(request.method()).to_string(),
+            method_override: request.original_method().map(|_| request.method().to_string()),
+            // the normalized form, so equivalent spellings of a path log alike
+            target: match request.query() {
+                Some(query) => format!("{}?{query}", request.path()),
+                None => request.path().to_string()
+            },
+            version: request.version().as_str(),
+            status: response.status(),
+            body_bytes,
+            duration,
+            route: request.route().map(str::to_string),
+            trace_id: request.trace_context().map(|trace| trace.trace_id()),
+            request_id: request.id().map(str::to_string),
+            queue_wait,
+            aborted,
+            request_headers: format.map(|format| format.request_headers(request.headers())).unwrap_or_default(),
+            response_headers: format.map(|format| format.response_headers(response.headers())).unwrap_or_default()
+        });
+        if !logged {
+            stats.log_line_dropped();
+        }
+        // only the first request on a connection waited in the pool's queue
+        queue_wait = Duration::ZERO;
+
+        match next_reader {
+            _ if aborted => return Ok(CloseReason::Client),
+            Some(next_reader) if keep_alive => reader = next_reader,
+            _ if !request.is_keep_alive() => return Ok(CloseReason::ClientRequested),
+            Some(_) if at_limit && !shutting_down && !timed_out => return Ok(CloseReason::MaxRequests),
+            _ => return Ok(CloseReason::Server)
+        }
+    }
+}
+
+/*
+    The Server-Timing value, durations in milliseconds: the wait in the pool's queue (only ever
+    nonzero for a connection's first request), reading and parsing the head, and running the
+    handler, or the built-in endpoint that answered instead. The header goes out ahead of the body, so
+    this response's write can't be in it; `write` is the previous response's on the same
+    connection instead, and left out on a connection's first request.
+ */
+fn server_timing(queue: Duration, parse: Duration, handler: Duration, write: Option<Duration>) -> String {
+    let ms = |duration: Duration| duration.as_micros() as f64 / 1000.0;
+    let mut value = format!("queue;dur={:.3}, parse;dur={:.3}, handler;dur={:.3}", ms(queue), ms(parse), ms(handler));
+    if let Some(write) = write {
+        value.push_str(&format!(", write;dur={:.3}", ms(write)));
+    }
+    value
+}
+
+// Longest the accept thread will wait for a shed client's next bytes, and for its whole head.
+const SHED_READ_TIMEOUT: Duration = Duration::from_millis(100);
+const SHED_HEAD_DEADLINE: Duration = Duration::from_millis(250);
+
+/*
+    Answers a connection on the accept thread instead of queueing it, because the pool is
+    overloaded. We still read the request head so the built-in endpoints keep working under
+    load: a health check that fails exactly when the server is busy would get a healthy
+    instance killed. Only the head, and only for so long, however the client trickles it in:
+    every other client is waiting on this thread. A body is left to the close to discard.
+ */
+pub(crate) fn shed(stream: &TcpStream, retry_after: Duration, shared: &Shared) {
+    let mut writer = stream;
+
+    let timeouts = ReadTimeouts::new(SHED_READ_TIMEOUT);
+    timeouts.set(SHED_READ_TIMEOUT, Some(Instant::now() + SHED_HEAD_DEADLINE));
+    let request = stream
+        .try_clone()
+        .ok()
+        .and_then(|read_half| {
+            let mut reader = BufReader::new(TimedStream::new(read_half, timeouts));
+            Request::parse_head(&mut reader, shared.config.encoded_slash).ok().flatten()
+        });
+
+    let mut response = request
+        .as_ref()
+        .and_then(|request| builtin_response(request, &shared.config, &shared.stats))
+        .unwrap_or_else(|| {
+            // Retry-After is whole seconds; round up so we never invite a retry too early
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            let response = Response::status_only(503).with_header("Retry-After", &seconds.max(1).to_string());
+            match &request {
+                Some(request) => negotiate::error_response(request, response),
+                None => response
+            }
+        });
+
+    let version = request.map_or(Version::Http11, |request| request.version());
+    if let Err(e) = response.write_to(&mut writer, version, false) {
+        eprintln!("Failed to write shed response: {e}");
+    }
+    close_connection(stream, ClosePolicy::ACCEPT_THREAD);
+}
+
+// Longest the accept thread will spend writing the 503 for a connection no worker could take.
+const REFUSE_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// The answer to a connection the pool wouldn't take, prebuilt so sending it allocates nothing.
+const UNAVAILABLE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Content-Length: 0\r\n\
+    Retry-After: 1\r\n\
+    Connection: close\r\n\r\n";
+
+// Longest a worker spends writing the 408 to a client that has already been too slow once.
+const TIMEOUT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// The answer to a request that didn't arrive in time, prebuilt like UNAVAILABLE.
+const REQUEST_TIMEOUT: &[u8] = b"HTTP/1.1 408 Request Timeout\r\n\
+    Content-Length: 0\r\n\
+    Connection: close\r\n\r\n";
+
+/*
+    Tells a client whose request didn't arrive in time that the connection is closing, so it
+    can retry on a fresh one instead of seeing the connection simply vanish. Best effort: a
+    client that doesn't read it within a moment is closed on all the same.
+ */
+fn send_request_timeout(stream: &TcpStream, stats: &ServerStats) {
+    stats.request_timed_out();
+    let mut writer = stream;
+    let sent = stream
+        .set_write_timeout(Some(TIMEOUT_WRITE_TIMEOUT))
+        .and_then(|_| writer.write_all(REQUEST_TIMEOUT));
+    if let Err(e) = sent.and_then(|_| stream.set_write_timeout(None)) {
+        println!("Couldn't send 408 to a timed-out client: {e}");
+    }
+}
+
+/*
+    Answers a connection the pool refused to queue, on the accept thread. Unlike `shed` this
+    doesn't wait for the request: we are past overloaded, and a bare 503 is all anyone gets.
+    Whatever the client already sent is read off before closing, because closing a socket with
+    unread data resets it, and the reset can reach the client before our 503 does.
+ */
+pub(crate) fn refuse(stream: &TcpStream) -> io::Result<()> {
+    stream.set_write_timeout(Some(REFUSE_WRITE_TIMEOUT))?;
+    let mut writer = stream;
+    writer.write_all(UNAVAILABLE)?;
+    close_connection(stream, ClosePolicy::ACCEPT_THREAD);
+    Ok(())
+}
+
+// The status page, metrics and health endpoints are answered by the server itself, ahead of the handler.
+fn builtin_response(request: &Request, config: &ServerConfig, stats: &ServerStats) -> Option<Response> {
+    if request.method() != "GET" {
+        return None;
+    }
+
+    let path = Some(request.path());
+    if path == config.health_path.as_deref() {
+        Some(Response::new(200).with_header("Content-Type", "text/plain").with_body("OK"))
+    } else if path == config.status_path.as_deref() {
+        Some(Response::html(200, stats.snapshot().render_status_with(&config.pool_settings())))
+    } else if path == config.metrics_path.as_deref() {
+        Some(
+            Response::new(200)
+                .with_header("Content-Type", "text/plain; version=0.0.4")
+                .with_body(stats.snapshot().render_metrics())
+        )
+    } else {
+        None
+    }
+}
+
+// Queue wait past which a connection is checked for a client that gave up before it was served.
+const ABANDON_CHECK_AFTER: Duration = Duration::from_millis(10);
+
+/*
+    Whether the client has reset the connection, without consuming anything it sent. Called once
+    the request head is parsed, so any bytes still in the socket are body or a pipelined request,
+    and say the client is there. A clean end of stream doesn't say it has gone: a client may shut
+    down its sending side after a complete request and still wait for the answer, and its FIN
+    can't be told apart from one sent by a closed socket. So only an error counts. A non-blocking
+    peek answers at once either way.
+ */
+fn peer_gone(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let gone = match stream.peek(&mut [0; 1]) {
+        Ok(_) => false,
+        Err(e) => e.kind() != io::ErrorKind::WouldBlock && e.kind() != io::ErrorKind::Interrupted
+    };
+    // the socket is shared with the reader and the watchdog, both of which block
+    let _ = stream.set_nonblocking(false);
+    gone
+}
+
+/*
+    The errors a socket reports once the client has gone: it closed the connection, or reset it,
+    while we were still writing. They say nothing about the server's health.
+ */
+pub(crate) fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted
+    )
+}
+
+// Counts what reaches the socket, so a response cut short can still be logged with its real size.
+struct CountingWriter<W> {
+    inner: W,
+    written: u64
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    // Unix reports an expired read timeout as WouldBlock, Windows as TimedOut
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn only_a_client_going_away_counts_as_a_disconnect() {
+        let cases = [
+            (io::ErrorKind::BrokenPipe, true),
+            (io::ErrorKind::ConnectionReset, true),
+            (io::ErrorKind::ConnectionAborted, true),
+            (io::ErrorKind::TimedOut, false),
+            (io::ErrorKind::WouldBlock, false),
+            (io::ErrorKind::Other, false)
+        ];
+        for (kind, expected) in cases {
+            assert_eq!(is_disconnect(&io::Error::from(kind)), expected, "{kind:?}");
+        }
+    }
+
+    // Takes `room` bytes, then fails as a socket does once the client has reset it.
+    struct Hangup {
+        room: usize
+    }
+
+    impl Write for Hangup {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+            let n = buf.len().min(self.room);
+            self.room -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_bytes_written_before_a_hangup_are_counted() {
+        let mut counted = CountingWriter { inner: Hangup { room: 10 }, written: 0 };
+        let error = counted.write_all(&[b'x'; 25]).unwrap_err();
+        assert!(is_disconnect(&error));
+        assert_eq!(counted.written, 10);
+    }
+
+    // A connected pair of sockets: the client's end, and the server's as `accept` returned it.
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn a_close_with_input_unread_still_delivers_the_whole_response() {
+        let (mut client, server) = socket_pair();
+        // a body the server never reads, already in its receive buffer
+        client.write_all(&[b'x'; 32 * 1024]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let closing = thread::spawn(move || {
+            (&server).write_all(b"the whole response").unwrap();
+            close_connection(&server, ClosePolicy::WORKER);
+        });
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"the whole response");
+        drop(client);
+        closing.join().unwrap();
+    }
+
+    #[test]
+    fn a_client_that_keeps_sending_is_only_drained_so_far() {
+        let (client, server) = socket_pair();
+        let sending = thread::spawn(move || {
+            let mut client = client;
+            while client.write_all(&[b'x'; 4096]).is_ok() {}
+        });
+
+        let started = Instant::now();
+        close_connection(&server, ClosePolicy::WORKER);
+        assert!(started.elapsed() < Duration::from_secs(2), "drained for {:?}", started.elapsed());
+        drop(server);
+        sending.join().unwrap();
+    }
+
+    #[test]
+    fn the_accept_thread_never_waits_for_a_quiet_client() {
+        let (_client, server) = socket_pair();
+
+        let started = Instant::now();
+        close_connection(&server, ClosePolicy::ACCEPT_THREAD);
+        assert!(started.elapsed() < Duration::from_millis(100), "waited {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn server_timing_lists_the_phases_in_milliseconds() {
+        let ms = Duration::from_micros;
+        assert_eq!(
+            server_timing(ms(0), ms(250), ms(12_345), None),
+            "queue;dur=0.000, parse;dur=0.250, handler;dur=12.345"
+        );
+        assert_eq!(
+            server_timing(ms(1_500), ms(1), ms(2), Some(ms(3_000))),
+            "queue;dur=1.500, parse;dur=0.001, handler;dur=0.002, write;dur=3.000"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+};
+
+/*
+    Values that live as long as one connection, kept by type: at most one of each. A keep-alive
+    client's later requests see what handlers stored during its earlier ones, which makes it a
+    place for per-connection setup worth doing only once, like the user a token was already
+    checked for. It is not a session: a new connection, even from the same client, starts empty,
+    and everything is dropped when the connection closes.
+ */
+#[derive(Default)]
+pub struct ConnectionState {
+    values: HashMap<TypeId, Box<dyn Any + Send>>
+}
+
+impl ConnectionState {
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>()).and_then(|value| value.downcast_mut())
+    }
+
+    /// Stores `value`, returning the `T` it replaces, if there was one.
+    pub fn set<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Debug for ConnectionState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionState").field("values", &self.values.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct User(&'static str);
+
+    #[test]
+    fn values_are_kept_one_per_type() {
+        let mut state = ConnectionState::default();
+        assert!(state.is_empty());
+        assert_eq!(state.get::<User>(), None);
+
+        assert_eq!(state.set(User("ada")), None);
+        assert_eq!(state.set(3_u32), None);
+        assert_eq!(state.get::<User>(), Some(&User("ada")));
+        assert_eq!(state.get::<u32>(), Some(&3));
+        assert_eq!(state.get::<u64>(), None);
+
+        assert_eq!(state.set(User("grace")), Some(User("ada")));
+        *state.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(state.get::<User>(), Some(&User("grace")));
+        assert_eq!(state.get::<u32>(), Some(&4));
+
+        assert_eq!(state.remove::<User>(), Some(User("grace")));
+        assert_eq!(state.remove::<User>(), None);
+        assert_eq!(state.remove::<u32>(), Some(4));
+        assert!(state.is_empty());
+    }
+}
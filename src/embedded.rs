@@ -0,0 +1,89 @@
+use crate::Response;
+
+/*
+    Pages and assets compiled into the binary, so a server started from any directory has
+    something to show. Anything the application serves from disk takes precedence; these are
+    only the fallbacks.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Asset {
+    pub body: &'static [u8],
+    pub content_type: &'static str
+}
+
+impl Asset {
+    /// The asset as a response with the given status.
+    pub fn response(&self, status: u16) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", self.content_type)
+            .with_body(self.body)
+    }
+}
+
+pub const INDEX: Asset = Asset {
+    body: include_bytes!("../assets/index.html"),
+    content_type: "text/html; charset=utf-8"
+};
+
+pub const NOT_FOUND: Asset = Asset {
+    body: include_bytes!("../assets/404.html"),
+    content_type: "text/html; charset=utf-8"
+};
+
+pub const INTERNAL_ERROR: Asset = Asset {
+    body: include_bytes!("../assets/500.html"),
+    content_type: "text/html; charset=utf-8"
+};
+
+pub const FAVICON: Asset = Asset {
+    body: include_bytes!("../assets/favicon.ico"),
+    content_type: "image/x-icon"
+};
+
+pub const STATUS_CSS: Asset = Asset {
+    body: include_bytes!("../assets/status.css"),
+    content_type: "text/css; charset=utf-8"
+};
+
+/// The built-in page for an error status, if there is one.
+pub fn error_page(status: u16) -> Option<Asset> {
+    match status {
+        404 => Some(NOT_FOUND),
+        500 => Some(INTERNAL_ERROR),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_asset_is_what_its_content_type_says() {
+        let pages = [INDEX, NOT_FOUND, INTERNAL_ERROR];
+        for page in pages {
+            assert!(page.body.starts_with(b"<!DOCTYPE html>"), "{}", page.content_type);
+            assert_eq!(page.content_type, "text/html; charset=utf-8");
+        }
+        // an .ico starts with a reserved zero word and type 1
+        assert!(FAVICON.body.starts_with(&[0, 0, 1, 0]));
+        assert!(std::str::from_utf8(STATUS_CSS.body).is_ok());
+    }
+
+    #[test]
+    fn only_404_and_500_have_built_in_pages() {
+        assert_eq!(error_page(404), Some(NOT_FOUND));
+        assert_eq!(error_page(500), Some(INTERNAL_ERROR));
+        for status in [200, 400, 403, 502, 503] {
+            assert_eq!(error_page(status), None, "{status}");
+        }
+    }
+
+    #[test]
+    fn an_asset_becomes_a_response_with_its_type() {
+        let response = NOT_FOUND.response(404);
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.header("Content-Type"), Some(NOT_FOUND.content_type));
+        assert_eq!(response.body(), NOT_FOUND.body);
+    }
+}
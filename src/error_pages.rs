@@ -0,0 +1,198 @@
+use std::{fs, path::PathBuf};
+use crate::{negotiate, Request, Response};
+
+/*
+    Localized pages for error statuses, chosen by the request's Accept-Language. Only bare errors
+    are dressed (no Content-Type of their own, as from `Response::status_only`), and only for
+    clients that don't prefer JSON; a handler's own error body always wins. Pages are read from
+    disk on each use, so they can be edited without a restart.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorPages {
+    // (status, lowercased language tag, file), in registration order
+    pages: Vec<(u16, String, PathBuf)>,
+    default_language: Option<String>
+}
+
+impl ErrorPages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `path` as the `lang` variant (`de`, `pt-BR`) of the page for `status`,
+    /// replacing an earlier one for the same pair.
+    pub fn add(&mut self, status: u16, lang: &str, path: impl Into<PathBuf>) -> &mut Self {
+        let lang = lang.to_ascii_lowercase();
+        self.pages.retain(|(existing, existing_lang, _)| (*existing, existing_lang) != (status, &lang));
+        self.pages.push((status, lang, path.into()));
+        self
+    }
+
+    /// The language served when none the client accepts is available. Without one, such
+    /// errors are left bare.
+    pub fn default_language(&mut self, lang: &str) -> &mut Self {
+        self.default_language = Some(lang.to_ascii_lowercase());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Every page file registered, for checking at startup that they can be read.
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.pages.iter().map(|(_, _, path)| path)
+    }
+
+    /*
+        Picks the variant to serve for `status`. The client's ranges are tried from the highest
+        q down (ties in the order sent); each matches a variant with the same tag, else one
+        under it (`pt` takes `pt-BR`), else one named by the range's primary subtag, so `de-AT`
+        falls back to `de`. `*` matches whatever is there, and q=0 rules a language out. The
+        default language comes last.
+     */
+    pub fn select(&self, status: u16, accept_language: Option<&str>) -> Option<(&str, &PathBuf)> {
+        let variants: Vec<(&str, &PathBuf)> = self.pages
+            .iter()
+            .filter(|(page_status, _, _)| *page_status == status)
+            .map(|(_, lang, path)| (lang.as_str(), path))
+            .collect();
+        let find = |tag: &str| variants.iter().find(|(lang, _)| lang.eq_ignore_ascii_case(tag)).copied();
+        // "pt" covers "pt-br" (RFC 4647 basic filtering)
+        let find_under = |range: &str| {
+            variants
+                .iter()
+                .find(|(lang, _)| lang.len() > range.len() && lang.as_bytes()[range.len()] == b'-'
+                    && lang[..range.len()].eq_ignore_ascii_case(range))
+                .copied()
+        };
+
+        let mut ranges = accept_language.map(negotiate::quality_values).unwrap_or_default();
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        let excluded: Vec<&str> = ranges.iter().filter(|(_, q)| *q == 0.0).map(|(range, _)| *range).collect();
+        let allowed = |(lang, _): &(&str, &PathBuf)| !excluded.iter().any(|range| range.eq_ignore_ascii_case(lang));
+
+        let chosen = ranges
+            .iter()
+            .filter(|(_, q)| *q > 0.0)
+            .find_map(|(range, _)| match *range {
+                "*" => variants.iter().find(|variant| allowed(variant)).copied(),
+                range => find(range)
+                    .or_else(|| find_under(range))
+                    .or_else(|| range.split('-').next().and_then(find))
+                    .filter(allowed)
+            });
+
+        chosen.or_else(|| self.default_language.as_deref().and_then(find))
+    }
+
+    // Dresses a bare error in the best page for the client's languages, if one is registered.
+    pub(crate) fn apply(&self, request: &Request, response: Response) -> Response {
+        if self.is_empty() || response.header("Content-Type").is_some() || negotiate::prefers_json(request) {
+            return response;
+        }
+        let status = response.status();
+        let Some((lang, path)) = self.select(status, request.header("Accept-Language")) else {
+            return response;
+        };
+
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("Failed to read error page {}: {e}", path.display());
+                return response;
+            }
+        };
+
+        let mut page = Response::html(status, contents).with_header("Content-Language", lang);
+        page.inherit_headers(&response);
+        page.vary("Accept-Language");
+        page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    // 404 pages in English, German and Brazilian Portuguese, named after their language.
+    fn pages() -> ErrorPages {
+        let mut pages = ErrorPages::new();
+        pages.add(404, "en", "en.html").add(404, "DE", "de.html").add(404, "pt-BR", "pt-br.html");
+        pages
+    }
+
+    fn selected(pages: &ErrorPages, accept_language: Option<&str>) -> Option<String> {
+        pages.select(404, accept_language).map(|(lang, _)| lang.to_string())
+    }
+
+    #[test]
+    fn the_best_language_the_client_accepts_is_chosen() {
+        let pages = pages();
+        let cases = [
+            (Some("de"), Some("de")),
+            (Some("fr, de;q=0.5, en;q=0.8"), Some("en")),
+            (Some("de-AT"), Some("de")),
+            (Some("pt"), Some("pt-br")),
+            (Some("PT-br"), Some("pt-br")),
+            (Some("fr"), None),
+            (Some("*"), Some("en")),
+            (Some("en;q=0, *"), Some("de")),
+            (Some("de;q=0"), None),
+            (None, None)
+        ];
+
+        for (accept_language, expected) in cases {
+            assert_eq!(selected(&pages, accept_language).as_deref(), expected, "{accept_language:?}");
+        }
+        assert_eq!(pages.select(500, Some("en")), None);
+    }
+
+    #[test]
+    fn the_default_language_comes_last() {
+        let mut pages = pages();
+        pages.default_language("DE");
+
+        assert_eq!(selected(&pages, Some("fr")).as_deref(), Some("de"));
+        assert_eq!(selected(&pages, None).as_deref(), Some("de"));
+        assert_eq!(selected(&pages, Some("en")).as_deref(), Some("en"));
+    }
+
+    #[test]
+    fn a_later_page_for_the_same_status_and_language_replaces_the_earlier_one() {
+        let mut pages = pages();
+        pages.add(404, "en", "other.html");
+
+        assert_eq!(pages.paths().count(), 3);
+        assert_eq!(pages.select(404, Some("en")).unwrap().1, &PathBuf::from("other.html"));
+    }
+
+    #[test]
+    fn only_bare_errors_for_html_clients_are_dressed() {
+        let dir = TempDir::new();
+        let mut pages = ErrorPages::new();
+        pages.add(404, "de", dir.write("404.de.html", "<h1>Nicht gefunden</h1>"));
+        pages.add(500, "de", dir.path().join("missing.html"));
+        let request = |headers: &str| {
+            let raw = format!("GET / HTTP/1.1\r\nHost: test\r\nAccept-Language: de\r\n{headers}\r\n");
+            Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+        };
+
+        let page = pages.apply(&request(""), Response::status_only(404).with_header("X-Kept", "yes"));
+        assert_eq!(page.status(), 404);
+        assert_eq!(page.body(), "<h1>Nicht gefunden</h1>".as_bytes());
+        assert_eq!(page.header("Content-Language"), Some("de"));
+        assert_eq!(page.header("X-Kept"), Some("yes"));
+
+        let cases = [
+            ("json client", request("Accept: application/json\r\n"), Response::status_only(404)),
+            ("own body", request(""), Response::html(404, "custom")),
+            ("unreadable page", request(""), Response::status_only(500))
+        ];
+        for (name, request, response) in cases {
+            let response = pages.apply(&request, response);
+            assert_eq!(response.header("Content-Language"), None, "{name}");
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{fs, path::PathBuf};
+use crate::{conditional::{self, PreconditionResult}, embedded, Request, Response};
+
+// Browsers ask for the icon on every page; let them keep it for a week.
+const CACHE_CONTROL: &str = "public, max-age=604800";
+
+/// Where the server gets `/favicon.ico` from when the handler has nothing to serve there.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Favicon {
+    /// A small icon compiled into the binary.
+    #[default]
+    Embedded,
+    /// An icon file read from disk on each request.
+    File(PathBuf),
+    /// Leave `/favicon.ico` as a 404.
+    Disabled
+}
+
+/*
+    Only consulted after the handler has answered 404, so a route or a favicon.ico in a static
+    docroot always wins over this fallback.
+ */
+pub(crate) fn respond(favicon: &Favicon, request: &Request) -> Option<Response> {
+    if request.method() != "GET" || request.path() != "/favicon.ico" {
+        return None;
+    }
+
+    let icon = match favicon {
+        Favicon::Embedded => embedded::FAVICON.body.to_vec(),
+        Favicon::File(path) => match fs::read(path) {
+            Ok(icon) => icon,
+            Err(e) => {
+                eprintln!("Failed to read favicon {}: {e}", path.display());
+                return None;
+            }
+        },
+        Favicon::Disabled => return None
+    };
+
+    let etag = conditional::etag(&icon);
+    let response = match conditional::evaluate_preconditions(request, Some(&etag), None) {
+        PreconditionResult::Proceed => Response::new(200)
+            .with_header("Content-Type", embedded::FAVICON.content_type)
+            .with_header("ETag", &etag)
+            .with_body(icon),
+        PreconditionResult::NotModified => conditional::not_modified(&etag),
+        PreconditionResult::Failed => return Some(conditional::precondition_failed())
+    };
+
+    Some(response.with_header("Cache-Control", CACHE_CONTROL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn request(method: &str, path: &str, headers: &str) -> Request {
+        let raw = format!("{method} {path} HTTP/1.1\r\nHost: test\r\n{headers}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn the_embedded_icon_is_served_cacheable() {
+        let response = respond(&Favicon::Embedded, &request("GET", "/favicon.ico", "")).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), embedded::FAVICON.body);
+        assert_eq!(response.header("Content-Type"), Some(embedded::FAVICON.content_type));
+        assert_eq!(response.header("Cache-Control"), Some(CACHE_CONTROL));
+
+        // a browser revalidating its cached copy gets a 304
+        let etag = response.header("ETag").unwrap();
+        let revalidated = respond(&Favicon::Embedded, &request("GET", "/favicon.ico", &format!("If-None-Match: {etag}\r\n"))).unwrap();
+        assert_eq!(revalidated.status(), 304);
+    }
+
+    #[test]
+    fn an_icon_file_is_read_from_disk() {
+        let dir = TempDir::new();
+        let path = dir.write("icon.ico", "my icon");
+        assert_eq!(respond(&Favicon::File(path), &request("GET", "/favicon.ico", "")).unwrap().body(), b"my icon");
+
+        // a missing file leaves the handler's 404 in place
+        assert!(respond(&Favicon::File(dir.path().join("missing.ico")), &request("GET", "/favicon.ico", "")).is_none());
+    }
+
+    #[test]
+    fn only_a_get_for_the_icon_is_answered() {
+        assert!(respond(&Favicon::Embedded, &request("POST", "/favicon.ico", "")).is_none());
+        assert!(respond(&Favicon::Embedded, &request("GET", "/icons/favicon.ico", "")).is_none());
+        assert!(respond(&Favicon::Disabled, &request("GET", "/favicon.ico", "")).is_none());
+    }
+}
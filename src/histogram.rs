@@ -0,0 +1,165 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/*
+    A fixed-bucket latency histogram. Bucket boundaries are chosen once at construction and never
+    change, which is what lets `record` be a single atomic increment with no locking: the bucket
+    index is computed from immutable data and only the counter itself is shared.
+ */
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    // upper bounds, ascending; counts has one more slot for everything above the last bound
+    bounds: Vec<Duration>,
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64
+}
+
+impl LatencyHistogram {
+    /// Builds a histogram with the given upper bounds. They are sorted and deduplicated.
+    pub fn new(mut bounds: Vec<Duration>) -> Self {
+        bounds.sort();
+        bounds.dedup();
+        let counts = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+
+        Self { bounds, counts, sum_micros: AtomicU64::new(0) }
+    }
+
+    /// `buckets` bounds spaced evenly on a log scale from `min` to `max` inclusive.
+    pub fn log_spaced(min: Duration, max: Duration, buckets: usize) -> Vec<Duration> {
+        let (min, max) = (min.as_secs_f64(), max.as_secs_f64());
+        let step = (max / min).powf(1.0 / (buckets.max(2) - 1) as f64);
+
+        (0..buckets)
+            .map(|i| Duration::from_micros((min * step.powi(i as i32) * 1e6).round() as u64))
+            .collect()
+    }
+
+    /// Twenty log-spaced buckets from 100 µs to 60 s.
+    pub fn default_bounds() -> Vec<Duration> {
+        Self::log_spaced(Duration::from_micros(100), Duration::from_secs(60), 20)
+    }
+
+    pub fn record(&self, duration: Duration) {
+        let index = self.bounds.partition_point(|bound| *bound < duration);
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            bounds: self.bounds.clone(),
+            counts: self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect(),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new(Self::default_bounds())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    pub bounds: Vec<Duration>,
+    /// Per-bucket (not cumulative) counts; the last entry is the overflow bucket.
+    pub counts: Vec<u64>,
+    pub sum: Duration
+}
+
+impl HistogramSnapshot {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /*
+        Estimates the q-th quantile (0.0..=1.0) by finding the bucket holding that rank and
+        interpolating linearly between its lower and upper bound, the same estimate Prometheus'
+        histogram_quantile makes. Samples in the overflow bucket are reported as the last bound
+        since we know nothing about how far above it they are.
+     */
+    pub fn percentile(&self, q: f64) -> Option<Duration> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+
+        let rank = q.clamp(0.0, 1.0) * total as f64;
+        let mut below = 0;
+
+        for (index, &count) in self.counts.iter().enumerate() {
+            if count > 0 && (below + count) as f64 >= rank {
+                let Some(&upper) = self.bounds.get(index) else {
+                    return self.bounds.last().copied();
+                };
+                let lower = index.checked_sub(1).map_or(Duration::ZERO, |i| self.bounds[i]);
+                let fraction = (rank - below as f64) / count as f64;
+
+                return Some(lower + (upper - lower).mul_f64(fraction));
+            }
+            below += count;
+        }
+
+        self.bounds.last().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&ms| Duration::from_millis(ms)).collect()
+    }
+
+    #[test]
+    fn a_sample_lands_in_the_first_bucket_whose_bound_covers_it() {
+        let histogram = LatencyHistogram::new(millis(&[100, 10, 1, 10]));
+        for ms in [0, 1, 2, 10, 11, 100, 101, 5000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        let snapshot = histogram.snapshot();
+        // sorted and deduplicated, with an overflow bucket past the last bound
+        assert_eq!(snapshot.bounds, millis(&[1, 10, 100]));
+        assert_eq!(snapshot.counts, [2, 2, 2, 2]);
+        assert_eq!(snapshot.count(), 8);
+        assert_eq!(snapshot.sum, Duration::from_millis(5225));
+    }
+
+    #[test]
+    fn percentiles_interpolate_within_a_bucket() {
+        let histogram = LatencyHistogram::new(millis(&[10, 20]));
+        assert_eq!(histogram.snapshot().percentile(0.5), None);
+
+        (0..4).for_each(|_| histogram.record(Duration::from_millis(15)));
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.percentile(0.5), Some(Duration::from_millis(15)));
+        assert_eq!(snapshot.percentile(1.0), Some(Duration::from_millis(20)));
+        // out of range quantiles are clamped
+        assert_eq!(snapshot.percentile(7.0), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn overflowing_samples_are_reported_as_the_last_bound() {
+        let histogram = LatencyHistogram::new(millis(&[10, 20]));
+        histogram.record(Duration::from_millis(5));
+        histogram.record(Duration::from_secs(60));
+        assert_eq!(histogram.snapshot().percentile(0.99), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn log_spaced_bounds_run_from_min_to_max() {
+        let bounds = LatencyHistogram::log_spaced(Duration::from_millis(1), Duration::from_secs(1), 4);
+        assert_eq!(bounds, millis(&[1, 10, 100, 1000]));
+
+        let defaults = LatencyHistogram::default_bounds();
+        assert_eq!(defaults.len(), 20);
+        assert_eq!(defaults.first(), Some(&Duration::from_micros(100)));
+        assert_eq!(defaults.last(), Some(&Duration::from_secs(60)));
+        assert!(defaults.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
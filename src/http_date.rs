@@ -0,0 +1,103 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`. Sub-second
+/// precision is dropped, and times before 1970 are clamped to the epoch.
+pub fn format(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = secs / 86_400;
+    let (year, month, day) = civil_from_days(days);
+    let seconds_of_day = secs % 86_400;
+
+    format!(
+        "{}, {day:02} {} {year} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Formats `time` the way the Common Log Format's `%t` does, e.g. `10/Oct/2000:13:55:36 +0000`.
+pub fn format_common_log(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let seconds_of_day = secs % 86_400;
+
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/*
+    Parses an HTTP-date. Senders must use IMF-fixdate, but RFC 9110 section 5.6.7 asks
+    recipients to accept the two obsolete forms as well: RFC 850 (`Sunday, 06-Nov-94 08:49:37
+    GMT`) and asctime (`Sun Nov  6 08:49:37 1994`). The weekday is not checked against the date.
+ */
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let fields: Vec<&str> = value.split_whitespace().collect();
+
+    let (day, month, year, time) = match fields.as_slice() {
+        // IMF-fixdate: "Sun, 06 Nov 1994 08:49:37 GMT"
+        [weekday, day, month, year, time, "GMT"] if weekday.ends_with(',') => {
+            (day.parse().ok()?, *month, year.parse().ok()?, *time)
+        }
+        // RFC 850: "Sunday, 06-Nov-94 08:49:37 GMT"; a two-digit year below 70 is 20xx, the rest 19xx
+        [weekday, date, time, "GMT"] if weekday.ends_with(',') => {
+            let mut parts = date.split('-');
+            let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+            let year: u64 = year.parse().ok().filter(|_| year.len() == 2)?;
+            (day.parse().ok()?, month, if year < 70 { 2000 + year } else { 1900 + year }, *time)
+        }
+        // asctime: "Sun Nov  6 08:49:37 1994"
+        [_, month, day, time, year] => (day.parse().ok()?, *month, year.parse().ok()?, *time),
+        _ => return None
+    };
+
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok().filter(|_| part.len() == 2));
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if clock.next().is_some() || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 || year < 1970 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3600 + minute * 60 + second))
+}
+
+/*
+    Days since 1970-01-01 and back, for the proleptic Gregorian calendar, after Howard Hinnant's
+    `days_from_civil` and `civil_from_days`. Only dates from the epoch on are needed, which keeps
+    everything unsigned.
+ */
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    (era * 146_097 + day_of_era).saturating_sub(719_468)
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    (year, month, day)
+}
@@ -0,0 +1,152 @@
+use std::{
+    io::{self, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use crate::{
+    connection::{self, ClosePolicy},
+    read_timeout::{ReadTimeouts, TimedStream},
+    request::{self, EncodedSlash, ParseError, Request, Version},
+    server::ACCEPT_POLL_INTERVAL,
+    response::Redirect,
+    Response,
+};
+
+// Longest the redirect thread waits for a client's next bytes, and for its whole request head.
+const REDIRECT_READ_TIMEOUT: Duration = Duration::from_millis(500);
+const REDIRECT_HEAD_DEADLINE: Duration = Duration::from_secs(1);
+
+/// A plaintext listener that answers every request with a redirect to the same path and query
+/// on HTTPS, and serves nothing else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpsRedirect {
+    /// Address the plaintext listener binds to, e.g. `0.0.0.0:80`.
+    pub addr: String,
+    /// Host and port to redirect to, e.g. `example.com` or `example.com:8443`. Empty means
+    /// the host the request was addressed to, without its port.
+    pub authority: String,
+    /// The `Strict-Transport-Security` value sent along with each redirect, if any.
+    pub hsts: Option<String>
+}
+
+impl HttpsRedirect {
+    pub fn new(addr: &str, authority: &str) -> Self {
+        Self { addr: addr.to_string(), authority: authority.to_string(), hsts: None }
+    }
+
+    /// Sends `Strict-Transport-Security: max-age=...` with each redirect. Browsers only honor
+    /// it when it arrives over HTTPS, so this mostly matters to clients that follow the
+    /// redirect and see it there too.
+    pub fn hsts(mut self, max_age: Duration, include_subdomains: bool) -> Self {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        self.hsts = Some(value);
+        self
+    }
+
+    /*
+        Where to send `request`: the same target on HTTPS. An absolute-form target has its own
+        authority, which is replaced like the Host header would be; `OPTIONS *` goes to the root.
+     */
+    fn location(&self, request: &Request) -> Option<String> {
+        let target = request.target();
+        let path = match request::split_absolute(target) {
+            Some((_, rest)) => rest,
+            None if target == "*" => "",
+            None => target
+        };
+        let slash = if path.starts_with('/') { "" } else { "/" };
+
+        let authority = match self.authority.as_str() {
+            "" => strip_port(request.host()?),
+            authority => authority
+        };
+        Some(format!("https://{authority}{slash}{path}"))
+    }
+}
+
+// "example.com:80" is "example.com", and "[::1]:80" is "[::1]".
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host
+    }
+}
+
+/*
+    The accept loop for the plaintext port, on a thread of its own so it never takes a pool
+    worker. Each connection gets one answer and is closed. Connections are answered one at a
+    time, so the whole head read is under a deadline, not just each read: a client trickling
+    its head in a byte at a time would otherwise hold up every redirect behind it for as long
+    as it liked. The redirect is a permanent one, so a redirected POST gets a 308 and isn't
+    turned into a GET on the way.
+ */
+pub(crate) fn spawn(listener: TcpListener, redirect: HttpsRedirect, shutdown: Arc<AtomicBool>) -> io::Result<thread::JoinHandle<()>> {
+    listener.set_nonblocking(true)?;
+
+    Ok(thread::spawn(move || {
+        while !shutdown.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = answer(&stream, &redirect) {
+                        eprintln!("Failed to redirect to HTTPS: {e}");
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                Err(e) => {
+                    eprintln!("Failed to accept connection for HTTPS redirect: {e}");
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                }
+            }
+        }
+    }))
+}
+
+fn answer(stream: &TcpStream, redirect: &HttpsRedirect) -> io::Result<()> {
+    let answered = write_redirect(stream, redirect);
+    connection::close_connection(stream, ClosePolicy::ACCEPT_THREAD);
+    answered
+}
+
+fn write_redirect(stream: &TcpStream, redirect: &HttpsRedirect) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(REDIRECT_READ_TIMEOUT))?;
+
+    let timeouts = ReadTimeouts::new(REDIRECT_READ_TIMEOUT);
+    timeouts.set(REDIRECT_READ_TIMEOUT, Some(Instant::now() + REDIRECT_HEAD_DEADLINE));
+    let mut reader = BufReader::new(TimedStream::new(stream.try_clone()?, timeouts));
+    let request = match Request::parse_head(&mut reader, EncodedSlash::default()) {
+        Ok(Some(request)) => request,
+        // closed or timed out before sending anything useful
+        Ok(None) | Err(ParseError::Io(_)) => return Ok(()),
+        Err(e) => {
+            let mut writer = stream;
+            Response::status_only(e.status()).write_to(&mut writer, Version::Http11, false)?;
+            return Ok(());
+        }
+    };
+
+    let mut response = match redirect.location(&request) {
+        Some(location) => Response::redirect(&request, Redirect::Permanent, &location),
+        // no authority configured and none in the request to reuse
+        None => Response::status_only(400)
+    };
+    if let Some(hsts) = &redirect.hsts {
+        response.set_header("Strict-Transport-Security", hsts);
+    }
+
+    if request.method() == "HEAD" {
+        response.omit_body();
+    }
+
+    let mut writer = stream;
+    response.write_to(&mut writer, request.version(), false)?;
+    Ok(())
+}
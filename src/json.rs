@@ -0,0 +1,455 @@
+use std::{
+    fmt::{self, Display, Formatter, Write},
+    iter::Peekable,
+    str::Chars,
+};
+
+// Deepest nesting of arrays and objects we parse, so a body of "[[[[..." can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/*
+    Just enough JSON for request and response bodies: a value tree, a strict parser and a
+    serializer (through Display). Objects keep their members in order, which also keeps the
+    output stable for tests and diffs.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+    /// The member `key` of an object; `None` for a missing key or a non-object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None
+        }
+    }
+
+    /// The number if it is a non-negative integer that fits a `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_f64()
+            .filter(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= u64::MAX as f64)
+            .map(|n| n as u64)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            // JSON has no NaN or infinities
+            Value::Number(n) if !n.is_finite() => f.write_str("null"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(members) => {
+                f.write_char('{')?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if u32::from(c) < 0x20 => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => f.write_char(c)?
+        }
+    }
+    f.write_char('"')
+}
+
+/// Parses a complete JSON document; anything but whitespace after the value is an error.
+pub fn parse(input: &str) -> Result<Value, JsonError> {
+    let mut parser = Parser { chars: input.chars().peekable(), depth: 0 };
+    let value = parser.value()?;
+
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(_) => Err(JsonError("trailing characters after the value"))
+    }
+}
+
+/// Why a document isn't valid JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError(&'static str);
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid JSON: {}", self.0)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    depth: usize
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('n') => self.literal("null", Value::Null),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.nested(Self::array),
+            Some('{') => self.nested(Self::object),
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(JsonError("unexpected character")),
+            None => Err(JsonError("unexpected end of input"))
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, JsonError>) -> Result<Value, JsonError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(JsonError("nested too deeply"));
+        }
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn array(&mut self) -> Result<Value, JsonError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&']').is_some() {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(JsonError("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, JsonError> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((name, self.value()?));
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(members)),
+                _ => return Err(JsonError("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut s = String::new();
+
+        loop {
+            match self.chars.next().ok_or(JsonError("unterminated string"))? {
+                '"' => return Ok(s),
+                '\\' => match self.chars.next().ok_or(JsonError("unterminated string"))? {
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    '/' => s.push('/'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => s.push(self.unicode_escape()?),
+                    _ => return Err(JsonError("invalid escape"))
+                },
+                c if u32::from(c) < 0x20 => return Err(JsonError("control character in string")),
+                c => s.push(c)
+            }
+        }
+    }
+
+    // The four hex digits after "\u", combining a surrogate pair into one character.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or(JsonError("invalid \\u escape"));
+        }
+
+        if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+            return Err(JsonError("unpaired surrogate"));
+        }
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(JsonError("unpaired surrogate"));
+        }
+
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).ok_or(JsonError("invalid \\u escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        (0..4).try_fold(0, |code, _| {
+            let digit = self.chars.next().and_then(|c| c.to_digit(16)).ok_or(JsonError("invalid \\u escape"))?;
+            Ok(code * 16 + digit)
+        })
+    }
+
+    // Collects the JSON number grammar strictly (no leading zeros, no bare '.') and lets f64 parse it.
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let mut text = String::new();
+        if let Some(minus) = self.chars.next_if_eq(&'-') {
+            text.push(minus);
+        }
+
+        match self.chars.next() {
+            Some('0') => text.push('0'),
+            Some(c @ '1'..='9') => {
+                text.push(c);
+                self.digits(&mut text);
+            }
+            _ => return Err(JsonError("invalid number"))
+        }
+        if let Some(dot) = self.chars.next_if_eq(&'.') {
+            text.push(dot);
+            if self.digits(&mut text) == 0 {
+                return Err(JsonError("invalid number"));
+            }
+        }
+        if let Some(e) = self.chars.next_if(|c| matches!(c, 'e' | 'E')) {
+            text.push(e);
+            if let Some(sign) = self.chars.next_if(|c| matches!(c, '+' | '-')) {
+                text.push(sign);
+            }
+            if self.digits(&mut text) == 0 {
+                return Err(JsonError("invalid number"));
+            }
+        }
+
+        text.parse().map(Value::Number).map_err(|_| JsonError("invalid number"))
+    }
+
+    fn digits(&mut self, text: &mut String) -> usize {
+        let mut count = 0;
+        while let Some(digit) = self.chars.next_if(char::is_ascii_digit) {
+            text.push(digit);
+            count += 1;
+        }
+        count
+    }
+
+    fn literal(&mut self, word: &'static str, value: Value) -> Result<Value, JsonError> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(JsonError("invalid literal"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(JsonError("unexpected character"))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r')).is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_parse_into_the_value_tree() {
+        let value = parse(r#" {"title": "Dune", "year": 1965, "tags": ["sf", null, true], "rating": -4.5e-1} "#).unwrap();
+
+        assert_eq!(value, Value::Object(vec![
+            ("title".to_string(), "Dune".into()),
+            ("year".to_string(), Value::Number(1965.0)),
+            ("tags".to_string(), Value::Array(vec!["sf".into(), Value::Null, true.into()])),
+            ("rating".to_string(), Value::Number(-0.45))
+        ]));
+        assert_eq!(value.get("year").and_then(Value::as_u64), Some(1965));
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(Value::Null.get("title"), None);
+    }
+
+    #[test]
+    fn values_serialize_compactly_and_parse_back() {
+        let value = Value::Object(vec![
+            ("name".to_string(), "a \"quoted\"\n\tline \u{1} é".into()),
+            ("empty".to_string(), Value::Array(Vec::new())),
+            ("nested".to_string(), Value::Object(vec![("ok".to_string(), false.into())])),
+            ("count".to_string(), 3u64.into()),
+            ("nothing".to_string(), None::<u64>.into())
+        ]);
+
+        let text = value.to_string();
+        assert_eq!(
+            text,
+            r#"{"name":"a \"quoted\"\n\tline \u0001 é","empty":[],"nested":{"ok":false},"count":3,"nothing":null}"#
+        );
+        assert_eq!(parse(&text).unwrap(), value);
+        assert_eq!(Value::Number(f64::NAN).to_string(), "null");
+    }
+
+    #[test]
+    fn escapes_and_surrogate_pairs_are_decoded() {
+        let cases = [
+            (r#""\"\\\/\b\f\n\r\t""#, "\"\\/\u{8}\u{c}\n\r\t"),
+            (r#""\u00e9\u4E2D""#, "é中"),
+            (r#""\ud83d\ude00""#, "😀")
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse(input).unwrap(), Value::String(expected.to_string()), "{input}");
+        }
+    }
+
+    #[test]
+    fn malformed_documents_are_refused() {
+        let cases = [
+            ("", "unexpected end of input"),
+            ("{", "unexpected character"),
+            ("[1 2]", "expected ',' or ']'"),
+            (r#"{"a" 1}"#, "unexpected character"),
+            (r#"{"a": 1,}"#, "unexpected character"),
+            ("[1,]", "unexpected character"),
+            ("01", "trailing characters after the value"),
+            ("1.", "invalid number"),
+            ("-", "invalid number"),
+            ("1e", "invalid number"),
+            ("nul", "invalid literal"),
+            (r#""abc"#, "unterminated string"),
+            ("\"a\nb\"", "control character in string"),
+            (r#""\x""#, "invalid escape"),
+            (r#""\u12g4""#, "invalid \\u escape"),
+            (r#""\ud83d""#, "unpaired surrogate"),
+            (r#""\ud83dA""#, "unpaired surrogate"),
+            ("{} {}", "trailing characters after the value")
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse(input), Err(JsonError(expected)), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let deep = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+        assert!(parse(&deep(MAX_DEPTH)).is_ok());
+        assert_eq!(parse(&deep(MAX_DEPTH + 1)), Err(JsonError("nested too deeply")));
+    }
+
+    #[test]
+    fn only_whole_non_negative_numbers_are_u64s() {
+        let cases = [(0.0, Some(0)), (42.0, Some(42)), (1.5, None), (-1.0, None), (1e300, None)];
+
+        for (n, expected) in cases {
+            assert_eq!(Value::Number(n).as_u64(), expected, "{n}");
+        }
+        assert_eq!(Value::from("42").as_u64(), None);
+    }
+}
@@ -6,32 +6,432 @@
     that returns Result instead.
  */
 use std::fmt::{Display, Formatter};
-use std::{sync::{mpsc, Arc, Mutex}, thread};
+use channel::{JobReceiver, JobSender};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    cmp::Ordering as Compared,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, mpsc::RecvTimeoutError, Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant}
+};
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+pub mod args;
+#[cfg(feature = "tokio")]
+mod async_accept;
+pub mod body;
+pub mod books;
+mod buffer_pool;
+pub mod cache_control;
+mod channel;
+pub mod client;
+pub mod compression;
+pub mod conditional;
+mod connection;
+pub mod connection_state;
+pub mod embedded;
+pub mod error_pages;
+pub mod favicon;
+pub mod histogram;
+pub mod http_date;
+pub mod https_redirect;
+pub mod json;
+mod linger;
+pub mod log;
+pub mod log_format;
+pub mod method_override;
+pub mod mime;
+pub mod negotiate;
+pub mod preflight;
+pub mod proxy;
+pub mod range;
+mod read_timeout;
+pub mod recovery;
+pub mod request;
+pub mod response;
+pub mod retry;
+pub mod router;
+pub mod server;
+pub mod sse;
+pub mod static_files;
+pub mod stats;
+mod systemd;
+#[cfg(test)]
+mod temp_dir;
+pub mod trace;
+pub mod trailers;
+pub mod vhost;
+mod watchdog;
+mod writable;
 
+pub use request::Request;
+pub use response::Response;
+pub use router::Router;
+pub use server::{BindError, BindMode, Server, ServerConfig, ShutdownHandle};
+pub use static_files::{SpaFallback, StaticFiles, Symlinks};
+pub use stats::{ServerStats, StatsSnapshot};
+pub use vhost::VirtualHostRouter;
+
+/// A unit of work for the pool, already boxed.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Called on a worker that waited a whole receiver timeout without a job, with the worker's id.
+pub type IdleCallback = dyn Fn(usize) + Send + Sync + 'static;
+
+/// Called on a worker thread as it starts or just before it exits, with the worker's id.
+pub type LifecycleCallback = dyn Fn(usize) + Send + Sync + 'static;
+
+// What actually travels through the channel.
+enum Message {
+    // a job, plus the point after which it isn't worth running and what to call it meanwhile
+    Run { job: Job, deadline: Option<Instant>, name: Option<String> },
+    // the worker that takes this leaves the pool, which is how shrinking picks one without interrupting any
+    Retire
+}
+
+// Weight given to the newest sample in the rolling mean job duration.
+const EWMA_ALPHA: f64 = 0.2;
+
+// Counters shared between the pool handle and its workers.
+#[derive(Default)]
+struct PoolMetrics {
+    expired_jobs: AtomicUsize,
+    panicked_jobs: AtomicUsize,
+    queued_jobs: AtomicUsize,
+    // workers running a job right now
+    busy_workers: AtomicUsize,
+    // what each busy worker is running and since when, by worker id
+    running: Mutex<HashMap<usize, (Option<String>, Instant)>>,
+    // submitters waiting for room in a full queue under OverflowPolicy::Block, and their wakeup
+    blocked_submitters: AtomicUsize,
+    room: Mutex<()>,
+    room_freed: Condvar,
+    inline_jobs: AtomicUsize,
+    mean_job_micros: AtomicU64
+}
+
+impl PoolMetrics {
+    /*
+        Folds a finished job's duration into an exponentially weighted moving average. Workers
+        race on the load/store pair, so an update can occasionally be lost; for a rolling
+        estimate that's an acceptable trade for not taking a lock after every job.
+     */
+    fn record_job(&self, duration: Duration) {
+        let sample = duration.as_micros() as f64;
+        let mean = self.mean_job_micros.load(Ordering::Relaxed) as f64;
+        let updated = if mean == 0.0 { sample } else { mean + EWMA_ALPHA * (sample - mean) };
+        self.mean_job_micros.store(updated as u64, Ordering::Relaxed);
+    }
+
+    /*
+        A worker took a job off the queue. The decrement and the check for blocked submitters
+        are SeqCst, as are their counterparts in `wait_for_room`: either the submitter sees the
+        room, or the worker sees the submitter and wakes it, taking the lock so the wakeup
+        can't land between the submitter's check and its wait.
+     */
+    fn job_dequeued(&self) {
+        self.queued_jobs.fetch_sub(1, Ordering::SeqCst);
+        if self.blocked_submitters.load(Ordering::SeqCst) > 0 {
+            let _room = self.room.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            self.room_freed.notify_one();
+        }
+    }
+}
+
+/// What `ThreadPool::try_execute` does with a job when the queue is at its `queue_capacity`.
+///
+/// Only `try_execute` honours it. `execute` and the other ways of submitting always queue the
+/// job, full or not, though what they queue counts towards the capacity all the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until a worker takes a job off the queue, then queue it. Back-pressure: the
+    /// submitter is slowed to the pool's pace.
+    Block,
+    /// Refuse it with `ExecuteError::QueueFull`; the job is dropped unrun.
+    Reject,
+    /// Run it on the submitting thread before returning, e.g. an accept thread that would
+    /// rather serve a connection itself than turn it away. The submitter takes no new work
+    /// meanwhile, which is back-pressure of its own.
+    RunInline
+}
+
+/// A fixed set of worker threads taking jobs from one queue.
+///
+/// A job that panics takes its worker down with it, unless the pool was built with
+/// `catch_panics`; either way nothing the job held is the pool's, so the other workers, the
+/// queue and shutdown carry on, and a job poisoning a mutex of its own affects only the jobs
+/// that share it. With `catch_panics` the pool keeps its size however many jobs panic.
+/// Dropping the pool never panics, so it is safe while a panic is already unwinding.
 // cargo doc --open
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>
+    workers: Mutex<Workers>,
+    sender: Option<channel::Sender<Message>>,
+    metrics: Arc<PoolMetrics>,
+    drain_timeout: Option<Duration>,
+    queue_capacity: Option<usize>,
+    // with a ceiling, `execute` adds a worker whenever jobs are waiting and none is idle
+    max_threads: Option<usize>,
+    overflow: OverflowPolicy,
+    spawner: Spawner
+}
+
+/*
+    The pool's threads. Shrinking only queues retirement notices, so for a while `list` still
+    holds workers that are about to leave; `retiring` counts those, and `reap` takes them off
+    the list as they exit.
+ */
+struct Workers {
+    list: Vec<Worker>,
+    retiring: usize,
+    next_id: usize
+}
+
+impl Workers {
+    fn active(&self) -> usize {
+        self.list.len().saturating_sub(self.retiring)
+    }
+
+    // Joins the workers that have exited. One that returned normally took a retirement notice.
+    fn reap(&mut self) {
+        let (finished, running): (Vec<Worker>, Vec<Worker>) = mem::take(&mut self.list)
+            .into_iter()
+            .partition(|worker| worker.thread.as_ref().is_some_and(|thread| thread.is_finished()));
+        self.list = running;
+
+        for thread in finished.into_iter().filter_map(|worker| worker.thread) {
+            // a worker killed by a panicking job was never counted as retiring
+            if thread.join().is_ok() {
+                self.retiring = self.retiring.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/*
+    Everything a new worker needs, kept so the pool can grow after it is built. The receiver is
+    a weak handle: only live workers keep the queue open, so once every one of them has died,
+    sending fails and `try_execute` reports `Disconnected` rather than queueing jobs nobody will
+    run. New workers get their receiver from the workers still alive.
+ */
+#[derive(Clone)]
+struct Spawner {
+    receiver: channel::WeakReceiver<Message>,
+    metrics: Arc<PoolMetrics>,
+    idle: Option<IdleWakeup>,
+    lifecycle: Lifecycle,
+    catch_panics: bool
+}
+
+impl Spawner {
+    // `None` once every worker has died and the queue with them.
+    fn spawn(&self, id: usize) -> Option<Worker> {
+        self.receiver.upgrade().map(|receiver| self.spawn_on(id, receiver))
+    }
+
+    fn spawn_on(&self, id: usize, receiver: channel::Receiver<Message>) -> Worker {
+        Worker::new(
+            id,
+            receiver,
+            Arc::clone(&self.metrics),
+            self.idle.clone(),
+            self.lifecycle.clone(),
+            self.catch_panics
+        )
+    }
+}
+
+// How often a bounded Drop checks whether the remaining workers have finished.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Configures a `ThreadPool` before its workers are spawned. Created by `ThreadPool::builder`.
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    size: usize,
+    drain_timeout: Option<Duration>,
+    queue_capacity: Option<usize>,
+    max_threads: Option<usize>,
+    overflow: Option<OverflowPolicy>,
+    idle: Option<IdleWakeup>,
+    on_idle: Option<Arc<IdleCallback>>,
+    lifecycle: Lifecycle,
+    catch_panics: bool
+}
+
+impl Debug for ThreadPoolBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadPoolBuilder")
+            .field("size", &self.size)
+            .field("drain_timeout", &self.drain_timeout)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("max_threads", &self.max_threads)
+            .field("overflow_policy", &self.overflow)
+            .field("receiver_timeout", &self.idle.as_ref().map(|idle| idle.interval))
+            .field("on_idle", &self.on_idle.is_some())
+            .field("on_worker_start", &self.lifecycle.on_start.is_some())
+            .field("on_worker_stop", &self.lifecycle.on_stop.is_some())
+            .field("catch_panics", &self.catch_panics)
+            .finish()
+    }
+}
+
+// How long an idle worker waits for a job before it wakes up, and what it does then.
+#[derive(Clone)]
+struct IdleWakeup {
+    interval: Duration,
+    on_idle: Option<Arc<IdleCallback>>
+}
+
+// What each worker runs on its own thread as it starts and as it leaves.
+#[derive(Clone, Default)]
+struct Lifecycle {
+    on_start: Option<Arc<LifecycleCallback>>,
+    on_stop: Option<Arc<LifecycleCallback>>
+}
+
+impl ThreadPoolBuilder {
+    /// Bounds how long dropping the pool waits for workers to finish their current job.
+    ///
+    /// Without it, `Drop` joins every worker however long that takes. With it, workers still
+    /// busy at the deadline are logged and left running detached, so a stuck job can't keep
+    /// the process from exiting.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Bounds the queue for `try_execute`, which applies the `overflow_policy` to a job once
+    /// `capacity` are waiting. `execute` always queues.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// What `try_execute` does with a job that finds the queue full: `Reject` by default when
+    /// there is a `queue_capacity`. Without one the queue is never full and the policy is
+    /// never applied; it reports `Block`, since that is what an unbounded queue amounts to.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = Some(policy);
+        self
+    }
+
+    /// Starts the pool with its `size` workers and lets it grow to `max` as load demands: a job
+    /// submitted while jobs are already waiting and no worker is idle spawns another worker,
+    /// until there are `max`. Workers added this way stay until the pool is dropped or
+    /// `resize_to` shrinks it. `build` refuses a `max` below the size.
+    pub fn max_threads(mut self, max: usize) -> Self {
+        self.max_threads = Some(max);
+        self
+    }
+
+    /// Makes idle workers wake up every `interval` instead of blocking until a job arrives,
+    /// and run the `on_idle` callback if one is set, for periodic per-worker upkeep.
+    ///
+    /// Workers take turns waiting on the queue, so with several idle workers each one wakes
+    /// about once per `interval` times the number of idle workers, not once per `interval`.
+    /// With the `crossbeam` feature they all wait on it at once, and each wakes once per `interval`.
+    pub fn receiver_timeout(mut self, interval: Duration) -> Self {
+        self.idle = Some(IdleWakeup { interval, on_idle: None });
+        self
+    }
+
+    /// Sets the callback run by a worker each time its receiver timeout expires. Has no effect
+    /// without `receiver_timeout`. It runs on the worker thread, which takes no jobs meanwhile.
+    pub fn on_idle<F>(mut self, callback: F) -> Self
+    where F: Fn(usize) + Send + Sync + 'static
+    {
+        self.on_idle = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback each worker runs on its own thread before taking any job, e.g. to
+    /// register the thread with a profiler or set up thread-local state.
+    pub fn on_worker_start<F>(mut self, callback: F) -> Self
+    where F: Fn(usize) + Send + Sync + 'static
+    {
+        self.lifecycle.on_start = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback each worker runs on its own thread once the pool is dropped and the
+    /// queue is closed, or a shrinking `resize_to` retires it, just before the thread exits,
+    /// e.g. to flush thread-local buffers. A worker killed by a panicking job doesn't run it,
+    /// and neither does one still busy when a `drain_timeout` gives up on it, until it finishes.
+    pub fn on_worker_stop<F>(mut self, callback: F) -> Self
+    where F: Fn(usize) + Send + Sync + 'static
+    {
+        self.lifecycle.on_stop = Some(Arc::new(callback));
+        self
+    }
+
+    /// Keeps a worker alive when a job it runs panics: the panic is caught, reported on stderr
+    /// by the panic hook as usual, and counted in `ThreadPool::panicked_jobs`, and the worker
+    /// goes on to the next job. Without it the worker thread dies with the job and the pool is
+    /// a worker short from then on.
+    pub fn catch_panics(mut self) -> Self {
+        self.catch_panics = true;
+        self
+    }
+
+    /// Spawns the workers. Returns an error if the size is zero, or `max_threads` is below it.
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        if self.max_threads.is_some_and(|max| max < self.size) {
+            return Err(PoolCreationError::MaxBelowSize);
+        }
+        let idle = self.idle.map(|idle| IdleWakeup { on_idle: self.on_idle, ..idle });
+        let mut pool = ThreadPool::spawn(self.size, idle, self.lifecycle, self.catch_panics)?;
+        pool.drain_timeout = self.drain_timeout;
+        pool.queue_capacity = self.queue_capacity;
+        pool.max_threads = self.max_threads;
+        pool.overflow = self.overflow.unwrap_or(match self.queue_capacity {
+            Some(_) => OverflowPolicy::Reject,
+            None => OverflowPolicy::Block
+        });
+        Ok(pool)
+    }
 }
 
 impl ThreadPool {
+    /// Creates a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool.
+    ///
+    /// Use `new` when the size is a known-good constant; use `build` when it comes from
+    /// configuration or user input and a zero should be handled rather than crash.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        match Self::build(size) {
+            Ok(pool) => pool,
+            Err(e) => panic!("ThreadPool::new called with an invalid size ({size}): {e}")
+        }
+    }
+
     /// Creates a new ThreadPool.
     ///
     /// The size is the number of threads in the pool.
     ///
     /// The `build` function returns an error type if the size is zero.
     pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
-        if size <= 0 {
+        Self::spawn(size, None, Lifecycle::default(), false)
+    }
+
+    fn spawn(
+        size: usize,
+        idle: Option<IdleWakeup>,
+        lifecycle: Lifecycle,
+        catch_panics: bool
+    ) -> Result<ThreadPool, PoolCreationError> {
+        if size == 0 {
             return Err(PoolCreationError::InvalidSize);
         }
 
-        // Taking a job off the channel queue involves mutating the receiver,
-        // so we need thread-safe smart pointers to share and modify receiver.
-        let (sender, receiver) = mpsc::channel();
-        // Mutex owns the receiver, Arc tracks mutex-wrapped receiver reference counts across threads
-        let receiver = Arc::new(Mutex::new(receiver));
+        let (sender, receiver) = channel::channel();
+        let metrics = Arc::new(PoolMetrics::default());
+        let spawner = Spawner { receiver: receiver.downgrade(), metrics: Arc::clone(&metrics), idle, lifecycle, catch_panics };
 
         /*
             The with_capacity function performs the same task as Vec::new but with an important
@@ -41,21 +441,40 @@ impl ThreadPool {
          */
         let mut workers = Vec::with_capacity(size);
 
-        // we clone the Arc to bump the reference count so the workers can share ownership of the receiver
-        (0..size).for_each(|id|
-            workers.push(
-                Worker::new(
-                    id,
-                    Arc::clone(&receiver)
-                )
-            )
-        );
+        // every worker gets a handle on the same queue; whichever is idle takes the next job
+        (0..size).for_each(|id| workers.push(spawner.spawn_on(id, receiver.clone())));
+        // from here on the workers' handles are the only ones keeping the queue open
+        drop(receiver);
 
         Ok(
-            ThreadPool { workers, sender: Some(sender) }
+            ThreadPool {
+                workers: Mutex::new(Workers { list: workers, retiring: 0, next_id: size }),
+                sender: Some(sender),
+                metrics,
+                drain_timeout: None,
+                queue_capacity: None,
+                max_threads: None,
+                overflow: OverflowPolicy::Block,
+                spawner
+            }
         )
     }
 
+    /// Starts configuring a pool of `size` threads with options beyond the size.
+    pub fn builder(size: usize) -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size,
+            drain_timeout: None,
+            queue_capacity: None,
+            max_threads: None,
+            overflow: None,
+            idle: None,
+            on_idle: None,
+            lifecycle: Lifecycle::default(),
+            catch_panics: false
+        }
+    }
+
     /*
         We need Send to transfer the closure from one thread to another and
         'static because we don’t know how long the thread will take to execute.
@@ -66,19 +485,243 @@ impl ThreadPool {
     pub fn execute<F>(&self, job: F)
     where F: FnOnce() + Send + 'static
     {
+        self.send(Message::Run { job: Box::new(job), deadline: None, name: None })
+    }
+
+    /// Like `execute`, for a job that is already boxed, which is sent on without boxing it again.
+    pub fn execute_boxed(&self, job: Job) {
+        self.send(Message::Run { job, deadline: None, name: None })
+    }
+
+    /// Like `execute`, but honours the `queue_capacity`: a job that finds the queue full is
+    /// handled by the pool's `OverflowPolicy`, and one that can't be queued because every
+    /// worker has died is reported instead of panicking. A refused job is dropped unrun.
+    ///
+    /// Under `Block`, waiting for room relies on the workers: if every one of them is stuck or
+    /// dead, this waits forever. Under `RunInline`, a job that panics panics the submitter,
+    /// unless the pool catches panics, in which case it is counted in `panicked_jobs`.
+    pub fn try_execute<F>(&self, job: F) -> Result<(), ExecuteError>
+    where F: FnOnce() + Send + 'static
+    {
+        // claim a queue slot first, so concurrent submitters can't overshoot the capacity
+        if !self.claim_slot() {
+            match self.overflow {
+                OverflowPolicy::Reject => return Err(ExecuteError::QueueFull),
+                OverflowPolicy::Block => self.wait_for_room(),
+                OverflowPolicy::RunInline => {
+                    self.run_inline(Box::new(job));
+                    return Ok(());
+                }
+            }
+        }
+
+        let sent = self.sender.as_ref().unwrap().send(Message::Run { job: Box::new(job), deadline: None, name: None });
+        if sent.is_err() {
+            self.metrics.queued_jobs.fetch_sub(1, Ordering::Relaxed);
+            return Err(ExecuteError::Disconnected);
+        }
+        self.grow_if_backlogged();
+        Ok(())
+    }
+
+    // Counts one more queued job unless that would exceed the capacity.
+    fn claim_slot(&self) -> bool {
+        let capacity = self.queue_capacity.unwrap_or(usize::MAX);
+        self.metrics
+            .queued_jobs
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| (queued < capacity).then_some(queued + 1))
+            .is_ok()
+    }
+
+    // Returns once a slot has been claimed; see `PoolMetrics::job_dequeued` for the other half.
+    fn wait_for_room(&self) {
+        self.metrics.blocked_submitters.fetch_add(1, Ordering::SeqCst);
+        let mut room = self.metrics.room.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while !self.claim_slot() {
+            room = self.metrics.room_freed.wait(room).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        drop(room);
+        self.metrics.blocked_submitters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn run_inline(&self, job: Job) {
+        self.metrics.inline_jobs.fetch_add(1, Ordering::Relaxed);
+        if !self.spawner.catch_panics {
+            job();
+        } else if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            self.metrics.panicked_jobs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The policy `try_execute` applies to a job that finds the queue full.
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    /// Number of jobs `try_execute` ran on the submitting thread under `OverflowPolicy::RunInline`.
+    pub fn inline_jobs(&self) -> usize {
+        self.metrics.inline_jobs.load(Ordering::Relaxed)
+    }
+
+    /// Like `execute`, with a name for the job that `current_jobs` reports while it runs, so a
+    /// stuck worker can be told apart: "worker 3 has been running render-report for 40s".
+    pub fn execute_with_name<F>(&self, name: impl Into<String>, job: F)
+    where F: FnOnce() + Send + 'static
+    {
+        self.send(Message::Run { job: Box::new(job), deadline: None, name: Some(name.into()) })
+    }
+
+    /// What every busy worker is running, and for how long, ordered by worker id.
+    pub fn current_jobs(&self) -> Vec<CurrentJob> {
+        let now = Instant::now();
+        let mut jobs: Vec<CurrentJob> = self.metrics.running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(&worker, (name, since))| CurrentJob {
+                worker,
+                name: name.clone(),
+                running_for: now.saturating_duration_since(*since)
+            })
+            .collect();
+        jobs.sort_by_key(|job| job.worker);
+        jobs
+    }
+
+    /// Queues `job`, but only runs it if a worker picks it up before `deadline`.
+    ///
+    /// A job that waited in the queue past its deadline is dropped unrun and counted in
+    /// `expired_jobs`. Useful when the result is worthless late, e.g. the client has timed out.
+    pub fn execute_with_deadline<F>(&self, deadline: Instant, job: F)
+    where F: FnOnce() + Send + 'static
+    {
+        self.send(Message::Run { job: Box::new(job), deadline: Some(deadline), name: None })
+    }
+
+    /// Number of jobs dropped because they were dequeued after their deadline.
+    pub fn expired_jobs(&self) -> usize {
+        self.metrics.expired_jobs.load(Ordering::Relaxed)
+    }
+
+    /// Number of jobs that panicked and were caught; see `ThreadPoolBuilder::catch_panics`.
+    pub fn panicked_jobs(&self) -> usize {
+        self.metrics.panicked_jobs.load(Ordering::Relaxed)
+    }
+
+    /// Number of jobs sent to the pool that no worker has picked up yet.
+    pub fn queued_jobs(&self) -> usize {
+        self.metrics.queued_jobs.load(Ordering::Relaxed)
+    }
+
+    /// Rolling (exponentially weighted) mean of how long jobs take to run.
+    pub fn mean_job_duration(&self) -> Duration {
+        Duration::from_micros(self.metrics.mean_job_micros.load(Ordering::Relaxed))
+    }
+
+    /// Number of workers, not counting any a shrinking `resize_to` has already told to leave.
+    pub fn worker_count(&self) -> usize {
+        let mut workers = self.workers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        workers.reap();
+        workers.active()
+    }
+
+    /// Grows or shrinks the pool to exactly `target` workers, e.g. as an autoscaler sees the
+    /// queue grow or drain; an error if `target` is zero. New workers start taking jobs at
+    /// once. Surplus workers leave by taking a retirement notice from the queue, so each
+    /// finishes the job it's on, and jobs queued before the resize still run first.
+    /// `worker_count` reports the target straight away either way.
+    ///
+    /// Returns `Disconnected` if every worker has died (see `ThreadPoolBuilder::catch_panics`),
+    /// since the queue went with them and new workers would have nothing to take jobs from.
+    ///
+    /// Safe to call while other threads submit jobs, or resize the pool themselves.
+    pub fn resize_to(&self, target: usize) -> Result<(), PoolCreationError> {
+        if target == 0 {
+            return Err(PoolCreationError::InvalidSize);
+        }
+
+        let mut workers = self.workers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        workers.reap();
+        let current = workers.active();
+        match target.cmp(&current) {
+            Compared::Greater => {
+                if !self.grow(&mut workers, target - current) {
+                    return Err(PoolCreationError::Disconnected);
+                }
+            }
+            Compared::Less => {
+                if !self.shrink(&mut workers, current - target) {
+                    return Err(PoolCreationError::Disconnected);
+                }
+            }
+            Compared::Equal => {}
+        }
+        Ok(())
+    }
+
+    // Adds `count` workers; false if there was no queue left to give them.
+    fn grow(&self, workers: &mut Workers, count: usize) -> bool {
+        for _ in 0..count {
+            let Some(worker) = self.spawner.spawn(workers.next_id) else { return false };
+            workers.next_id += 1;
+            workers.list.push(worker);
+        }
+        true
+    }
+
+    // Tells `count` workers to retire; false if there was no queue left to tell them through.
+    fn shrink(&self, workers: &mut Workers, count: usize) -> bool {
+        let sender = self.sender.as_ref().unwrap();
+        for _ in 0..count {
+            if sender.send(Message::Retire).is_err() {
+                return false;
+            }
+            workers.retiring += 1;
+        }
+        true
+    }
+
+    /// Rough time a job submitted now would wait before starting: the queue ahead of it,
+    /// each costing the mean job duration, spread across the workers.
+    pub fn estimated_wait(&self) -> Duration {
+        let per_worker = self.queued_jobs() as f64 / self.worker_count().max(1) as f64;
+        self.mean_job_duration().mul_f64(per_worker)
+    }
+
+    fn send(&self, message: Message) {
+        self.metrics.queued_jobs.fetch_add(1, Ordering::Relaxed);
+
         /*
-            We’re calling unwrap on send for the case that sending fails. This might happen if, for
-            example, we stop all our threads from executing, meaning the receiving end has stopped
-            receiving new messages. At the moment, we can’t stop our threads from executing: our
-            threads continue executing as long as the pool exists. The reason we use unwrap is that
-            we know the failure case won’t happen, but the compiler doesn’t know that.
+            Sending fails once every worker has died, taking the receiving end with it: without
+            catch_panics, a panicking job kills its worker. Nothing would ever run the job, so
+            rather than drop it silently we panic; `try_execute` reports the same case instead.
          */
         self.sender
             .as_ref()
             .unwrap()
-            .send(Box::new(job))
-            .unwrap()
+            .send(message)
+            .unwrap_or_else(|_| panic!("every worker has stopped receiving jobs"));
         // there is a single instance of the receiver that receives these jobs (messages)
+
+        self.grow_if_backlogged();
+    }
+
+    /*
+        Adds a worker for `max_threads` when more jobs are waiting than there are idle workers
+        to take them. The counters are read without the lock and can be a job off, which at
+        worst spawns a worker a job early or late; the ceiling itself is checked under it.
+     */
+    fn grow_if_backlogged(&self) {
+        let Some(max) = self.max_threads else { return };
+        let queued = self.metrics.queued_jobs.load(Ordering::Relaxed);
+        let busy = self.metrics.busy_workers.load(Ordering::Relaxed);
+
+        let mut workers = self.workers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        workers.reap();
+        let active = workers.active();
+        if active < max && queued > active.saturating_sub(busy) {
+            self.grow(&mut workers, 1);
+        }
     }
 }
 
@@ -91,16 +734,67 @@ impl Drop for ThreadPool {
          */
         drop(self.sender.take());
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        if let Some(timeout) = self.drain_timeout {
+            self.drain_until(Instant::now() + timeout);
+            return;
+        }
 
+        // none left to join after shutdown_until
+        let workers = &mut self.workers.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).list;
+        for worker in workers {
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                println!("Shutting down worker {}", worker.id);
+                join(worker.id, thread);
             }
         }
     }
 }
 
+impl ThreadPool {
+    /// Drops the pool, waiting for the workers only until `deadline`, as a `drain_timeout`
+    /// ending then would. For a caller shutting down to a deadline of its own, such as the
+    /// server's grace period, which the pool should share rather than start another one.
+    pub fn shutdown_until(mut self, deadline: Instant) {
+        drop(self.sender.take());
+        self.drain_until(deadline);
+    }
+
+    /*
+        Join only blocks, so waiting with a deadline means polling is_finished instead. Workers
+        still running at the deadline are given up on: their handles are dropped, which detaches
+        the threads, and they die with the process.
+     */
+    fn drain_until(&mut self, deadline: Instant) {
+        let workers = &mut self.workers.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).list;
+        while Instant::now() < deadline
+            && workers.iter().any(|worker| worker.thread.as_ref().is_some_and(|thread| !thread.is_finished()))
+        {
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+
+        for worker in workers {
+            match worker.thread.take() {
+                Some(thread) if thread.is_finished() => {
+                    println!("Shutting down worker {}", worker.id);
+                    join(worker.id, thread);
+                }
+                Some(_) => eprintln!("Worker {} still busy after the drain timeout; abandoning it.", worker.id),
+                None => {}
+            }
+        }
+    }
+}
+
+/*
+    A worker whose job panicked has already had its panic reported, so its join error is only
+    noted. Unwrapping it would make dropping the pool panic, and abort the process if the pool
+    is being dropped by a panic unwinding.
+ */
+fn join(id: usize, thread: thread::JoinHandle<()>) {
+    if let Err(payload) = thread.join() {
+        eprintln!("Worker {id} had been killed by a panicking job: {}", recovery::format_panic_payload(&*payload));
+    }
+}
 
 struct Worker {
     id: usize,
@@ -108,16 +802,39 @@ struct Worker {
 }
 impl Worker {
     // each worker loops forever, attempting to read messages from the receiver singleton
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            /*
-                We first call lock on the receiver to acquire the mutex, and then we call unwrap to
-                panic on any errors. Acquiring a lock might fail if the mutex is in a poisoned state,
-                which can happen if some other thread panicked while holding the lock rather than
-                releasing the lock. In this situation, calling unwrap to have this thread panic is
-                the correct action to take. Feel free to change this unwrap to an expect with an
-                error message that is meaningful to you.
-             */
+    fn new(
+        id: usize,
+        receiver: channel::Receiver<Message>,
+        metrics: Arc<PoolMetrics>,
+        idle: Option<IdleWakeup>,
+        lifecycle: Lifecycle,
+        catch_panics: bool
+    ) -> Self {
+        let thread = thread::spawn(move || {
+            if let Some(on_start) = &lifecycle.on_start {
+                on_start(id);
+            }
+            Self::run(id, &receiver, &metrics, idle.as_ref(), catch_panics);
+            if let Some(on_stop) = &lifecycle.on_stop {
+                on_stop(id);
+            }
+        });
+
+        Self {
+            id,
+            thread: Some(thread)
+        }
+    }
+
+    // takes jobs until the channel is closed
+    fn run(
+        id: usize,
+        receiver: &channel::Receiver<Message>,
+        metrics: &PoolMetrics,
+        idle: Option<&IdleWakeup>,
+        catch_panics: bool
+    ) {
+        loop {
             /*
                 With let, any temporary values used in the expression on the right hand side of the
                 equals sign are immediately dropped when the let statement ends. However, while let
@@ -131,35 +848,107 @@ impl Worker {
                     job();
                 }
              */
-            let message = receiver
-                .lock()
-                .expect("Mutex poisoned: Another thread panicked while holding the lock.")
-                .recv(); // blocks the given thread until a message is received or the thread holding the sender shuts down
+            let message = match idle {
+                None => receiver.recv(),
+                Some(idle) => match receiver.recv_timeout(idle.interval) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Some(on_idle) = &idle.on_idle {
+                            on_idle(id);
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => None
+                }
+            };
 
-            // lock automatically released
+            if let Some(Message::Run { .. }) = message {
+                metrics.job_dequeued();
+            }
 
             match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
-                    job();
+                Some(Message::Run { deadline: Some(deadline), .. }) if Instant::now() > deadline => {
+                    println!("Worker {id} dropped an expired job.");
+                    metrics.expired_jobs.fetch_add(1, Ordering::Relaxed);
                 }
-                Err(_) => {
+                Some(Message::Run { job, name, .. }) => {
+                    match &name {
+                        Some(name) => println!("Worker {id} got job {name}; executing."),
+                        None => println!("Worker {id} got a job; executing.")
+                    }
+                    #[cfg(feature = "tracing")]
+                    let _span = tracing::info_span!("job", worker = id, job = name.as_deref()).entered();
+                    let started = Instant::now();
+                    let busy = Busy::start(metrics, id, name, started);
+                    if !catch_panics {
+                        job();
+                    } else if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        // the hook has already printed the panic; the job's state is its own
+                        metrics.panicked_jobs.fetch_add(1, Ordering::Relaxed);
+                    }
+                    drop(busy);
+                    metrics.record_job(started.elapsed());
+                }
+                Some(Message::Retire) => {
+                    println!("Worker {id} retired; shutting down.");
+                    break;
+                }
+                None => {
                     println!("Worker {id} disconnected; shutting down.");
                     break;
                 }
             }
-        });
-
-        Self {
-            id,
-            thread: Some(thread)
         }
     }
 }
 
+/*
+    Counts a worker as busy, and records what it runs, until dropped, which a job killing the
+    worker by panicking also does.
+ */
+struct Busy<'a> {
+    metrics: &'a PoolMetrics,
+    id: usize
+}
+
+impl<'a> Busy<'a> {
+    fn start(metrics: &'a PoolMetrics, id: usize, name: Option<String>, started: Instant) -> Self {
+        metrics.busy_workers.fetch_add(1, Ordering::Relaxed);
+        metrics.running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(id, (name, started));
+        Busy { metrics, id }
+    }
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.metrics.busy_workers.fetch_sub(1, Ordering::Relaxed);
+        // recover the map even if poisoned; the drop may run while a panic unwinds
+        self.metrics.running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/// A job a worker is running, from `ThreadPool::current_jobs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentJob {
+    /// Id of the worker running it.
+    pub worker: usize,
+    /// The name it was given by `execute_with_name`; `None` for the other ways of submitting.
+    pub name: Option<String>,
+    /// How long it has been running.
+    pub running_for: Duration
+}
+
 #[derive(Debug)]
 pub enum PoolCreationError {
-    InvalidSize
+    InvalidSize,
+    MaxBelowSize,
+    Disconnected
 }
 
 impl Display for PoolCreationError {
@@ -169,3 +958,517 @@ impl Display for PoolCreationError {
 }
 
 impl std::error::Error for PoolCreationError {}
+
+/// Why `ThreadPool::try_execute` couldn't queue a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    /// The queue already holds `queue_capacity` jobs.
+    QueueFull,
+    /// Every worker has stopped, so nothing would ever run the job.
+    Disconnected
+}
+
+impl Display for ExecuteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecuteError::QueueFull => write!(f, "the job queue is full"),
+            ExecuteError::Disconnected => write!(f, "every worker has stopped")
+        }
+    }
+}
+
+impl std::error::Error for ExecuteError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicBool, mpsc};
+
+    // Long enough for anything a test waits on to have happened, short enough not to hang CI.
+    const PATIENCE: Duration = Duration::from_secs(5);
+
+    #[test]
+    #[should_panic(expected = "invalid size")]
+    fn new_panics_on_a_size_of_zero() {
+        ThreadPool::new(0);
+    }
+
+    #[test]
+    fn build_reports_a_size_of_zero() {
+        assert!(matches!(ThreadPool::build(0), Err(PoolCreationError::InvalidSize)));
+        assert!(matches!(ThreadPool::builder(0).build(), Err(PoolCreationError::InvalidSize)));
+    }
+
+    // Starts a job on `pool` that runs for `duration`, and returns once a worker has picked it up.
+    fn start_job(pool: &ThreadPool, duration: Duration) {
+        let (started, job_started) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            thread::sleep(duration);
+        });
+        job_started.recv_timeout(PATIENCE).unwrap();
+    }
+
+    #[test]
+    fn drop_gives_up_on_a_job_outlasting_the_drain_timeout() {
+        let pool = ThreadPool::builder(2).drain_timeout(Duration::from_millis(100)).build().unwrap();
+        start_job(&pool, Duration::from_secs(10));
+
+        let dropping = Instant::now();
+        drop(pool);
+        assert!(dropping.elapsed() < Duration::from_secs(2), "drop took {:?}", dropping.elapsed());
+    }
+
+    #[test]
+    fn shutdown_until_waits_no_longer_than_the_deadline() {
+        // a drain timeout of its own must not be started on top of the deadline
+        let pool = ThreadPool::builder(1).drain_timeout(Duration::from_secs(10)).build().unwrap();
+        start_job(&pool, Duration::from_secs(10));
+
+        let stopping = Instant::now();
+        pool.shutdown_until(stopping + Duration::from_millis(100));
+        assert!(stopping.elapsed() < Duration::from_secs(2), "shutdown took {:?}", stopping.elapsed());
+    }
+
+    #[test]
+    fn shutdown_until_joins_workers_that_finish_in_time() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&stopped);
+        let pool = ThreadPool::builder(3)
+            .on_worker_stop(move |_| { counter.fetch_add(1, Ordering::SeqCst); })
+            .build()
+            .unwrap();
+        start_job(&pool, Duration::from_millis(50));
+
+        pool.shutdown_until(Instant::now() + PATIENCE);
+        assert_eq!(stopped.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn a_job_dequeued_after_its_deadline_is_skipped_and_counted() {
+        let pool = ThreadPool::new(1);
+        start_job(&pool, Duration::from_millis(200));
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let expiring = Arc::clone(&ran);
+        pool.execute_with_deadline(Instant::now() + Duration::from_millis(20), move || expiring.store(true, Ordering::SeqCst));
+        // queued behind both, so by the time it runs the expired job has been looked at
+        let (done, after) = mpsc::channel();
+        pool.execute(move || done.send(()).unwrap());
+        after.recv_timeout(PATIENCE).unwrap();
+
+        assert!(!ran.load(Ordering::SeqCst));
+        assert_eq!(pool.expired_jobs(), 1);
+    }
+
+    #[test]
+    fn a_job_dequeued_before_its_deadline_runs() {
+        let pool = ThreadPool::new(1);
+        let (done, ran) = mpsc::channel();
+        pool.execute_with_deadline(Instant::now() + PATIENCE, move || done.send(()).unwrap());
+
+        ran.recv_timeout(PATIENCE).unwrap();
+        assert_eq!(pool.expired_jobs(), 0);
+    }
+
+    // Polls `condition` until it holds, failing the test if it doesn't within PATIENCE.
+    fn wait_for(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + PATIENCE;
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for {what}");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Whether a job sent to `pool` now gets run.
+    fn runs_a_job(pool: &ThreadPool) -> bool {
+        let (done, ran) = mpsc::channel();
+        pool.execute(move || done.send(()).unwrap());
+        ran.recv_timeout(PATIENCE).is_ok()
+    }
+
+    #[test]
+    fn resize_to_grows_then_shrinks_the_pool() {
+        let pool = ThreadPool::new(2);
+
+        pool.resize_to(5).unwrap();
+        assert_eq!(pool.worker_count(), 5);
+        assert!(runs_a_job(&pool));
+
+        pool.resize_to(1).unwrap();
+        assert_eq!(pool.worker_count(), 1);
+        // the retired workers leave for good, and the one left still takes jobs
+        wait_for("the retired workers to exit", || {
+            let mut workers = pool.workers.lock().unwrap();
+            workers.reap();
+            workers.list.len() == 1
+        });
+        assert_eq!(pool.worker_count(), 1);
+        assert!(runs_a_job(&pool));
+    }
+
+    #[test]
+    fn jobs_are_refused_once_every_worker_has_died() {
+        let pool = ThreadPool::new(2);
+        // without catch_panics each of these takes a worker down with it
+        pool.execute(|| panic!("first worker down"));
+        pool.execute(|| panic!("second worker down"));
+        wait_for("both workers to die", || pool.worker_count() == 0);
+
+        assert_eq!(pool.try_execute(|| {}), Err(ExecuteError::Disconnected));
+        assert!(matches!(pool.resize_to(2), Err(PoolCreationError::Disconnected)));
+        assert_eq!(pool.worker_count(), 0);
+    }
+
+    #[test]
+    fn a_pool_a_worker_short_can_still_grow() {
+        let pool = ThreadPool::new(2);
+        pool.execute(|| panic!("one worker down"));
+        wait_for("the worker to die", || pool.worker_count() == 1);
+
+        pool.resize_to(3).unwrap();
+        assert_eq!(pool.worker_count(), 3);
+        assert!(runs_a_job(&pool));
+    }
+
+    #[test]
+    fn shrinking_a_pool_whose_workers_died_is_an_error() {
+        let pool = ThreadPool::new(2);
+        pool.execute(|| panic!("first worker down"));
+        pool.execute(|| panic!("second worker down"));
+        wait_for("both workers to exit", || {
+            let workers = pool.workers.lock().unwrap();
+            workers.list.iter().all(|worker| worker.thread.as_ref().is_some_and(|thread| thread.is_finished()))
+        });
+
+        // not reaped yet, so the pool still counts two workers to retire, with no queue to reach them
+        let mut workers = pool.workers.lock().unwrap();
+        assert_eq!(workers.active(), 2);
+        assert!(!pool.shrink(&mut workers, 1));
+        assert_eq!(workers.retiring, 0);
+    }
+
+    #[test]
+    fn poisoned_pool_locks_are_recovered() {
+        let pool = ThreadPool::new(2);
+        thread::scope(|scope| {
+            let holding_workers = scope.spawn(|| {
+                let _workers = pool.workers.lock().unwrap();
+                panic!("panicked holding the workers");
+            });
+            assert!(holding_workers.join().is_err());
+            let holding_running = scope.spawn(|| {
+                let _running = pool.metrics.running.lock().unwrap();
+                panic!("panicked holding the running jobs");
+            });
+            assert!(holding_running.join().is_err());
+        });
+        assert!(pool.workers.is_poisoned());
+        assert!(pool.metrics.running.is_poisoned());
+
+        assert_eq!(pool.worker_count(), 2);
+        assert!(pool.current_jobs().is_empty());
+        pool.resize_to(3).unwrap();
+        pool.resize_to(1).unwrap();
+        assert_eq!(pool.worker_count(), 1);
+        assert!(runs_a_job(&pool));
+    }
+
+    #[test]
+    #[cfg(not(feature = "crossbeam"))]
+    fn a_poisoned_job_queue_lock_is_recovered() {
+        let pool = ThreadPool::new(1);
+        let (release, released) = mpsc::channel::<()>();
+        let (started, job_started) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = released.recv();
+        });
+        job_started.recv_timeout(PATIENCE).unwrap();
+
+        // the only worker is busy with the job, so nothing is holding the lock
+        let receiver = pool.spawner.receiver.upgrade().unwrap();
+        receiver.poison();
+        assert!(receiver.is_poisoned());
+        release.send(()).unwrap();
+
+        assert!(runs_a_job(&pool));
+        assert!(!receiver.is_poisoned());
+        assert!(runs_a_job(&pool));
+        assert_eq!(pool.worker_count(), 1);
+    }
+
+    fn catching_pool(size: usize) -> ThreadPool {
+        ThreadPool::builder(size).catch_panics().build().unwrap()
+    }
+
+    #[test]
+    fn a_caught_panic_keeps_the_worker() {
+        let pool = catching_pool(2);
+        pool.execute(|| panic!("job failed"));
+        wait_for("the panic to be counted", || pool.panicked_jobs() == 1);
+
+        assert!(runs_a_job(&pool));
+        assert_eq!(pool.worker_count(), 2);
+    }
+
+    #[test]
+    fn a_job_poisoning_a_shared_mutex_affects_only_that_mutex() {
+        let pool = catching_pool(2);
+        let shared = Arc::new(Mutex::new(0));
+
+        let poisoning = Arc::clone(&shared);
+        pool.execute(move || {
+            let _guard = poisoning.lock().unwrap();
+            panic!("panicked holding the lock");
+        });
+        wait_for("the panic to be counted", || pool.panicked_jobs() == 1);
+        assert!(shared.is_poisoned());
+
+        // a later job can still take the lock, by choosing to recover it
+        let recovering = Arc::clone(&shared);
+        let (done, ran) = mpsc::channel();
+        pool.execute(move || {
+            *recovering.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) += 1;
+            done.send(()).unwrap();
+        });
+        ran.recv_timeout(PATIENCE).unwrap();
+
+        assert_eq!(*shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner()), 1);
+        assert!(runs_a_job(&pool));
+        assert_eq!(pool.worker_count(), 2);
+    }
+
+    #[test]
+    fn panicking_and_healthy_jobs_interleaved_all_get_run() {
+        let pool = catching_pool(2);
+        let healthy = Arc::new(AtomicUsize::new(0));
+
+        for job in 0..20 {
+            if job % 2 == 0 {
+                pool.execute(move || panic!("job {job} failed"));
+            } else {
+                let healthy = Arc::clone(&healthy);
+                pool.execute(move || { healthy.fetch_add(1, Ordering::SeqCst); });
+            }
+        }
+        wait_for("every job to run", || pool.panicked_jobs() == 10 && healthy.load(Ordering::SeqCst) == 10);
+
+        assert!(runs_a_job(&pool));
+        assert_eq!(pool.worker_count(), 2);
+    }
+
+    #[test]
+    fn a_panic_during_shutdown_doesnt_stop_the_drain() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&stopped);
+        let pool = ThreadPool::builder(2)
+            .catch_panics()
+            .on_worker_stop(move |_| { counter.fetch_add(1, Ordering::SeqCst); })
+            .build()
+            .unwrap();
+
+        // the panicking job is still running when the pool is dropped, with healthy jobs queued behind it
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = released.recv();
+            panic!("panicked while shutting down");
+        });
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..4 {
+            let ran = Arc::clone(&ran);
+            pool.execute(move || { ran.fetch_add(1, Ordering::SeqCst); });
+        }
+
+        let dropping = thread::spawn(move || drop(pool));
+        // give the drop time to close the queue, so the job panics with the pool shutting down
+        thread::sleep(Duration::from_millis(50));
+        release.send(()).unwrap();
+        dropping.join().expect("dropping the pool panicked");
+
+        assert_eq!(ran.load(Ordering::SeqCst), 4);
+        // both workers outlived the panic and left when the queue closed
+        assert_eq!(stopped.load(Ordering::SeqCst), 2);
+    }
+
+    /*
+        A pool of one worker with room for one queued job, and both taken: the worker is held
+        by a job that waits on the returned sender, and one more job is queued behind it.
+        Returns the pool, the release for the worker and a count of the jobs that ran.
+     */
+    fn full_pool(policy: OverflowPolicy) -> (ThreadPool, mpsc::Sender<()>, Arc<AtomicUsize>) {
+        let pool = ThreadPool::builder(1).queue_capacity(1).overflow_policy(policy).build().unwrap();
+        let (release, released) = mpsc::channel();
+        let (started, job_started) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = released.recv();
+        });
+        job_started.recv_timeout(PATIENCE).unwrap();
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let queued = Arc::clone(&ran);
+        pool.try_execute(move || { queued.fetch_add(1, Ordering::SeqCst); }).unwrap();
+        assert_eq!(pool.queued_jobs(), 1);
+        (pool, release, ran)
+    }
+
+    #[test]
+    fn reject_refuses_a_job_when_the_queue_is_full() {
+        let (pool, release, ran) = full_pool(OverflowPolicy::Reject);
+
+        let refused = Arc::clone(&ran);
+        assert_eq!(pool.try_execute(move || { refused.fetch_add(1, Ordering::SeqCst); }), Err(ExecuteError::QueueFull));
+        // execute doesn't look at the policy
+        let queued = Arc::clone(&ran);
+        pool.execute(move || { queued.fetch_add(1, Ordering::SeqCst); });
+        assert_eq!(pool.queued_jobs(), 2);
+
+        release.send(()).unwrap();
+        drop(pool);
+        assert_eq!(ran.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn run_inline_runs_a_job_on_the_submitter_when_the_queue_is_full() {
+        let (pool, release, ran) = full_pool(OverflowPolicy::RunInline);
+
+        let submitter = thread::current().id();
+        let ran_on = Arc::new(Mutex::new(None));
+        let recorded = Arc::clone(&ran_on);
+        pool.try_execute(move || *recorded.lock().unwrap() = Some(thread::current().id())).unwrap();
+
+        // it ran before try_execute returned, with the worker still held
+        assert_eq!(*ran_on.lock().unwrap(), Some(submitter));
+        assert_eq!(pool.inline_jobs(), 1);
+        assert_eq!(pool.queued_jobs(), 1);
+
+        release.send(()).unwrap();
+        drop(pool);
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn block_waits_for_room_when_the_queue_is_full() {
+        let (pool, release, ran) = full_pool(OverflowPolicy::Block);
+        let pool = Arc::new(pool);
+
+        let (submitted, was_submitted) = mpsc::channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            let ran = Arc::clone(&ran);
+            thread::spawn(move || {
+                let result = pool.try_execute(move || { ran.fetch_add(1, Ordering::SeqCst); });
+                submitted.send(result).unwrap();
+            })
+        };
+        // still waiting, since the worker hasn't taken anything off the queue
+        assert!(was_submitted.recv_timeout(Duration::from_millis(200)).is_err());
+
+        release.send(()).unwrap();
+        assert_eq!(was_submitted.recv_timeout(PATIENCE).unwrap(), Ok(()));
+        submitter.join().unwrap();
+        wait_for("both queued jobs to run", || ran.load(Ordering::SeqCst) == 2);
+        assert_eq!(pool.inline_jobs(), 0);
+    }
+
+    // A pool of `size` whose idle callback records the id of every worker it runs on.
+    fn idle_pool(size: usize, interval: Option<Duration>) -> (ThreadPool, Arc<Mutex<Vec<usize>>>) {
+        let idled = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&idled);
+        let mut builder = ThreadPool::builder(size).on_idle(move |id| recorded.lock().unwrap().push(id));
+        if let Some(interval) = interval {
+            builder = builder.receiver_timeout(interval);
+        }
+        (builder.build().unwrap(), idled)
+    }
+
+    #[test]
+    fn on_idle_fires_while_the_pool_has_nothing_to_do() {
+        let (pool, idled) = idle_pool(2, Some(Duration::from_millis(20)));
+
+        wait_for("a few idle wakeups", || idled.lock().unwrap().len() >= 4);
+        assert!(idled.lock().unwrap().iter().all(|&id| id < 2));
+        // waking up to run the callback doesn't get in the way of jobs
+        assert!(runs_a_job(&pool));
+    }
+
+    #[test]
+    fn without_a_receiver_timeout_on_idle_never_fires() {
+        let (pool, idled) = idle_pool(2, None);
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(runs_a_job(&pool));
+        assert!(idled.lock().unwrap().is_empty());
+    }
+
+    type Calls = Arc<Mutex<Vec<(usize, thread::ThreadId)>>>;
+
+    // A pool of `size` whose lifecycle hooks record the worker id and the thread they ran on.
+    fn hooked_pool(size: usize) -> (ThreadPool, Calls, Calls) {
+        let (started, stopped) = (Calls::default(), Calls::default());
+        let (on_start, on_stop) = (Arc::clone(&started), Arc::clone(&stopped));
+        let pool = ThreadPool::builder(size)
+            .on_worker_start(move |id| on_start.lock().unwrap().push((id, thread::current().id())))
+            .on_worker_stop(move |id| on_stop.lock().unwrap().push((id, thread::current().id())))
+            .build()
+            .unwrap();
+        (pool, started, stopped)
+    }
+
+    #[test]
+    fn every_worker_runs_its_start_and_stop_hooks_on_its_own_thread() {
+        let (pool, started, stopped) = hooked_pool(3);
+        wait_for("every worker to start", || started.lock().unwrap().len() == 3);
+        assert!(stopped.lock().unwrap().is_empty());
+
+        // dropping the pool disconnects the queue, which is what ends each worker
+        drop(pool);
+        let mut started = started.lock().unwrap().clone();
+        let mut stopped = stopped.lock().unwrap().clone();
+        started.sort_by_key(|&(id, _)| id);
+        stopped.sort_by_key(|&(id, _)| id);
+        assert_eq!(started, stopped);
+        assert_eq!(started.iter().map(|&(id, _)| id).collect::<Vec<_>>(), [0, 1, 2]);
+        assert!(started.iter().all(|&(_, thread)| thread != thread::current().id()));
+    }
+
+    #[test]
+    fn workers_added_or_retired_by_a_resize_run_their_hooks() {
+        let (pool, started, stopped) = hooked_pool(1);
+
+        pool.resize_to(3).unwrap();
+        wait_for("the new workers to start", || started.lock().unwrap().len() == 3);
+        pool.resize_to(2).unwrap();
+        wait_for("a worker to retire", || stopped.lock().unwrap().len() == 1);
+        assert!(runs_a_job(&pool));
+
+        drop(pool);
+        assert_eq!(started.lock().unwrap().len(), 3);
+        assert_eq!(stopped.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn a_running_job_is_listed_with_its_name_until_it_finishes() {
+        let pool = ThreadPool::new(2);
+        let (release_named, named_released) = mpsc::channel::<()>();
+        let (release_plain, plain_released) = mpsc::channel::<()>();
+        pool.execute_with_name("render-report", move || { let _ = named_released.recv(); });
+        pool.execute(move || { let _ = plain_released.recv(); });
+        wait_for("both jobs to start", || pool.current_jobs().len() == 2);
+
+        thread::sleep(Duration::from_millis(50));
+        let jobs = pool.current_jobs();
+        let mut names: Vec<Option<&str>> = jobs.iter().map(|job| job.name.as_deref()).collect();
+        names.sort();
+        assert_eq!(names, [None, Some("render-report")]);
+        assert!(jobs.iter().all(|job| job.running_for >= Duration::from_millis(50)), "{jobs:?}");
+        assert_ne!(jobs[0].worker, jobs[1].worker);
+
+        release_named.send(()).unwrap();
+        wait_for("the named job to finish", || pool.current_jobs().len() == 1);
+        assert_eq!(pool.current_jobs()[0].name, None);
+        release_plain.send(()).unwrap();
+        wait_for("the plain job to finish", || pool.current_jobs().is_empty());
+    }
+}
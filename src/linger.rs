@@ -0,0 +1,67 @@
+use std::{io, net::TcpStream};
+
+/*
+    Sets SO_LINGER to zero, so that closing the socket resets the connection (RST) instead of
+    going through the FIN handshake and TIME_WAIT. std only has this behind an unstable feature,
+    so we call setsockopt ourselves; the option values differ between the Linux and BSD families,
+    and on anything else this reports Unsupported.
+ */
+#[cfg(any(
+    all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64", target_arch = "sparc64"))),
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub(crate) fn set_abortive_close(stream: &TcpStream) -> io::Result<()> {
+    use std::{
+        ffi::{c_int, c_void},
+        os::fd::AsRawFd,
+    };
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const SOL_SOCKET: c_int = 1;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const SO_LINGER: c_int = 13;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const SOL_SOCKET: c_int = 0xffff;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const SO_LINGER: c_int = 0x0080;
+
+    #[repr(C)]
+    struct Linger {
+        l_onoff: c_int,
+        l_linger: c_int
+    }
+
+    extern "C" {
+        fn setsockopt(socket: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+    }
+
+    let linger = Linger { l_onoff: 1, l_linger: 0 };
+    // SAFETY: the fd is open for as long as `stream` is borrowed, and `linger` outlives the call
+    let result = unsafe {
+        setsockopt(
+            stream.as_raw_fd(),
+            SOL_SOCKET,
+            SO_LINGER,
+            (&linger as *const Linger).cast(),
+            size_of::<Linger>() as u32
+        )
+    };
+
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(any(
+    all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64", target_arch = "sparc64"))),
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+pub(crate) fn set_abortive_close(_stream: &TcpStream) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "abortive close is not supported on this platform"))
+}
@@ -0,0 +1,532 @@
+use std::{
+    ffi::OsString,
+    fmt::{Display, Formatter},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use crate::log_format::LogFormat;
+
+/// One access log line, describing a request and how it was answered.
+#[derive(Debug, Clone)]
+pub struct AccessRecord {
+    pub peer: Option<SocketAddr>,
+    /// When the request line started to arrive.
+    pub time: SystemTime,
+    pub method: String,
+    /// The method `X-HTTP-Method-Override` turned `method` into, if it did.
+    pub method_override: Option<String>,
+    pub target: String,
+    pub version: &'static str,
+    pub status: u16,
+    pub body_bytes: u64,
+    /// From the first byte of the request line to the last byte of the response being flushed.
+    pub duration: Duration,
+    /// Pattern of the route that handled the request, if the router matched one.
+    pub route: Option<String>,
+    /// Trace id from the request's `traceparent` header, if it had a valid one.
+    pub trace_id: Option<String>,
+    /// The id the server gave the request, as sent back in `X-Request-Id`.
+    pub request_id: Option<String>,
+    /// Time the connection spent queued in the pool before a worker picked it up.
+    /// Only the first request on a connection can have waited; later ones report zero.
+    pub queue_wait: Duration,
+    /// The client disconnected before the whole response was written. `body_bytes` is then
+    /// every byte that did reach the socket, head included.
+    pub aborted: bool,
+    /// Request headers a custom log format prints; empty with the default line.
+    pub request_headers: Vec<(String, String)>,
+    /// Response headers a custom log format prints; empty with the default line.
+    pub response_headers: Vec<(String, String)>
+}
+
+impl Display for AccessRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.peer {
+            Some(peer) => write!(f, "{peer} ")?,
+            None => write!(f, "- ")?
+        }
+        write!(
+            f,
+            "\"{} {} {}\" {} {} {:.3}ms queue={:.3}ms",
+            self.method,
+            self.target,
+            self.version,
+            self.status,
+            self.body_bytes,
+            millis(self.duration),
+            millis(self.queue_wait)
+        )?;
+        if let Some(method) = &self.method_override {
+            write!(f, " override={method}")?;
+        }
+        if let Some(route) = &self.route {
+            write!(f, " route={route}")?;
+        }
+        if let Some(trace_id) = &self.trace_id {
+            write!(f, " trace={trace_id}")?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " id={request_id}")?;
+        }
+        if self.aborted {
+            write!(f, " aborted")?;
+        }
+        Ok(())
+    }
+}
+
+// Milliseconds with microsecond precision.
+fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Writes an access log line to stdout.
+pub fn access(record: &AccessRecord) {
+    println!("{record}");
+}
+
+/// Writes an access log line to `file`, or to stdout without one.
+pub fn access_to(file: Option<&RotatingFile>, record: &AccessRecord) {
+    match file {
+        Some(file) => write_line(file, &record.to_string()),
+        None => access(record)
+    }
+}
+
+fn write_line(file: &RotatingFile, line: &str) {
+    if let Err(e) = file.write_line(line) {
+        eprintln!("Failed to write access log {}: {e}", file.settings.path.display());
+    }
+}
+
+/// What a full `LogQueue` does with another line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Throw the line away and count it, so the request goes on undelayed.
+    #[default]
+    Drop,
+    /// Wait for room, so no line is lost but a slow sink slows requests down again.
+    Block
+}
+
+/// How access log lines are handed to a background writer thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogQueueSettings {
+    /// Most lines waiting to be written before `overflow` applies.
+    pub capacity: usize,
+    pub overflow: Overflow
+}
+
+impl LogQueueSettings {
+    /// Drops lines once `capacity` are waiting.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, overflow: Overflow::Drop }
+    }
+}
+
+/*
+    Log lines written by a thread of their own, so a worker that logs only pays for a channel
+    send and never waits on a slow disk or a stalled pipe. Lines still queued when the queue is
+    dropped are written out before the drop returns.
+ */
+#[derive(Debug)]
+pub struct LogQueue {
+    sender: Option<SyncSender<String>>,
+    overflow: Overflow,
+    writer: Option<thread::JoinHandle<()>>
+}
+
+impl LogQueue {
+    /// Starts the writer thread, which passes each line to `sink` in the order they were queued.
+    pub fn spawn<S>(settings: LogQueueSettings, mut sink: S) -> io::Result<Self>
+    where S: FnMut(&str) + Send + 'static
+    {
+        let (sender, receiver) = mpsc::sync_channel::<String>(settings.capacity);
+        let writer = thread::Builder::new()
+            .name(String::from("log-writer"))
+            .spawn(move || receiver.iter().for_each(|line| sink(&line)))?;
+
+        Ok(Self { sender: Some(sender), overflow: settings.overflow, writer: Some(writer) })
+    }
+
+    /// Queues `line`. Returns false if it was dropped because the queue was full.
+    pub fn push(&self, line: String) -> bool {
+        let sender = self.sender.as_ref().expect("the sender lives until the queue is dropped");
+        match self.overflow {
+            Overflow::Drop => match sender.try_send(line) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => false,
+                // the writer thread died (its sink panicked); nothing more will be written
+                Err(TrySendError::Disconnected(_)) => false
+            },
+            Overflow::Block => sender.send(line).is_ok()
+        }
+    }
+}
+
+impl Drop for LogQueue {
+    fn drop(&mut self) {
+        // closing the channel ends the writer once it has written everything queued
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                eprintln!("The log writer thread panicked.");
+            }
+        }
+    }
+}
+
+/*
+    Where the server's access log lines go: straight to stdout or the log file from the worker
+    that served the request, or through a `LogQueue` to either. Lines are the record's own
+    Display, or rendered with the configured format.
+ */
+#[derive(Debug)]
+pub(crate) struct AccessLog {
+    format: Option<LogFormat>,
+    sink: Sink
+}
+
+#[derive(Debug)]
+enum Sink {
+    Direct(Option<RotatingFile>),
+    Queued(LogQueue)
+}
+
+impl AccessLog {
+    pub(crate) fn open(file: Option<LogFile>, queue: Option<LogQueueSettings>, format: Option<LogFormat>) -> io::Result<Self> {
+        let file = file.map(RotatingFile::open).transpose()?;
+        let sink = match queue {
+            None => Sink::Direct(file),
+            Some(settings) => Sink::Queued(LogQueue::spawn(settings, move |line| match &file {
+                Some(file) => write_line(file, line),
+                None => println!("{line}")
+            })?)
+        };
+        Ok(Self { format, sink })
+    }
+
+    pub(crate) fn format(&self) -> Option<&LogFormat> {
+        self.format.as_ref()
+    }
+
+    // Logs `record`; false if the line was dropped because the queue was full.
+    pub(crate) fn write(&self, record: &AccessRecord) -> bool {
+        let line = match &self.format {
+            Some(format) => format.render(record),
+            None => record.to_string()
+        };
+        match &self.sink {
+            Sink::Direct(Some(file)) => {
+                write_line(file, &line);
+                true
+            }
+            Sink::Direct(None) => {
+                println!("{line}");
+                true
+            }
+            Sink::Queued(queue) => queue.push(line)
+        }
+    }
+}
+
+/*
+    A warning that may fire for every connection during an overload, written at most once per
+    `interval`. The occurrences in between are only counted, and the count goes out with the
+    next warning written, so the log says how bad it got without becoming part of the problem.
+ */
+#[derive(Debug)]
+pub(crate) struct Throttled {
+    interval: Duration,
+    last: Option<Instant>,
+    suppressed: u64
+}
+
+impl Throttled {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self { interval, last: None, suppressed: 0 }
+    }
+
+    pub(crate) fn warn(&mut self, message: impl Display) {
+        let now = Instant::now();
+        if self.last.is_some_and(|last| now.duration_since(last) < self.interval) {
+            self.suppressed += 1;
+            return;
+        }
+
+        match self.suppressed {
+            0 => eprintln!("Warning: {message}"),
+            suppressed => eprintln!("Warning: {message} ({suppressed} more since the last warning)")
+        }
+        self.last = Some(now);
+        self.suppressed = 0;
+    }
+}
+
+/// Where a log file lives and when it is rotated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// Size past which the file is rotated before the next line is written.
+    pub max_bytes: u64,
+    /// How many rotated files (`access.log.1` being the newest) are kept; older ones are deleted.
+    pub keep: usize
+}
+
+impl LogFile {
+    /// Rotates at 10 MiB and keeps five old files.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), max_bytes: 10 * 1024 * 1024, keep: 5 }
+    }
+}
+
+/*
+    An append-only log file that rotates itself by size: `access.log` becomes `access.log.1`,
+    `.1` becomes `.2` and so on up to `keep`, and a fresh file is opened. Writers share one
+    mutex, and a line is written while it's held, so every line lands whole in exactly one file
+    no matter how many threads log while a rotation happens.
+ */
+#[derive(Debug)]
+pub struct RotatingFile {
+    settings: LogFile,
+    current: Mutex<Current>
+}
+
+#[derive(Debug)]
+struct Current {
+    file: File,
+    len: u64
+}
+
+impl RotatingFile {
+    /// Opens (or creates) the log, appending to what is already there.
+    pub fn open(settings: LogFile) -> io::Result<Self> {
+        let file = open_append(&settings.path)?;
+        let len = file.metadata()?.len();
+
+        Ok(Self { settings, current: Mutex::new(Current { file, len }) })
+    }
+
+    /// Appends `line` and a newline, rotating first if it would take the file past `max_bytes`.
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut current = self.current
+            .lock()
+            .expect("Mutex poisoned: Another thread panicked while holding the lock.");
+
+        let line_len = line.len() as u64 + 1;
+        if current.len > 0 && current.len + line_len > self.settings.max_bytes {
+            // if rotating fails, the line still goes into the current file rather than being dropped
+            match self.rotate() {
+                Ok(file) => *current = Current { file, len: 0 },
+                Err(e) => eprintln!("Failed to rotate log {}: {e}", self.settings.path.display())
+            }
+        }
+
+        writeln!(current.file, "{line}")?;
+        current.len += line_len;
+        Ok(())
+    }
+
+    // Shifts the numbered files up by one and moves the live file to `.1`; returns the fresh file.
+    fn rotate(&self) -> io::Result<File> {
+        let path = &self.settings.path;
+
+        if self.settings.keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..self.settings.keep).rev() {
+                match fs::rename(numbered(path, n), numbered(path, n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(path, numbered(path, 1))?;
+        }
+
+        open_append(path)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// "access.log" and 2 make "access.log.2".
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use std::{sync::Arc, thread};
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    // Lines of ten bytes, newline included, so the file sizes are easy to follow.
+    fn line(n: usize) -> String {
+        format!("line {n:04}")
+    }
+
+    #[test]
+    fn a_full_file_is_rotated_and_only_keep_old_ones_remain() {
+        let dir = TempDir::new();
+        let path = dir.path().join("access.log");
+        let log = RotatingFile::open(LogFile { path: path.clone(), max_bytes: 20, keep: 2 }).unwrap();
+
+        for n in 0..7 {
+            log.write_line(&line(n)).unwrap();
+        }
+
+        assert_eq!(read(&path), "line 0006\n");
+        assert_eq!(read(&numbered(&path, 1)), "line 0004\nline 0005\n");
+        assert_eq!(read(&numbered(&path, 2)), "line 0002\nline 0003\n");
+        assert!(!numbered(&path, 3).exists());
+    }
+
+    #[test]
+    fn keeping_no_old_files_starts_over() {
+        let dir = TempDir::new();
+        let path = dir.path().join("access.log");
+        let log = RotatingFile::open(LogFile { path: path.clone(), max_bytes: 20, keep: 0 }).unwrap();
+
+        for n in 0..3 {
+            log.write_line(&line(n)).unwrap();
+        }
+
+        assert_eq!(read(&path), "line 0002\n");
+        assert!(!numbered(&path, 1).exists());
+    }
+
+    #[test]
+    fn an_existing_log_is_appended_to_and_counted() {
+        let dir = TempDir::new();
+        let path = dir.write("access.log", "line 0000\n");
+        let log = RotatingFile::open(LogFile { path: path.clone(), max_bytes: 20, keep: 1 }).unwrap();
+
+        log.write_line(&line(1)).unwrap();
+        log.write_line(&line(2)).unwrap();
+
+        assert_eq!(read(&numbered(&path, 1)), "line 0000\nline 0001\n");
+        assert_eq!(read(&path), "line 0002\n");
+    }
+
+    #[test]
+    fn a_line_longer_than_the_limit_still_goes_into_an_empty_file() {
+        let dir = TempDir::new();
+        let path = dir.path().join("access.log");
+        let log = RotatingFile::open(LogFile { path: path.clone(), max_bytes: 5, keep: 1 }).unwrap();
+
+        log.write_line(&line(0)).unwrap();
+
+        assert_eq!(read(&path), "line 0000\n");
+        assert!(!numbered(&path, 1).exists());
+    }
+
+    #[test]
+    fn concurrent_writers_lose_and_split_no_lines() {
+        let dir = TempDir::new();
+        let path = dir.path().join("access.log");
+        let log = Arc::new(RotatingFile::open(LogFile { path: path.clone(), max_bytes: 200, keep: 100 }).unwrap());
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let log = Arc::clone(&log);
+                thread::spawn(move || (0..100).for_each(|n| log.write_line(&line(writer * 1000 + n)).unwrap()))
+            })
+            .collect();
+        writers.into_iter().for_each(|writer| writer.join().unwrap());
+
+        let mut lines: Vec<String> = (1..=100)
+            .map(|n| read(&numbered(&path, n)))
+            .chain([read(&path)])
+            .flat_map(|contents| contents.lines().map(str::to_string).collect::<Vec<_>>())
+            .collect();
+        lines.sort();
+        let mut expected: Vec<String> = (0..4).flat_map(|writer| (0..100).map(move |n| line(writer * 1000 + n))).collect();
+        expected.sort();
+        assert_eq!(lines, expected);
+    }
+
+    // A queue whose sink holds each line until `release` is dropped, then keeps what it was given.
+    fn held_queue(settings: LogQueueSettings) -> (LogQueue, mpsc::Sender<()>, Arc<Mutex<Vec<String>>>) {
+        let (release, held) = mpsc::channel::<()>();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sink_written = Arc::clone(&written);
+        let queue = LogQueue::spawn(settings, move |line| {
+            let _ = held.recv();
+            sink_written.lock().unwrap().push(line.to_string());
+        }).unwrap();
+        (queue, release, written)
+    }
+
+    #[test]
+    fn a_stalled_sink_costs_dropped_lines_not_waiting() {
+        let (queue, release, written) = held_queue(LogQueueSettings::new(2));
+
+        let started = Instant::now();
+        let accepted: Vec<bool> = (0..10).map(|n| queue.push(line(n))).collect();
+        assert!(started.elapsed() < Duration::from_secs(1), "pushing took {:?}", started.elapsed());
+        // the channel's two slots, and perhaps the line the writer already took out of it
+        let kept = accepted.iter().filter(|&&accepted| accepted).count();
+        assert!((2..=3).contains(&kept), "{accepted:?}");
+        assert!(accepted[..kept].iter().all(|&accepted| accepted), "{accepted:?}");
+
+        drop(release);
+        drop(queue);
+        assert_eq!(*written.lock().unwrap(), (0..kept).map(line).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn blocking_on_a_full_queue_loses_nothing() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sink_written = Arc::clone(&written);
+        let settings = LogQueueSettings { capacity: 1, overflow: Overflow::Block };
+        let queue = LogQueue::spawn(settings, move |line| {
+            thread::sleep(Duration::from_millis(1));
+            sink_written.lock().unwrap().push(line.to_string());
+        }).unwrap();
+
+        assert!((0..20).all(|n| queue.push(line(n))));
+        drop(queue);
+        assert_eq!(*written.lock().unwrap(), (0..20).map(line).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn lines_still_queued_are_written_before_the_queue_is_gone() {
+        let (queue, release, written) = held_queue(LogQueueSettings::new(8));
+        for n in 0..5 {
+            assert!(queue.push(line(n)));
+        }
+        assert!(written.lock().unwrap().is_empty());
+
+        drop(release);
+        drop(queue);
+        assert_eq!(written.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn a_dead_writer_makes_pushes_fail_instead_of_hang() {
+        let queue = LogQueue::spawn(LogQueueSettings::new(1), |_| panic!("the sink broke")).unwrap();
+        assert!(queue.push(line(0)));
+        // once the writer has died, the channel is closed and every push reports it
+        let started = Instant::now();
+        while queue.push(line(1)) {
+            assert!(started.elapsed() < Duration::from_secs(5), "the writer never died");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::fmt::{Display, Formatter, Write};
+use crate::{http_date, log::AccessRecord};
+
+/// Apache's Common Log Format.
+pub const COMMON: &str = "%h - - %t \"%r\" %s %b";
+
+/// An access log line format, compiled once into segments so each line is only a walk over
+/// them. The directives follow Apache's where there is one:
+///
+/// ```text
+/// %h        client address, - if unknown
+/// %t        when the request arrived, [10/Oct/2000:13:55:36 +0000]
+/// %r        request line, GET /books?page=2 HTTP/1.1
+/// %m        method
+/// %s        status
+/// %b        body bytes sent, - for none
+/// %B        body bytes sent, 0 for none
+/// %D        duration in milliseconds, to the microsecond (12.345)
+/// %L        the request id, as in X-Request-Id
+/// %{Name}i  a request header, - if absent
+/// %{Name}o  a response header, - if absent
+/// %%        a literal %
+/// ```
+///
+/// Everything a client controls (the request line and header values) is escaped: `"` and `\`
+/// get a backslash and control characters become `\xHH`, so a value can't end a quoted field
+/// early or forge a line of its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFormat {
+    source: String,
+    segments: Vec<Segment>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    RemoteAddr,
+    Time,
+    RequestLine,
+    Method,
+    Status,
+    BodyBytes,
+    BodyBytesOrZero,
+    Duration,
+    RequestId,
+    RequestHeader(String),
+    ResponseHeader(String)
+}
+
+/// A format string `LogFormat::parse` refused, with where in it the problem is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    format: String,
+    /// Byte offset of the offending `%` directive.
+    position: usize,
+    token: String
+}
+
+impl Display for FormatError {
+    // the format with a caret under the bad directive
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let column = self.format[..self.position].chars().count();
+        write!(
+            f,
+            "unknown log format directive {} at column {}:\n  {}\n  {}^",
+            self.token,
+            column + 1,
+            self.format,
+            " ".repeat(column)
+        )
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> Result<Self, FormatError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = format;
+
+        while let Some(percent) = rest.find('%') {
+            literal.push_str(&rest[..percent]);
+            let position = format.len() - rest.len() + percent;
+            let after = &rest[percent + 1..];
+            let error = |token: &str| FormatError { format: format.to_string(), position, token: token.to_string() };
+
+            // the directive, and how many bytes after the `%` it took up
+            let (directive, len) = match after.chars().next() {
+                Some('%') => {
+                    literal.push('%');
+                    rest = &after[1..];
+                    continue;
+                }
+                Some('{') => {
+                    let Some(close) = after.find('}') else {
+                        return Err(error(&rest[percent..]));
+                    };
+                    let name = &after[1..close];
+                    let kind = after[close + 1..].chars().next();
+                    let directive = match kind {
+                        Some('i') if !name.is_empty() => Segment::RequestHeader(name.to_string()),
+                        Some('o') if !name.is_empty() => Segment::ResponseHeader(name.to_string()),
+                        _ => return Err(error(&format!("%{{{name}}}{}", kind.map(String::from).unwrap_or_default())))
+                    };
+                    (directive, close + 2)
+                }
+                Some(c) => {
+                    let directive = match c {
+                        'h' => Segment::RemoteAddr,
+                        't' => Segment::Time,
+                        'r' => Segment::RequestLine,
+                        'm' => Segment::Method,
+                        's' => Segment::Status,
+                        'b' => Segment::BodyBytes,
+                        'B' => Segment::BodyBytesOrZero,
+                        'D' => Segment::Duration,
+                        'L' => Segment::RequestId,
+                        other => return Err(error(&format!("%{other}")))
+                    };
+                    (directive, c.len_utf8())
+                }
+                None => return Err(error("%"))
+            };
+
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(directive);
+            rest = &after[len..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { source: format.to_string(), segments })
+    }
+
+    /// The format string this was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Renders `record` as one log line, without the newline.
+    pub fn render(&self, record: &AccessRecord) -> String {
+        let mut line = String::with_capacity(128);
+
+        for segment in &self.segments {
+            // writing to a String can't fail
+            let _ = match segment {
+                Segment::Literal(text) => write!(line, "{text}"),
+                Segment::RemoteAddr => match record.peer {
+                    Some(peer) => write!(line, "{}", peer.ip()),
+                    None => write!(line, "-")
+                },
+                Segment::Time => write!(line, "[{}]", http_date::format_common_log(record.time)),
+                Segment::RequestLine => {
+                    escape_into(&mut line, &format!("{} {} {}", record.method, record.target, record.version));
+                    Ok(())
+                }
+                Segment::Method => {
+                    escape_into(&mut line, &record.method);
+                    Ok(())
+                }
+                Segment::Status => write!(line, "{}", record.status),
+                Segment::BodyBytes if record.body_bytes == 0 => write!(line, "-"),
+                Segment::BodyBytes | Segment::BodyBytesOrZero => write!(line, "{}", record.body_bytes),
+                Segment::Duration => write!(line, "{:.3}", record.duration.as_micros() as f64 / 1000.0),
+                Segment::RequestId => write!(line, "{}", record.request_id.as_deref().unwrap_or("-")),
+                Segment::RequestHeader(name) => {
+                    header_into(&mut line, &record.request_headers, name);
+                    Ok(())
+                }
+                Segment::ResponseHeader(name) => {
+                    header_into(&mut line, &record.response_headers, name);
+                    Ok(())
+                }
+            };
+        }
+
+        line
+    }
+
+    // The request headers this format prints, so only those are kept for the record.
+    pub(crate) fn request_headers(&self, headers: &[(String, String)]) -> Vec<(String, String)> {
+        self.capture(headers, |segment| match segment {
+            Segment::RequestHeader(name) => Some(name),
+            _ => None
+        })
+    }
+
+    // The same for response headers.
+    pub(crate) fn response_headers(&self, headers: &[(String, String)]) -> Vec<(String, String)> {
+        self.capture(headers, |segment| match segment {
+            Segment::ResponseHeader(name) => Some(name),
+            _ => None
+        })
+    }
+
+    fn capture(&self, headers: &[(String, String)], name: fn(&Segment) -> Option<&String>) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .filter(|(key, _)| self.segments.iter().filter_map(name).any(|name| key.eq_ignore_ascii_case(name)))
+            .cloned()
+            .collect()
+    }
+}
+
+// A repeated header is logged as its values joined by ", ", as it could have been sent.
+fn header_into(line: &mut String, headers: &[(String, String)], name: &str) {
+    let values: Vec<&str> = headers
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+        .collect();
+    if values.is_empty() {
+        line.push('-');
+    } else {
+        escape_into(line, &values.join(", "));
+    }
+}
+
+fn escape_into(line: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                line.push('\\');
+                line.push(c);
+            }
+            c if c.is_ascii_control() => {
+                let _ = write!(line, "\\x{:02x}", c as u32);
+            }
+            c => line.push(c)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn record() -> AccessRecord {
+        AccessRecord {
+            peer: Some("192.0.2.7:51234".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            method: "GET".to_string(),
+            method_override: None,
+            target: "/books?page=2".to_string(),
+            version: "HTTP/1.1",
+            status: 200,
+            body_bytes: 2326,
+            duration: Duration::from_micros(12_345),
+            route: None,
+            trace_id: None,
+            request_id: Some("req-1".to_string()),
+            queue_wait: Duration::ZERO,
+            aborted: false,
+            request_headers: vec![
+                ("User-Agent".to_string(), "curl/8.5".to_string()),
+                ("Via".to_string(), "1.1 a".to_string()),
+                ("via".to_string(), "1.1 b".to_string())
+            ],
+            response_headers: vec![("Content-Type".to_string(), "text/html".to_string())]
+        }
+    }
+
+    fn render(format: &str, record: &AccessRecord) -> String {
+        LogFormat::parse(format).unwrap().render(record)
+    }
+
+    #[test]
+    fn the_common_log_format_renders_as_apache_does() {
+        assert_eq!(
+            render(COMMON, &record()),
+            "192.0.2.7 - - [10/Oct/2000:13:55:36 +0000] \"GET /books?page=2 HTTP/1.1\" 200 2326"
+        );
+    }
+
+    #[test]
+    fn each_directive_renders_its_field() {
+        let empty = AccessRecord { peer: None, body_bytes: 0, request_id: None, ..record() };
+        let cases = [
+            ("%m %s", "GET 200", "GET 200"),
+            ("%b/%B", "2326/2326", "-/0"),
+            ("%D ms", "12.345 ms", "12.345 ms"),
+            ("%h %L", "192.0.2.7 req-1", "- -"),
+            ("%{user-agent}i %{Referer}i", "curl/8.5 -", "curl/8.5 -"),
+            ("%{Via}i", "1.1 a, 1.1 b", "1.1 a, 1.1 b"),
+            ("%{Content-Type}o", "text/html", "text/html"),
+            ("100%% of %s", "100% of 200", "100% of 200")
+        ];
+        for (format, full, sparse) in cases {
+            assert_eq!(render(format, &record()), full, "{format}");
+            assert_eq!(render(format, &empty), sparse, "{format} on an empty record");
+        }
+    }
+
+    #[test]
+    fn what_a_client_sends_is_escaped() {
+        let record = AccessRecord {
+            target: "/a\"b\\c\nforged line".to_string(),
+            request_headers: vec![("User-Agent".to_string(), "x\"\r\n".to_string())],
+            ..record()
+        };
+        assert_eq!(render("\"%r\" \"%{User-Agent}i\"", &record), "\"GET /a\\\"b\\\\c\\x0aforged line HTTP/1.1\" \"x\\\"\\x0d\\x0a\"");
+    }
+
+    #[test]
+    fn an_unknown_directive_is_pointed_at() {
+        let cases = [
+            ("%h %Q", 3, "%Q"),
+            ("%h %", 3, "%"),
+            ("%{Host}x", 0, "%{Host}x"),
+            ("%{}i", 0, "%{}i"),
+            ("é %{Host", 3, "%{Host")
+        ];
+        for (format, position, token) in cases {
+            let error = LogFormat::parse(format).unwrap_err();
+            assert_eq!((error.position, error.token.as_str()), (position, token), "{format}");
+        }
+
+        let message = LogFormat::parse("%h %Q").unwrap_err().to_string();
+        assert_eq!(message, "unknown log format directive %Q at column 4:\n  %h %Q\n     ^");
+    }
+
+    #[test]
+    fn only_the_headers_the_format_prints_are_kept() {
+        let format = LogFormat::parse("%{Via}i %{ETag}o").unwrap();
+        let record = record();
+        assert_eq!(format.request_headers(&record.request_headers), record.request_headers[1..]);
+        assert!(format.response_headers(&record.response_headers).is_empty());
+        assert_eq!(format.as_str(), "%{Via}i %{ETag}o");
+    }
+}
@@ -1,73 +1,180 @@
-use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
-    thread,
-    time::Duration
+use std::{fs, io, path::PathBuf, process, sync::Arc, thread, time::Duration};
+use book_web_server::{
+    args::{UsageError, USAGE},
+    embedded::{self, Asset},
+    negotiate,
+    Request, Response, Router, Server, ServerConfig, StaticFiles,
 };
-use book_web_server::ThreadPool;
 
 type Result = anyhow::Result<()>;
 
 fn main() -> Result {
-    let listener = TcpListener::bind("127.0.0.1:7878")?;
+    // --check is a mode of the binary rather than a server setting, so it never reaches the config
+    let (check, args): (Vec<String>, Vec<String>) = std::env::args().partition(|arg| arg == "--check");
+
+    let config = match ServerConfig::from_args(args.into_iter()) {
+        Ok(config) => config,
+        Err(UsageError::Help) => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    if !check.is_empty() {
+        match config.check() {
+            Ok(()) => println!("Configuration OK"),
+            Err(errors) => {
+                errors.iter().for_each(|error| eprintln!("{error}"));
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let pages = Arc::new(Pages { root: config.root.clone(), index_file: config.index_file.clone() });
+
+    let mut router = Router::new();
+    if config.root.is_none() {
+        // with a docroot, / is its index file like any other file; without one, the built-in page
+        router.get("/", |_| embedded::INDEX.response(200));
+    }
+    // If we make a request to /sleep, the server will be able to serve other requests by having another thread run them.
+    let p = Arc::clone(&pages);
+    // slow on purpose, so it reports where the time went
+    router.get("/sleep", move |request| sleep(request, &p)).server_timing();
+    if let Some(root) = config.root.clone() {
+        // anything the routes don't cover is looked up under the docroot
+        let files = StaticFiles::new(root).index_file(&config.index_file).stream_threshold(config.stream_threshold);
+        router.fallback(move |request| files.handle(request));
+    }
+
     /*
-        There are never more than four threads created, so our system won’t get overloaded if the
-        server receives a lot of requests. If we make a request to /sleep, the server will be able
-        to serve other requests by having another thread run them.
+        There are never more than `--threads` (four by default), or `--max-threads` if set, threads created, so our system won’t get overloaded if the
+        server receives a lot of requests.
      */
-    let thread_pool = ThreadPool::build(4)?;
+    let server = Server::bind(config, move |request| {
+        let response = router.handle(request);
+        pages.error_page(request, response)
+    })?;
 
     /*
-        Iterating over connection attempts. Many operating systems have a limit to the number of
-        simultaneous open connections they can support; new connection attempts beyond that number
-        will produce an error until some of the open connections are closed.
+        The server iterates over connection attempts. Many operating systems have a limit to the
+        number of simultaneous open connections they can support; new connection attempts beyond
+        that number will produce an error until some of the open connections are closed.
      */
-    for stream in listener.incoming() {
-        let stream = stream?;
-        thread_pool.execute(|| {
-            handle_connection(stream).unwrap();
-        });
+    server.run()
+}
+
+// Longest /sleep?ms= will hold a worker, so a client can't tie up the pool for as long as it likes.
+const MAX_SLEEP_MILLIS: u64 = 10_000;
+
+fn sleep(request: &mut Request, pages: &Pages) -> Response {
+    // /sleep?ms=100 lets us pick the delay; without it we keep the original five seconds
+    let millis = request.query_param("ms").and_then(|ms| ms.parse().ok()).unwrap_or(5000).min(MAX_SLEEP_MILLIS);
+    thread::sleep(Duration::from_millis(millis));
+
+    pages.page(200, &pages.index_file, embedded::INDEX)
+}
+
+// The site's pages: a file of the same name in the docroot if there is one, else the built-in page.
+struct Pages {
+    root: Option<PathBuf>,
+    index_file: String
+}
+
+impl Pages {
+    fn page(&self, status: u16, file: &str, builtin: Asset) -> Response {
+        let Some(root) = &self.root else {
+            return builtin.response(status);
+        };
+
+        match fs::read(root.join(file)) {
+            Ok(contents) => Response::html(status, contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => builtin.response(status),
+            Err(e) => {
+                eprintln!("Failed to read {file}: {e}");
+                builtin.response(status)
+            }
+        }
     }
+
     /*
-        When stream goes out of scope and is dropped at the end of the loop,
-        the connection is closed as part of the drop implementation.
+        Dresses a bare 404 or 500 (status_only, from the router or a handler) in an HTML page.
+        A client that would rather have JSON gets it bare, for the server to answer in JSON.
      */
+    fn error_page(&self, request: &Request, response: Response) -> Response {
+        let builtin = match embedded::error_page(response.status()) {
+            Some(builtin) if response.header("Content-Type").is_none() && !negotiate::prefers_json(request) => builtin,
+            _ => return response
+        };
 
-    Ok(())
+        let mut page = self.page(response.status(), &format!("{}.html", response.status()), builtin);
+        page.inherit_headers(&response);
+        page.vary("Accept");
+        page
+    }
 }
 
-fn handle_connection(mut stream: TcpStream) -> Result {
-    let reader = BufReader::new(&mut stream);
-    // first line is always of the form: "GET / HTTP/1.1"
-    let request_line = reader.lines().next().unwrap()?;
+#[cfg(test)]
+#[path = "temp_dir.rs"]
+mod temp_dir;
 
-    /*
-        We need to explicitly match on a slice of request_line to pattern match against the string
-        literal values; match doesn’t do automatic referencing and dereferencing like the equality method does.
-     */
-    let (status, file) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
-        },
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html")
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    fn request(headers: &str) -> Request {
+        let raw = format!("GET /missing HTTP/1.1\r\nHost: test\r\n{headers}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
 
-    let contents = fs::read_to_string(file)?;
-    let length = contents.len(); // ensures a valid HTTP response
-    let response = format!("{status}\r\nContent-Length: {length}\r\n\r\n{contents}");
+    fn pages(root: Option<&TempDir>) -> Pages {
+        Pages { root: root.map(|root| root.path().to_path_buf()), index_file: String::from("index.html") }
+    }
+
+    #[test]
+    fn without_a_docroot_the_built_in_pages_are_served() {
+        let response = pages(None).page(200, "index.html", embedded::INDEX);
+        assert_eq!(response.body(), embedded::INDEX.body);
 
-    stream.write_all(response.as_bytes())?;
+        let response = pages(None).error_page(&request(""), Response::status_only(500));
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.body(), embedded::INTERNAL_ERROR.body);
+    }
 
-    Ok(())
+    #[test]
+    fn a_docroot_page_wins_over_the_built_in_one() {
+        let root = TempDir::new();
+        root.write("404.html", "<h1>our own 404</h1>");
+        let pages = pages(Some(&root));
 
-    /*
-    let http_request: Vec<_> = reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
-    */
+        let response = pages.error_page(&request(""), Response::status_only(404));
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.body(), b"<h1>our own 404</h1>");
+        // nothing in the docroot for these
+        assert_eq!(pages.error_page(&request(""), Response::status_only(500)).body(), embedded::INTERNAL_ERROR.body);
+        assert_eq!(pages.page(200, "index.html", embedded::INDEX).body(), embedded::INDEX.body);
+    }
+
+    #[test]
+    fn only_bare_error_responses_for_html_clients_are_dressed() {
+        let cases = [
+            ("", Response::status_only(404), true),
+            ("", Response::status_only(403), false),
+            ("", Response::html(404, "handler's own page"), false),
+            ("Accept: application/json\r\n", Response::status_only(404), false)
+        ];
+
+        for (headers, response, dressed) in cases {
+            let status = response.status();
+            let response = pages(None).error_page(&request(headers), response);
+            assert_eq!(response.status(), status);
+            assert_eq!(response.body() == embedded::NOT_FOUND.body, dressed, "{status} for {headers:?}");
+        }
+    }
 }
@@ -0,0 +1,94 @@
+use crate::Request;
+
+/*
+    Some corporate proxies and HTML forms only get GET and POST through, so clients tunnel the
+    method they mean in `X-HTTP-Method-Override`. Only a POST may be overridden: a GET is safe
+    and cacheable, and letting a header turn it into a DELETE would make any link a weapon. The
+    target method must be in `allowed`, which keeps things like CONNECT or TRACE out.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodOverride {
+    allowed: Vec<String>
+}
+
+impl Default for MethodOverride {
+    fn default() -> Self {
+        Self::new(["PUT", "PATCH", "DELETE"])
+    }
+}
+
+impl MethodOverride {
+    /// Honors the header for the given target methods only.
+    pub fn new<I, S>(allowed: I) -> Self
+    where I: IntoIterator<Item = S>, S: AsRef<str>
+    {
+        Self { allowed: allowed.into_iter().map(|method| method.as_ref().to_ascii_uppercase()).collect() }
+    }
+
+    pub fn allowed(&self) -> &[String] {
+        &self.allowed
+    }
+
+    // Rewrites the method of a POST that asks for an allowed one; anything else is left as it came.
+    pub(crate) fn apply(&self, request: &mut Request) {
+        if request.method() != "POST" {
+            return;
+        }
+
+        let target = request
+            .header("X-HTTP-Method-Override")
+            .map(str::trim)
+            .and_then(|wanted| self.allowed.iter().find(|allowed| allowed.eq_ignore_ascii_case(wanted)))
+            .cloned();
+        if let Some(target) = target {
+            request.override_method(target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The method after the override, and the one on the request line if it was replaced.
+    fn overridden(method_override: &MethodOverride, method: &str, wanted: Option<&str>) -> (String, Option<String>) {
+        let header = wanted.map(|wanted| format!("X-HTTP-Method-Override: {wanted}\r\n")).unwrap_or_default();
+        let raw = format!("{method} /books/1 HTTP/1.1\r\nHost: test\r\n{header}\r\n");
+        let mut request = Request::parse(&mut raw.as_bytes()).unwrap().unwrap();
+
+        method_override.apply(&mut request);
+        (request.method().to_string(), request.original_method().map(str::to_string))
+    }
+
+    #[test]
+    fn a_post_can_ask_for_an_allowed_method() {
+        let method_override = MethodOverride::default();
+        let cases = [
+            ("POST", Some("DELETE"), "DELETE"),
+            ("POST", Some(" patch "), "PATCH"),
+            ("POST", Some("put"), "PUT"),
+            ("POST", Some("TRACE"), "POST"),
+            ("POST", Some("CONNECT"), "POST"),
+            ("POST", Some(""), "POST"),
+            ("POST", None, "POST"),
+            ("GET", Some("DELETE"), "GET"),
+            ("PUT", Some("DELETE"), "PUT")
+        ];
+
+        for (method, wanted, expected) in cases {
+            let (method_now, original) = overridden(&method_override, method, wanted);
+            assert_eq!(method_now, expected, "{method} asking for {wanted:?}");
+            let replaced = method_now != method;
+            assert_eq!(original.as_deref(), replaced.then_some(method), "{method} asking for {wanted:?}");
+        }
+    }
+
+    #[test]
+    fn the_allowed_methods_are_configurable() {
+        let method_override = MethodOverride::new(["delete"]);
+        assert_eq!(method_override.allowed(), ["DELETE"]);
+
+        assert_eq!(overridden(&method_override, "POST", Some("DELETE")).0, "DELETE");
+        assert_eq!(overridden(&method_override, "POST", Some("PUT")).0, "POST");
+    }
+}
@@ -0,0 +1,261 @@
+use std::path::Path;
+
+// How much of a file's head `sniff` looks at.
+pub const SNIFF_LENGTH: usize = 512;
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+pub const TEXT_PLAIN: &str = "text/plain; charset=utf-8";
+
+/// Maps a file extension (without the dot, any case) to its media type.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    let mime = match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "application/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp4" => "video/mp4",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        _ => return None
+    };
+
+    Some(mime)
+}
+
+/// Whether `content_type` is text that a charset parameter applies to.
+pub fn is_textual(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    media_type.starts_with("text/")
+        || matches!(media_type.as_str(), "application/json" | "application/javascript" | "application/xml" | "image/svg+xml")
+}
+
+/// `content_type` with `; charset=<charset>` appended if it is textual and doesn't name a charset yet.
+pub fn with_charset(content_type: &str, charset: &str) -> Option<String> {
+    let has_charset = content_type
+        .split(';')
+        .skip(1)
+        .any(|parameter| parameter.trim().to_ascii_lowercase().starts_with("charset="));
+
+    (is_textual(content_type) && !has_charset).then(|| format!("{content_type}; charset={charset}"))
+}
+
+/*
+    A parsed `Content-Type` value: the `type/subtype` essence, lowercased, and its parameters
+    with lowercased names. Quoted parameter values are unquoted; other values are kept as sent,
+    since some (a multipart boundary) are case-sensitive.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    essence: String,
+    params: Vec<(String, String)>
+}
+
+impl MediaType {
+    /// Parses `text/html; charset=utf-8`. None unless there is a non-empty type and subtype.
+    pub fn parse(value: &str) -> Option<MediaType> {
+        let mut parts = value.split(';');
+        let essence = parts.next()?.trim().to_ascii_lowercase();
+        let (type_, subtype) = essence.split_once('/')?;
+        if !is_token(type_) || !is_token(subtype) {
+            return None;
+        }
+
+        let params = parts
+            .filter(|parameter| !parameter.trim().is_empty())
+            .map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+                let name = name.trim();
+                if !is_token(name) {
+                    return None;
+                }
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|quoted| quoted.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.to_ascii_lowercase(), value.to_string()))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(MediaType { essence, params })
+    }
+
+    /// `type/subtype`, lowercased, e.g. `application/json`.
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    pub fn type_(&self) -> &str {
+        self.essence.split_once('/').map_or("", |(type_, _)| type_)
+    }
+
+    pub fn subtype(&self) -> &str {
+        self.essence.split_once('/').map_or("", |(_, subtype)| subtype)
+    }
+
+    /// The value of the parameter `name`, compared case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+}
+
+// An RFC 9110 token: the characters allowed in media types and parameter names.
+fn is_token(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| {
+        byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+    })
+}
+
+/// Picks a media type for `path`, falling back to sniffing `head` (the first bytes of the
+/// file) when the extension is missing or unknown.
+pub fn for_path(path: &Path, head: &[u8]) -> &'static str {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(from_extension)
+        .unwrap_or_else(|| sniff(head))
+}
+
+/*
+    Content sniffing is deliberately conservative: it only recognises a handful of unambiguous
+    magic numbers and otherwise decides between "text" and "binary". Guessing anything richer
+    (HTML, scripts) from content is how browsers ended up executing uploads, so we don't.
+ */
+pub fn sniff(head: &[u8]) -> &'static str {
+    let head = &head[..head.len().min(SNIFF_LENGTH)];
+
+    const SIGNATURES: [(&[u8], &str); 6] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1F\x8B", "application/gzip")
+    ];
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return mime;
+    }
+
+    if looks_like_text(head) { TEXT_PLAIN } else { OCTET_STREAM }
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    let valid_utf8 = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // the sample may have cut a multi-byte character in half; that alone isn't binary
+        Err(e) => e.error_len().is_none()
+    };
+
+    // control characters other than ordinary whitespace (and ESC, for ANSI colours) mean binary
+    valid_utf8 && !head.iter().any(|&byte| {
+        byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffing_knows_a_few_magic_numbers_and_text() {
+        let cases: [(&[u8], &str); 10] = [
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "image/png"),
+            (b"\xFF\xD8\xFF\xE0", "image/jpeg"),
+            (b"GIF89a\x01\0", "image/gif"),
+            (b"%PDF-1.7\n", "application/pdf"),
+            (b"\x1F\x8B\x08\0", "application/gzip"),
+            (b"plain words\r\n\tand a tab", TEXT_PLAIN),
+            (b"\x1b[31mred\x1b[0m", TEXT_PLAIN),
+            (b"", TEXT_PLAIN),
+            (b"ELF\0\x01\x02", OCTET_STREAM),
+            (b"\xC3\x28 not utf-8", OCTET_STREAM)
+        ];
+
+        for (head, expected) in cases {
+            assert_eq!(sniff(head), expected, "{}", head.escape_ascii());
+        }
+    }
+
+    #[test]
+    fn markup_is_never_sniffed_as_html() {
+        assert_eq!(sniff(b"<!DOCTYPE html><script>alert(1)</script>"), TEXT_PLAIN);
+    }
+
+    #[test]
+    fn a_character_cut_off_at_the_end_of_the_sample_is_still_text() {
+        let mut head = "é".repeat(SNIFF_LENGTH / 2).into_bytes();
+        head.push(0xC3);
+        assert_eq!(sniff(&head), TEXT_PLAIN);
+        // only the first SNIFF_LENGTH bytes count
+        head.extend_from_slice(&[0; 16]);
+        assert_eq!(sniff(&head), TEXT_PLAIN);
+    }
+
+    #[test]
+    fn a_known_extension_wins_over_the_contents() {
+        assert_eq!(for_path(Path::new("page.HTML"), b"\x89PNG\r\n\x1a\n"), "text/html");
+        assert_eq!(for_path(Path::new("photo.png"), b"not a png"), "image/png");
+        assert_eq!(for_path(Path::new("README"), b"# Title"), TEXT_PLAIN);
+        assert_eq!(for_path(Path::new("data.unknown"), b"\x89PNG\r\n\x1a\n"), "image/png");
+    }
+
+    #[test]
+    fn text_and_structured_text_are_textual() {
+        let cases = [
+            ("text/html", true),
+            ("Text/CSS; charset=latin1", true),
+            ("application/json", true),
+            ("application/javascript", true),
+            ("application/xml", true),
+            ("image/svg+xml", true),
+            ("application/json-seq", false),
+            ("image/png", false),
+            ("application/octet-stream", false),
+            ("", false)
+        ];
+
+        for (content_type, expected) in cases {
+            assert_eq!(is_textual(content_type), expected, "{content_type:?}");
+        }
+    }
+
+    #[test]
+    fn a_charset_is_added_only_where_one_is_missing() {
+        let cases = [
+            ("text/html", Some("text/html; charset=utf-8")),
+            ("application/json", Some("application/json; charset=utf-8")),
+            ("text/plain; format=flowed", Some("text/plain; format=flowed; charset=utf-8")),
+            ("text/html; charset=iso-8859-1", None),
+            ("text/html;CHARSET=\"utf-16\"", None),
+            ("image/png", None)
+        ];
+
+        for (content_type, expected) in cases {
+            assert_eq!(with_charset(content_type, "utf-8").as_deref(), expected, "{content_type:?}");
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{json::Value, response::reason_phrase, Request, Response};
+
+// Statuses whose bare, server-generated bodies are worth dressing for the client.
+const NEGOTIATED_ERRORS: [u16; 7] = [400, 404, 405, 413, 500, 501, 503];
+
+/*
+    Parses a list like `gzip;q=0.8, br, *;q=0` into (token, q) pairs. A missing q means 1.0 and an
+    unparsable one is treated as 0, which errs on the side of not using what the client may not
+    understand. Parameters other than q, like a media type's `charset=`, are skipped, so the
+    same parser serves Accept, Accept-Encoding and Accept-Language.
+ */
+pub(crate) fn quality_values(header: &str) -> Vec<(&str, f32)> {
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let token = parts.next().filter(|token| !token.is_empty())?;
+            let q = parts
+                .find_map(|param| param.strip_prefix("q=").or_else(|| param.strip_prefix("Q=")))
+                .map_or(1.0, |q| q.parse::<f32>().unwrap_or(0.0).clamp(0.0, 1.0));
+            Some((token, q))
+        })
+        .collect()
+}
+
+/*
+    How much the `Accept` header wants `media_type` (`type/subtype`, without parameters). The most
+    specific matching range decides (`text/html` over `text/*` over `*/*`), as RFC 9110 section
+    12.5.1 says; a type no range matches is not acceptable. Without an Accept header, anything goes.
+ */
+pub fn media_type_quality(request: &Request, media_type: &str) -> f32 {
+    let Some(accept) = request.header("Accept") else {
+        return 1.0;
+    };
+    let Some((kind, _)) = media_type.split_once('/') else {
+        return 0.0;
+    };
+
+    quality_values(accept)
+        .into_iter()
+        .filter_map(|(range, q)| {
+            let specificity = match range.split_once('/')? {
+                ("*", "*") => 0,
+                (range_kind, "*") if range_kind.eq_ignore_ascii_case(kind) => 1,
+                _ if range.eq_ignore_ascii_case(media_type) => 2,
+                _ => return None
+            };
+            Some((specificity, q))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, q)| q)
+}
+
+/// Whether the client would rather have JSON than HTML. On a tie (a browser's `*/*`, or no
+/// Accept at all) HTML wins.
+pub fn prefers_json(request: &Request) -> bool {
+    media_type_quality(request, "application/json") > media_type_quality(request, "text/html")
+}
+
+/*
+    Gives a bare error response (one from `Response::status_only`, with no Content-Type of its own)
+    a small JSON body when the client prefers JSON, e.g.
+    `{"status":404,"error":"Not Found","request_id":"7"}`; a client that prefers HTML gets the
+    response as it was, for the application to dress as a page. Either way the answer now depends
+    on Accept, so the response says so in Vary. Errors with a body of their own are left alone.
+ */
+pub fn error_response(request: &Request, response: Response) -> Response {
+    let status = response.status();
+    if !NEGOTIATED_ERRORS.contains(&status) || response.header("Content-Type").is_some() {
+        return response;
+    }
+
+    let mut response = if prefers_json(request) {
+        let body = Value::Object(vec![
+            ("status".to_string(), u64::from(status).into()),
+            ("error".to_string(), reason_phrase(status).into()),
+            ("request_id".to_string(), request.id().into())
+        ]);
+        let mut json = Response::json(status, &body);
+        json.inherit_headers(&response);
+        json
+    } else {
+        response
+    };
+    response.vary("Accept");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Version;
+
+    fn request(accept: Option<&str>) -> Request {
+        let header = accept.map(|accept| format!("Accept: {accept}\r\n")).unwrap_or_default();
+        let raw = format!("GET / HTTP/1.1\r\nHost: test\r\n{header}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn quality_values_default_to_one_and_skip_other_parameters() {
+        let parsed = quality_values("gzip;q=0.8, br , text/html;charset=utf-8;Q=0.5, x;q=2, y;q=bad, , *;q=0");
+
+        assert_eq!(parsed, [("gzip", 0.8), ("br", 1.0), ("text/html", 0.5), ("x", 1.0), ("y", 0.0), ("*", 0.0)]);
+    }
+
+    #[test]
+    fn the_most_specific_range_decides() {
+        let cases = [
+            (None, "application/json", 1.0),
+            (Some("text/html"), "application/json", 0.0),
+            (Some("*/*;q=0.1, application/*;q=0.5, application/json"), "application/json", 1.0),
+            (Some("*/*;q=0.1, application/*;q=0.5"), "application/xml", 0.5),
+            (Some("*/*;q=0.1, application/*;q=0.5"), "text/plain", 0.1),
+            (Some("application/json;q=0, */*"), "application/json", 0.0),
+            (Some("Application/JSON"), "application/json", 1.0),
+            (Some("*/*"), "not-a-media-type", 0.0)
+        ];
+
+        for (accept, media_type, expected) in cases {
+            assert_eq!(media_type_quality(&request(accept), media_type), expected, "{media_type} for {accept:?}");
+        }
+    }
+
+    #[test]
+    fn html_wins_ties() {
+        let cases = [
+            (None, false),
+            (Some("*/*"), false),
+            (Some("text/html,application/xhtml+xml,*/*;q=0.8"), false),
+            (Some("application/json"), true),
+            (Some("application/json, text/html;q=0.9"), true),
+            (Some("application/json;q=0.5, text/html;q=0.5"), false)
+        ];
+
+        for (accept, expected) in cases {
+            assert_eq!(prefers_json(&request(accept)), expected, "{accept:?}");
+        }
+    }
+
+    #[test]
+    fn a_bare_error_is_dressed_in_json_for_a_client_that_prefers_it() {
+        let mut request = request(Some("application/json"));
+        request.set_id(String::from("7"));
+        let response = error_response(&request, Response::status_only(405).with_header("Allow", "GET"));
+
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.header("Allow"), Some("GET"));
+        assert!(response.header("Content-Type").unwrap().starts_with("application/json"));
+        assert_eq!(response.body(), br#"{"status":405,"error":"Method Not Allowed","request_id":"7"}"#);
+    }
+
+    #[test]
+    fn other_responses_are_left_as_they_are() {
+        let cases = [
+            ("html client", request(None), Response::status_only(404)),
+            ("success", request(Some("application/json")), Response::status_only(200)),
+            ("not negotiated", request(Some("application/json")), Response::status_only(418)),
+            ("own body", request(Some("application/json")), Response::html(404, "custom"))
+        ];
+
+        for (name, request, response) in cases {
+            let before = response.body().to_vec();
+            let response = error_response(&request, response);
+            assert_eq!(response.body(), before, "{name}");
+        }
+    }
+
+    #[test]
+    fn a_negotiated_error_varies_by_accept_either_way() {
+        for accept in [None, Some("application/json")] {
+            let mut response = error_response(&request(accept), Response::status_only(404));
+            let mut sent = Vec::new();
+            response.write_to(&mut sent, Version::Http11, false).unwrap();
+            assert!(String::from_utf8_lossy(&sent).contains("\r\nVary: Accept\r\n"), "{accept:?}");
+        }
+    }
+
+    #[test]
+    fn a_json_error_keeps_every_line_of_a_repeated_header() {
+        let mut bare = Response::status_only(400);
+        bare.add_header("Set-Cookie", "a=1");
+        bare.add_header("Set-Cookie", "b=2");
+
+        let response = error_response(&request(Some("application/json")), bare);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(response.header_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+    }
+}
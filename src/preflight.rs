@@ -0,0 +1,173 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs::{self, File, OpenOptions},
+    io,
+    net::ToSocketAddrs,
+    path::PathBuf,
+    thread,
+};
+use crate::{favicon::Favicon, ServerConfig};
+
+/// A problem with a `ServerConfig` found before the server starts taking traffic.
+#[derive(Debug)]
+pub enum PreflightError {
+    /// `addr` isn't a host and port we can listen on.
+    Address { addr: String, error: io::Error },
+    /// The pool was configured with zero workers.
+    NoWorkers,
+    /// `max_workers` is below `workers`, so the pool could never grow.
+    MaxWorkers { workers: usize, max: usize },
+    /// Zero acceptor threads would never take a connection.
+    NoAcceptors,
+    /// The OS refused to start the pool's threads.
+    Threads { spawned: usize, wanted: usize, error: io::Error },
+    /// The docroot is missing, not a directory, or can't be listed.
+    Docroot { path: PathBuf, error: io::Error },
+    /// A file the server reads or writes (favicon, page, log) can't be opened.
+    File { path: PathBuf, error: io::Error }
+}
+
+impl Display for PreflightError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreflightError::Address { addr, error } => write!(f, "can't listen on {addr}: {error}"),
+            PreflightError::NoWorkers => write!(f, "the worker pool needs at least one thread"),
+            PreflightError::MaxWorkers { workers, max } => {
+                write!(f, "the worker pool can't start with {workers} threads and grow to only {max}")
+            }
+            PreflightError::NoAcceptors => write!(f, "at least one thread has to accept connections"),
+            PreflightError::Threads { spawned, wanted, error } => {
+                write!(f, "could only start {spawned} of {wanted} worker threads: {error}")
+            }
+            PreflightError::Docroot { path, error } => write!(f, "docroot {}: {error}", path.display()),
+            PreflightError::File { path, error } => write!(f, "{}: {error}", path.display())
+        }
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+/// Every problem `ServerConfig::check` found, for returning them all as one error.
+#[derive(Debug)]
+pub struct PreflightErrors(pub Vec<PreflightError>);
+
+impl Display for PreflightErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} configuration problem(s):", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PreflightErrors {}
+
+impl ServerConfig {
+    /*
+        Catches misconfiguration at startup rather than on the first request that trips over it,
+        and reports everything wrong at once so fixing a config isn't one restart per mistake.
+        It doesn't bind the address: that happens for real moments later, and binding twice
+        would race with anything else grabbing the port in between.
+     */
+    pub fn check(&self) -> Result<(), Vec<PreflightError>> {
+        let mut errors = Vec::new();
+
+        match self.addr.to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(_)) => {}
+            Ok(None) => errors.push(PreflightError::Address {
+                addr: self.addr.clone(),
+                error: io::Error::new(io::ErrorKind::NotFound, "resolves to no address")
+            }),
+            Err(error) => errors.push(PreflightError::Address { addr: self.addr.clone(), error })
+        }
+
+        // a growing pool may want its ceiling's worth of threads eventually
+        let workers = self.max_workers.unwrap_or(self.workers).max(self.workers);
+        if self.workers == 0 {
+            errors.push(PreflightError::NoWorkers);
+        } else if let Err(error) = check_threads(workers + self.acceptor_threads.saturating_sub(1)) {
+            errors.push(error);
+        }
+        if let Some(max) = self.max_workers.filter(|&max| max < self.workers) {
+            errors.push(PreflightError::MaxWorkers { workers: self.workers, max });
+        }
+        if self.acceptor_threads == 0 {
+            errors.push(PreflightError::NoAcceptors);
+        }
+
+        if let Some(root) = &self.root {
+            let listed = fs::metadata(root).and_then(|metadata| {
+                if !metadata.is_dir() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a directory"));
+                }
+                fs::read_dir(root).map(drop)
+            });
+            if let Err(error) = listed {
+                errors.push(PreflightError::Docroot { path: root.clone(), error });
+            }
+        }
+
+        let favicon = match &self.favicon {
+            Favicon::File(path) => Some(path),
+            Favicon::Embedded | Favicon::Disabled => None
+        };
+        for path in favicon.into_iter().chain(&self.required_files).chain(self.error_pages.paths()) {
+            if let Err(error) = File::open(path) {
+                errors.push(PreflightError::File { path: path.clone(), error });
+            }
+        }
+
+        if let Some(log) = &self.access_log {
+            let opened = OpenOptions::new().create(true).append(true).open(&log.path);
+            if let Err(error) = opened {
+                errors.push(PreflightError::File { path: log.path.clone(), error });
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+/*
+    ThreadPool uses thread::spawn, which panics when the OS is out of threads, so we find out
+    here instead by starting as many short-lived threads as the pool will want.
+ */
+fn check_threads(wanted: usize) -> Result<(), PreflightError> {
+    let mut threads = Vec::with_capacity(wanted);
+    let mut failure = None;
+
+    for _ in 0..wanted {
+        match thread::Builder::new().spawn(|| ()) {
+            Ok(thread) => threads.push(thread),
+            Err(error) => {
+                failure = Some(error);
+                break;
+            }
+        }
+    }
+
+    let spawned = threads.len();
+    threads.into_iter().for_each(|thread| thread.join().unwrap());
+
+    match failure {
+        Some(error) => Err(PreflightError::Threads { spawned, wanted, error }),
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_thread_count_problem_is_reported_at_once() {
+        let config = ServerConfig { workers: 0, acceptor_threads: 0, ..ServerConfig::default() };
+        let errors = config.check().unwrap_err();
+        assert!(matches!(errors.as_slice(), [PreflightError::NoWorkers, PreflightError::NoAcceptors]), "{errors:?}");
+        assert_eq!(errors[1].to_string(), "at least one thread has to accept connections");
+
+        let config = ServerConfig { acceptor_threads: 4, ..ServerConfig::default() };
+        assert!(config.check().is_ok());
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+use crate::{
+    client::{self, ClientError},
+    request::split_absolute,
+    Request, Response,
+};
+
+// Largest upstream response body we buffer before giving up with 502.
+const MAX_RESPONSE_LENGTH: u64 = 16 * 1024 * 1024;
+
+// Connection-level headers that describe one hop and must not be passed on (RFC 9110 section 7.6.1).
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade"
+];
+
+/*
+    A reverse proxy handler: forwards each request to one upstream server and relays its answer.
+    Every request gets a fresh upstream connection, closed after the response, which keeps the
+    exchange simple at the cost of a connect per request.
+
+    The timeouts bound how long a worker can be held by a bad upstream. An upstream that can't
+    be reached is a 502; one that accepted the connection but is too slow is a 504. The upstream
+    response is buffered whole before anything is sent on, so the client has always received
+    nothing yet when a timeout hits, and can be given a clean 504.
+ */
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    upstream: String,
+    connect_timeout: Duration,
+    read_timeout: Duration
+}
+
+impl Proxy {
+    /// A proxy to `upstream`, a `host:port` address.
+    pub fn new(upstream: &str) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(30)
+        }
+    }
+
+    /// How long to wait for the upstream to accept a connection. Past it, the client gets 504.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long any single read from (or write to) the upstream may block. Past it, the client gets 504.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Forwards `request` upstream and returns its response, or 502/504 if that failed.
+    pub fn handle(&self, request: &Request) -> Response {
+        match self.forward(request) {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Proxying {} {} to {} failed: {e}", request.method(), request.path(), self.upstream);
+                Response::status_only(e.status())
+            }
+        }
+    }
+
+    fn forward(&self, request: &Request) -> Result<Response, UpstreamError> {
+        let stream = self.connect()?;
+        stream.set_read_timeout(Some(self.read_timeout)).map_err(UpstreamError::from_io)?;
+        stream.set_write_timeout(Some(self.read_timeout)).map_err(UpstreamError::from_io)?;
+
+        let mut writer = &stream;
+        writer.write_all(&upstream_request(request)).map_err(UpstreamError::from_io)?;
+        writer.flush().map_err(UpstreamError::from_io)?;
+
+        read_response(&mut BufReader::new(&stream), request.method() == "HEAD")
+    }
+
+    // Tries each address the upstream resolves to, like TcpStream::connect, but with a timeout.
+    fn connect(&self) -> Result<TcpStream, UpstreamError> {
+        let addrs = self.upstream.to_socket_addrs().map_err(UpstreamError::Connect)?;
+
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "upstream resolves to no address");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e
+            }
+        }
+
+        Err(match last_error.kind() {
+            io::ErrorKind::TimedOut => UpstreamError::Timeout,
+            _ => UpstreamError::Connect(last_error)
+        })
+    }
+}
+
+// The request as sent upstream: same method, target and end-to-end headers, on a one-shot connection.
+fn upstream_request(request: &Request) -> Vec<u8> {
+    let target = split_absolute(request.target()).map_or(request.target(), |(_, rest)| rest);
+    let target = if target.is_empty() { "/" } else { target };
+
+    let mut head = format!("{} {target} HTTP/1.1\r\n", request.method());
+    for (name, value) in request.headers() {
+        let replaced = name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("traceparent")
+            || (name.eq_ignore_ascii_case("X-Request-Id") && request.id().is_some());
+        if !replaced && !is_hop_by_hop(name, request.headers()) {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    if let Some(id) = request.id() {
+        // so the upstream's logs can be matched up with ours
+        head.push_str(&format!("X-Request-Id: {id}\r\n"));
+    }
+    if let Some(trace) = request.trace_context() {
+        // we are a hop in the caller's trace; the upstream's parent is our span, not theirs
+        head.push_str(&format!("traceparent: {}\r\n", trace.child()));
+    }
+
+    let body = request.body();
+    if !body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/*
+    The upstream's response, ready to relay: what `client::read_response` read, minus the
+    hop-by-hop headers, and minus Content-Length, since our serializer frames the body itself.
+ */
+fn read_response<R: BufRead>(reader: &mut R, head_only: bool) -> Result<Response, UpstreamError> {
+    let (upstream, _) = client::read_response(reader, head_only, MAX_RESPONSE_LENGTH).map_err(|e| match e {
+        ClientError::Io(e) => UpstreamError::from_io(e),
+        ClientError::Closed => UpstreamError::Protocol(String::from("empty response")),
+        ClientError::Malformed(what) => UpstreamError::Protocol(what)
+    })?;
+
+    let mut response = Response::new(upstream.status()).with_body(upstream.body().to_vec());
+    for (name, value) in upstream.headers() {
+        if !is_hop_by_hop(name, upstream.headers()) && !name.eq_ignore_ascii_case("Content-Length") {
+            response.add_header(name, value);
+        }
+    }
+    Ok(response)
+}
+
+// Hop-by-hop headers are the fixed set plus whatever the Connection header names.
+fn is_hop_by_hop(name: &str, headers: &[(String, String)]) -> bool {
+    HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name))
+        || headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Connection"))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(name))
+}
+
+/// Why a request couldn't be proxied.
+#[derive(Debug)]
+enum UpstreamError {
+    // refused, unreachable or unresolvable
+    Connect(io::Error),
+    // connecting, sending or receiving took longer than allowed
+    Timeout,
+    Io(io::Error),
+    // the upstream's response wasn't HTTP we can relay
+    Protocol(String)
+}
+
+impl UpstreamError {
+    fn from_io(error: io::Error) -> Self {
+        // a read timeout shows up as WouldBlock on Unix and TimedOut on Windows
+        match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => UpstreamError::Timeout,
+            _ => UpstreamError::Io(error)
+        }
+    }
+
+    fn status(&self) -> u16 {
+        match self {
+            UpstreamError::Timeout => 504,
+            UpstreamError::Connect(_) | UpstreamError::Io(_) | UpstreamError::Protocol(_) => 502
+        }
+    }
+}
+
+impl Display for UpstreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::Connect(e) => write!(f, "connect failed: {e}"),
+            UpstreamError::Timeout => write!(f, "timed out"),
+            UpstreamError::Io(e) => write!(f, "{e}"),
+            UpstreamError::Protocol(what) => write!(f, "malformed upstream response: {what}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(raw: &str) -> Request {
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn the_upstream_request_keeps_only_end_to_end_headers() {
+        let request = request(
+            "POST http://example.com/books?page=2 HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Hop\r\n\
+             Keep-Alive: timeout=5\r\nX-Hop: 1\r\nTE: trailers\r\nAccept: */*\r\nContent-Length: 5\r\n\r\nhello"
+        );
+
+        let sent = String::from_utf8(upstream_request(&request)).unwrap();
+        assert_eq!(
+            sent,
+            "POST /books?page=2 HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+        );
+    }
+
+    #[test]
+    fn the_upstream_gets_our_request_id_in_place_of_the_clients() {
+        let mut request = request("GET / HTTP/1.1\r\nHost: example.com\r\nX-Request-Id: from-client\r\n\r\n");
+        request.set_id(String::from("42"));
+
+        let sent = String::from_utf8(upstream_request(&request)).unwrap();
+        assert!(sent.contains("\r\nX-Request-Id: 42\r\n"), "{sent}");
+        assert!(!sent.contains("from-client"), "{sent}");
+    }
+
+    #[test]
+    fn an_absolute_target_without_a_path_asks_for_the_root() {
+        let request = request("GET http://example.com HTTP/1.1\r\nHost: example.com\r\n\r\n");
+
+        let sent = String::from_utf8(upstream_request(&request)).unwrap();
+        assert!(sent.starts_with("GET / HTTP/1.1\r\n"), "{sent}");
+        assert!(!sent.contains("Content-Length"), "{sent}");
+    }
+
+    #[test]
+    fn the_relayed_response_drops_hop_by_hop_headers_and_its_length() {
+        let raw = "HTTP/1.1 201 Created\r\nContent-Length: 2\r\nConnection: close, X-Hop\r\nX-Hop: 1\r\n\
+                   Keep-Alive: timeout=5\r\nLocation: /books/1\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\nok";
+
+        let response = read_response(&mut raw.as_bytes(), false).unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.body(), b"ok");
+        let names: Vec<&str> = response.headers().iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Location", "Set-Cookie", "Set-Cookie"]);
+    }
+
+    #[test]
+    fn a_response_that_isnt_http_is_a_502() {
+        for raw in ["", "SSH-2.0-OpenSSH_9.6\r\n", "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort"] {
+            let error = read_response(&mut raw.as_bytes(), false).unwrap_err();
+            assert_eq!(error.status(), 502, "{raw:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn timeouts_are_504s_and_other_failures_502s() {
+        let cases = [
+            (io::ErrorKind::WouldBlock, 504),
+            (io::ErrorKind::TimedOut, 504),
+            (io::ErrorKind::ConnectionReset, 502),
+            (io::ErrorKind::BrokenPipe, 502)
+        ];
+
+        for (kind, status) in cases {
+            assert_eq!(UpstreamError::from_io(io::Error::from(kind)).status(), status, "{kind:?}");
+        }
+        assert_eq!(UpstreamError::Connect(io::Error::from(io::ErrorKind::ConnectionRefused)).status(), 502);
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use crate::{http_date, Request, Response};
+
+/// How many ranges one request may ask for, unless configured otherwise.
+pub const DEFAULT_MAX_RANGES: usize = 8;
+
+/// What a `Range` header asks of a representation `len` bytes long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable `Range` header: send the whole representation.
+    Full,
+    /// Send these byte ranges, sorted and with overlapping or adjacent ones merged.
+    Partial(Vec<Range<u64>>),
+    /// Nothing asked for lies within the representation, or too many ranges were asked for.
+    Unsatisfiable
+}
+
+/*
+    Parses `Range: bytes=0-99,200-,-50` against a representation of `len` bytes (RFC 9110
+    section 14.2). A header we don't understand (another unit, bad syntax) is ignored, which the
+    RFC allows, so the client simply gets the whole thing. Ranges that start past the end are
+    dropped, and if none is left the request is unsatisfiable.
+
+    Many small or overlapping ranges can make a response larger than the file many times over,
+    or cost a seek per range, so more than `max_ranges` is refused outright rather than served,
+    and the rest are merged where they touch before anything is read.
+ */
+pub fn parse(header: &str, len: u64, max_ranges: usize) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    let specs: Vec<&str> = specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()).collect();
+    if specs.is_empty() {
+        return RangeRequest::Full;
+    }
+    if specs.len() > max_ranges {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    let mut ranges = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (Some(first), Some(last)) = (position(first), position(last)) else {
+            return RangeRequest::Full;
+        };
+
+        let range = match (first, last) {
+            // "-500": the last 500 bytes
+            (None, Some(suffix)) if suffix > 0 && len > 0 => len.saturating_sub(suffix)..len,
+            (None, _) => continue,
+            (Some(first), _) if first >= len => continue,
+            (Some(first), None) => first..len,
+            (Some(first), Some(last)) if last < first => return RangeRequest::Full,
+            (Some(first), Some(last)) => first..last.min(len - 1) + 1
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(coalesce(ranges))
+}
+
+// "" is an absent position, anything else must be plain digits; the outer None is a syntax error.
+fn position(text: &str) -> Option<Option<u64>> {
+    if text.is_empty() {
+        return Some(None);
+    }
+    if !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok().map(Some)
+}
+
+fn coalesce(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range)
+        }
+    }
+    merged
+}
+
+/*
+    Whether the `Range` header still applies. With `If-Range` the client says "only if it's still
+    this version", naming it by a strong ETag or by its Last-Modified date; if it has changed
+    since, the client wants the whole new representation rather than pieces of it.
+ */
+pub fn if_range_matches(request: &Request, etag: &str, last_modified: Option<SystemTime>) -> bool {
+    let Some(if_range) = request.header("If-Range").map(str::trim) else {
+        return true;
+    };
+
+    if if_range.starts_with('"') {
+        return if_range == etag && !etag.starts_with("W/");
+    }
+    match (http_date::parse(if_range), last_modified) {
+        (Some(date), Some(modified)) => seconds(date) == seconds(modified),
+        _ => false
+    }
+}
+
+/*
+    Ranges are a property of the response: only one that honors them (a static file, which sets
+    `Accept-Ranges: bytes` itself) serves a Range header. Everything else, generated per request,
+    ignores it and sends the whole body, which a 200 always allows. Saying `Accept-Ranges: none`
+    on those keeps clients from trying to resume or split a download of content that may differ
+    the next time it is generated. Only a 200 to GET or HEAD is marked, the one answer a range
+    could have turned into a 206.
+ */
+pub fn refuse_by_default(request: &Request, response: &mut Response) {
+    let ranged = matches!(request.method(), "GET" | "HEAD") && response.status() == 200;
+    if ranged && response.header("Accept-Ranges").is_none() {
+        response.set_header("Accept-Ranges", "none");
+    }
+}
+
+/// Answers with the requested parts of `contents`, or the `416` for an unsatisfiable range.
+pub fn respond(ranges: RangeRequest, contents: &[u8], content_type: &str) -> Option<Response> {
+    let len = contents.len() as u64;
+
+    match ranges {
+        RangeRequest::Full => None,
+        RangeRequest::Unsatisfiable => Some(unsatisfiable(len)),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = &ranges[0];
+            Some(
+                Response::new(206)
+                    .with_header("Content-Type", content_type)
+                    .with_header("Content-Range", &content_range(range, len))
+                    .with_body(&contents[range.start as usize..range.end as usize])
+            )
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = boundary();
+            Some(
+                Response::new(206)
+                    .with_header("Content-Type", &format!("multipart/byteranges; boundary={boundary}"))
+                    .with_body(multipart(&ranges, contents, content_type, &boundary))
+            )
+        }
+    }
+}
+
+/*
+    Like `respond`, for a file too big to hold in memory: the `len`-byte file at `path` is
+    only read as the response is sent, and only the requested parts of it. Each part reads
+    through a handle of its own, already at the right offset, so there is nothing to seek
+    while sending; there are at most `max_ranges` of them.
+ */
+pub fn respond_from_file(ranges: RangeRequest, path: &Path, len: u64, content_type: &str) -> io::Result<Option<Response>> {
+    let slice = |range: &Range<u64>| -> io::Result<io::Take<File>> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(range.start))?;
+        Ok(file.take(range.end - range.start))
+    };
+
+    match ranges {
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = &ranges[0];
+            Ok(Some(
+                Response::new(206)
+                    .with_header("Content-Type", content_type)
+                    .with_header("Content-Range", &content_range(range, len))
+                    .with_reader(slice(range)?, Some(range.end - range.start))
+            ))
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = boundary();
+            let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+            let mut length = 0;
+            for range in &ranges {
+                let head = part_head(range, len, content_type, &boundary);
+                length += head.len() as u64 + (range.end - range.start);
+                body = Box::new(body.chain(Cursor::new(head)).chain(slice(range)?));
+            }
+            let closing = closing_boundary(&boundary);
+            length += closing.len() as u64;
+
+            Ok(Some(
+                Response::new(206)
+                    .with_header("Content-Type", &format!("multipart/byteranges; boundary={boundary}"))
+                    .with_reader(body.chain(Cursor::new(closing)), Some(length))
+            ))
+        }
+        RangeRequest::Full => Ok(None),
+        RangeRequest::Unsatisfiable => Ok(Some(unsatisfiable(len)))
+    }
+}
+
+/*
+    The multipart/byteranges body (RFC 9110 section 14.6): each part has its own Content-Type
+    and Content-Range, and is the exact slice of the file. The body is built whole, so the
+    Content-Length the serializer sends is the real size of everything that follows.
+ */
+fn multipart(ranges: &[Range<u64>], contents: &[u8], content_type: &str, boundary: &str) -> Vec<u8> {
+    let len = contents.len() as u64;
+    let mut body = Vec::new();
+
+    for range in ranges {
+        body.extend_from_slice(part_head(range, len, content_type, boundary).as_bytes());
+        body.extend_from_slice(&contents[range.start as usize..range.end as usize]);
+    }
+    body.extend_from_slice(closing_boundary(boundary).as_bytes());
+
+    body
+}
+
+// What goes before each part's bytes: the boundary and the part's own headers.
+fn part_head(range: &Range<u64>, len: u64, content_type: &str, boundary: &str) -> String {
+    format!("\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n", content_range(range, len))
+}
+
+fn closing_boundary(boundary: &str) -> String {
+    format!("\r\n--{boundary}--\r\n")
+}
+
+fn unsatisfiable(len: u64) -> Response {
+    Response::status_only(416).with_header("Content-Range", &format!("bytes */{len}"))
+}
+
+fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end - 1)
+}
+
+// Random, so that the boundary can't be predicted (and planted) by whoever wrote the file.
+fn boundary() -> String {
+    let random = RandomState::new().build_hasher().finish();
+    format!("byteranges-{random:016x}")
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+// a vec of one range is exactly what a single-range request parses to
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(headers: &str) -> Request {
+        let raw = format!("GET / HTTP/1.1\r\nHost: test\r\n{headers}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn range_headers_are_parsed_against_the_length() {
+        use RangeRequest::*;
+        let cases = [
+            ("bytes=0-99", Partial(vec![0..100])),
+            ("bytes=100-", Partial(vec![100..1000])),
+            ("bytes=-50", Partial(vec![950..1000])),
+            ("bytes=-5000", Partial(vec![0..1000])),
+            ("bytes=900-5000", Partial(vec![900..1000])),
+            (" bytes=0-0, 999-999 ", Partial(vec![0..1, 999..1000])),
+            ("bytes=500-599,0-9", Partial(vec![0..10, 500..600])),
+            ("bytes=0-9,5-19,20-29", Partial(vec![0..30])),
+            ("bytes=0-9,2000-", Partial(vec![0..10])),
+            ("bytes=1000-", Unsatisfiable),
+            ("bytes=-0", Unsatisfiable),
+            ("bytes=0-0,1-1,2-2,3-3,4-4,5-5,6-6,7-7,8-8", Unsatisfiable),
+            ("bytes=9-0", Full),
+            ("bytes=a-b", Full),
+            ("bytes=+1-2", Full),
+            ("bytes=5", Full),
+            ("bytes=", Full),
+            ("items=0-9", Full)
+        ];
+
+        for (header, expected) in cases {
+            assert_eq!(parse(header, 1000, DEFAULT_MAX_RANGES), expected, "{header:?}");
+        }
+        assert_eq!(parse("bytes=-10", 0, DEFAULT_MAX_RANGES), Unsatisfiable);
+    }
+
+    #[test]
+    fn if_range_takes_a_strong_etag_or_the_exact_date() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let date = |offset| http_date::format(modified + Duration::from_secs(offset));
+        let cases = [
+            (String::new(), true),
+            ("If-Range: \"abc\"".to_string(), true),
+            ("If-Range: \"abd\"".to_string(), false),
+            ("If-Range: W/\"abc\"".to_string(), false),
+            (format!("If-Range: {}", date(0)), true),
+            (format!("If-Range: {}", date(1)), false),
+            ("If-Range: someday".to_string(), false)
+        ];
+
+        for (header, expected) in cases {
+            let headers = if header.is_empty() { header.clone() } else { format!("{header}\r\n") };
+            assert_eq!(if_range_matches(&request(&headers), "\"abc\"", Some(modified)), expected, "{header:?}");
+        }
+        assert!(!if_range_matches(&request("If-Range: \"abc\"\r\n"), "W/\"abc\"", None));
+        assert!(!if_range_matches(&request(&format!("If-Range: {}\r\n", date(0))), "\"abc\"", None));
+    }
+
+    #[test]
+    fn a_single_range_is_sent_as_is() {
+        let response = respond(RangeRequest::Partial(vec![2..5]), b"0123456789", "text/plain").unwrap();
+
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.header("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(response.body(), b"234");
+    }
+
+    #[test]
+    fn several_ranges_are_sent_as_multipart_byteranges() {
+        let response = respond(RangeRequest::Partial(vec![0..2, 7..10]), b"0123456789", "text/plain").unwrap();
+
+        assert_eq!(response.status(), 206);
+        let boundary = response.header("Content-Type").unwrap().strip_prefix("multipart/byteranges; boundary=").unwrap();
+        let expected = format!(
+            "\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 7-9/10\r\n\r\n789\
+             \r\n--{boundary}--\r\n"
+        );
+        assert_eq!(String::from_utf8_lossy(response.body()), expected);
+    }
+
+    #[test]
+    fn an_unsatisfiable_range_is_a_416_and_a_full_one_is_left_to_the_caller() {
+        let response = respond(RangeRequest::Unsatisfiable, b"0123456789", "text/plain").unwrap();
+        assert_eq!(response.status(), 416);
+        assert_eq!(response.header("Content-Range"), Some("bytes */10"));
+
+        assert!(respond(RangeRequest::Full, b"0123456789", "text/plain").is_none());
+    }
+
+    #[test]
+    fn boundaries_differ_from_response_to_response() {
+        assert_ne!(boundary(), boundary());
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    io::{self, Read},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/*
+    The read limits currently in force on a connection. A socket's own read timeout only bounds
+    each read, so a client that trickles in a byte at a time never trips it; a deadline bounds
+    the whole phase (receiving the head, say) however the bytes arrive. The connection loop
+    switches phases by calling `set`, and every read of the `TimedStream` honors whatever was set
+    last.
+ */
+#[derive(Debug)]
+pub(crate) struct ReadTimeouts {
+    limits: Mutex<(Duration, Option<Instant>)>
+}
+
+impl ReadTimeouts {
+    pub(crate) fn new(idle: Duration) -> Arc<Self> {
+        Arc::new(Self { limits: Mutex::new((idle, None)) })
+    }
+
+    /// Each read may wait at most `idle` for data, and none may go past `deadline`.
+    pub(crate) fn set(&self, idle: Duration, deadline: Option<Instant>) {
+        *self.limits.lock().expect("Mutex poisoned: Another thread panicked while holding the lock.") = (idle, deadline);
+    }
+
+    fn limits(&self) -> (Duration, Option<Instant>) {
+        *self.limits.lock().expect("Mutex poisoned: Another thread panicked while holding the lock.")
+    }
+}
+
+/// A connection's read half, reading under its `ReadTimeouts`.
+pub(crate) struct TimedStream {
+    stream: TcpStream,
+    timeouts: Arc<ReadTimeouts>
+}
+
+impl TimedStream {
+    pub(crate) fn new(stream: TcpStream, timeouts: Arc<ReadTimeouts>) -> Self {
+        Self { stream, timeouts }
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (idle, deadline) = self.timeouts.limits();
+        let timeout = match deadline {
+            None => idle,
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "read deadline passed"));
+                }
+                idle.min(left)
+            }
+        };
+
+        // a zero timeout would mean "block forever" to the socket
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        self.stream.read(buf)
+    }
+}
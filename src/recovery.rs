@@ -0,0 +1,342 @@
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    collections::hash_map::RandomState,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    hash::{BuildHasher, Hasher},
+    panic::{self, AssertUnwindSafe},
+};
+use crate::{json::Value, negotiate, response::reason_phrase, Request, Response};
+
+/// Called with the details of every handler panic the server recovers from.
+pub type PanicCallback = dyn Fn(&HandlerPanic) + Send + Sync + 'static;
+
+/// A handler panic, with the request that triggered it, as passed to `Server::on_panic`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerPanic {
+    /// The request line as the client sent it, e.g. `GET /books?page=2 HTTP/1.1`.
+    pub request_line: String,
+    /// The method the handler saw, after any method override.
+    pub method: String,
+    pub path: String,
+    /// The pattern of the route that panicked, e.g. `/books/:id`, if the router matched one.
+    pub route: Option<String>,
+    /// The request's id, or the correlation id made up for it; the client's 500 carries it.
+    pub request_id: Option<String>,
+    /// The panic's message; see `format_panic_payload`.
+    pub message: String
+}
+
+impl Display for HandlerPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handler panicked on \"{}\"", self.request_line)?;
+        if let Some(route) = &self.route {
+            write!(f, " (route {route})")?;
+        }
+        if let Some(id) = &self.request_id {
+            write!(f, " (request {id})")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/*
+    Runs the handler, turning a panic into a 500 instead of letting it unwind out of the job
+    and take the worker thread with it. The request is still in hand when the panic is caught,
+    so the log line says which one did it; the panic hook's own message (file and line) has
+    already gone to stderr by then.
+
+    The handler may have left the request, and anything it shares with other requests, half
+    updated; that's why the unwind safety is asserted rather than proven, and why the caller
+    closes the connection afterwards.
+ */
+pub(crate) fn call_handler<F>(request: &mut Request, on_panic: Option<&PanicCallback>, handler: F) -> Result<Response, Response>
+where F: FnOnce(&mut Request) -> Response
+{
+    let payload = match panic::catch_unwind(AssertUnwindSafe(|| handler(request))) {
+        Ok(response) => return Ok(response),
+        Err(payload) => payload
+    };
+
+    let report = HandlerPanic {
+        request_line: format!(
+            "{} {} {}",
+            request.original_method().unwrap_or(request.method()),
+            request.target(),
+            request.version().as_str()
+        ),
+        method: request.method().to_string(),
+        path: request.path().to_string(),
+        route: request.route().map(str::to_string),
+        request_id: Some(correlation_id(request)),
+        message: format_panic_payload(payload.as_ref())
+    };
+    eprintln!("{report}");
+    if let Some(on_panic) = on_panic {
+        on_panic(&report);
+    }
+
+    Err(internal_error_response(request, report.request_id.as_deref().unwrap_or_default()))
+}
+
+/*
+    An error a handler couldn't deal with, on its way to becoming a 500. The client only ever
+    learns the correlation id; the error itself, its causes and the backtrace (captured where the
+    error was converted, if RUST_BACKTRACE allows) go to stderr under that same id, so a user's
+    report of the id leads straight to the details. Nothing from the error reaches the body:
+    messages tend to carry paths, queries and other things a client has no business seeing.
+
+    Anything that converts into a boxed error converts into one, `anyhow::Error` and strings
+    included, so `?` works in a handler wrapped in `fallible`.
+ */
+pub struct InternalError {
+    error: Box<dyn Error + Send + Sync>,
+    backtrace: Backtrace
+}
+
+impl InternalError {
+    pub fn new(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self { error: error.into(), backtrace: Backtrace::capture() }
+    }
+
+    /// Logs the error under the request's correlation id and returns the 500 the client gets.
+    pub fn respond(self, request: &Request) -> Response {
+        let id = correlation_id(request);
+
+        let mut details = format!(
+            "Internal error {id} on \"{} {} {}\": {}\n  {:?}",
+            request.original_method().unwrap_or(request.method()),
+            request.target(),
+            request.version().as_str(),
+            self.error,
+            self.error
+        );
+        let mut source = self.error.source();
+        while let Some(cause) = source {
+            details.push_str(&format!("\n  caused by: {cause}"));
+            source = cause.source();
+        }
+        if self.backtrace.status() == BacktraceStatus::Captured {
+            details.push_str(&format!("\n{}", self.backtrace));
+        }
+        eprintln!("{details}");
+
+        internal_error_response(request, &id)
+    }
+}
+
+impl<E> From<E> for InternalError
+where E: Into<Box<dyn Error + Send + Sync>>
+{
+    fn from(error: E) -> Self {
+        Self::new(error)
+    }
+}
+
+impl Debug for InternalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InternalError").field("error", &self.error).finish()
+    }
+}
+
+/// Turns a handler that returns a `Result` into one the server and router take, answering each
+/// error with `InternalError::respond`.
+pub fn fallible<F, E>(handler: F) -> impl Fn(&mut Request) -> Response + Send + Sync + 'static
+where
+    F: Fn(&mut Request) -> Result<Response, E> + Send + Sync + 'static,
+    E: Into<InternalError>
+{
+    move |request| match handler(request) {
+        Ok(response) => response,
+        Err(error) => error.into().respond(request)
+    }
+}
+
+// The request's own id where the server gave it one; otherwise a random one, so the log and the page still agree.
+fn correlation_id(request: &Request) -> String {
+    match request.id() {
+        Some(id) => id.to_string(),
+        None => format!("{:016x}", RandomState::new().build_hasher().finish())
+    }
+}
+
+/*
+    The 500 for an internal error: the correlation id and nothing else. A client that prefers
+    JSON gets the usual error object, which has the id as its request_id; anyone else a minimal
+    page. The body has a Content-Type of its own, so the error pages leave it alone.
+ */
+fn internal_error_response(request: &Request, id: &str) -> Response {
+    let mut response = if negotiate::prefers_json(request) {
+        let body = Value::Object(vec![
+            ("status".to_string(), 500_u64.into()),
+            ("error".to_string(), reason_phrase(500).into()),
+            ("request_id".to_string(), id.into())
+        ]);
+        Response::json(500, &body)
+    } else {
+        let page = format!(
+            "<!DOCTYPE html>\n<title>500 Internal Server Error</title>\n<h1>Internal Server Error</h1>\n<p>Reference: {id}</p>\n"
+        );
+        Response::html(500, page)
+    };
+    response.vary("Accept");
+    response
+}
+
+/// The message of a panic payload, as `catch_unwind` or `JoinHandle::join` return it: the text
+/// of a `panic!` with a message, which is a `&str` or a `String`, and otherwise
+/// `<non-string panic payload>`, e.g. for `std::panic::panic_any(42)`.
+///
+/// Pass the payload itself (`&*payload`); a box passed by reference is unwrapped as well, since
+/// it would otherwise coerce to `dyn Any` and match neither.
+pub fn format_panic_payload(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    match payload.downcast_ref::<Box<dyn Any + Send>>() {
+        Some(inner) => format_panic_payload(inner.as_ref()),
+        None => String::from("<non-string panic payload>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, sync::{Arc, Mutex}};
+    use super::*;
+    use crate::request::Version;
+
+    fn request(raw: &str) -> Request {
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn a_handler_that_returns_is_passed_through() {
+        let mut request = request("GET /ok HTTP/1.1\r\nHost: x\r\n\r\n");
+        let response = call_handler(&mut request, None, |_| Response::status_only(204));
+        assert_eq!(response.map(|response| response.status()).ok(), Some(204));
+    }
+
+    #[test]
+    fn a_panicking_handler_becomes_a_500_naming_the_request() {
+        let mut request = request("GET /boom HTTP/1.1\r\nHost: x\r\n\r\n");
+        request.set_id("abc123".to_string());
+
+        let response = call_handler(&mut request, None, |_| panic!("kaboom")).unwrap_err();
+        assert_eq!(response.status(), 500);
+        let body = String::from_utf8_lossy(response.body()).into_owned();
+        assert!(body.contains("Reference: abc123"), "{body}");
+    }
+
+    #[test]
+    fn the_callback_hears_which_request_panicked() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let heard = Arc::clone(&reports);
+        let on_panic = move |report: &HandlerPanic| heard.lock().unwrap().push(report.clone());
+
+        let mut request = request("POST /books/7?x=1 HTTP/1.1\r\nHost: x\r\n\r\n");
+        request.override_method("DELETE".to_string());
+        request.set_route("/books/:id");
+        request.set_id("req-1".to_string());
+        let _ = call_handler(&mut request, Some(&on_panic), |_| panic!("no book {}", 7));
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.request_line, "POST /books/7?x=1 HTTP/1.1");
+        assert_eq!(report.method, "DELETE");
+        assert_eq!(report.path, "/books/7");
+        assert_eq!(report.route.as_deref(), Some("/books/:id"));
+        assert_eq!(report.request_id.as_deref(), Some("req-1"));
+        assert_eq!(report.message, "no book 7");
+    }
+
+    #[test]
+    fn the_report_reads_as_one_line() {
+        let mut report = HandlerPanic {
+            request_line: "GET /a HTTP/1.1".to_string(),
+            method: "GET".to_string(),
+            path: "/a".to_string(),
+            route: Some("/:name".to_string()),
+            request_id: Some("r1".to_string()),
+            message: "oops".to_string()
+        };
+        assert_eq!(report.to_string(), "Handler panicked on \"GET /a HTTP/1.1\" (route /:name) (request r1): oops");
+
+        report.route = None;
+        report.request_id = None;
+        assert_eq!(report.to_string(), "Handler panicked on \"GET /a HTTP/1.1\": oops");
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8_lossy(response.body()).into_owned()
+    }
+
+    #[test]
+    fn the_correlation_id_is_the_request_id_when_there_is_one() {
+        let mut request = request("GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+        let (first, second) = (correlation_id(&request), correlation_id(&request));
+        assert_eq!(first.len(), 16);
+        assert!(first.bytes().all(|byte| byte.is_ascii_hexdigit()), "{first}");
+        assert_ne!(first, second);
+
+        request.set_id("req-42".to_string());
+        assert_eq!(correlation_id(&request), "req-42");
+    }
+
+    #[test]
+    fn an_internal_error_shows_the_client_only_its_id() {
+        let mut page = request("GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+        page.set_id("req-7".to_string());
+        let mut api = request("GET / HTTP/1.1\r\nHost: x\r\nAccept: application/json\r\n\r\n");
+        api.set_id("req-8".to_string());
+        let error = || InternalError::new("connecting to /var/db/books.sqlite failed");
+
+        let response = error().respond(&page);
+        assert_eq!(response.status(), 500);
+        assert!(body(&response).contains("<p>Reference: req-7</p>"), "{}", body(&response));
+        assert!(!body(&response).contains("sqlite"));
+
+        let mut response = error().respond(&api);
+        assert_eq!(response.status(), 500);
+        assert_eq!(body(&response), "{\"status\":500,\"error\":\"Internal Server Error\",\"request_id\":\"req-8\"}");
+        let mut sent = Vec::new();
+        response.write_to(&mut sent, Version::Http11, false).unwrap();
+        assert!(String::from_utf8_lossy(&sent).contains("\r\nVary: Accept\r\n"));
+    }
+
+    #[test]
+    fn a_fallible_handler_answers_its_errors_with_a_500() {
+        let handler = fallible(|request: &mut Request| {
+            let n: u32 = request.query_param("n").unwrap_or_default().parse()?;
+            if n == 0 {
+                return Err(InternalError::new(io::Error::other("division by zero")));
+            }
+            Ok(Response::html(200, (100 / n).to_string()))
+        });
+
+        let cases = [("/?n=4", 200, "25"), ("/?n=zero", 500, "Reference: "), ("/?n=0", 500, "Reference: ")];
+        for (target, status, text) in cases {
+            let response = handler(&mut request(&format!("GET {target} HTTP/1.1\r\nHost: x\r\n\r\n")));
+            assert_eq!(response.status(), status, "{target}");
+            assert!(body(&response).contains(text), "{target}: {}", body(&response));
+        }
+    }
+
+    #[test]
+    fn a_panic_payload_gives_up_its_message() {
+        let payload = |f: fn()| panic::catch_unwind(f).unwrap_err();
+
+        assert_eq!(format_panic_payload(&*payload(|| panic!("plain"))), "plain");
+        assert_eq!(format_panic_payload(&*payload(|| panic!("formatted {}", 42))), "formatted 42");
+        assert_eq!(format_panic_payload(&*payload(|| panic::panic_any(42_u32))), "<non-string panic payload>");
+        assert_eq!(format_panic_payload(&*payload(|| panic::panic_any(String::from("owned")))), "owned");
+
+        // a box passed as is, rather than what it holds
+        let boxed = payload(|| panic!("boxed"));
+        assert_eq!(format_panic_payload(&boxed), "boxed");
+    }
+}
@@ -0,0 +1,1111 @@
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    fmt::{Display, Formatter},
+    io::{self, BufRead, Read},
+    sync::Arc,
+    time::Duration,
+};
+use crate::{
+    body::{BodyReader, Framing},
+    connection_state::ConnectionState,
+    mime::MediaType,
+    trace::TraceContext,
+    watchdog::Watchdog,
+};
+
+// Longest request line or header line we are willing to buffer.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY_LENGTH: u64 = 1024 * 1024;
+
+/// The connection's reader, lent to a request while its body is being consumed.
+pub(crate) type Source = Box<dyn BufRead + Send>;
+
+/*
+    What to do with `%2F` in the path. Decoded, it becomes a separator the client didn't write,
+    so `/files/a%2Fb` would reach the same handler (and file) as `/files/a/b`; proxies and
+    access rules in front of us may well have seen one segment. Refusing it is the safe default.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncodedSlash {
+    /// Answer 400.
+    #[default]
+    Reject,
+    /// Decode it to `/` like any other escape.
+    Decode,
+    /// Leave `%2F` in the decoded path as is, so it stays part of its segment. Note that `%252F`
+    /// decodes to the same text, so a handler can't tell the two apart.
+    KeepEncoded
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1"
+        }
+    }
+}
+
+/// A request method: one of those the server knows, or any other token the client sent.
+/// Methods are case-sensitive, so `get` is `Other("get")`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+    Head,
+    Options,
+    Patch,
+    Other(String)
+}
+
+impl Method {
+    /// Every method that isn't `Other`.
+    pub const KNOWN: [Method; 7] = [
+        Method::Get,
+        Method::Post,
+        Method::Put,
+        Method::Delete,
+        Method::Head,
+        Method::Options,
+        Method::Patch
+    ];
+
+    pub fn parse(method: &str) -> Method {
+        match method {
+            "GET" => Method::Get,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "HEAD" => Method::Head,
+            "OPTIONS" => Method::Options,
+            "PATCH" => Method::Patch,
+            other => Method::Other(other.to_string())
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Other(other) => other
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/*
+    A parsed HTTP/1.x request. The body is read lazily: the connection lends its reader to the
+    request, and the handler either asks for the whole body at once through `body()` or streams
+    it through `body_reader()`. Whichever comes first wins; the body can only be consumed once.
+ */
+#[derive(Debug)]
+pub struct Request {
+    method: Method,
+    // what the request line said, when a method override replaced it
+    original_method: Option<String>,
+    target: String,
+    path: String,
+    version: Version,
+    headers: Vec<(String, String)>,
+    framing: Framing,
+    body: OnceCell<Vec<u8>>,
+    body_reader: RefCell<Option<BodyReader<Source>>>,
+    body_failed: Cell<bool>,
+    params: Vec<(String, String)>,
+    route: Option<String>,
+    // set by a route that reports its timings in Server-Timing
+    server_timing: bool,
+    id: Option<String>,
+    watchdog: Option<Arc<Watchdog>>,
+    // lent by the connection for the duration of the handler, like the body reader
+    connection_state: ConnectionState
+}
+
+impl Request {
+    /// Reads one request, including its body, from `reader`.
+    ///
+    /// Returns `Ok(None)` if the peer closed the connection before sending anything,
+    /// which is how a keep-alive client says it is done.
+    pub fn parse<R: BufRead>(reader: &mut R) -> Result<Option<Request>, ParseError> {
+        Self::parse_with(reader, EncodedSlash::default())
+    }
+
+    /// Like `parse`, handling `%2F` in the path as `encoded_slash` says.
+    pub fn parse_with<R: BufRead>(reader: &mut R, encoded_slash: EncodedSlash) -> Result<Option<Request>, ParseError> {
+        let Some(request) = Self::parse_head(reader, encoded_slash)? else {
+            return Ok(None);
+        };
+
+        let mut body = Vec::new();
+        BodyReader::new(reader, request.framing, MAX_BODY_LENGTH).read_to_end(&mut body)?;
+        request.body.get_or_init(|| body);
+
+        Ok(Some(request))
+    }
+
+    /*
+        Reads the request line and headers, leaving the reader at the first byte of the body.
+        The body framing is worked out here so that malformed or ambiguous lengths are refused
+        before any handler runs.
+     */
+    pub(crate) fn parse_head<R: BufRead>(reader: &mut R, encoded_slash: EncodedSlash) -> Result<Option<Request>, ParseError> {
+        let request_line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Ok(None)
+        };
+
+        // tabs are fine in header values, but nowhere on the request line
+        if request_line.contains('\t') {
+            return Err(ParseError::ControlCharacter(escape(request_line.as_bytes())));
+        }
+
+        // first line is always of the form: "GET / HTTP/1.1"
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version), None)
+                if !method.is_empty() && !target.is_empty() => (method, target, version),
+            _ => return Err(ParseError::Malformed("request line"))
+        };
+
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            _ => return Err(ParseError::UnsupportedVersion)
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader)?.ok_or(ParseError::Malformed("unexpected end of headers"))?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(ParseError::TooLarge);
+            }
+
+            let (name, value) = line.split_once(':').ok_or(ParseError::Malformed("header"))?;
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(ParseError::Malformed("header name"));
+            }
+            // only SP and HTAB count as the optional whitespace around a value
+            headers.push((name.to_string(), value.trim_matches([' ', '\t']).to_string()));
+        }
+
+        let mut request = Request {
+            method: Method::parse(method),
+            original_method: None,
+            target: target.to_string(),
+            path: request_path(target, encoded_slash)?,
+            version,
+            headers,
+            framing: Framing::Length(0),
+            body: OnceCell::new(),
+            body_reader: RefCell::new(None),
+            body_failed: Cell::new(false),
+            params: Vec::new(),
+            route: None,
+            server_timing: false,
+            id: None,
+            watchdog: None,
+            connection_state: ConnectionState::default()
+        };
+        request.framing = request.body_framing()?;
+        request.check_host()?;
+
+        Ok(Some(request))
+    }
+
+    /*
+        HTTP/1.1 requires exactly one Host header, except that an absolute-form target
+        ("GET http://example.com/ HTTP/1.1") carries the authority itself. Two Host headers are
+        refused for any version: picking one would let a proxy and us disagree on the vhost.
+     */
+    fn check_host(&self) -> Result<(), ParseError> {
+        let hosts = self.headers.iter().filter(|(key, _)| key.eq_ignore_ascii_case("Host")).count();
+
+        match hosts {
+            0 if self.version == Version::Http11 && split_absolute(&self.target).is_none() => {
+                Err(ParseError::Malformed("request without Host header"))
+            }
+            0 | 1 => Ok(()),
+            _ => Err(ParseError::Malformed("duplicate Host header"))
+        }
+    }
+
+    fn body_framing(&self) -> Result<Framing, ParseError> {
+        let transfer_encoding = self.header("Transfer-Encoding");
+        let content_length = self.header("Content-Length");
+
+        match (transfer_encoding, content_length) {
+            // both at once is the classic request smuggling setup; refuse to pick one
+            (Some(_), Some(_)) => Err(ParseError::Malformed("both Transfer-Encoding and Content-Length")),
+            (Some(coding), None) if coding.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+            (Some(_), None) => Err(ParseError::Unsupported("Transfer-Encoding")),
+            (None, Some(_)) => {
+                let length = self.declared_length()?.unwrap_or_default();
+                if length > MAX_BODY_LENGTH {
+                    return Err(ParseError::TooLarge);
+                }
+                Ok(Framing::Length(length))
+            }
+            (None, None) => Ok(Framing::Length(0))
+        }
+    }
+
+    /*
+        Content-Length must be plain digits: `u64::from_str` alone would also take "+5". The
+        header may repeat, or hold a comma-separated list, only if every value is the same
+        (RFC 9110 section 8.6); anything else leaves the body's end ambiguous, so it's a 400.
+     */
+    fn declared_length(&self) -> Result<Option<u64>, ParseError> {
+        let mut declared = None;
+        for value in self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+            .flat_map(|(_, value)| value.split(','))
+        {
+            let value = value.trim();
+            if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(ParseError::Malformed("Content-Length"));
+            }
+            let length: u64 = value.parse().map_err(|_| ParseError::Malformed("Content-Length"))?;
+            if declared.is_some_and(|declared| declared != length) {
+                return Err(ParseError::Malformed("conflicting Content-Length values"));
+            }
+            declared = Some(length);
+        }
+
+        Ok(declared)
+    }
+
+    pub fn method(&self) -> &str {
+        self.method.as_str()
+    }
+
+    /// Like `method`, parsed, e.g. to tell a known method from an extension one.
+    pub fn method_kind(&self) -> &Method {
+        &self.method
+    }
+
+    /// The method on the request line, if an `X-HTTP-Method-Override` replaced it in `method`.
+    pub fn original_method(&self) -> Option<&str> {
+        self.original_method.as_deref()
+    }
+
+    pub(crate) fn override_method(&mut self, method: String) {
+        let original = std::mem::replace(&mut self.method, Method::parse(&method));
+        self.original_method.get_or_insert(original.to_string());
+    }
+
+    /// The request target exactly as it appeared on the request line.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The target without its query string (nor scheme and authority, for an absolute-form target).
+    ///
+    /// The path is percent-decoded and has its dot-segments resolved (`/a/./b/../c` is `/a/c`);
+    /// use `target` for the raw form.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The decoded path split at `/`, without the empty segments that leading, trailing or
+    /// doubled slashes leave: `/a//b/` yields `a`, `b`. Segments are not altered further; the
+    /// path's dot-segments were already resolved when the request was parsed.
+    pub fn path_segments(&self) -> impl Iterator<Item = &str> {
+        path_segments(&self.path)
+    }
+
+    /// The host the request is addressed to: the authority of an absolute-form target if there
+    /// is one, else the `Host` header. Only `None` for an HTTP/1.0 request without `Host`.
+    pub fn host(&self) -> Option<&str> {
+        split_absolute(&self.target).map(|(authority, _)| authority).or_else(|| self.header("Host"))
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Returns the value of the first `name=value` pair in the query string.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the first value of the header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the header `name`, one per header line, in the order they arrived; for
+    /// headers that may repeat, like `Forwarded` or `Via`. A line holding a comma-separated
+    /// list is one value here.
+    pub fn header_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The body length the client declared in `Content-Length`; None if it sent none.
+    ///
+    /// Parsing already refused malformed or conflicting values, so this is the length the
+    /// body is actually read with. A chunked request has no declared length.
+    pub fn content_length(&self) -> Option<usize> {
+        self.declared_length().ok().flatten().and_then(|length| usize::try_from(length).ok())
+    }
+
+    /// The parsed `Content-Type` header; None if it is absent or not a valid media type.
+    pub fn content_type(&self) -> Option<MediaType> {
+        self.header("Content-Type").and_then(MediaType::parse)
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The whole body, read from the connection on first use.
+    ///
+    /// Empty if the body was already taken through `body_reader`, or if reading it failed
+    /// (in which case the server closes the connection after responding).
+    pub fn body(&self) -> &[u8] {
+        self.body.get_or_init(|| {
+            let mut body = Vec::new();
+            if let Some(reader) = self.body_reader.borrow_mut().as_mut() {
+                if let Err(e) = reader.read_to_end(&mut body) {
+                    eprintln!("Failed to read request body: {e}");
+                    self.body_failed.set(true);
+                }
+            }
+            body
+        })
+    }
+
+    /// Streams the body instead of buffering it, for handlers that deal with large uploads.
+    ///
+    /// Reads stop at the end of this request's body. Returns `None` once `body()` has
+    /// buffered it, or if the request didn't come from a connection.
+    pub fn body_reader(&mut self) -> Option<impl Read + '_> {
+        if self.body.get().is_some() {
+            return None;
+        }
+        self.body_reader.get_mut().as_mut()
+    }
+
+    /// Takes ownership of the body stream. The connection it came from can't be reused
+    /// afterwards, so the server closes it once the response is written.
+    pub fn into_body_reader(self) -> Option<impl Read> {
+        if self.body.get().is_some() {
+            return None;
+        }
+        self.body_reader.into_inner()
+    }
+
+    // Lends the connection's reader to this request so the body can be read lazily.
+    pub(crate) fn attach_body(&mut self, source: Source) {
+        *self.body_reader.get_mut() = Some(BodyReader::new(source, self.framing, MAX_BODY_LENGTH));
+    }
+
+    /*
+        Returns the connection's reader, positioned at the start of the next request, once the
+        handler is done. Any body the handler didn't read is skipped over. None means the
+        reader is gone or the body couldn't be read, and the connection must be closed.
+     */
+    pub(crate) fn detach_body(&mut self) -> Option<Source> {
+        let reader = self.body_reader.get_mut().take()?;
+        if self.body_failed.get() {
+            return None;
+        }
+
+        reader.finish().ok()
+    }
+
+    /// Returns a path parameter captured by the router, e.g. `id` for the pattern `/books/:id`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn set_params(&mut self, params: Vec<(String, String)>) {
+        self.params = params;
+    }
+
+    /// The pattern of the route the router dispatched this request to, e.g. `/books/:id`.
+    ///
+    /// Unlike the path, the set of patterns is fixed, which makes it the thing to group
+    /// requests by in logs and metrics.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    pub(crate) fn set_route(&mut self, pattern: &str) {
+        self.route = Some(pattern.to_string());
+    }
+
+    pub(crate) fn enable_server_timing(&mut self) {
+        self.server_timing = true;
+    }
+
+    pub(crate) fn server_timing(&self) -> bool {
+        self.server_timing
+    }
+
+    /// The id the server assigned this request, also sent back in the `X-Request-Id` response
+    /// header and logged with it. None for a request that wasn't read by the server.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub(crate) fn set_id(&mut self, id: String) {
+        self.id = Some(id);
+    }
+
+    /// The caller's trace, from a valid W3C `traceparent` header. Invalid values are ignored.
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        self.header("traceparent").and_then(TraceContext::parse)
+    }
+
+    /// State kept for the connection this request came in on, shared with the requests before
+    /// and after it there. Empty for a request that wasn't read by the server.
+    pub fn connection_state(&mut self) -> &mut ConnectionState {
+        &mut self.connection_state
+    }
+
+    pub(crate) fn lend_connection_state(&mut self, state: ConnectionState) {
+        self.connection_state = state;
+    }
+
+    pub(crate) fn return_connection_state(&mut self) -> ConnectionState {
+        std::mem::take(&mut self.connection_state)
+    }
+
+    pub(crate) fn set_watchdog(&mut self, watchdog: Arc<Watchdog>) {
+        self.watchdog = Some(watchdog);
+    }
+
+    pub(crate) fn take_watchdog(&mut self) -> Option<Arc<Watchdog>> {
+        self.watchdog.take()
+    }
+
+    // Only requests read from a live connection carry a watchdog; for any other the timeout is a no-op.
+    pub(crate) fn arm_timeout(&self, timeout: Duration) {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.arm(timeout);
+        }
+    }
+
+    /// Whether the connection should stay open after this request is answered.
+    ///
+    /// HTTP/1.1 connections persist unless the client sends `Connection: close`;
+    /// HTTP/1.0 connections close unless the client sends `Connection: keep-alive`.
+    pub fn is_keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.has_connection_option("close"),
+            Version::Http10 => self.has_connection_option("keep-alive")
+                && !self.has_connection_option("close")
+        }
+    }
+
+    // Connection is a comma-separated list of case-insensitive tokens.
+    fn has_connection_option(&self, option: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Connection"))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(option))
+    }
+}
+
+/*
+    Whether a client-supplied `X-Request-Id` is safe to adopt. It ends up in our logs and our
+    response headers, so only a short run of characters that can't break either is taken:
+    letters, digits and `-_.:`, which covers UUIDs and the usual tracing id formats.
+ */
+pub(crate) fn is_valid_request_id(id: &str) -> bool {
+    (1..=128).contains(&id.len())
+        && id.bytes().all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b':'))
+}
+
+/*
+    The path handlers and logs see: the target's path, percent-decoded, then with dot-segments
+    resolved lexically. Doing it once here means routes, static mounts and logs all agree on
+    what was asked for, and a path that climbs above the root never gets past parsing.
+ */
+fn request_path(target: &str, encoded_slash: EncodedSlash) -> Result<String, ParseError> {
+    check_target(target)?;
+
+    // "OPTIONS * HTTP/1.1" addresses the server itself, not a path
+    if target == "*" {
+        return Ok(target.to_string());
+    }
+
+    let target = split_absolute(target).map_or(target, |(_, rest)| rest);
+    let raw = match target.split_once('?').map_or(target, |(path, _)| path) {
+        // "http://example.com" and "http://example.com?q" name the root
+        "" => "/",
+        path => path
+    };
+    if !raw.starts_with('/') {
+        return Err(ParseError::Malformed("request target"));
+    }
+
+    let decoded = decode_path(raw, encoded_slash)?;
+    normalize_path(&decoded).ok_or(ParseError::Malformed("path above the root"))
+}
+
+/*
+    Control bytes (C0 and DEL, tab included) have no business in a target, raw or escaped.
+    Decoded into a path, NUL can truncate a filename at the OS boundary; echoed from a query
+    parameter into a header or a log line, CR and LF split it, and the rest garble it. The
+    query is checked too since handlers decode it themselves. A malformed escape is left to
+    the decoding that follows.
+ */
+fn check_target(target: &str) -> Result<(), ParseError> {
+    if target.bytes().any(|byte| byte.is_ascii_control()) {
+        return Err(ParseError::Malformed("control character in request target"));
+    }
+
+    let mut escapes = target.split('%').skip(1);
+    let encodes_control = |escape: &str| escape
+        .get(..2)
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        .is_some_and(|byte| byte.is_ascii_control());
+    if escapes.any(encodes_control) {
+        return Err(ParseError::Malformed("encoded control character in request target"));
+    }
+    Ok(())
+}
+
+// Percent-decodes a path, where `+` is just a plus sign, applying the `%2F` policy.
+fn decode_path(path: &str, encoded_slash: EncodedSlash) -> Result<String, ParseError> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let escape = rest.get(..2).ok_or(ParseError::Malformed("percent-encoding in path"))?;
+        let decoded = std::str::from_utf8(escape)
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or(ParseError::Malformed("percent-encoding in path"))?;
+        match (decoded, encoded_slash) {
+            (b'/', EncodedSlash::Reject) => return Err(ParseError::Malformed("encoded slash in path")),
+            (b'/', EncodedSlash::KeepEncoded) => bytes.extend_from_slice(&[b'%', escape[0], escape[1]]),
+            (decoded, _) => bytes.push(decoded)
+        }
+        rest = &rest[2..];
+    }
+
+    String::from_utf8(bytes).map_err(|_| ParseError::Malformed("non UTF-8 path"))
+}
+
+/*
+    Removes `.` and `..` segments and empty segments (`//`) from an absolute path, in the
+    spirit of RFC 3986's remove_dot_segments. A trailing slash survives, as does one implied by
+    a final `.` or `..`, since `/docs/` and `/docs` can mean different things to a handler.
+    None if a `..` would climb above the root.
+ */
+pub(crate) fn normalize_path(path: &str) -> Option<String> {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment)
+        }
+    }
+
+    let trailing_slash = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+
+    Some(normalized)
+}
+
+// The non-empty `/`-separated segments of `path`.
+pub(crate) fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+// Splits "http://example.com:8080/path?q" into ("example.com:8080", "/path?q"); None unless the target is in absolute form.
+pub(crate) fn split_absolute(target: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    Some(rest.split_at(end))
+}
+
+/*
+    Decodes a query string component: `%XX` escapes and `+` for space. None if an escape is
+    truncated or not hex, or if the decoded bytes aren't UTF-8.
+ */
+pub(crate) fn percent_decode(component: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(component.len());
+    let mut rest = component.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = std::str::from_utf8(rest.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &rest[2..];
+            }
+            byte => bytes.push(byte)
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+// Reads a CRLF terminated line, returning None on a clean end of stream; a bare LF is refused.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    reader.by_ref().take(MAX_LINE_LENGTH).read_until(b'\n', &mut line)?;
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        // either the line hit the length cap or the peer hung up mid-line
+        return Err(if line.len() as u64 + 1 >= MAX_LINE_LENGTH {
+            ParseError::TooLarge
+        } else {
+            ParseError::Malformed("unexpected end of stream")
+        });
+    }
+    /*
+        Every line must end in CRLF, and no other CR, LF or control byte (HTAB aside) may appear
+        in it. Such bytes are how response splitting and log injection get in: a value a handler
+        echoes into a header, or that we log, could otherwise end the line early. Refusing them
+        here means no handler ever sees one.
+     */
+    if line.pop() != Some(b'\r') || line.iter().any(|&byte| is_forbidden_control(byte)) {
+        return Err(ParseError::ControlCharacter(escape(&line)));
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::Malformed("non UTF-8 bytes"))
+}
+
+// C0 controls and DEL, except the HTAB that may separate words in a header value.
+fn is_forbidden_control(byte: u8) -> bool {
+    (byte.is_ascii_control() && byte != b'\t') || byte == 0x7f
+}
+
+// The line as printable ASCII (`\r`, `\x00`, ...), so logging it can't inject anything.
+fn escape(line: &[u8]) -> String {
+    line.escape_ascii().to_string()
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    Malformed(&'static str),
+    /// A bare CR or LF, or another control byte, in the request line or a header line. Holds
+    /// the offending line, escaped.
+    ControlCharacter(String),
+    UnsupportedVersion,
+    Unsupported(&'static str),
+    TooLarge
+}
+
+impl ParseError {
+    /// The status code a server should answer with when this error occurs.
+    pub fn status(&self) -> u16 {
+        match self {
+            ParseError::Io(_) | ParseError::Malformed(_) | ParseError::ControlCharacter(_) => 400,
+            ParseError::UnsupportedVersion => 505,
+            ParseError::Unsupported(_) => 501,
+            ParseError::TooLarge => 413
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> Self {
+        ParseError::Io(error)
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "{e}"),
+            ParseError::Malformed(what) => write!(f, "malformed {what}"),
+            ParseError::ControlCharacter(line) => write!(f, "control character in \"{line}\""),
+            ParseError::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+            ParseError::Unsupported(what) => write!(f, "unsupported {what}"),
+            ParseError::TooLarge => write!(f, "request too large")
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Request {
+        Request::parse(&mut raw.as_bytes()).expect("a valid request").expect("a request, not EOF")
+    }
+
+    fn refused(raw: &str) -> ParseError {
+        Request::parse(&mut raw.as_bytes()).map(|_| ()).expect_err(raw)
+    }
+
+    #[test]
+    fn dot_segments_and_empty_segments_are_normalized_away() {
+        // None means the path is refused for climbing above the root
+        let cases = [
+            ("/", Some("/")),
+            ("/a/b", Some("/a/b")),
+            ("/a/./b/../c", Some("/a/c")),
+            ("//", Some("/")),
+            ("//a//b//", Some("/a/b/")),
+            ("/a/b/", Some("/a/b/")),
+            ("/a/.", Some("/a/")),
+            ("/a/b/..", Some("/a/")),
+            ("/a/..", Some("/")),
+            ("/./", Some("/")),
+            ("/a/../b/./c/..", Some("/b/")),
+            ("/a/b/c/../../d", Some("/a/d")),
+            ("/...", Some("/...")),
+            ("/.hidden/..x", Some("/.hidden/..x")),
+            ("/..", None),
+            ("/../x", None),
+            ("/a/../../x", None),
+            ("/a/./../..", None)
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(normalize_path(path).as_deref(), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn the_path_is_normalized_after_decoding_and_the_target_kept_raw() {
+        let request = parse("GET /a/%2e/b/%2E%2E/c?x=1 HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(request.path(), "/a/c");
+        assert_eq!(request.target(), "/a/%2e/b/%2E%2E/c?x=1");
+
+        let climbing = Request::parse(&mut "GET /%2e%2e/etc/passwd HTTP/1.1\r\nHost: a\r\n\r\n".as_bytes());
+        assert_eq!(climbing.map(|_| ()).unwrap_err().status(), 400);
+    }
+
+    #[test]
+    fn control_bytes_are_refused_in_the_target_raw_or_encoded() {
+        for target in ["/a%00b", "/a%0Ab", "/a%0db", "/a%09b", "/a%1fb", "/a%7Fb", "/a?q=%01", "/?q=x%1B[31m"] {
+            let parsed = Request::parse(&mut format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n").as_bytes());
+            assert_eq!(parsed.map(|_| ()).unwrap_err().status(), 400, "{target}");
+        }
+        // raw ones end the line early or are refused by read_line before the target is looked at
+        assert!(Request::parse(&mut "GET /a\x01b HTTP/1.1\r\nHost: a\r\n\r\n".as_bytes()).is_err());
+
+        for target in ["/a%20b", "/%E2%82%AC", "/a?q=%7E", "/a%25"] {
+            let parsed = Request::parse(&mut format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n").as_bytes());
+            assert!(parsed.is_ok(), "{target}");
+        }
+    }
+
+    #[test]
+    fn a_line_ending_in_a_bare_lf_is_refused() {
+        let parsed = Request::parse(&mut "GET / HTTP/1.1\nHost: a\r\n\r\n".as_bytes());
+        assert!(matches!(parsed, Err(ParseError::ControlCharacter(_))));
+    }
+
+    #[test]
+    fn a_control_byte_anywhere_in_the_head_is_a_400() {
+        let lines = ["GET /books?id=7 HTTP/1.1", "Host: example.com", "User-Agent: test agent"];
+        // HTAB is allowed in header values, so it's only tried on the request line
+        let controls = [b'\0', 0x01, b'\r', b'\n', 0x1b, 0x1f, 0x7f];
+
+        for (index, line) in lines.iter().enumerate() {
+            for position in 0..=line.len() {
+                let tab = (index == 0).then_some(b'\t');
+                for control in controls.iter().copied().chain(tab) {
+                    let mut head = Vec::new();
+                    for (other, text) in lines.iter().enumerate() {
+                        let mut text = text.as_bytes().to_vec();
+                        if other == index {
+                            text.insert(position, control);
+                        }
+                        head.extend_from_slice(&text);
+                        head.extend_from_slice(b"\r\n");
+                    }
+                    head.extend_from_slice(b"\r\n");
+
+                    let parsed = Request::parse(&mut head.as_slice()).map(|_| ());
+                    assert_eq!(parsed.map_err(|e| e.status()), Err(400), "{:?}", head.escape_ascii().to_string());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keep_alive_follows_the_version_and_connection_header() {
+        let cases = [
+            ("HTTP/1.1", None, true),
+            ("HTTP/1.1", Some("keep-alive"), true),
+            ("HTTP/1.1", Some("close"), false),
+            ("HTTP/1.0", None, false),
+            ("HTTP/1.0", Some("keep-alive"), true),
+            ("HTTP/1.0", Some("close"), false)
+        ];
+
+        for (version, connection, expected) in cases {
+            let connection = connection.map(|value| format!("Connection: {value}\r\n")).unwrap_or_default();
+            let request = parse(&format!("GET / {version}\r\nHost: example.com\r\n{connection}\r\n"));
+            assert_eq!(request.is_keep_alive(), expected, "{version} with {connection:?}");
+        }
+    }
+
+    #[test]
+    fn connection_tokens_are_case_insensitive_and_listed() {
+        assert!(!parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, CLOSE\r\n\r\n").is_keep_alive());
+        assert!(parse("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").is_keep_alive());
+        // close wins when a client sends both
+        assert!(!parse("GET / HTTP/1.0\r\nConnection: keep-alive\r\nConnection: close\r\n\r\n").is_keep_alive());
+    }
+
+    #[test]
+    fn an_http_1_1_request_needs_exactly_one_host() {
+        assert_eq!(parse("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").host(), Some("example.com"));
+        assert_eq!(parse("GET / HTTP/1.1\r\nhost: example.com:8080\r\n\r\n").host(), Some("example.com:8080"));
+
+        let missing = refused("GET / HTTP/1.1\r\nAccept: */*\r\n\r\n");
+        assert!(matches!(missing, ParseError::Malformed("request without Host header")), "{missing}");
+        assert_eq!(missing.status(), 400);
+
+        // refused for 1.0 too: which one to believe is just as ambiguous there
+        for version in ["HTTP/1.1", "HTTP/1.0"] {
+            let duplicate = refused(&format!("GET / {version}\r\nHost: a.example\r\nHOST: b.example\r\n\r\n"));
+            assert!(matches!(duplicate, ParseError::Malformed("duplicate Host header")), "{duplicate}");
+            assert_eq!(duplicate.status(), 400);
+        }
+    }
+
+    #[test]
+    fn an_http_1_0_request_may_leave_out_host() {
+        assert_eq!(parse("GET / HTTP/1.0\r\n\r\n").host(), None);
+    }
+
+    #[test]
+    fn an_absolute_target_stands_in_for_host() {
+        let request = parse("GET http://example.com/books?id=1 HTTP/1.1\r\n\r\n");
+        assert_eq!(request.host(), Some("example.com"));
+        assert_eq!(request.path(), "/books");
+
+        // the target's authority wins over a Host header that disagrees
+        assert_eq!(parse("GET http://example.com/ HTTP/1.1\r\nHost: other.example\r\n\r\n").host(), Some("example.com"));
+    }
+
+    #[test]
+    fn content_length_is_read_as_declared() {
+        let post = |length: &str, body: &str| format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {length}\r\n\r\n{body}");
+        assert_eq!(parse("GET / HTTP/1.1\r\nHost: a\r\n\r\n").content_length(), None);
+        assert_eq!(parse(&post("0", "")).content_length(), Some(0));
+        assert_eq!(parse(&post("5", "hello")).content_length(), Some(5));
+        // repeats are fine as long as they agree
+        assert_eq!(parse(&post("5, 5", "hello")).content_length(), Some(5));
+        let chunked = parse("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+        assert_eq!(chunked.content_length(), None);
+    }
+
+    #[test]
+    fn a_malformed_content_length_is_a_400() {
+        let post = |length: &str| format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {length}\r\n\r\n");
+        for length in ["-1", "+5", "five", "5.0", "0x10", "", "5 5", "5,", "5, 6", "99999999999999999999999"] {
+            let error = refused(&post(length));
+            assert_eq!(error.status(), 400, "{length:?}: {error}");
+        }
+        let both = refused("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n");
+        assert_eq!(both.status(), 400);
+    }
+
+    #[test]
+    fn content_type_is_split_into_its_parts() {
+        let request = parse("POST / HTTP/1.1\r\nHost: a\r\nContent-Type: Text/HTML; Charset=\"utf-8\"\r\n\r\n");
+        let media_type = request.content_type().unwrap();
+        assert_eq!(media_type.essence(), "text/html");
+        assert_eq!(media_type.type_(), "text");
+        assert_eq!(media_type.subtype(), "html");
+        assert_eq!(media_type.charset(), Some("utf-8"));
+
+        assert!(parse("GET / HTTP/1.1\r\nHost: a\r\n\r\n").content_type().is_none());
+        for malformed in ["text", "text/", "/html", "text html", "te xt/html"] {
+            let request = parse(&format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Type: {malformed}\r\n\r\n"));
+            assert!(request.content_type().is_none(), "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn each_encoded_slash_policy_treats_2f_its_own_way() {
+        // None means a 400
+        let cases = [
+            ("/files/a%2Fb", [None, Some("/files/a/b"), Some("/files/a%2Fb")]),
+            ("/files/a%2fb", [None, Some("/files/a/b"), Some("/files/a%2fb")]),
+            ("/files/a/b", [Some("/files/a/b"), Some("/files/a/b"), Some("/files/a/b")]),
+            // decoding first would turn this into a climb out of /files
+            ("/files/..%2F..%2Fetc", [None, None, Some("/files/..%2F..%2Fetc")])
+        ];
+        let policies = [EncodedSlash::Reject, EncodedSlash::Decode, EncodedSlash::KeepEncoded];
+
+        for (target, expected) in cases {
+            for (policy, path) in policies.into_iter().zip(expected) {
+                let raw = format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+                let parsed = Request::parse_with(&mut raw.as_bytes(), policy);
+                match path {
+                    Some(path) => assert_eq!(parsed.unwrap().unwrap().path(), path, "{target} with {policy:?}"),
+                    None => assert_eq!(parsed.map(|_| ()).unwrap_err().status(), 400, "{target} with {policy:?}")
+                }
+            }
+        }
+        assert_eq!(EncodedSlash::default(), EncodedSlash::Reject);
+    }
+
+    #[test]
+    fn a_path_that_isnt_utf_8_once_decoded_is_a_400() {
+        for target in ["/%FF", "/a%C3", "/%C3%28", "/%E2%82"] {
+            let error = refused(&format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n"));
+            assert!(matches!(error, ParseError::Malformed("non UTF-8 path")), "{target}: {error}");
+        }
+        for target in ["/%", "/%4", "/%zz"] {
+            let error = refused(&format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n"));
+            assert!(matches!(error, ParseError::Malformed("percent-encoding in path")), "{target}: {error}");
+        }
+    }
+
+    #[test]
+    fn path_segments_skip_empty_ones_and_are_decoded() {
+        let cases: [(&str, &[&str]); 9] = [
+            ("/", &[]),
+            ("//", &[]),
+            ("/a/b/c", &["a", "b", "c"]),
+            ("/a/b/", &["a", "b"]),
+            ("//a//b//", &["a", "b"]),
+            ("/caf%C3%A9/a%20b", &["café", "a b"]),
+            // dot-segments were resolved at parse time; names made of more dots are kept
+            ("/a/../b/./c", &["b", "c"]),
+            ("/.../..x", &["...", "..x"]),
+            ("/a?b=/c/d", &["a"])
+        ];
+
+        for (target, segments) in cases {
+            let request = parse(&format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n"));
+            assert_eq!(request.path_segments().collect::<Vec<_>>(), segments, "{target}");
+        }
+    }
+
+    #[test]
+    fn a_kept_encoded_slash_stays_inside_its_segment() {
+        let raw = "GET /files/a%2Fb/c HTTP/1.1\r\nHost: a\r\n\r\n";
+        let request = Request::parse_with(&mut raw.as_bytes(), EncodedSlash::KeepEncoded).unwrap().unwrap();
+        assert_eq!(request.path_segments().collect::<Vec<_>>(), ["files", "a%2Fb", "c"]);
+    }
+
+    // A request whose body is still on the wire, as the server hands it to a handler.
+    fn lazy(raw: &'static str) -> Request {
+        let mut reader = std::io::BufReader::new(raw.as_bytes());
+        let mut request = Request::parse_head(&mut reader, EncodedSlash::default()).unwrap().unwrap();
+        request.attach_body(Box::new(reader));
+        request
+    }
+
+    #[test]
+    fn the_body_is_buffered_or_streamed_but_not_both() {
+        let raw = "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET /next HTTP/1.1\r\n";
+
+        let mut buffered = lazy(raw);
+        assert_eq!(buffered.body(), b"hello");
+        assert_eq!(buffered.body(), b"hello");
+        assert!(buffered.body_reader().is_none());
+
+        let mut streamed = lazy(raw);
+        let mut body = String::new();
+        streamed.body_reader().unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+        assert!(streamed.body().is_empty());
+
+        // the reader comes back at the start of the next request either way
+        let mut next = String::new();
+        streamed.detach_body().unwrap().read_to_string(&mut next).unwrap();
+        assert_eq!(next, "GET /next HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn only_short_plain_request_ids_are_valid() {
+        let cases = [
+            ("7", true),
+            ("0f8fad5b-d9cb-469f-a165-70867728950e", true),
+            ("trace:span.1_a", true),
+            ("", false),
+            ("has space", false),
+            ("new\r\nline", false),
+            ("quote\"", false),
+            ("caf\u{e9}", false)
+        ];
+        for (id, expected) in cases {
+            assert_eq!(is_valid_request_id(id), expected, "{id:?}");
+        }
+
+        assert!(is_valid_request_id(&"a".repeat(128)));
+        assert!(!is_valid_request_id(&"a".repeat(129)));
+    }
+
+    #[test]
+    fn every_line_of_a_repeated_header_is_kept_in_order() {
+        let request = parse("GET / HTTP/1.1\r\nHost: x\r\nVia: 1.1 a\r\nAccept: */*\r\nvia: 1.0 b, 1.1 c\r\n\r\n");
+
+        assert_eq!(request.header("Via"), Some("1.1 a"));
+        assert_eq!(request.header_all("VIA").collect::<Vec<_>>(), ["1.1 a", "1.0 b, 1.1 c"]);
+        assert_eq!(request.header_all("Forwarded").count(), 0);
+    }
+}
@@ -0,0 +1,965 @@
+use std::{
+    fmt::{Debug, Formatter},
+    fs::File,
+    io::{self, Cursor, Read, Write},
+    path::Path,
+};
+use crate::{http_date, json, mime, request::{Request, Version}, trailers::{TrailerError, Trailers}};
+
+/// Known-length streamed bodies up to this size are read into memory and sent in one piece,
+/// unless the server is configured otherwise.
+pub const DEFAULT_STREAM_THRESHOLD: usize = 64 * 1024;
+
+/// An HTTP response built by a handler and serialized by the server.
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Body,
+    trailers: Option<Trailers>,
+    omit_body: bool,
+    vary: Vec<String>
+}
+
+/// What a response's body is made of.
+pub enum Body {
+    /// Bytes already in memory.
+    Bytes(Vec<u8>),
+    /// A reader that produces the body as it is sent, with its length if known up front.
+    Stream { reader: Box<dyn Read + Send>, length: Option<u64> }
+}
+
+/*
+    Which redirect status to use. 301 and 302 predate the distinction, and browsers have long
+    turned a redirected POST into a GET (dropping the body) on both, which the RFC now allows;
+    307 and 308 were added to forbid that, so the client repeats the same method and body at
+    the new location. For GET and HEAD the two mean the same, and the older codes are understood
+    by every client, so those are kept there.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redirect {
+    /// The resource has moved for good: 301 for GET and HEAD, 308 for other methods.
+    Permanent,
+    /// Go elsewhere this time only: 302 for GET and HEAD, 307 for other methods.
+    Temporary
+}
+
+impl Redirect {
+    /// The status this kind of redirect is sent with for a request using `method`.
+    pub fn status_for(self, method: &str) -> u16 {
+        let preserves_anyway = matches!(method, "GET" | "HEAD");
+        match self {
+            Redirect::Permanent if preserves_anyway => 301,
+            Redirect::Permanent => 308,
+            Redirect::Temporary if preserves_anyway => 302,
+            Redirect::Temporary => 307
+        }
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Stream { length, .. } => f.debug_struct("Stream").field("length", length).finish()
+        }
+    }
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self { status, headers: Vec::new(), body: Body::Bytes(Vec::new()), trailers: None, omit_body: false, vary: Vec::new() }
+    }
+
+    /// A `text/html` response with the given status.
+    pub fn html(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "text/html")
+            .with_body(body)
+    }
+
+    /// An `application/json` response with `value` serialized as the body.
+    pub fn json(status: u16, value: &json::Value) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(value.to_string())
+    }
+
+    /// A `200` with the file at `path` as its body, for a handler serving a file of its own
+    /// choosing. `Content-Type` comes from the extension, or from sniffing the first bytes
+    /// if it doesn't name one, and `Last-Modified` from the file's metadata, as does the length.
+    /// A file up to `DEFAULT_STREAM_THRESHOLD` bytes is read into memory, where compression
+    /// can reach it; a larger one is streamed from the open file as the response is sent.
+    /// Use `from_file_with_threshold` on a server configured with another `stream_threshold`.
+    ///
+    /// Fails with the error from opening the file, `NotFound` for a missing one, which the
+    /// handler can turn into a 404. A directory is `InvalidInput`. Conditional and range
+    /// requests are up to the caller; `StaticFiles` handles both.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_file_with_threshold(path, DEFAULT_STREAM_THRESHOLD)
+    }
+
+    /// Like `from_file`, reading the file into memory only if it is at most `stream_threshold`
+    /// bytes, e.g. the server's `ServerConfig::stream_threshold`.
+    pub fn from_file_with_threshold(path: impl AsRef<Path>, stream_threshold: usize) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file", path.display())));
+        }
+        let length = metadata.len();
+
+        let mut response = Self::new(200);
+        if let Ok(modified) = metadata.modified() {
+            response.set_header("Last-Modified", &http_date::format(modified));
+        }
+        if length <= stream_threshold as u64 {
+            let mut contents = Vec::with_capacity(length as usize);
+            file.read_to_end(&mut contents)?;
+            response.set_header("Content-Type", mime::for_path(path, &contents));
+            return Ok(response.with_body(contents));
+        }
+
+        // the bytes sniffed for the type are still part of the body, so they go out ahead of the rest
+        let mut head = Vec::with_capacity(mime::SNIFF_LENGTH);
+        (&mut file).take(mime::SNIFF_LENGTH as u64).read_to_end(&mut head)?;
+        response.set_header("Content-Type", mime::for_path(path, &head));
+        Ok(response.with_reader(Cursor::new(head).chain(file), Some(length)))
+    }
+
+    /// A bodyless response carrying only a status line, e.g. for errors raised before routing.
+    pub fn status_only(status: u16) -> Self {
+        Self::new(status).with_body(reason_phrase(status))
+    }
+
+    /// A redirect to `location`, with the status `kind` calls for given the request's method,
+    /// so that a redirected POST stays a POST. See `Redirect`.
+    pub fn redirect(request: &Request, kind: Redirect, location: &str) -> Self {
+        Self::redirect_with_status(kind.status_for(request.method()), location)
+    }
+
+    /// A redirect to `location` with exactly `status`, e.g. 303 to send a POST's client on to
+    /// a GET of the result.
+    pub fn redirect_with_status(status: u16, location: &str) -> Self {
+        Self::status_only(status).with_header("Location", location)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.set_header(name, value);
+        self
+    }
+
+    /// Builder form of `vary`.
+    pub fn with_vary(mut self, field: &str) -> Self {
+        self.vary(field);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// Makes the body whatever `reader` produces, read while the response is being sent.
+    /// `length` is the exact number of bytes it will produce, if known; see `write_to` for
+    /// how that decides the framing.
+    pub fn with_reader(mut self, reader: impl Read + Send + 'static, length: Option<u64>) -> Self {
+        self.body = Body::Stream { reader: Box::new(reader), length };
+        self
+    }
+
+    /// Declares the trailer fields `names`, which are announced in a `Trailer` header and sent
+    /// after the body, with whatever values the returned handle was given by then. Call it once
+    /// the body is set: only a stream of unknown length is chunked, and only a chunked body
+    /// can carry trailers, which rules out HTTP/1.0 clients as well.
+    pub fn trailers(&mut self, request: &Request, names: &[&str]) -> Result<Trailers, TrailerError> {
+        if request.version() == Version::Http10 {
+            return Err(TrailerError::Http10);
+        }
+        if !matches!(self.body, Body::Stream { length: None, .. }) || self.is_bodyless() {
+            return Err(TrailerError::NotChunked);
+        }
+
+        let trailers = Trailers::declare(names)?;
+        self.trailers = Some(trailers.clone());
+        Ok(trailers)
+    }
+
+    /// Replaces any existing header with the same (case-insensitive) name. A `Content-Length`
+    /// or `Transfer-Encoding` set here is never sent: the framing is worked out from the body
+    /// when the response is written. Debug builds warn when the one set disagrees with how the
+    /// body goes out; release builds skip that check.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.remove_header(name);
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Adds a header line, keeping any others of the same name, for headers that have to
+    /// repeat rather than be combined into one line, like `Set-Cookie`.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Adds every header line of `other` whose name this response doesn't have yet, repeats
+    /// included, and the request headers `other` varies on. For wrapping one response in
+    /// another, e.g. a bare error in a page.
+    pub fn inherit_headers(&mut self, other: &Response) {
+        let inherited: Vec<(String, String)> = other.headers
+            .iter()
+            .filter(|(name, _)| self.header(name).is_none())
+            .cloned()
+            .collect();
+        self.headers.extend(inherited);
+        other.vary.iter().for_each(|field| self.vary(field));
+    }
+
+    /// Records that the response depends on the request header `field` (`Accept-Encoding`,
+    /// `Origin`, ...), so shared caches keep a variant per value of it. Whatever calls this,
+    /// handler or middleware, the fields are merged with any `Vary` header the handler set
+    /// when the response is sent: each listed once, compared case-insensitively, or only `*`
+    /// if that is among them. Until then `header("Vary")` shows the handler's header alone.
+    pub fn vary(&mut self, field: &str) {
+        let field = field.trim();
+        if !field.is_empty() && !self.vary.iter().any(|listed| listed.eq_ignore_ascii_case(field)) {
+            self.vary.push(field.to_string());
+        }
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The first value of the header `name`, compared case-insensitively; see `header_all`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the header `name`, one per header line, in order.
+    pub fn header_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The body, if it is in memory. Empty for a streamed body, which can only be read once,
+    /// while it is sent.
+    pub fn body(&self) -> &[u8] {
+        match &self.body {
+            Body::Bytes(bytes) => bytes,
+            Body::Stream { .. } => &[]
+        }
+    }
+
+    /// The body's length, if known before sending it: always for bytes, not always for a stream.
+    pub fn body_len(&self) -> Option<u64> {
+        match &self.body {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } => *length
+        }
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        matches!(self.body, Body::Stream { .. })
+    }
+
+    /// Whether the connection has to close after this response: an HTTP/1.0 client can't be
+    /// sent chunks, so a body of unknown length is delimited by closing the connection.
+    pub fn needs_close(&self, version: Version) -> bool {
+        !self.omit_body
+            && version == Version::Http10 && matches!(self.body, Body::Stream { length: None, .. }) && !self.is_bodyless()
+    }
+
+    /*
+        Answers a HEAD request: the head goes out with the framing headers the body would have
+        had (its Content-Length, or chunked), and the body is never read. A handler can so
+        describe a body it never produces, as a stream of known length that's empty.
+     */
+    pub(crate) fn omit_body(&mut self) {
+        self.omit_body = true;
+    }
+
+    fn is_bodyless(&self) -> bool {
+        matches!(self.status, 204 | 304)
+    }
+
+    /// Serializes the response. `Content-Length`, `Transfer-Encoding` and `Connection` are
+    /// always computed here, overriding anything the handler set, so the framing can't disagree
+    /// with the body. Returns the number of body bytes sent.
+    ///
+    /// How the body goes out is decided here, by what it is:
+    /// - bytes in memory are sent with `Content-Length`;
+    /// - a stream of known length up to `DEFAULT_STREAM_THRESHOLD` is read into memory first
+    ///   and sent the same way, which costs little and takes the fewest writes;
+    /// - a longer stream of known length is sent with `Content-Length`, copied through a
+    ///   fixed-size buffer so memory use doesn't grow with the body;
+    /// - a stream of unknown length is sent chunked, or to HTTP/1.0 clients (which don't
+    ///   understand chunks) as is, with the connection closed afterwards to mark its end.
+    ///
+    /// `204` and `304` responses are sent without a body or `Content-Length`, as HTTP requires.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W, version: Version, keep_alive: bool) -> io::Result<u64> {
+        self.write_buffered(writer, version, keep_alive, &mut Vec::new(), DEFAULT_STREAM_THRESHOLD)
+    }
+
+    /*
+        Serializes through `buffer`, which is left empty but keeps its capacity for reuse. The head
+        and a small body go out in a single write; a body that doesn't fit the buffer's spare
+        capacity is written straight from the response instead of being copied. A stream longer
+        than `stream_threshold` (or of unknown length) is copied through the buffer in pieces.
+     */
+    pub(crate) fn write_buffered<W: Write>(
+        &mut self,
+        writer: &mut W,
+        version: Version,
+        keep_alive: bool,
+        buffer: &mut Vec<u8>,
+        stream_threshold: usize
+    ) -> io::Result<u64> {
+        let bodyless = self.is_bodyless();
+        let keep_alive = keep_alive && !self.needs_close(version);
+        self.merge_vary();
+        if !bodyless && !self.omit_body {
+            self.buffer_small_stream(stream_threshold)?;
+        }
+        let chunked = !bodyless && version == Version::Http11 && self.body_len().is_none();
+        if cfg!(debug_assertions) && !bodyless {
+            for problem in self.framing_problems(chunked) {
+                eprintln!("Warning: {problem}");
+            }
+        }
+        buffer.clear();
+
+        write!(buffer, "{} {} {}\r\n", version.as_str(), self.status, reason_phrase(self.status))?;
+        for (name, value) in &self.headers {
+            if is_framing_header(name) {
+                continue;
+            }
+            write!(buffer, "{name}: {value}\r\n")?;
+        }
+        // trailers are only declared if they'll be sent, which takes a chunked body
+        let trailers = self.trailers.as_ref().filter(|_| chunked);
+        if chunked {
+            buffer.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+            if let Some(trailers) = trailers {
+                write!(buffer, "Trailer: {}\r\n", trailers.header_value())?;
+            }
+        } else if let (false, Some(length)) = (bodyless, self.body_len()) {
+            write!(buffer, "Content-Length: {length}\r\n")?;
+        }
+        buffer.extend_from_slice(if keep_alive { b"Connection: keep-alive\r\n" } else { b"Connection: close\r\n" });
+        buffer.extend_from_slice(b"\r\n");
+
+        if bodyless || self.omit_body {
+            writer.write_all(buffer)?;
+            buffer.clear();
+            return writer.flush().map(|_| 0);
+        }
+
+        let sent = match &mut self.body {
+            Body::Bytes(body) => {
+                let inline_body = body.len() <= buffer.capacity() - buffer.len();
+                if inline_body {
+                    buffer.extend_from_slice(body);
+                }
+                // write_all, never a bare write: a socket may take only part of a buffer per call, and a
+                // write that makes no progress at all surfaces as WriteZero instead of a truncated response
+                writer.write_all(buffer)?;
+                if !inline_body {
+                    writer.write_all(body)?;
+                }
+                body.len() as u64
+            }
+            Body::Stream { reader, length } => {
+                writer.write_all(buffer)?;
+                match *length {
+                    Some(length) => copy_exact(reader, writer, length, buffer)?,
+                    None if chunked => copy_chunked(reader, writer, buffer, trailers)?,
+                    None => copy_to_end(reader, writer, buffer)?
+                }
+            }
+        };
+        buffer.clear();
+
+        writer.flush()?;
+        Ok(sent)
+    }
+
+    /*
+        Folds the recorded vary fields into the Vary header lines the handler set, leaving one
+        line: the handler's fields first, in their order, then the recorded ones not already
+        listed. `*` says the response varies on more than headers, so it stands alone. Done in
+        place rather than while writing, so the access log sees the header that was sent.
+     */
+    fn merge_vary(&mut self) {
+        if self.vary.is_empty() {
+            return;
+        }
+
+        let mut fields: Vec<String> = Vec::new();
+        let set = self.header_all("Vary").flat_map(|value| value.split(','));
+        for field in set.chain(self.vary.iter().map(String::as_str)).map(str::trim) {
+            if !field.is_empty() && !fields.iter().any(|listed| listed.eq_ignore_ascii_case(field)) {
+                fields.push(field.to_string());
+            }
+        }
+        let value = if fields.iter().any(|field| field == "*") { "*".to_string() } else { fields.join(", ") };
+
+        self.vary.clear();
+        self.set_header("Vary", &value);
+    }
+
+    // A stream known to be short is cheaper to read up front and send like any in-memory body.
+    fn buffer_small_stream(&mut self, stream_threshold: usize) -> io::Result<()> {
+        let Body::Stream { reader, length: Some(length) } = &mut self.body else {
+            return Ok(());
+        };
+        if *length > stream_threshold as u64 {
+            return Ok(());
+        }
+
+        let mut bytes = Vec::with_capacity(*length as usize);
+        reader.take(*length).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < *length {
+            return Err(short_body(*length, bytes.len() as u64));
+        }
+        self.body = Body::Bytes(bytes);
+        Ok(())
+    }
+}
+
+// The buffer's capacity, but never less than this, is how much of a stream is held at a time.
+const MIN_COPY_SIZE: usize = 8 * 1024;
+
+// Copies exactly `length` bytes; a reader that ends early leaves the response short, which is an error.
+fn copy_exact<W: Write>(reader: &mut (dyn Read + Send), writer: &mut W, length: u64, buffer: &mut Vec<u8>) -> io::Result<u64> {
+    let mut sent = 0;
+    while sent < length {
+        let n = read_piece(reader, buffer, length - sent)?;
+        if n == 0 {
+            return Err(short_body(length, sent));
+        }
+        writer.write_all(&buffer[..n])?;
+        sent += n as u64;
+    }
+    Ok(sent)
+}
+
+/*
+    Each read becomes one chunk, so a slow producer's output reaches the client as it is made.
+    Trailers are read only once the reader is exhausted, so values it set along the way (a
+    digest of everything it produced, say) are complete.
+ */
+fn copy_chunked<W: Write>(
+    reader: &mut (dyn Read + Send),
+    writer: &mut W,
+    buffer: &mut Vec<u8>,
+    trailers: Option<&Trailers>
+) -> io::Result<u64> {
+    let mut sent = 0;
+    loop {
+        let n = read_piece(reader, buffer, u64::MAX)?;
+        if n == 0 {
+            writer.write_all(b"0\r\n")?;
+            if let Some(trailers) = trailers {
+                trailers.write_fields(writer)?;
+            }
+            writer.write_all(b"\r\n")?;
+            return Ok(sent);
+        }
+        write!(writer, "{n:x}\r\n")?;
+        writer.write_all(&buffer[..n])?;
+        writer.write_all(b"\r\n")?;
+        sent += n as u64;
+    }
+}
+
+fn copy_to_end<W: Write>(reader: &mut (dyn Read + Send), writer: &mut W, buffer: &mut Vec<u8>) -> io::Result<u64> {
+    let mut sent = 0;
+    loop {
+        let n = read_piece(reader, buffer, u64::MAX)?;
+        if n == 0 {
+            return Ok(sent);
+        }
+        writer.write_all(&buffer[..n])?;
+        sent += n as u64;
+    }
+}
+
+// Reads at most `limit` bytes (and at most a buffer's worth) into the start of `buffer`.
+fn read_piece(reader: &mut (dyn Read + Send), buffer: &mut Vec<u8>, limit: u64) -> io::Result<usize> {
+    let size = buffer.capacity().max(MIN_COPY_SIZE);
+    buffer.resize(size, 0);
+    let wanted = (size as u64).min(limit) as usize;
+
+    loop {
+        match reader.read(&mut buffer[..wanted]) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result
+        }
+    }
+}
+
+fn short_body(expected: u64, got: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("response body ended after {got} of its {expected} bytes")
+    )
+}
+
+// Headers describing how the body is delimited, which only the serializer may set.
+fn is_framing_header(name: &str) -> bool {
+    ["Content-Length", "Transfer-Encoding", "Connection", "Trailer"]
+        .iter()
+        .any(|framing| name.eq_ignore_ascii_case(framing))
+}
+
+impl Response {
+    /*
+        A handler-set Content-Length or Transfer-Encoding is never sent, but one that disagrees
+        with how the body actually goes out means the handler is wrong about its own response:
+        it truncated the body, say, counted characters rather than bytes, gave the length of
+        the body before it was gzipped, or declared a length for a body sent in chunks. Debug
+        builds print each problem found here; release builds don't look, and just send the
+        right framing.
+     */
+    fn framing_problems(&self, chunked: bool) -> Vec<String> {
+        let declared = |name: &'static str| self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim());
+        let mut problems = Vec::new();
+
+        for transfer_encoding in declared("Transfer-Encoding").filter(|_| !chunked) {
+            problems.push(format!(
+                "a {} response was given Transfer-Encoding: {transfer_encoding} but isn't sent chunked; dropping it.",
+                self.status
+            ));
+        }
+
+        let Some(length) = self.body_len() else {
+            let framing = if chunked { "is sent chunked" } else { "ends where the connection closes" };
+            for declared in declared("Content-Length") {
+                problems.push(format!(
+                    "a {} response was given Content-Length: {declared} but {framing}; dropping it.",
+                    self.status
+                ));
+            }
+            return problems;
+        };
+        // the usual way to get it wrong once the body is compressed
+        let encoded = match self.header("Content-Encoding") {
+            Some(encoding) => format!(" (with Content-Encoding: {encoding}, the length is that of the encoded bytes)"),
+            None => String::new()
+        };
+        for declared in declared("Content-Length") {
+            if declared.parse::<u64>().ok() != Some(length) {
+                problems.push(format!(
+                    "a {} response was given Content-Length: {declared} but has a {length}-byte body{encoded}; sending {length} instead.",
+                    self.status
+                ));
+            }
+        }
+        problems
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_status_the_crate_sends_has_its_reason_phrase() {
+        let sent = [200, 201, 204, 206, 301, 302, 303, 304, 307, 308, 400, 404, 405, 408, 409, 412, 413, 415, 416, 500, 501, 502, 503, 504, 505];
+        for status in sent {
+            assert_ne!(reason_phrase(status), "Unknown", "{status}");
+        }
+        assert_eq!(reason_phrase(409), "Conflict");
+        assert_eq!(reason_phrase(415), "Unsupported Media Type");
+        assert_eq!(reason_phrase(502), "Bad Gateway");
+    }
+
+    // A writer that takes only 1 to 3 bytes per call, as a socket under pressure may.
+    #[derive(Default)]
+    struct Trickle {
+        written: Vec<u8>,
+        calls: usize
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            let n = bytes.len().min(self.calls % 3 + 1);
+            self.written.extend_from_slice(&bytes[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Sends `response` to an HTTP/1.1 keep-alive client through a Trickle, returning what arrived.
+    fn trickled(mut response: Response, stream_threshold: usize) -> String {
+        let mut writer = Trickle::default();
+        response.write_buffered(&mut writer, Version::Http11, true, &mut Vec::new(), stream_threshold).unwrap();
+        String::from_utf8(writer.written).unwrap()
+    }
+
+    // Keeps everything written, and the size of each write call.
+    #[derive(Default)]
+    struct Recorder {
+        written: Vec<u8>,
+        writes: Vec<usize>
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(bytes);
+            self.writes.push(bytes.len());
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Sends `response` to an HTTP/1.1 keep-alive client with `stream_threshold`; returns the head and the writer.
+    fn recorded(mut response: Response, stream_threshold: usize) -> (String, Recorder) {
+        let mut writer = Recorder::default();
+        // a pooled buffer, as the server passes, with room for the head and a small body
+        let mut buffer = Vec::with_capacity(4096);
+        response.write_buffered(&mut writer, Version::Http11, true, &mut buffer, stream_threshold).unwrap();
+        let end = writer.written.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        (String::from_utf8(writer.written[..end].to_vec()).unwrap(), writer)
+    }
+
+    #[test]
+    fn a_body_in_memory_is_sent_with_its_length_in_one_write() {
+        let (head, writer) = recorded(Response::new(200).with_body(vec![b'x'; 100]), 16);
+        assert!(head.contains("Content-Length: 100\r\n"));
+        assert_eq!(writer.writes.len(), 1);
+    }
+
+    #[test]
+    fn a_stream_of_unknown_length_is_chunked() {
+        let (head, writer) = recorded(Response::new(200).with_reader(Cursor::new(vec![b'x'; 100]), None), 16);
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+        let mut chunks = b"64\r\n".to_vec();
+        chunks.extend_from_slice(&[b'x'; 100]);
+        chunks.extend_from_slice(b"\r\n0\r\n\r\n");
+        assert!(writer.written.ends_with(&chunks));
+    }
+
+    #[test]
+    fn a_short_stream_of_known_length_is_read_up_front() {
+        let (head, writer) = recorded(Response::new(200).with_reader(Cursor::new(vec![b'x'; 100]), Some(100)), 1024);
+        assert!(head.contains("Content-Length: 100\r\n"));
+        // read into memory, so it goes out with the head like any body in memory
+        assert_eq!(writer.writes.len(), 1);
+    }
+
+    #[test]
+    fn a_long_stream_of_known_length_is_copied_a_buffer_at_a_time() {
+        let length = 10 * MIN_COPY_SIZE + 5;
+        let (head, writer) = recorded(Response::new(200).with_reader(Cursor::new(vec![b'x'; length]), Some(length as u64)), 1024);
+        assert!(head.contains(&format!("Content-Length: {length}\r\n")));
+        assert!(!head.contains("Transfer-Encoding"));
+        assert_eq!(writer.written.len(), head.len() + length);
+        // the body never passes through in more than a buffer's worth at once
+        assert!(writer.writes.len() > 10);
+        assert!(writer.writes.iter().all(|&size| size <= MIN_COPY_SIZE), "{:?}", writer.writes);
+    }
+
+    #[test]
+    fn from_file_streams_only_files_over_the_threshold() {
+        let dir = crate::temp_dir::TempDir::new();
+        let path = dir.write("page.html", "<p>0123456789</p>");
+
+        let buffered = Response::from_file_with_threshold(&path, 1024).unwrap();
+        assert!(!buffered.is_streaming());
+        assert_eq!(buffered.body(), b"<p>0123456789</p>");
+        assert_eq!(buffered.header("Content-Type"), Some("text/html"));
+
+        let streamed = Response::from_file_with_threshold(&path, 8).unwrap();
+        assert!(streamed.is_streaming());
+        assert_eq!(streamed.body_len(), Some(17));
+        assert_eq!(streamed.header("Content-Type"), Some("text/html"));
+        assert!(streamed.header("Last-Modified").is_some());
+
+        assert_eq!(Response::from_file(dir.path()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Response::from_file(dir.path().join("missing")).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn a_body_in_memory_survives_partial_writes() {
+        let response = Response::new(200).with_body("hello world");
+        assert_eq!(
+            trickled(response, DEFAULT_STREAM_THRESHOLD),
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: keep-alive\r\n\r\nhello world"
+        );
+    }
+
+    #[test]
+    fn a_known_length_stream_survives_partial_writes() {
+        let body = "0123456789".repeat(10);
+        // over the threshold, so it is copied through the buffer rather than read up front
+        let response = Response::new(200).with_reader(Cursor::new(body.clone()), Some(100));
+        assert_eq!(
+            trickled(response, 16),
+            format!("HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: keep-alive\r\n\r\n{body}")
+        );
+    }
+
+    #[test]
+    fn a_chunked_stream_with_trailers_survives_partial_writes() {
+        let request = Request::parse(&mut "GET / HTTP/1.1\r\nHost: test\r\n\r\n".as_bytes()).unwrap().unwrap();
+        // each read of the chain becomes a chunk of its own
+        let mut response = Response::new(200).with_reader(Cursor::new("abc").chain(Cursor::new("defgh")), None);
+        let trailers = response.trailers(&request, &["X-Checksum"]).unwrap();
+        trailers.set("X-Checksum", "1234").unwrap();
+
+        assert_eq!(
+            trickled(response, DEFAULT_STREAM_THRESHOLD),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\nConnection: keep-alive\r\n\r\n\
+             3\r\nabc\r\n5\r\ndefgh\r\n0\r\nX-Checksum: 1234\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn added_headers_go_out_one_line_each() {
+        let mut response = Response::html(200, "hi");
+        response.add_header("Set-Cookie", "a=1");
+        response.add_header("set-cookie", "b=2");
+        assert_eq!(response.header("Set-Cookie"), Some("a=1"));
+        assert_eq!(response.header_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+
+        let (head, _) = recorded(response, DEFAULT_STREAM_THRESHOLD);
+        assert!(head.contains("\r\nSet-Cookie: a=1\r\nset-cookie: b=2\r\n"), "{head}");
+
+        // set_header, by contrast, replaces them all
+        let response = Response::new(200).with_header("Set-Cookie", "a=1").with_header("Set-Cookie", "c=3");
+        assert_eq!(response.header_all("Set-Cookie").collect::<Vec<_>>(), ["c=3"]);
+    }
+
+    #[test]
+    fn a_wrapping_response_inherits_only_the_names_it_lacks() {
+        let mut inner = Response::status_only(401).with_header("WWW-Authenticate", "Basic").with_header("Content-Type", "text/plain");
+        inner.add_header("Set-Cookie", "a=1");
+        inner.add_header("Set-Cookie", "b=2");
+
+        let mut page = Response::html(401, "<h1>Log in</h1>");
+        page.inherit_headers(&inner);
+        assert_eq!(page.header_all("Content-Type").collect::<Vec<_>>(), ["text/html"]);
+        assert_eq!(page.header("WWW-Authenticate"), Some("Basic"));
+        assert_eq!(page.header_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+    }
+
+    // The Vary lines that go out for a response with `set` as header lines and `fields` passed to vary().
+    fn sent_vary(set: &[&str], fields: &[&str]) -> Vec<String> {
+        let mut response = Response::new(200);
+        set.iter().for_each(|value| response.add_header("Vary", value));
+        fields.iter().for_each(|field| response.vary(field));
+        let (head, _) = recorded(response, DEFAULT_STREAM_THRESHOLD);
+        head.lines().filter_map(|line| line.strip_prefix("Vary: ")).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn vary_fields_are_merged_into_one_line_when_sent() {
+        let cases: [(&[&str], &[&str], &[&str]); 7] = [
+            (&[], &[], &[]),
+            (&["Origin"], &[], &["Origin"]),
+            (&[], &["Accept-Encoding", "Accept"], &["Accept-Encoding, Accept"]),
+            (&["Origin, Accept"], &["accept", "Accept-Encoding"], &["Origin, Accept, Accept-Encoding"]),
+            (&["Origin", "Cookie"], &["Accept"], &["Origin, Cookie, Accept"]),
+            (&["*"], &["Accept"], &["*"]),
+            (&[], &["Accept", " ", "*"], &["*"])
+        ];
+        for (set, fields, expected) in cases {
+            assert_eq!(sent_vary(set, fields), expected, "{set:?} + {fields:?}");
+        }
+    }
+
+    #[test]
+    fn a_wrapping_response_varies_on_what_it_wraps() {
+        let inner = Response::status_only(404).with_vary("Accept");
+        let mut page = Response::html(404, "<h1>Not Found</h1>").with_vary("Accept-Language");
+        page.inherit_headers(&inner);
+        // only merged on the way out
+        assert_eq!(page.header("Vary"), None);
+
+        let (head, _) = recorded(page, DEFAULT_STREAM_THRESHOLD);
+        assert!(head.contains("\r\nVary: Accept-Language, Accept\r\n"), "{head}");
+    }
+
+    #[test]
+    fn an_omitted_body_keeps_its_framing_headers() {
+        let mut sized = Response::new(200).with_body("hello");
+        sized.omit_body();
+        let (head, writer) = recorded(sized, DEFAULT_STREAM_THRESHOLD);
+        assert!(head.contains("Content-Length: 5\r\n"), "{head}");
+        assert_eq!(writer.written.len(), head.len());
+
+        // the reader is never touched
+        let mut streamed = Response::new(200).with_reader(io::repeat(b'x'), None);
+        streamed.omit_body();
+        assert!(!streamed.needs_close(Version::Http10));
+        let (head, writer) = recorded(streamed, DEFAULT_STREAM_THRESHOLD);
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{head}");
+        assert_eq!(writer.written.len(), head.len());
+    }
+
+    #[test]
+    fn trailers_need_a_chunked_body_and_an_http_1_1_client() {
+        let request = Request::parse(&mut "GET / HTTP/1.1\r\nHost: test\r\n\r\n".as_bytes()).unwrap().unwrap();
+        let old_client = Request::parse(&mut "GET / HTTP/1.0\r\n\r\n".as_bytes()).unwrap().unwrap();
+
+        let mut sized = Response::new(200).with_body("abc");
+        assert_eq!(sized.trailers(&request, &["X-Checksum"]).unwrap_err(), TrailerError::NotChunked);
+        let mut streamed = Response::new(200).with_reader(Cursor::new("abc"), None);
+        assert_eq!(streamed.trailers(&old_client, &["X-Checksum"]).unwrap_err(), TrailerError::Http10);
+        assert_eq!(streamed.trailers(&request, &["Content-Type"]).unwrap_err(), TrailerError::Forbidden("Content-Type".to_string()));
+        assert!(streamed.trailers(&request, &["X-Checksum"]).is_ok());
+    }
+
+    #[test]
+    fn a_content_length_that_disagrees_with_the_body_is_reported_and_replaced() {
+        let response = Response::new(200).with_header("Content-Length", "3").with_body("hello");
+        let problems = response.framing_problems(false);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("Content-Length: 3 but has a 5-byte body"), "{}", problems[0]);
+
+        let (head, _) = recorded(response, DEFAULT_STREAM_THRESHOLD);
+        assert!(head.contains("Content-Length: 5\r\n"));
+        assert!(!head.contains("Content-Length: 3"));
+    }
+
+    #[test]
+    fn a_wrong_length_on_an_encoded_body_says_which_length_counts() {
+        let response = Response::new(200)
+            .with_header("Content-Encoding", "gzip")
+            .with_header("Content-Length", "100")
+            .with_body(vec![0; 40]);
+        let problems = response.framing_problems(false);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("the length is that of the encoded bytes"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn a_correct_or_absent_content_length_is_no_problem() {
+        assert!(Response::new(200).with_body("hello").framing_problems(false).is_empty());
+        assert!(Response::new(200).with_header("Content-Length", " 5 ").with_body("hello").framing_problems(false).is_empty());
+    }
+
+    #[test]
+    fn a_content_length_on_a_chunked_body_is_reported_and_dropped() {
+        let response = Response::new(200)
+            .with_header("Content-Length", "100")
+            .with_reader(Cursor::new(vec![b'x'; 100]), None);
+        let problems = response.framing_problems(true);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("Content-Length: 100 but is sent chunked"), "{}", problems[0]);
+
+        let (head, _) = recorded(response, 16);
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn a_content_length_on_a_close_delimited_body_is_reported() {
+        let response = Response::new(200)
+            .with_header("Content-Length", "100")
+            .with_reader(Cursor::new(vec![b'x'; 100]), None);
+        let problems = response.framing_problems(false);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("ends where the connection closes"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn a_transfer_encoding_on_a_body_with_a_length_is_reported_and_dropped() {
+        let response = Response::new(200).with_header("Transfer-Encoding", "chunked").with_body("hello");
+        let problems = response.framing_problems(false);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("Transfer-Encoding: chunked but isn't sent chunked"), "{}", problems[0]);
+
+        let (head, _) = recorded(response, DEFAULT_STREAM_THRESHOLD);
+        assert!(head.contains("Content-Length: 5\r\n"));
+        assert!(!head.contains("Transfer-Encoding"));
+    }
+
+    #[test]
+    fn a_redirect_keeps_the_method_of_anything_but_get_and_head() {
+        let cases = [
+            ("GET", Redirect::Permanent, 301),
+            ("HEAD", Redirect::Permanent, 301),
+            ("POST", Redirect::Permanent, 308),
+            ("DELETE", Redirect::Permanent, 308),
+            ("GET", Redirect::Temporary, 302),
+            ("HEAD", Redirect::Temporary, 302),
+            ("POST", Redirect::Temporary, 307),
+            ("PUT", Redirect::Temporary, 307)
+        ];
+        for (method, kind, expected) in cases {
+            assert_eq!(kind.status_for(method), expected, "{method} {kind:?}");
+        }
+    }
+
+    #[test]
+    fn a_redirect_goes_by_the_request_method_unless_the_status_is_given() {
+        let post = Request::parse(&mut &b"POST /old HTTP/1.1\r\nHost: x\r\nContent-Length: 0\r\n\r\n"[..]).unwrap().unwrap();
+        let response = Response::redirect(&post, Redirect::Temporary, "/new");
+        assert_eq!(response.status(), 307);
+        assert_eq!(response.header("Location"), Some("/new"));
+
+        let get = Request::parse(&mut &b"GET /old HTTP/1.1\r\nHost: x\r\n\r\n"[..]).unwrap().unwrap();
+        assert_eq!(Response::redirect(&get, Redirect::Temporary, "/new").status(), 302);
+
+        let response = Response::redirect_with_status(303, "/books/7");
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.header("Location"), Some("/books/7"));
+    }
+}
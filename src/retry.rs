@@ -0,0 +1,126 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::Duration,
+};
+
+/// How `retry` spaces out attempts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of tries, including the first one. Zero behaves like one.
+    pub max_attempts: u32,
+    /// Backoff before the second attempt; doubles after every failure.
+    pub base_delay: Duration,
+    /// Cap on the backoff, however many failures came before.
+    pub max_delay: Duration,
+    /// Sleep a random duration between zero and the backoff instead of the backoff itself.
+    pub jitter: bool
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(2),
+            jitter: true
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The longest we wait after the `failures`-th consecutive failure (1-based).
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    fn delay(&self, failures: u32) -> Duration {
+        let backoff = self.backoff(failures);
+        if !self.jitter || backoff.is_zero() {
+            return backoff;
+        }
+
+        // full jitter: uniform in [0, backoff], so clients that failed together don't retry together
+        let random = RandomState::new().build_hasher().finish();
+        backoff.mul_f64(random as f64 / u64::MAX as f64)
+    }
+}
+
+/*
+    Runs `op` until it succeeds or `policy.max_attempts` tries have failed, and returns the last
+    error in that case. The backoff sleeps on the calling thread; inside a handler that is a pool
+    worker, which serves nobody else meanwhile, so keep attempts and delays small relative to the
+    pool size and the client's patience.
+ */
+pub fn retry<T, E>(policy: &RetryPolicy, mut op: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+    let mut failures = 0;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                failures += 1;
+                if failures >= policy.max_attempts.max(1) {
+                    return Err(e);
+                }
+                thread::sleep(policy.delay(failures));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy { max_attempts, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(4), jitter: false }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy { jitter: false, ..RetryPolicy::default() };
+        let cases = [(0, 50), (1, 50), (2, 100), (3, 200), (6, 1600), (7, 2000), (u32::MAX, 2000)];
+
+        for (failures, expected) in cases {
+            assert_eq!(policy.backoff(failures), Duration::from_millis(expected), "after {failures} failures");
+        }
+    }
+
+    #[test]
+    fn jittered_delays_stay_within_the_backoff() {
+        let policy = RetryPolicy::default();
+        for failures in 1..=8 {
+            let delay = policy.delay(failures);
+            assert!(delay <= policy.backoff(failures), "{delay:?} after {failures} failures");
+        }
+        assert_eq!(quick(3).delay(2), Duration::from_millis(2));
+    }
+
+    #[test]
+    fn the_first_success_is_returned() {
+        let mut calls = 0;
+        let result: Result<_, &str> = retry(&quick(5), || {
+            calls += 1;
+            if calls < 3 { Err("not yet") } else { Ok(calls) }
+        });
+
+        assert_eq!(result, Ok(3));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn the_last_error_is_returned_once_attempts_run_out() {
+        for (max_attempts, expected_calls) in [(0, 1), (1, 1), (4, 4)] {
+            let mut calls = 0;
+            let result: Result<(), u32> = retry(&quick(max_attempts), || {
+                calls += 1;
+                Err(calls)
+            });
+
+            assert_eq!(result, Err(expected_calls), "max_attempts {max_attempts}");
+            assert_eq!(calls, expected_calls, "max_attempts {max_attempts}");
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use std::{path::PathBuf, time::Duration};
+use crate::{request, writable::WritableDir, Request, Response, StaticFiles};
+
+pub type RouteHandler = dyn Fn(&mut Request) -> Response + Send + Sync + 'static;
+
+/// Dispatches requests to handlers by method and path pattern.
+///
+/// Patterns are `/`-separated; a segment starting with `:` captures that segment of the path
+/// as a parameter, readable through `Request::param`. A final segment starting with `*` captures
+/// the rest of the path, possibly empty. Routes are tried in registration order.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    // fallbacks of mounted routers, each for the paths under its mount point
+    mounted_fallbacks: Vec<(Vec<Segment>, Box<RouteHandler>)>,
+    fallback: Option<Box<RouteHandler>>
+}
+
+pub struct Route {
+    method: String,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: Box<RouteHandler>,
+    timeout: Option<Duration>,
+    server_timing: bool
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String)
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for requests with the given method whose path matches `pattern`.
+    pub fn route<H>(&mut self, method: &str, pattern: &str, handler: H) -> &mut Route
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            pattern: pattern.to_string(),
+            segments: parse_pattern(pattern),
+            handler: Box::new(handler),
+            timeout: None,
+            server_timing: false
+        });
+
+        self.routes.last_mut().expect("a route was just pushed")
+    }
+
+    pub fn get<H>(&mut self, pattern: &str, handler: H) -> &mut Route
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post<H>(&mut self, pattern: &str, handler: H) -> &mut Route
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put<H>(&mut self, pattern: &str, handler: H) -> &mut Route
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn delete<H>(&mut self, pattern: &str, handler: H) -> &mut Route
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        self.route("DELETE", pattern, handler)
+    }
+
+    pub fn patch<H>(&mut self, pattern: &str, handler: H) -> &mut Route
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        self.route("PATCH", pattern, handler)
+    }
+
+    /*
+        Exposes `dir` under `prefix` for reading and writing: GET serves files like StaticFiles,
+        PUT stores the request body at the path (201 if new, 204 if replaced, creating parent
+        directories inside `dir`), DELETE removes it. A path StaticFiles wouldn't serve, a
+        dotfile or one through a symlink, can't be written either. Nothing is writable unless
+        mounted this way.
+     */
+    pub fn mount_writable(&mut self, prefix: &str, dir: impl Into<PathBuf>) -> &mut Self {
+        let dir = dir.into();
+        let pattern = format!("{}/*path", prefix.trim_end_matches('/'));
+
+        // writes go through the same symlink and dotfile rules as reads
+        let files = StaticFiles::new(dir);
+        let writable = WritableDir::new(files.clone());
+        self.get(&pattern, move |request| files.handle_path(request, rest(request)));
+        self.put(&pattern, {
+            let writable = writable.clone();
+            move |request| {
+                let path = rest(request).to_string();
+                writable.put(request, &path)
+            }
+        });
+        self.delete(&pattern, move |request| writable.delete(rest(request)));
+
+        self
+    }
+
+    /*
+        Serves `router`'s routes under `prefix`: its `/users/:id` becomes `/api/users/:id`. The
+        routes are moved into this router, keeping their order, at the point of the call; so a
+        route of ours registered earlier for the same path wins, and one registered later loses.
+        Parameters are extracted as before, and the prefix may have its own (`/orgs/:org`).
+        `router`'s fallback answers whatever under the prefix none of the routes match.
+        Handlers still see the full path in `Request::path`, so links they build stay correct.
+     */
+    pub fn mount(&mut self, prefix: &str, router: Router) -> &mut Self {
+        let prefix_segments = parse_pattern(prefix);
+        let prefixed = |segments: Vec<Segment>| prefix_segments.iter().cloned().chain(segments).collect::<Vec<_>>();
+
+        for route in router.routes {
+            self.routes.push(Route {
+                pattern: format!("{}/{}", prefix.trim_end_matches('/'), route.pattern.trim_start_matches('/')),
+                segments: prefixed(route.segments),
+                ..route
+            });
+        }
+        for (segments, fallback) in router.mounted_fallbacks {
+            self.mounted_fallbacks.push((prefixed(segments), fallback));
+        }
+        if let Some(fallback) = router.fallback {
+            self.mounted_fallbacks.push((prefixed(vec![Segment::Rest(String::new())]), fallback));
+        }
+
+        self
+    }
+
+    /// Handler for requests no route matches. Without one, they get a plain 404.
+    pub fn fallback<H>(&mut self, handler: H) -> &mut Self
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Finds the route for `request` and runs it.
+    ///
+    /// A path that matches only routes for other methods gets `405` with an `Allow` header.
+    pub fn handle(&self, request: &mut Request) -> Response {
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            let Some(params) = route.matches(request.path()) else {
+                continue;
+            };
+            if route.method != request.method() {
+                allowed.push(route.method.as_str());
+                continue;
+            }
+
+            request.set_params(params);
+            request.set_route(&route.pattern);
+            if let Some(timeout) = route.timeout {
+                request.arm_timeout(timeout);
+            }
+            if route.server_timing {
+                request.enable_server_timing();
+            }
+            return (route.handler)(request);
+        }
+
+        if !allowed.is_empty() {
+            allowed.dedup();
+            return Response::status_only(405).with_header("Allow", &allowed.join(", "));
+        }
+
+        let mounted = self.mounted_fallbacks
+            .iter()
+            .find_map(|(segments, fallback)| Some((match_segments(segments, request.path())?, fallback)));
+        if let Some((params, fallback)) = mounted {
+            // parameters of the mount point's prefix, if it has any
+            request.set_params(params);
+            return fallback(request);
+        }
+
+        match &self.fallback {
+            Some(fallback) => fallback(request),
+            None => Response::status_only(404)
+        }
+    }
+}
+
+impl Route {
+    /// Bounds how long this route's handler may take before the client gets `504 Gateway Timeout`.
+    ///
+    /// The timeout is cooperative: the handler can't be interrupted, so it keeps running on its
+    /// worker after the 504 is sent and the connection is closed. Its response is discarded.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Reports where this route's requests spend their time in a `Server-Timing` header, as
+    /// `ServerConfig::server_timing` does for every request.
+    pub fn server_timing(&mut self) -> &mut Self {
+        self.server_timing = true;
+        self
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        match_segments(&self.segments, path)
+    }
+}
+
+fn match_segments(segments: &[Segment], path: &str) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let mut parts = request::path_segments(path);
+
+    for segment in segments {
+        if let Segment::Rest(name) = segment {
+            params.push((name.clone(), parts.by_ref().collect::<Vec<_>>().join("/")));
+            break;
+        }
+
+        let part = parts.next()?;
+        match segment {
+            Segment::Literal(literal) if literal == part => {}
+            Segment::Literal(_) => return None,
+            Segment::Param(name) => params.push((name.clone(), part.to_string())),
+            Segment::Rest(_) => unreachable!("handled before taking a part")
+        }
+    }
+
+    // the path must not be longer than the pattern either
+    parts.next().is_none().then_some(params)
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| match (part.strip_prefix(':'), part.strip_prefix('*')) {
+            (Some(name), _) => Segment::Param(name.to_string()),
+            (_, Some(name)) => Segment::Rest(name.to_string()),
+            _ => Segment::Literal(part.to_string())
+        })
+        .collect()
+}
+
+// The part of the path a mount's `*path` segment captured.
+fn rest(request: &Request) -> &str {
+    request.param("path").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> Request {
+        let raw = format!("{method} {path} HTTP/1.1\r\nHost: test\r\n\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    // Answers with the route's pattern and the parameters it captured, so a test can see which ran.
+    fn echo(request: &mut Request) -> Response {
+        let params: Vec<String> = ["id", "name", "path"]
+            .iter()
+            .filter_map(|name| Some(format!("{name}={}", request.param(name)?)))
+            .collect();
+        Response::html(200, format!("{} {}", request.route().unwrap_or("-"), params.join(" ")))
+    }
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.body().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn patterns_capture_segments_and_the_rest_of_the_path() {
+        let mut router = Router::new();
+        router.get("/books/:id", echo);
+        router.get("/authors/:name/books/:id", echo);
+        router.get("/files/*path", echo);
+
+        let cases = [
+            ("/books/42", "/books/:id id=42"),
+            ("/books/42/", "/books/:id id=42"),
+            ("/authors/le%20guin/books/7", "/authors/:name/books/:id id=7 name=le guin"),
+            ("/files/a/b/c.txt", "/files/*path path=a/b/c.txt"),
+            ("/files", "/files/*path path=")
+        ];
+        for (path, expected) in cases {
+            assert_eq!(body(router.handle(&mut request("GET", path))), expected, "{path}");
+        }
+        assert_eq!(router.handle(&mut request("GET", "/books")).status(), 404);
+        assert_eq!(router.handle(&mut request("GET", "/books/42/reviews")).status(), 404);
+    }
+
+    #[test]
+    fn the_first_route_registered_wins() {
+        let mut router = Router::new();
+        router.get("/books/new", |_| Response::html(200, "form"));
+        router.get("/books/:id", echo);
+
+        assert_eq!(body(router.handle(&mut request("GET", "/books/new"))), "form");
+        assert_eq!(body(router.handle(&mut request("GET", "/books/1"))), "/books/:id id=1");
+    }
+
+    #[test]
+    fn a_path_with_routes_for_other_methods_only_is_a_405() {
+        let mut router = Router::new();
+        router.get("/books", echo);
+        router.post("/books", echo);
+        router.delete("/books/:id", echo);
+
+        let response = router.handle(&mut request("PUT", "/books"));
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.header("Allow"), Some("GET, POST"));
+        assert_eq!(router.handle(&mut request("DELETE", "/books/1")).status(), 200);
+    }
+
+    #[test]
+    fn unmatched_paths_go_to_the_fallback() {
+        let mut router = Router::new();
+        router.get("/", echo);
+        assert_eq!(router.handle(&mut request("GET", "/missing")).status(), 404);
+
+        router.fallback(|request| Response::html(200, format!("fallback for {}", request.path())));
+        assert_eq!(body(router.handle(&mut request("GET", "/missing"))), "fallback for /missing");
+    }
+
+    // An API with a parameterised route, a catch-all and a fallback, to be mounted.
+    fn api() -> Router {
+        let mut api = Router::new();
+        api.get("/books/:id", echo);
+        api.get("/files/*path", echo);
+        api.fallback(|request| Response::html(404, format!("api fallback for {}", request.path())));
+        api
+    }
+
+    #[test]
+    fn mounted_routes_are_served_under_the_prefix() {
+        let mut router = Router::new();
+        router.mount("/api/", api());
+
+        let cases = [
+            ("/api/books/42", "/api/books/:id id=42"),
+            ("/api/files/a/b.txt", "/api/files/*path path=a/b.txt"),
+            ("/api/anything", "api fallback for /api/anything"),
+            ("/api", "api fallback for /api")
+        ];
+        for (path, expected) in cases {
+            assert_eq!(body(router.handle(&mut request("GET", path))), expected, "{path}");
+        }
+        assert_eq!(router.handle(&mut request("GET", "/books/42")).status(), 404);
+        assert_eq!(router.handle(&mut request("GET", "/other")).status(), 404);
+    }
+
+    #[test]
+    fn the_prefix_can_capture_parameters() {
+        let mut members = Router::new();
+        members.get("/members/:name", |request| {
+            let org = request.param("org").unwrap_or("-").to_string();
+            Response::html(200, format!("{org}: {}", request.param("name").unwrap_or("-")))
+        });
+        members.fallback(|request| Response::html(404, format!("no such page in {}", request.param("org").unwrap_or("-"))));
+        let mut router = Router::new();
+        router.mount("/orgs/:org", members);
+
+        assert_eq!(body(router.handle(&mut request("GET", "/orgs/rust/members/ferris"))), "rust: ferris");
+        assert_eq!(body(router.handle(&mut request("GET", "/orgs/rust/missing"))), "no such page in rust");
+    }
+
+    #[test]
+    fn routes_registered_before_a_mount_win_over_it() {
+        let mut router = Router::new();
+        router.get("/api/books/new", |_| Response::html(200, "ours, before"));
+        router.mount("/api", api());
+        router.get("/api/books/:id", |_| Response::html(200, "ours, after"));
+        router.post("/api/books/:id", |_| Response::html(200, "ours, posted"));
+
+        assert_eq!(body(router.handle(&mut request("GET", "/api/books/new"))), "ours, before");
+        assert_eq!(body(router.handle(&mut request("GET", "/api/books/1"))), "/api/books/:id id=1");
+        assert_eq!(body(router.handle(&mut request("POST", "/api/books/1"))), "ours, posted");
+        let response = router.handle(&mut request("PUT", "/api/books/1"));
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.header("Allow"), Some("GET, POST"));
+    }
+
+    #[test]
+    fn mounts_nest() {
+        let mut v1 = Router::new();
+        v1.mount("/library", api());
+        let mut router = Router::new();
+        router.mount("/v1", v1);
+        router.fallback(|_| Response::html(404, "top-level fallback"));
+
+        assert_eq!(body(router.handle(&mut request("GET", "/v1/library/books/7"))), "/v1/library/books/:id id=7");
+        assert_eq!(body(router.handle(&mut request("GET", "/v1/library/nope"))), "api fallback for /v1/library/nope");
+        assert_eq!(body(router.handle(&mut request("GET", "/v1/nope"))), "top-level fallback");
+    }
+}
@@ -0,0 +1,887 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    fs, io,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use crate::{
+    buffer_pool::BufferPool,
+    cache_control::{CacheControl, InvalidRule},
+    compression::CompressionPolicy,
+    connection,
+    error_pages::ErrorPages,
+    favicon::Favicon,
+    histogram::LatencyHistogram,
+    https_redirect::{self, HttpsRedirect},
+    linger,
+    log::{AccessLog, LogFile, LogQueueSettings, Throttled},
+    log_format::LogFormat,
+    method_override::MethodOverride,
+    preflight::PreflightErrors,
+    recovery::{HandlerPanic, PanicCallback},
+    request::{EncodedSlash, Method},
+    response::DEFAULT_STREAM_THRESHOLD,
+    stats::{CloseReason, ServerStats},
+    systemd,
+    OverflowPolicy, Request, Response, ThreadPool,
+};
+
+// How long the accept loop sleeps when no connection is pending before re-checking for shutdown.
+pub(crate) const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Least time between two warnings about connections refused because the pool wouldn't take them.
+pub(crate) const REFUSED_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many requests a keep-alive connection serves before it is closed, unless configured otherwise.
+pub const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 1000;
+
+/// The file served for a directory, unless configured otherwise.
+pub const DEFAULT_INDEX_FILE: &str = "index.html";
+
+/// Which of the addresses `ServerConfig::addr` resolves to the server listens on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BindMode {
+    /// Every one that can be bound, so `localhost` is reachable as both `127.0.0.1` and `::1`.
+    /// Starting fails only if none can be.
+    #[default]
+    All,
+    /// Only the first one that can be bound, in resolution order.
+    First
+}
+
+/// No address `ServerConfig::addr` resolved to could be bound, with why for each one tried.
+#[derive(Debug)]
+pub struct BindError {
+    pub addr: String,
+    pub attempts: Vec<(SocketAddr, io::Error)>
+}
+
+impl Display for BindError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "couldn't listen on {}", self.addr)?;
+        if self.attempts.is_empty() {
+            return write!(f, ": it resolves to no address");
+        }
+        for (addr, error) in &self.attempts {
+            write!(f, "\n  - {addr}: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BindError {}
+
+/// The application callback that turns each request into a response.
+pub type Handler = dyn Fn(&mut Request) -> Response + Send + Sync + 'static;
+
+/// Settings used by `Server::bind`.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address the listener binds to. A host name may resolve to several addresses;
+    /// `bind_mode` says which of them are bound.
+    pub addr: String,
+    /// Listen on every address `addr` resolves to, the default, or only the first that works.
+    pub bind_mode: BindMode,
+    /// Number of worker threads in the pool.
+    pub workers: usize,
+    /// Lets the pool start `workers` threads and spawn more, up to this many, while
+    /// connections wait with every worker busy; see `ThreadPoolBuilder::max_threads`. A fixed
+    /// pool of `workers` if `None`.
+    pub max_workers: Option<usize>,
+    /// Threads accepting connections on each listening socket, each thread on its own handle
+    /// of it, all feeding the one pool. 1 by default, which is plenty unless connections are
+    /// very short and very many, e.g. clients without keep-alive; see `benches/accept_rate.rs`.
+    /// Only `run` uses it; `run_async` accepts on the runtime.
+    pub acceptor_threads: usize,
+    /// Directory the application serves static files from, if any. The server itself doesn't
+    /// read it; it's carried here so it can be set alongside the rest from the command line,
+    /// and so the preflight check can make sure it is a readable directory. Best absolute:
+    /// `from_args` resolves a relative `--root` against the working directory at startup.
+    pub root: Option<PathBuf>,
+    /// File served for a request for the root path (and any other directory) under `root`,
+    /// `index.html` by default. If the directory has no such file the request is a 404.
+    pub index_file: String,
+    /// File the process id is written to while the server runs, removed again on a clean shutdown.
+    pub pid_file: Option<PathBuf>,
+    /// File access log lines are appended to, with its rotation settings; stdout if `None`.
+    pub access_log: Option<LogFile>,
+    /// Write access log lines from a background thread through a bounded queue, so a slow
+    /// sink can't hold up requests. Written by the worker that served the request if `None`,
+    /// the default.
+    pub access_log_queue: Option<LogQueueSettings>,
+    /// Format of access log lines, e.g. `log_format::COMMON`; the server's own line if `None`.
+    pub access_log_format: Option<LogFormat>,
+    /// Files the handler reads at request time, such as page templates. The server doesn't
+    /// use them either, but the preflight check refuses to start if one can't be opened.
+    pub required_files: Vec<PathBuf>,
+    /// How long shutdown waits for in-flight connections before force-closing them. The whole
+    /// shutdown fits in it: joining the workers afterwards gets only what is left.
+    pub shutdown_grace: Duration,
+    /// Reset (RST) the connections force-closed when `shutdown_grace` runs out, rather than
+    /// closing them gracefully. Their sockets are freed at once instead of lingering in
+    /// TIME_WAIT, which helps fast restarts; the cost is that those clients see a connection
+    /// reset, and anything still unsent to them is discarded. Normal closes are unaffected.
+    pub abortive_close_on_shutdown: bool,
+    /// How long an idle keep-alive connection is kept open waiting for the next request.
+    pub keep_alive_timeout: Duration,
+    /// Longest a client may take to send a request's whole head, counted from its first byte.
+    /// A client that takes longer is disconnected without an answer. 10 seconds by default.
+    pub header_timeout: Duration,
+    /// Longest a handler reading the request body waits for the next bytes of it; the read
+    /// then fails. 30 seconds by default.
+    pub body_timeout: Duration,
+    /// Cap on a request from its first byte until the handler returns, body reading
+    /// included. Past it the client gets a 504 and the connection is closed, though the handler
+    /// runs on until it returns; its response is discarded. A route's own timeout still applies
+    /// if it is shorter. Unlimited if `None`, the default.
+    pub request_timeout: Option<Duration>,
+    /// Streamed response bodies of known length up to this many bytes are read into memory and
+    /// sent in one piece; longer ones are copied to the socket a buffer at a time. Files are
+    /// opened by handlers, so pass it on to `StaticFiles::stream_threshold` and
+    /// `Response::from_file_with_threshold`, which only then decide the same way.
+    pub stream_threshold: usize,
+    /// Close a keep-alive connection after it has served this many requests, sending the last
+    /// one with `Connection: close`; requests the client pipelined past it are never read.
+    /// Recycling connections lets a load balancer spread clients out again. 1000 by default,
+    /// unlimited if `None`.
+    pub max_requests_per_connection: Option<usize>,
+    /// Path of the built-in HTML status page, or `None` to disable it.
+    pub status_path: Option<String>,
+    /// Path of the built-in Prometheus metrics endpoint, or `None` to disable it.
+    pub metrics_path: Option<String>,
+    /// Path of the built-in liveness endpoint, or `None` to disable it.
+    pub health_path: Option<String>,
+    /// Shed new connections with 503 once this many jobs are already queued.
+    pub shed_queue_depth: Option<usize>,
+    /// Shed new connections with 503 once the pool's estimated queue wait exceeds this.
+    pub shed_wait_budget: Option<Duration>,
+    /// Most connections that may wait in the pool's queue. Past it, new connections get an
+    /// immediate 503 from the accept thread. Unbounded if `None`.
+    pub queue_capacity: Option<usize>,
+    /// What the accept thread does with a connection that finds the queue at `queue_capacity`:
+    /// `Reject` (the default) answers it with 503, `Block` stops accepting until a worker is
+    /// free, and `RunInline` serves it on the accept thread, which accepts nothing meanwhile.
+    pub queue_overflow: OverflowPolicy,
+    /// Fallback source for `/favicon.ico` when the handler answers it with 404.
+    pub favicon: Favicon,
+    /// Per-language pages for bare error responses, picked by the request's Accept-Language.
+    pub error_pages: ErrorPages,
+    /// Charset appended to textual Content-Types that don't declare one, or `None` to leave them as is.
+    pub default_charset: Option<String>,
+    /// Cache-Control values added to responses by path prefix or extension.
+    pub cache_control: CacheControl,
+    /// When and what to gzip for clients that accept it, or `None` to never compress.
+    pub compression: Option<CompressionPolicy>,
+    /// How `%2F` in a request path is treated; rejected with 400 by default.
+    pub encoded_slash: EncodedSlash,
+    /// Methods the server implements. A request with any other gets 501 before routing, where
+    /// a known method that no route takes gets the router's 405. Add extension methods here
+    /// (`Method::Other("PROPFIND".into())`) to route them.
+    pub implemented_methods: Vec<Method>,
+    /// Honor `X-HTTP-Method-Override` on POST requests, for the methods it allows. Off by
+    /// default; the router then sees the overridden method.
+    pub method_override: Option<MethodOverride>,
+    /// Adopt the `X-Request-Id` a client sends, if it is well-formed, instead of numbering the
+    /// request ourselves. Only turn this on behind a proxy that sets or vets the header: ids
+    /// from arbitrary clients can collide, by accident or on purpose.
+    pub trust_request_id: bool,
+    /// Send a `Server-Timing` header with how long each phase of the request took, for reading
+    /// in the browser's dev tools; see `Route::server_timing` to turn it on for one route. It
+    /// tells anyone who asks how long requests take here, so it is off by default.
+    pub server_timing: bool,
+    /// Upper bounds of the request latency histogram buckets. Fixed for the server's lifetime.
+    pub latency_buckets: Vec<Duration>,
+    /// Also listen on a plaintext port that only redirects to HTTPS, for when this server
+    /// sits behind a TLS terminator. `None` by default.
+    pub https_redirect: Option<HttpsRedirect>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: String::from("127.0.0.1:7878"),
+            bind_mode: BindMode::All,
+            workers: 4,
+            max_workers: None,
+            acceptor_threads: 1,
+            root: None,
+            index_file: String::from(DEFAULT_INDEX_FILE),
+            required_files: Vec::new(),
+            pid_file: None,
+            access_log: None,
+            access_log_queue: None,
+            access_log_format: None,
+            shutdown_grace: Duration::from_secs(30),
+            abortive_close_on_shutdown: false,
+            keep_alive_timeout: Duration::from_secs(5),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            request_timeout: None,
+            stream_threshold: DEFAULT_STREAM_THRESHOLD,
+            max_requests_per_connection: Some(DEFAULT_MAX_REQUESTS_PER_CONNECTION),
+            status_path: Some(String::from("/status")),
+            metrics_path: Some(String::from("/metrics")),
+            health_path: Some(String::from("/health")),
+            shed_queue_depth: None,
+            shed_wait_budget: None,
+            queue_capacity: None,
+            queue_overflow: OverflowPolicy::Reject,
+            latency_buckets: LatencyHistogram::default_bounds(),
+            favicon: Favicon::Embedded,
+            error_pages: ErrorPages::default(),
+            default_charset: Some(String::from("utf-8")),
+            cache_control: CacheControl::default(),
+            compression: Some(CompressionPolicy::default()),
+            encoded_slash: EncodedSlash::default(),
+            implemented_methods: Method::KNOWN.to_vec(),
+            method_override: None,
+            trust_request_id: false,
+            server_timing: false,
+            https_redirect: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The pool settings the server runs with, as label and value, for the startup banner
+    /// and the status page.
+    pub fn pool_settings(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Worker threads", self.workers.to_string()),
+            ("Max worker threads", self.max_workers.map_or(String::from("fixed"), |max| max.to_string())),
+            ("Queue capacity", self.queue_capacity.map_or(String::from("unbounded"), |capacity| capacity.to_string())),
+            ("Queue overflow", self.queue_capacity.map_or(String::from("-"), |_| format!("{:?}", self.queue_overflow))),
+            ("Shutdown grace", format!("{}s", self.shutdown_grace.as_secs_f64()))
+        ]
+    }
+}
+
+pub struct Server {
+    // one per bound address, never empty
+    listeners: Vec<TcpListener>,
+    pool: ThreadPool,
+    connections: Arc<Connections>,
+    shared: Arc<Shared>,
+    // bound up front so a taken port fails at startup; moved to its thread by run()
+    redirect_listener: Option<TcpListener>,
+    redirect_thread: Option<thread::JoinHandle<()>>,
+}
+
+// Everything a connection needs from the server, bundled so each job clones a single Arc.
+pub(crate) struct Shared {
+    pub(crate) config: ServerConfig,
+    pub(crate) handler: Box<Handler>,
+    pub(crate) stats: Arc<ServerStats>,
+    pub(crate) shutdown: Arc<AtomicBool>,
+    pub(crate) buffers: Arc<BufferPool>,
+    pub(crate) access_log: AccessLog,
+    // source of request ids, counting up from 1 across all connections
+    pub(crate) request_ids: Arc<AtomicUsize>,
+    pub(crate) on_panic: Option<Box<PanicCallback>>,
+}
+
+impl Server {
+    /// Checks the config, then binds the listener and spawns the worker pool.
+    ///
+    /// A config that fails `ServerConfig::check` is refused with a `PreflightErrors` listing
+    /// every problem found.
+    ///
+    /// `handler` is called on a worker thread for every request; a keep-alive connection
+    /// stays on the same worker until it is closed.
+    pub fn bind<H>(config: ServerConfig, handler: H) -> anyhow::Result<Server>
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        config.check().map_err(PreflightErrors)?;
+
+        let listeners = bind_listeners(&config.addr, config.bind_mode)?;
+        let redirect_listener = config.https_redirect.as_ref().map(|redirect| TcpListener::bind(&redirect.addr)).transpose()?;
+        // handler panics are caught closer to the request; this keeps any other bug from costing a worker
+        let mut pool = ThreadPool::builder(config.workers).catch_panics();
+        if let Some(capacity) = config.queue_capacity {
+            pool = pool.queue_capacity(capacity).overflow_policy(config.queue_overflow);
+        }
+        if let Some(max) = config.max_workers {
+            pool = pool.max_threads(max);
+        }
+        let pool = pool.build()?;
+        let stats = ServerStats::with_latency_buckets(config.latency_buckets.clone());
+        // each busy worker holds one read and one write buffer; that's all worth keeping around
+        let buffers = BufferPool::new(2 * config.workers);
+        let access_log = AccessLog::open(config.access_log.clone(), config.access_log_queue, config.access_log_format.clone())?;
+
+        Ok(
+            Server {
+                listeners,
+                pool,
+                connections: Arc::new(Connections::default()),
+                redirect_listener,
+                redirect_thread: None,
+                shared: Arc::new(Shared {
+                    config,
+                    handler: Box::new(handler),
+                    stats: Arc::new(stats),
+                    shutdown: Arc::new(AtomicBool::new(false)),
+                    buffers: Arc::new(buffers),
+                    access_log,
+                    request_ids: Arc::new(AtomicUsize::new(1)),
+                    on_panic: None
+                })
+            }
+        )
+    }
+
+    /// The first address the server listens on; see `local_addrs` for all of them.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// Every address the server listens on, one per address `ServerConfig::addr` resolved to
+    /// that could be bound. With port 0 each gets a port of its own.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    // Second handles on the listening sockets, for an accept loop that isn't `run`.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn listener_handles(&self) -> io::Result<Vec<TcpListener>> {
+        self.listeners.iter().map(TcpListener::try_clone).collect()
+    }
+
+    /// Binds `http_addr` as a plaintext port that answers every request with a redirect to
+    /// `https://<https_authority>` and the same path and query, serving no content.
+    /// Connections there are answered by a thread of their own, not the worker pool, and it
+    /// stops with the server. Set `ServerConfig::https_redirect` instead to add HSTS.
+    pub fn redirect_to_https(&mut self, http_addr: &str, https_authority: &str) -> io::Result<&mut Self> {
+        let redirect = HttpsRedirect::new(http_addr, https_authority);
+        self.redirect_listener = Some(TcpListener::bind(&redirect.addr)?);
+        self.config_mut().https_redirect = Some(redirect);
+        Ok(self)
+    }
+
+    /// Address of the HTTPS redirect listener, if there is one and the server isn't running yet.
+    pub fn redirect_addr(&self) -> Option<SocketAddr> {
+        self.redirect_listener.as_ref().and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Closes keep-alive connections after `max` requests; 0 means never.
+    pub fn max_requests_per_connection(&mut self, max: usize) -> &mut Self {
+        self.config_mut().max_requests_per_connection = (max > 0).then_some(max);
+        self
+    }
+
+    /// Serves `/favicon.ico` from `path` instead of the embedded icon, unless the handler
+    /// already serves it. Use `Favicon::Disabled` through `ServerConfig` to turn it off.
+    pub fn favicon(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.config_mut().favicon = Favicon::File(path.into());
+        self
+    }
+
+    /// Serves the file at `path` as the `lang` variant of the page for bare `status` errors,
+    /// e.g. `server.error_page_lang(404, "de", "404.de.html")`. A client asking for `de-AT`
+    /// gets the `de` page; one whose languages are all missing gets the default language's.
+    pub fn error_page_lang(&mut self, status: u16, lang: &str, path: impl Into<PathBuf>) -> &mut Self {
+        self.config_mut().error_pages.add(status, lang, path);
+        self
+    }
+
+    /// The language of the error page served when none of the client's is available.
+    pub fn default_error_language(&mut self, lang: &str) -> &mut Self {
+        self.config_mut().error_pages.default_language(lang);
+        self
+    }
+
+    /// Adds `Cache-Control: value` to successful responses for paths under `prefix`, unless the
+    /// handler set its own. The longest matching prefix wins.
+    pub fn cache_control(&mut self, prefix: &str, value: &str) -> Result<&mut Self, InvalidRule> {
+        self.config_mut().cache_control.prefix(prefix, value)?;
+        Ok(self)
+    }
+
+    /// Like `cache_control`, for paths with the file extension `extension`. Extension rules
+    /// take precedence over prefix rules.
+    pub fn cache_control_ext(&mut self, extension: &str, value: &str) -> Result<&mut Self, InvalidRule> {
+        self.config_mut().cache_control.extension(extension, value)?;
+        Ok(self)
+    }
+
+    /// Calls `callback` whenever a handler panics, with the request that was being handled.
+    /// The panic is already logged to stderr, and the client gets a 500 either way; this is
+    /// for forwarding it to an error tracker. It runs on the worker thread.
+    pub fn on_panic<F>(&mut self, callback: F) -> &mut Self
+    where F: Fn(&HandlerPanic) + Send + Sync + 'static
+    {
+        self.shared_mut().on_panic = Some(Box::new(callback));
+        self
+    }
+
+    // Settings can only change before run() hands the shared state out to workers.
+    fn config_mut(&mut self) -> &mut ServerConfig {
+        &mut self.shared_mut().config
+    }
+
+    fn shared_mut(&mut self) -> &mut Shared {
+        Arc::get_mut(&mut self.shared).expect("server settings can't change once it is running")
+    }
+
+    /// The server's lifetime counters, shared with every connection.
+    pub fn stats(&self) -> Arc<ServerStats> {
+        Arc::clone(&self.shared.stats)
+    }
+
+    /// Returns a handle that can stop the server from another thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { flag: Arc::clone(&self.shared.shutdown) }
+    }
+
+    /// Accepts connections until shutdown is requested, then drains in-flight
+    /// connections for at most `ServerConfig::shutdown_grace`.
+    ///
+    /// When this returns the listening socket is closed and every worker has been joined.
+    pub fn run(mut self) -> anyhow::Result<()> {
+        /*
+            A blocking accept can't be interrupted from another thread, so the listener is put in
+            nonblocking mode and polled. WouldBlock just means nobody is waiting to connect; we nap
+            briefly and look at the shutdown flag again.
+         */
+        for listener in &self.listeners {
+            listener.set_nonblocking(true)?;
+        }
+        self.started()?;
+
+        /*
+            Each listener gets `acceptor_threads` acceptors, one of them this thread for the
+            first. The rest borrow the server, so they run in a scope that joins them before it
+            ends. They all poll the same shutdown flag; one that fails sets it, so the rest stop
+            too instead of serving on without it.
+         */
+        let server = &self;
+        let accepted = thread::scope(|scope| -> io::Result<()> {
+            let per_listener = server.shared.config.acceptor_threads;
+            let extra = (0..server.listeners.len())
+                .flat_map(|listener| (0..per_listener).map(move |n| (listener, n)))
+                .skip(1);
+            let mut acceptors = Vec::with_capacity(server.listeners.len() * per_listener);
+            let mut result = Ok(());
+            for (index, n) in extra {
+                let spawned = server.listeners[index].try_clone().and_then(|listener| {
+                    thread::Builder::new()
+                        .name(format!("acceptor-{index}-{n}"))
+                        .spawn_scoped(scope, move || server.accept_loop(&listener))
+                });
+                match spawned {
+                    Ok(acceptor) => acceptors.push(acceptor),
+                    Err(e) => {
+                        // the scope waits for the acceptors already running, so they have to be told to stop
+                        server.shared.shutdown.store(true, Ordering::SeqCst);
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+
+            result = result.and_then(|_| server.accept_loop(&server.listeners[0]));
+            for acceptor in acceptors {
+                let joined = acceptor.join().unwrap_or_else(|_| Err(io::Error::other("an acceptor thread panicked")));
+                result = result.and(joined);
+            }
+            result
+        });
+        accepted?;
+
+        self.stop();
+        Ok(())
+    }
+
+    // Accepts on `listener` and dispatches until shutdown is requested, or it fails.
+    fn accept_loop(&self, listener: &TcpListener) -> io::Result<()> {
+        let accepted = self.accept_until_shutdown(listener);
+        if accepted.is_err() {
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+        }
+        accepted
+    }
+
+    fn accept_until_shutdown(&self, listener: &TcpListener) -> io::Result<()> {
+        let mut refused_warning = Throttled::new(REFUSED_WARNING_INTERVAL);
+
+        while !self.shared.shutdown.load(Ordering::SeqCst) {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                    continue;
+                }
+                Err(e) => {
+                    self.accept_failed(&e);
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                    continue;
+                }
+            };
+            // some platforms hand out accepted sockets that inherit the listener's nonblocking flag
+            let dispatched = stream
+                .set_nonblocking(false)
+                .and_then(|_| self.dispatch(stream, &mut refused_warning));
+            // only this connection is lost, e.g. with no descriptor left to track it; the listener is fine
+            if let Err(e) = dispatched {
+                self.accept_failed(&e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Starts the redirect listener, writes the PID file and tells systemd we're up.
+    pub(crate) fn started(&mut self) -> io::Result<()> {
+        let redirect = self.shared.config.https_redirect.clone();
+        if let (Some(listener), Some(redirect)) = (self.redirect_listener.take(), redirect) {
+            self.redirect_thread = Some(https_redirect::spawn(listener, redirect, Arc::clone(&self.shared.shutdown))?);
+        }
+        if let Some(pid_file) = &self.shared.config.pid_file {
+            fs::write(pid_file, format!("{}\n", process::id()))?;
+        }
+        // what is actually running, after defaults and auto-sizing, so operators can confirm it
+        let addrs = self.local_addrs()?.iter().map(SocketAddr::to_string).collect::<Vec<_>>().join(", ");
+        let settings = self.shared.config
+            .pool_settings()
+            .into_iter()
+            .map(|(label, value)| format!("{}: {value}", label.to_lowercase()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("Listening on {addrs} ({settings})");
+        // the listener is bound, so under systemd's Type=notify this is the moment we're up
+        systemd::notify_or_log("READY=1");
+        Ok(())
+    }
+
+    /*
+        Accept failures are usually per-connection (the peer reset before we got to it) or
+        transient resource exhaustion (out of file descriptors). Neither is a reason to stop
+        serving everyone else, so the caller counts it and goes on; a failed accept backs off
+        briefly, a connection that was accepted but couldn't be set up is just dropped.
+     */
+    pub(crate) fn accept_failed(&self, error: &io::Error) {
+        self.shared.stats.accept_error();
+        eprintln!("Failed to accept connection: {error}");
+    }
+
+    /*
+        Hands an accepted, blocking-mode connection to the pool, or answers it on this thread.
+        An error means the connection couldn't be tracked for the drain and was dropped
+        unanswered; the caller reports it as a failed accept and goes on accepting.
+     */
+    pub(crate) fn dispatch(&self, stream: TcpStream, refused_warning: &mut Throttled) -> io::Result<()> {
+        let accepted_at = Instant::now();
+        self.shared.stats.connection_accepted();
+
+        if let Some(retry_after) = self.overloaded() {
+            self.shared.stats.request_shed();
+            connection::shed(&stream, retry_after, &self.shared);
+            self.shared.stats.connection_closed(CloseReason::Server);
+            return Ok(());
+        }
+
+        let guard = self.connections.register(&stream).inspect_err(|_| {
+            self.shared.stats.connection_closed(CloseReason::Server);
+        })?;
+        let tracked = self.shared.stats.track_connection();
+        let shared = Arc::clone(&self.shared);
+        // kept by the accept thread too, to answer the client if the pool won't take the job
+        let stream = Arc::new(stream);
+        let job_stream = Arc::clone(&stream);
+
+        let submitted = self.pool.try_execute(move || {
+            let reason = connection::serve(&job_stream, accepted_at, &shared, &tracked)
+                .unwrap_or_else(|e| {
+                    if connection::is_disconnect(&e) {
+                        shared.stats.client_disconnected();
+                        println!("Client went away: {e}");
+                        return CloseReason::Client;
+                    }
+                    eprintln!("Connection error: {e}");
+                    CloseReason::Server
+                });
+            shared.stats.connection_closed(reason);
+            // the guard must outlive the handler so the drain sees this connection as in flight
+            drop(guard);
+        });
+
+        /*
+            The job, and with it the guard, has already been dropped, so the drain won't
+            wait on this connection. The client is owed an answer rather than a reset.
+         */
+        if let Err(e) = submitted {
+            self.shared.stats.request_shed();
+            refused_warning.warn(format_args!("refusing connections with 503: {e}"));
+            let _ = connection::refuse(&stream);
+            self.shared.stats.connection_closed(CloseReason::Server);
+        }
+        Ok(())
+    }
+
+    /*
+        Closes the listener, drains in-flight connections and joins the workers, all within the
+        one grace period: the workers get only what the connections left of it. Connections
+        still open at the deadline are force-closed; a handler that stays stuck even then is
+        abandoned with its worker, so it can't keep the process from exiting.
+     */
+    pub(crate) fn stop(self) {
+        let Server { listeners, pool, connections, shared, redirect_listener, redirect_thread } = self;
+        let deadline = Instant::now() + shared.config.shutdown_grace;
+
+        systemd::notify_or_log("STOPPING=1");
+        // not set yet if run_async's shutdown future is what stopped us
+        shared.shutdown.store(true, Ordering::SeqCst);
+        // stop accepting first so new clients are refused while we drain
+        drop(listeners);
+        drop(redirect_listener);
+        if let Some(thread) = redirect_thread {
+            thread.join().unwrap_or_else(|_| eprintln!("The HTTPS redirect thread panicked."));
+        }
+        drain(&connections, &shared.config, deadline);
+        pool.shutdown_until(deadline);
+
+        if let Some(pid_file) = &shared.config.pid_file {
+            if let Err(e) = fs::remove_file(pid_file) {
+                eprintln!("Failed to remove PID file {}: {e}", pid_file.display());
+            }
+        }
+    }
+}
+
+impl Server {
+    /*
+        Once the queue is this deep, anything we enqueue will likely time out before a worker
+        reaches it, so it's kinder to say so immediately. Returns the Retry-After hint to send.
+     */
+    fn overloaded(&self) -> Option<Duration> {
+        let config = &self.shared.config;
+        let estimated_wait = self.pool.estimated_wait();
+
+        let too_deep = config.shed_queue_depth.is_some_and(|depth| self.pool.queued_jobs() >= depth);
+        let too_slow = config.shed_wait_budget.is_some_and(|budget| estimated_wait > budget);
+
+        (too_deep || too_slow).then_some(estimated_wait)
+    }
+}
+
+/*
+    Binds what `addr` resolves to, as `mode` says. Resolution failures were already reported by
+    the preflight check; should the name stop resolving in between, the error lists no
+    attempts. A name that resolves to the same address twice (hosts files do that) is bound once.
+ */
+fn bind_listeners(addr: &str, mode: BindMode) -> Result<Vec<TcpListener>, BindError> {
+    let mut resolved: Vec<SocketAddr> = Vec::new();
+    for socket_addr in addr.to_socket_addrs().into_iter().flatten() {
+        if !resolved.contains(&socket_addr) {
+            resolved.push(socket_addr);
+        }
+    }
+
+    let mut attempts = Vec::new();
+    let mut listeners = Vec::new();
+    for socket_addr in resolved {
+        match TcpListener::bind(socket_addr) {
+            Ok(listener) => {
+                listeners.push(listener);
+                if mode == BindMode::First {
+                    break;
+                }
+            }
+            Err(error) => attempts.push((socket_addr, error))
+        }
+    }
+
+    if listeners.is_empty() {
+        return Err(BindError { addr: addr.to_string(), attempts });
+    }
+    for (socket_addr, error) in &attempts {
+        eprintln!("Not listening on {socket_addr}: {error}");
+    }
+    Ok(listeners)
+}
+
+/*
+    Waits for tracked connections to finish. Anything still open at `deadline`, when the grace
+    period runs out, has its socket shut down, which makes the handler's next read or write
+    fail so the worker can move on.
+ */
+fn drain(connections: &Connections, config: &ServerConfig, deadline: Instant) {
+    if !connections.wait_until_idle(deadline) {
+        let stragglers = connections.force_close(config.abortive_close_on_shutdown);
+        println!("Shutdown grace period elapsed; force-closed {stragglers} connection(s).");
+    }
+}
+
+/// Requests a graceful stop of the `Server` it was obtained from.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    flag: Arc<AtomicBool>
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
+/*
+    Registry of in-flight connections. Each entry holds a clone of the accepted stream so the
+    server can shut the socket down from the accept thread while a worker still owns the
+    original. The condvar is signalled every time a connection finishes.
+ */
+#[derive(Default)]
+struct Connections {
+    next_id: AtomicU64,
+    active: Mutex<HashMap<u64, TcpStream>>,
+    idle: Condvar
+}
+
+impl Connections {
+    fn register(self: &Arc<Self>, stream: &TcpStream) -> io::Result<ConnectionGuard> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let clone = stream.try_clone()?;
+
+        self.active
+            .lock()
+            .expect("Mutex poisoned: Another thread panicked while holding the lock.")
+            .insert(id, clone);
+
+        Ok(ConnectionGuard { id, connections: Arc::clone(self) })
+    }
+
+    // returns true if every connection finished before the deadline
+    fn wait_until_idle(&self, deadline: Instant) -> bool {
+        let mut active = self.active
+            .lock()
+            .expect("Mutex poisoned: Another thread panicked while holding the lock.");
+
+        while !active.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            active = self.idle
+                .wait_timeout(active, deadline - now)
+                .expect("Mutex poisoned: Another thread panicked while holding the lock.")
+                .0;
+        }
+
+        true
+    }
+
+    fn force_close(&self, abortive: bool) -> usize {
+        let active = self.active
+            .lock()
+            .expect("Mutex poisoned: Another thread panicked while holding the lock.");
+
+        for stream in active.values() {
+            /*
+                With a zero linger, the close that follows once the worker drops its end sends
+                an RST rather than finishing the FIN handshake. The shutdown below still goes
+                first, since that's what wakes a worker blocked on the socket.
+             */
+            if abortive {
+                if let Err(e) = linger::set_abortive_close(stream) {
+                    eprintln!("Failed to set an abortive close: {e}");
+                }
+            }
+            // the peer may already be gone, in which case there is nothing left to close
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+
+        active.len()
+    }
+}
+
+// Removes its connection from the registry when the worker is done with it.
+struct ConnectionGuard {
+    id: u64,
+    connections: Arc<Connections>
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        // recover the map even if poisoned; the drop must not panic during unwinding
+        let mut active = self.connections.active
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        active.remove(&self.id);
+        self.connections.idle.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_pool_is_built_with_the_configured_sizes() {
+        let args = ["book-web-server", "--bind", "127.0.0.1", "--port", "0", "--threads", "2", "--max-threads", "5", "--queue-capacity", "7"];
+        let config = ServerConfig::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+        let server = Server::bind(config, |_| Response::status_only(200)).unwrap();
+
+        assert_eq!(server.pool.worker_count(), 2);
+        assert_eq!(server.pool.max_threads, Some(5));
+        assert_eq!(server.pool.queue_capacity, Some(7));
+    }
+
+    #[test]
+    fn every_resolved_address_is_bound_once() {
+        let mut resolved: Vec<SocketAddr> = Vec::new();
+        for socket_addr in "localhost:0".to_socket_addrs().unwrap() {
+            if !resolved.contains(&socket_addr) {
+                resolved.push(socket_addr);
+            }
+        }
+        let bindable = resolved.iter().filter(|addr| TcpListener::bind(addr).is_ok()).count();
+
+        let listeners = bind_listeners("localhost:0", BindMode::All).unwrap();
+        assert_eq!(listeners.len(), bindable);
+        let first = bind_listeners("localhost:0", BindMode::First).unwrap();
+        assert_eq!(first.len(), 1);
+    }
+
+    #[test]
+    fn an_address_in_use_is_reported_with_its_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap().to_string();
+
+        let error = bind_listeners(&addr, BindMode::All).unwrap_err();
+        assert_eq!(error.addr, addr);
+        assert_eq!(error.attempts.len(), 1);
+        assert_eq!(error.attempts[0].0, taken.local_addr().unwrap());
+        assert_eq!(error.attempts[0].1.kind(), io::ErrorKind::AddrInUse);
+
+        let message = error.to_string();
+        assert!(message.starts_with(&format!("couldn't listen on {addr}\n  - {addr}: ")), "{message}");
+    }
+
+    #[test]
+    fn a_name_with_no_addresses_says_so() {
+        let error = BindError { addr: "example.invalid:80".to_string(), attempts: Vec::new() };
+        assert_eq!(error.to_string(), "couldn't listen on example.invalid:80: it resolves to no address");
+    }
+
+    #[test]
+    fn the_server_reports_every_address_it_listens_on() {
+        let config = ServerConfig { addr: "localhost:0".to_string(), ..ServerConfig::default() };
+        let server = Server::bind(config, |_| Response::status_only(200)).unwrap();
+
+        let addrs = server.local_addrs().unwrap();
+        assert!(!addrs.is_empty());
+        assert_eq!(addrs[0], server.local_addr().unwrap());
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()), "{addrs:?}");
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, Read},
+    sync::mpsc::{self, Receiver, SyncSender},
+    time::Duration,
+};
+use crate::Response;
+
+// Events a handler can get ahead of the client before `send` waits for it to catch up.
+const QUEUED_EVENTS: usize = 16;
+
+/// One server-sent event. Only `data` is required; a multi-line `data` arrives as one event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    name: Option<String>,
+    data: String,
+    id: Option<String>,
+    retry: Option<Duration>
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self { name: None, data: data.into(), id: None, retry: None }
+    }
+
+    /// The event type, which picks the client's listener (`addEventListener(name, ...)`).
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// The id a reconnecting client sends back in `Last-Event-ID`.
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// How long the client should wait before reconnecting if the stream breaks.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    // The wire form: one `field: value` line each, a blank line to end the event. Line breaks
+    // in name and id would start fields of their own, so they're dropped.
+    fn encode(&self) -> Vec<u8> {
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+        let mut out = String::new();
+
+        if let Some(name) = &self.name {
+            out.push_str(&format!("event: {}\n", single_line(name)));
+        }
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", single_line(id)));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split('\n') {
+            out.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        out.push('\n');
+
+        out.into_bytes()
+    }
+}
+
+/// Returned by `EventSender` once the client is gone: the response was cut short (a write
+/// failed) or has ended. Nothing more will be delivered, so the producer should stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientGone;
+
+impl Display for ClientGone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the client of the event stream has disconnected")
+    }
+}
+
+impl std::error::Error for ClientGone {}
+
+/// The producing end of an event stream, usually moved to a thread of its own. The stream
+/// ends once every clone of it has been dropped.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: SyncSender<Vec<u8>>
+}
+
+impl EventSender {
+    /// Queues `event` for the client. Blocks while the client is too far behind.
+    pub fn send(&self, event: &Event) -> Result<(), ClientGone> {
+        self.sender.send(event.encode()).map_err(|_| ClientGone)
+    }
+
+    /// Sends a comment line, which clients ignore. Sent now and then on a quiet stream, it
+    /// keeps proxies from timing the connection out, and makes a vanished client show up as
+    /// `ClientGone` instead of only on the next real event.
+    pub fn comment(&self, text: &str) -> Result<(), ClientGone> {
+        let line = format!(": {}\n\n", text.replace(['\r', '\n'], " "));
+        self.sender.send(line.into_bytes()).map_err(|_| ClientGone)
+    }
+}
+
+/// Starts an event stream: the response to return from the handler, and the sender to produce
+/// events with. The body is streamed (chunked for HTTP/1.1), so each event reaches the socket
+/// as soon as the server has it.
+///
+/// The response body owns the receiving end. When a write to the client fails the server
+/// stops the stream and drops the response, and with it the receiver, so the producer's next
+/// `send` fails with `ClientGone` instead of generating events for nobody.
+pub fn stream() -> (EventSender, Response) {
+    let (sender, receiver) = mpsc::sync_channel(QUEUED_EVENTS);
+    let reader = EventReader { receiver, pending: Vec::new(), pos: 0 };
+
+    let response = Response::new(200)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        .with_reader(reader, None);
+
+    (EventSender { sender }, response)
+}
+
+// Hands out one queued event per read, so each becomes a chunk of its own.
+struct EventReader {
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    pos: usize
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.pending.len() {
+            match self.receiver.recv() {
+                Ok(event) => (self.pending, self.pos) = (event, 0),
+                // every sender is gone: the stream is over
+                Err(_) => return Ok(0)
+            }
+        }
+
+        let n = (self.pending.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Version;
+
+    fn encoded(event: Event) -> String {
+        String::from_utf8(event.encode()).unwrap()
+    }
+
+    #[test]
+    fn an_event_is_encoded_one_field_per_line() {
+        assert_eq!(encoded(Event::new("hello")), "data: hello\n\n");
+        assert_eq!(
+            encoded(Event::new("{\"n\":1}").name("tick").id("7").retry(Duration::from_secs(3))),
+            "event: tick\nid: 7\nretry: 3000\ndata: {\"n\":1}\n\n"
+        );
+    }
+
+    #[test]
+    fn line_breaks_cannot_start_fields_of_their_own() {
+        assert_eq!(encoded(Event::new("one\r\ntwo\nthree")), "data: one\ndata: two\ndata: three\n\n");
+        assert_eq!(encoded(Event::new("x").name("a\nid: 9").id("1\r\n")), "event: aid: 9\nid: 1\ndata: x\n\n");
+    }
+
+    #[test]
+    fn the_stream_is_chunked_one_event_at_a_time_until_the_senders_are_gone() {
+        let (sender, mut response) = stream();
+        assert_eq!(response.header("Content-Type"), Some("text/event-stream"));
+        assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+
+        sender.send(&Event::new("first")).unwrap();
+        sender.clone().comment("still\nhere").unwrap();
+        drop(sender);
+
+        let mut written = Vec::new();
+        response.write_to(&mut written, Version::Http11, true).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("Transfer-Encoding: chunked\r\n"), "{written}");
+        assert!(written.ends_with("\r\n\r\nd\r\ndata: first\n\n\r\ne\r\n: still here\n\n\r\n0\r\n\r\n"), "{written}");
+    }
+
+    #[test]
+    fn the_producer_learns_the_client_is_gone_once_the_response_is() {
+        let (sender, response) = stream();
+        drop(response);
+        assert_eq!(sender.send(&Event::new("anyone?")), Err(ClientGone));
+        assert_eq!(sender.comment("ping"), Err(ClientGone));
+    }
+}
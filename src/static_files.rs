@@ -0,0 +1,1014 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use crate::{
+    compression,
+    conditional::{self, PreconditionResult},
+    http_date, mime,
+    range::{self, DEFAULT_MAX_RANGES},
+    response::DEFAULT_STREAM_THRESHOLD,
+    Request, Response,
+};
+
+/// Serves files from a directory on disk.
+///
+/// Use it as (part of) a handler: `Server::bind(config, move |request| files.handle(request))`.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    symlinks: Symlinks,
+    dotfiles: bool,
+    hidden: Vec<String>,
+    max_ranges: usize,
+    index_file: Option<String>,
+    stream_threshold: usize
+}
+
+/// What `StaticFiles` does with symbolic links below its root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Any symlink between the root and the requested file makes it a 404.
+    #[default]
+    Deny,
+    /// Symlinks are followed as long as the file they lead to is still inside the root.
+    WithinRoot,
+    /// Symlinks are followed wherever they point.
+    Follow
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            symlinks: Symlinks::default(),
+            dotfiles: false,
+            hidden: Vec::new(),
+            max_ranges: DEFAULT_MAX_RANGES,
+            index_file: None,
+            stream_threshold: DEFAULT_STREAM_THRESHOLD
+        }
+    }
+
+    /// Most byte ranges one request may ask for (8 by default); more is answered with 416.
+    /// Zero turns range requests off, so every file is sent whole.
+    pub fn max_ranges(mut self, max_ranges: usize) -> Self {
+        self.max_ranges = max_ranges;
+        self
+    }
+
+    /// Serves paths with a component starting with `.` (`.git/config`, `.env`), which are 404 by default.
+    pub fn allow_dotfiles(mut self, allow: bool) -> Self {
+        self.dotfiles = allow;
+        self
+    }
+
+    /// Answers 404 for paths matching `pattern`, relative to the root.
+    ///
+    /// A pattern without `/` is matched against every path component (`*.bak`, `*~`); one with
+    /// `/` against the whole path, where `**` stands for any number of components (`secret/**`).
+    pub fn hide(mut self, pattern: &str) -> Self {
+        self.hidden.push(pattern.trim_start_matches('/').to_string());
+        self
+    }
+
+    /// Sets how symlinks below the root are treated. The root itself may always be a symlink.
+    pub fn symlinks(mut self, policy: Symlinks) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Serves `name` (e.g. `index.html`) for a request for a directory, the root included,
+    /// when the path ends in `/`. A directory without that file is a 404, as is any directory
+    /// when no index file is set, the default.
+    pub fn index_file(mut self, name: &str) -> Self {
+        self.index_file = Some(name.to_string());
+        self
+    }
+
+    /// Files up to `bytes` long are read into memory to be sent, where compression can reach
+    /// them; longer ones are streamed from disk, ranges included. Pass the server's
+    /// `ServerConfig::stream_threshold`, which it defaults to as well.
+    pub fn stream_threshold(mut self, bytes: usize) -> Self {
+        self.stream_threshold = bytes;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Answers `request` with the file it names, or 404 if there is no such file.
+    pub fn handle(&self, request: &Request) -> Response {
+        self.handle_path(request, request.path())
+    }
+
+    // Like `handle`, but for a path relative to the root that isn't the request's own (a mount's remainder).
+    pub(crate) fn handle_path(&self, request: &Request, request_path: &str) -> Response {
+        if !matches!(request.method(), "GET" | "HEAD") {
+            return Response::status_only(405).with_header("Allow", "GET, HEAD");
+        }
+
+        match self.resolve(request_path) {
+            Some(path) => self.serve_file(request, &path),
+            None => Response::status_only(404)
+        }
+    }
+
+    fn resolve(&self, request_path: &str) -> Option<PathBuf> {
+        // request paths arrive decoded, so an encoded NUL shows up here as a real one
+        if request_path.contains('\0') {
+            return None;
+        }
+        let mut path = sanitize(&self.root, request_path)?;
+        // only with the trailing slash, so the page's relative links resolve inside the directory
+        if let Some(index_file) = self.index_file.as_ref().filter(|_| request_path.is_empty() || request_path.ends_with('/')) {
+            path = sanitize(&path, index_file)?;
+        }
+        if self.is_hidden(&path) {
+            return None;
+        }
+
+        (path.is_file() && self.allows(&path)).then_some(path)
+    }
+
+    // Runs on the decoded, sanitized path, so `/a/./.env` and `/%2Eenv` are caught as well.
+    pub(crate) fn is_hidden(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        let components: Vec<String> = relative.iter().filter_map(|component| component.to_str()).map(fold_case).collect();
+        let components: Vec<&str> = components.iter().map(String::as_str).collect();
+
+        if !self.dotfiles && components.iter().any(|component| component.starts_with('.')) {
+            return true;
+        }
+        self.hidden.iter().any(|pattern| {
+            let pattern = fold_case(pattern);
+            if pattern.contains('/') {
+                let pattern: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+                glob_path(&pattern, &components)
+            } else {
+                components.iter().any(|component| glob(&pattern, component))
+            }
+        })
+    }
+
+    /*
+        Rejecting `..` keeps request paths inside the root lexically, but a symlink below the
+        root can still lead anywhere. A single canonicalize would hide whether a link was
+        involved at all, so each component from the root down is checked with symlink_metadata.
+     */
+    fn allows(&self, path: &Path) -> bool {
+        self.check_links(path, false)
+    }
+
+    /*
+        Like `allows`, for a path about to be written that may not exist yet. Only the part that
+        exists is checked; whatever is missing will be created as plain directories and a file.
+     */
+    pub(crate) fn allows_write(&self, path: &Path) -> bool {
+        self.check_links(path, true)
+    }
+
+    fn check_links(&self, path: &Path, may_be_missing: bool) -> bool {
+        if self.symlinks == Symlinks::Follow {
+            return true;
+        }
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+
+        let mut current = self.root.clone();
+        let mut linked = false;
+        for component in relative.components() {
+            current.push(component);
+            match fs::symlink_metadata(&current) {
+                Ok(metadata) => linked |= metadata.file_type().is_symlink(),
+                Err(_) if may_be_missing => {
+                    current.pop();
+                    break;
+                }
+                Err(_) => return false
+            }
+        }
+        if !linked {
+            return true;
+        }
+
+        // `current` is the deepest part of the path that exists: all of it, unless may_be_missing
+        match self.symlinks {
+            Symlinks::WithinRoot => match (fs::canonicalize(&current), fs::canonicalize(&self.root)) {
+                (Ok(target), Ok(root)) => target.starts_with(root),
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    fn serve_file(&self, request: &Request, path: &Path) -> Response {
+        let sibling = precompressed_sibling(path).filter(|sibling| self.allows(sibling));
+        serve_file(request, path, sibling, self.max_ranges, self.stream_threshold)
+    }
+}
+
+// Matches path components against pattern segments, where a `**` segment spans any number of components.
+fn glob_path(pattern: &[&str], components: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => components.is_empty(),
+        Some((&"**", rest)) => (0..=components.len()).any(|skip| glob_path(rest, &components[skip..])),
+        Some((segment, rest)) => components
+            .split_first()
+            .is_some_and(|(component, remaining)| glob(segment, component) && glob_path(rest, remaining))
+    }
+}
+
+// Matches one component against a pattern where `*` stands for any run of characters.
+fn glob(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => text
+            .strip_prefix(prefix)
+            .is_some_and(|text| (0..=text.len()).filter(|&i| text.is_char_boundary(i)).any(|i| glob(rest, &text[i..])))
+    }
+}
+
+// Windows and macOS filesystems ignore case by default, so `SECRET.BAK` opens `secret.bak`.
+const CASE_INSENSITIVE_FS: bool = cfg!(any(windows, target_os = "macos"));
+
+// Lowercases `text` where the filesystem would treat both spellings as the same file.
+fn fold_case(text: &str) -> String {
+    if CASE_INSENSITIVE_FS { text.to_lowercase() } else { text.to_string() }
+}
+
+/*
+    Maps a request path onto a path below `root`, whether or not anything exists there. Any `..`
+    component is refused outright rather than resolved, so a request can never climb out of the
+    root directory. The path is split on `/` only; each segment then has to be a plain file name
+    on this platform, see `is_plain_segment`.
+ */
+pub(crate) fn sanitize(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+
+    for segment in request_path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment if is_plain_segment(segment, cfg!(windows)) => path.push(segment),
+            _ => return None
+        }
+    }
+
+    Some(path)
+}
+
+/*
+    Whether a URL path segment names a single file or directory, rather than something the OS
+    would read as more (or less) than that. A backslash is a separator on Windows, so it's
+    refused everywhere to keep both platforms serving the same URLs. On Windows, also refused:
+    `:` (drive letters like `C:` and alternate data streams like `a.txt::$DATA`), a trailing
+    dot or space (which Windows strips, turning `secret.txt.` into `secret.txt` after the
+    hide and extension checks saw something else), and device names such as `CON` or `nul.txt`.
+    The Windows rules take a flag rather than a cfg so they can be exercised on any platform.
+ */
+fn is_plain_segment(segment: &str, windows: bool) -> bool {
+    if segment.contains(['\\', '\0']) {
+        return false;
+    }
+    if !windows {
+        return true;
+    }
+
+    !segment.contains(':') && !segment.ends_with(['.', ' ']) && !is_device_name(segment)
+}
+
+// CON, PRN, AUX, NUL, COM1-9 and LPT1-9 open devices on Windows, whatever extension follows.
+fn is_device_name(segment: &str) -> bool {
+    let stem = segment.split('.').next().unwrap_or_default().trim_end().to_ascii_uppercase();
+
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => ["COM", "LPT"].iter().any(|device| {
+            stem.strip_prefix(device).is_some_and(|digit| matches!(digit.as_bytes(), [b'1'..=b'9']))
+        })
+    }
+}
+
+/*
+    Hosting for single-page apps with client-side routing: real files under the root are served
+    as usual, and any other path that looks like a route (no extension in its last segment) gets
+    the index file, so a reload on /books/42 still boots the app. A missing `app.js` stays a 404
+    rather than turning into HTML the browser would try to execute.
+ */
+#[derive(Debug, Clone)]
+pub struct SpaFallback {
+    files: StaticFiles,
+    index: PathBuf
+}
+
+impl SpaFallback {
+    /// `index` is relative to `root`, typically `index.html`.
+    pub fn new(root: impl Into<PathBuf>, index: impl Into<PathBuf>) -> Self {
+        Self { files: StaticFiles::new(root), index: index.into() }
+    }
+
+    /// Sets how symlinks below the root are treated, as for `StaticFiles::symlinks`.
+    pub fn symlinks(mut self, policy: Symlinks) -> Self {
+        self.files = self.files.symlinks(policy);
+        self
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        let response = self.files.handle(request);
+        if response.status() != 404 || looks_like_asset(request.path()) {
+            return response;
+        }
+
+        let index = self.files.root.join(&self.index);
+        if !self.files.allows(&index) {
+            return response;
+        }
+        self.files.serve_file(request, &index)
+    }
+}
+
+fn looks_like_asset(request_path: &str) -> bool {
+    request_path
+        .rsplit('/')
+        .next()
+        .is_some_and(|segment| Path::new(segment).extension().is_some())
+}
+
+/*
+    Files are tagged from their metadata rather than their contents, so that a HEAD request can
+    be answered without reading the file at all, however big it is, and still carry the same
+    validators (and so get the same 304s) as a GET. Its Content-Type comes from the extension;
+    only a file whose extension doesn't name a type has its first bytes read to sniff one.
+    A Range header is ignored on HEAD: the answer is the 200 a GET without one would get, with
+    Accept-Ranges saying ranges could be asked for.
+ */
+fn serve_file(request: &Request, path: &Path, precompressed: Option<PathBuf>, max_ranges: usize, stream_threshold: usize) -> Response {
+    let head = request.method() == "HEAD";
+    // ranges are served from the identity file, whose bytes are the ones a client can resume
+    let range = request.header("Range").filter(|_| max_ranges > 0 && !head);
+
+    if let Some(sibling) = &precompressed {
+        if compression::accepts_gzip(request) && range.is_none() {
+            match serve_precompressed(request, path, sibling, stream_threshold) {
+                Ok(response) => return response,
+                // the identity file is still there to fall back on
+                Err(e) => eprintln!("Failed to read {}: {e}", sibling.display())
+            }
+        }
+    }
+
+    let response = match fs::metadata(path) {
+        Ok(metadata) => {
+            let etag = conditional::file_etag(&metadata);
+            let last_modified = metadata.modified().ok();
+            match precondition_response(request, &etag, last_modified) {
+                Some(response) => response,
+                None if head => match sniff_file(path) {
+                    Ok(content_type) => {
+                        let response = Response::new(200)
+                            .with_header("Content-Type", content_type)
+                            .with_reader(io::empty(), Some(metadata.len()));
+                        accept_ranges(with_validators(response, &etag, last_modified), max_ranges)
+                    }
+                    Err(e) => read_failed(path, e)
+                },
+                None if metadata.len() > stream_threshold as u64 => {
+                    let ranges = range
+                        .filter(|_| range::if_range_matches(request, &etag, last_modified))
+                        .map(|range| range::parse(range, metadata.len(), max_ranges));
+                    match stream_file(path, ranges, metadata.len(), stream_threshold) {
+                        Ok(response) => accept_ranges(with_validators(response, &etag, last_modified), max_ranges),
+                        Err(e) => read_failed(path, e)
+                    }
+                }
+                None => match fs::read(path) {
+                    Ok(contents) => {
+                        let content_type = mime::for_path(path, &contents);
+                        let partial = range
+                            .filter(|_| range::if_range_matches(request, &etag, last_modified))
+                            .and_then(|range| {
+                                let ranges = range::parse(range, contents.len() as u64, max_ranges);
+                                range::respond(ranges, &contents, content_type)
+                            });
+                        let response = match partial {
+                            Some(partial) => partial,
+                            None => Response::new(200).with_header("Content-Type", content_type).with_body(contents)
+                        };
+                        accept_ranges(with_validators(response, &etag, last_modified), max_ranges)
+                    }
+                    Err(e) => read_failed(path, e)
+                }
+            }
+        }
+        Err(e) => read_failed(path, e)
+    };
+
+    // caches must not hand the identity bytes to a client that could have had the .gz, or vice versa
+    match precompressed {
+        Some(_) if response.status() == 200 || response.status() == 304 => {
+            response.with_vary("Accept-Encoding")
+        }
+        _ => response
+    }
+}
+
+/*
+    A file over the stream threshold is never held in memory, whole or in part: the 200 is
+    `Response::from_file`'s, streamed from the open file, and the parts of a 206 are read
+    straight from the file as they are sent.
+ */
+fn stream_file(path: &Path, ranges: Option<range::RangeRequest>, len: u64, stream_threshold: usize) -> io::Result<Response> {
+    if let Some(ranges) = ranges {
+        if let Some(partial) = range::respond_from_file(ranges, path, len, sniff_file(path)?)? {
+            return Ok(partial);
+        }
+    }
+    Response::from_file_with_threshold(path, stream_threshold)
+}
+
+/*
+    `<file>.gz` next to `path`, if it exists and is at least as new as the file itself. An older
+    .gz is a leftover from a previous deploy and would serve stale content, so it is ignored.
+ */
+fn precompressed_sibling(path: &Path) -> Option<PathBuf> {
+    let mut name = OsString::from(path.file_name()?);
+    name.push(".gz");
+    let sibling = path.with_file_name(name);
+
+    let sibling_modified = fs::metadata(&sibling).ok().filter(|meta| meta.is_file())?.modified().ok()?;
+    let original_modified = fs::metadata(path).ok()?.modified().ok()?;
+
+    (sibling_modified >= original_modified).then_some(sibling)
+}
+
+fn serve_precompressed(request: &Request, path: &Path, sibling: &Path, stream_threshold: usize) -> io::Result<Response> {
+    let metadata = fs::metadata(sibling)?;
+    // tagged the same way as runtime-compressed responses, never equal to the identity variant's
+    let etag = compression::gzip_etag(&conditional::file_etag(&metadata));
+    // both variants carry the original's modification time; the .gz is only a copy of it
+    let last_modified = modified(path);
+    if let Some(response) = precondition_response(request, &etag, last_modified) {
+        return Ok(response.with_vary("Accept-Encoding"));
+    }
+
+    // the type is the original's
+    let response = with_validators(Response::new(200), &etag, last_modified)
+        .with_header("Content-Type", sniff_file(path)?)
+        .with_header("Content-Encoding", "gzip")
+        .with_vary("Accept-Encoding");
+    if request.method() == "HEAD" {
+        return Ok(response.with_reader(io::empty(), Some(metadata.len())));
+    }
+    if metadata.len() > stream_threshold as u64 {
+        return Ok(response.with_reader(File::open(sibling)?, Some(metadata.len())));
+    }
+    Ok(response.with_body(fs::read(sibling)?))
+}
+
+// The file's media type, opening it only if the extension doesn't say, and then reading just its first bytes.
+fn sniff_file(path: &Path) -> io::Result<&'static str> {
+    let by_extension = path.extension().and_then(|extension| extension.to_str()).and_then(mime::from_extension);
+    if let Some(content_type) = by_extension {
+        return Ok(content_type);
+    }
+
+    let mut head = Vec::with_capacity(mime::SNIFF_LENGTH);
+    File::open(path)?.take(mime::SNIFF_LENGTH as u64).read_to_end(&mut head)?;
+    Ok(mime::for_path(path, &head))
+}
+
+fn accept_ranges(response: Response, max_ranges: usize) -> Response {
+    response.with_header("Accept-Ranges", if max_ranges > 0 { "bytes" } else { "none" })
+}
+
+fn read_failed(path: &Path, e: io::Error) -> Response {
+    if e.kind() == io::ErrorKind::NotFound {
+        return Response::status_only(404);
+    }
+    eprintln!("Failed to read {}: {e}", path.display());
+    Response::status_only(500)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// The 304 or 412 the request's preconditions call for, or None to send the file.
+fn precondition_response(request: &Request, etag: &str, last_modified: Option<SystemTime>) -> Option<Response> {
+    match conditional::evaluate_preconditions(request, Some(etag), last_modified) {
+        PreconditionResult::Proceed => None,
+        PreconditionResult::NotModified => Some(with_validators(Response::new(304), etag, last_modified)),
+        PreconditionResult::Failed => Some(conditional::precondition_failed())
+    }
+}
+
+fn with_validators(response: Response, etag: &str, last_modified: Option<SystemTime>) -> Response {
+    let response = response.with_header("ETag", etag);
+    match last_modified {
+        Some(last_modified) => response.with_header("Last-Modified", &http_date::format(last_modified)),
+        None => response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{request::Version, temp_dir::TempDir};
+    use std::time::Duration;
+
+    fn request(path: &str, headers: &str) -> Request {
+        let raw = format!("GET {path} HTTP/1.1\r\nHost: test\r\n{headers}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    // The body as it goes out on the wire, streamed or not.
+    fn sent_body(mut response: Response) -> Vec<u8> {
+        let mut sent = Vec::new();
+        let length = response.write_to(&mut sent, Version::Http11, true).unwrap();
+        sent.split_off(sent.len() - length as usize)
+    }
+
+    // 4 KiB of bytes that differ from one position to the next, so a misplaced slice shows.
+    fn large_contents() -> Vec<u8> {
+        (0..4096u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn a_small_file_is_served_from_memory() {
+        let dir = TempDir::new();
+        dir.write("index.html", "<h1>hello</h1>");
+        let files = StaticFiles::new(dir.path()).stream_threshold(1024);
+
+        let response = files.handle(&request("/index.html", ""));
+        assert_eq!(response.status(), 200);
+        assert!(!response.is_streaming());
+        assert_eq!(response.header("Content-Type"), Some("text/html"));
+        assert_eq!(response.header("Accept-Ranges"), Some("bytes"));
+        assert!(response.header("ETag").is_some());
+        assert!(response.header("Last-Modified").is_some());
+        assert_eq!(response.body(), b"<h1>hello</h1>");
+    }
+
+    #[test]
+    fn a_file_over_the_threshold_is_streamed() {
+        let dir = TempDir::new();
+        dir.write("data.txt", large_contents());
+        let files = StaticFiles::new(dir.path()).stream_threshold(1024);
+
+        let response = files.handle(&request("/data.txt", ""));
+        assert_eq!(response.status(), 200);
+        assert!(response.is_streaming());
+        assert_eq!(response.body_len(), Some(4096));
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.header("Accept-Ranges"), Some("bytes"));
+        assert!(response.header("ETag").is_some());
+        assert!(response.header("Last-Modified").is_some());
+        assert_eq!(sent_body(response), large_contents());
+    }
+
+    #[test]
+    fn a_range_of_a_large_file_is_read_from_disk() {
+        let dir = TempDir::new();
+        dir.write("data.txt", large_contents());
+        let files = StaticFiles::new(dir.path()).stream_threshold(1024);
+
+        let response = files.handle(&request("/data.txt", "Range: bytes=1000-1999\r\n"));
+        assert_eq!(response.status(), 206);
+        assert!(response.is_streaming());
+        assert_eq!(response.header("Content-Range"), Some("bytes 1000-1999/4096"));
+        assert_eq!(sent_body(response), &large_contents()[1000..2000]);
+
+        let response = files.handle(&request("/data.txt", "Range: bytes=5000-\r\n"));
+        assert_eq!(response.status(), 416);
+        assert_eq!(response.header("Content-Range"), Some("bytes */4096"));
+    }
+
+    #[test]
+    fn several_ranges_of_a_large_file_match_their_declared_length() {
+        let dir = TempDir::new();
+        dir.write("data.txt", large_contents());
+        let files = StaticFiles::new(dir.path()).stream_threshold(1024);
+
+        let response = files.handle(&request("/data.txt", "Range: bytes=0-9, 4000-\r\n"));
+        assert_eq!(response.status(), 206);
+        let declared = response.body_len().unwrap();
+        let boundary = response.header("Content-Type").unwrap().split_once("boundary=").unwrap().1.to_string();
+
+        // sending checks the body against the declared length, and fails if it comes up short
+        let body = sent_body(response);
+        assert_eq!(body.len() as u64, declared);
+        assert!(body.windows(10).any(|window| window == &large_contents()[..10]));
+        assert!(body.windows(96).any(|window| window == &large_contents()[4000..]));
+        assert!(body.ends_with(format!("\r\n--{boundary}--\r\n").as_bytes()));
+    }
+
+    #[test]
+    fn a_large_precompressed_sibling_is_streamed() {
+        let dir = TempDir::new();
+        dir.write("app.js", "console.log('hi')");
+        // not real gzip; what matters is which file's bytes go out, and how
+        dir.write("app.js.gz", large_contents());
+        let files = StaticFiles::new(dir.path()).stream_threshold(1024);
+
+        let response = files.handle(&request("/app.js", "Accept-Encoding: gzip\r\n"));
+        assert_eq!(response.status(), 200);
+        assert!(response.is_streaming());
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(sent_body(response), large_contents());
+    }
+
+    // Whether the response goes out saying it varies with Accept-Encoding.
+    fn varies_by_encoding(mut response: Response) -> bool {
+        let mut sent = Vec::new();
+        response.write_to(&mut sent, Version::Http11, true).unwrap();
+        String::from_utf8_lossy(&sent).contains("\r\nVary: Accept-Encoding\r\n")
+    }
+
+    // A root with `app.js` and a .gz sibling holding different bytes, so it shows which one went out.
+    fn precompressed_root() -> TempDir {
+        let dir = TempDir::new();
+        dir.write("app.js", "console.log('hi')");
+        dir.write("app.js.gz", "gzipped bytes");
+        dir
+    }
+
+    #[test]
+    fn a_client_accepting_gzip_gets_the_sibling() {
+        let dir = precompressed_root();
+        let files = StaticFiles::new(dir.path());
+
+        let response = files.handle(&request("/app.js", "Accept-Encoding: gzip\r\n"));
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Content-Type"), Some("application/javascript"));
+        assert!(response.header("ETag").unwrap().ends_with("-gzip\""));
+        assert_eq!(response.body(), b"gzipped bytes");
+        assert!(varies_by_encoding(response));
+    }
+
+    #[test]
+    fn other_clients_get_the_identity_file() {
+        let dir = precompressed_root();
+        let files = StaticFiles::new(dir.path());
+
+        for headers in ["", "Accept-Encoding: gzip;q=0\r\n", "Accept-Encoding: gzip\r\nRange: bytes=0-6\r\n"] {
+            let response = files.handle(&request("/app.js", headers));
+            assert_eq!(response.header("Content-Encoding"), None, "{headers:?}");
+            assert!(response.body().starts_with(b"console"), "{headers:?}");
+        }
+
+        // the full identity response is one of two variants; a range of it is a range of the file
+        assert!(varies_by_encoding(files.handle(&request("/app.js", ""))));
+        assert!(!varies_by_encoding(files.handle(&request("/app.js", "Range: bytes=0-6\r\n"))));
+    }
+
+    #[test]
+    fn a_sibling_older_than_its_file_is_ignored() {
+        let dir = precompressed_root();
+        let gz = File::options().write(true).open(dir.path().join("app.js.gz")).unwrap();
+        gz.set_modified(fs::metadata(dir.path().join("app.js")).unwrap().modified().unwrap() - Duration::from_secs(60))
+            .unwrap();
+        let files = StaticFiles::new(dir.path());
+
+        let response = files.handle(&request("/app.js", "Accept-Encoding: gzip\r\n"));
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.body(), b"console.log('hi')");
+        assert!(!varies_by_encoding(response));
+    }
+
+    #[test]
+    fn each_variant_is_revalidated_against_its_own_etag() {
+        let dir = precompressed_root();
+        let files = StaticFiles::new(dir.path());
+        let gzip_etag = files.handle(&request("/app.js", "Accept-Encoding: gzip\r\n")).header("ETag").unwrap().to_string();
+        let identity_etag = files.handle(&request("/app.js", "")).header("ETag").unwrap().to_string();
+        assert_ne!(gzip_etag, identity_etag);
+
+        let cases = [
+            ("Accept-Encoding: gzip\r\n", &gzip_etag, 304),
+            ("Accept-Encoding: gzip\r\n", &identity_etag, 200),
+            ("", &identity_etag, 304),
+            ("", &gzip_etag, 200)
+        ];
+        for (accept_encoding, etag, status) in cases {
+            let headers = format!("{accept_encoding}If-None-Match: {etag}\r\n");
+            let response = files.handle(&request("/app.js", &headers));
+            assert_eq!(response.status(), status, "{headers:?}");
+            assert!(varies_by_encoding(response), "{headers:?}");
+        }
+    }
+
+    /*
+        A root holding a plain file, a link to it, and a link to a file in another directory,
+        with a directory link to each side as well.
+     */
+    #[cfg(unix)]
+    fn linked_root() -> (TempDir, TempDir) {
+        use std::os::unix::fs::symlink;
+
+        let root = TempDir::new();
+        let outside = TempDir::new();
+        let page = root.write("real/page.txt", "inside");
+        let secret = outside.write("secret.txt", "outside");
+        symlink(&page, root.path().join("inside.txt")).unwrap();
+        symlink(root.path().join("real"), root.path().join("inside-dir")).unwrap();
+        symlink(&secret, root.path().join("outside.txt")).unwrap();
+        symlink(outside.path(), root.path().join("outside-dir")).unwrap();
+        (root, outside)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn each_symlink_policy_serves_what_it_allows() {
+        let (root, _outside) = linked_root();
+        let table = [
+            (Symlinks::Deny, [200, 404, 404, 404, 404]),
+            (Symlinks::WithinRoot, [200, 200, 200, 404, 404]),
+            (Symlinks::Follow, [200, 200, 200, 200, 200])
+        ];
+        let paths = ["/real/page.txt", "/inside.txt", "/inside-dir/page.txt", "/outside.txt", "/outside-dir/secret.txt"];
+
+        for (policy, statuses) in table {
+            let files = StaticFiles::new(root.path()).symlinks(policy);
+            for (path, status) in paths.iter().zip(statuses) {
+                assert_eq!(files.handle(&request(path, "")).status(), status, "{path} with {policy:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn windows_segments_that_arent_plain_are_refused() {
+        let table = [
+            ("page.html", true, true),
+            ("CON", true, false),
+            ("con", true, false),
+            ("nul.txt", true, false),
+            ("COM1", true, false),
+            ("LPT9.log", true, false),
+            ("COM0", true, true),
+            ("CONSOLE", true, true),
+            ("a:b", true, false),
+            ("a.txt::$DATA", true, false),
+            ("secret.txt.", true, false),
+            ("secret.txt ", true, false),
+            ("a\\b", false, false),
+            ("a\0b", false, false)
+        ];
+
+        for (segment, elsewhere, on_windows) in table {
+            assert_eq!(is_plain_segment(segment, false), elsewhere, "{segment:?} off Windows");
+            assert_eq!(is_plain_segment(segment, true), on_windows, "{segment:?} on Windows");
+        }
+    }
+
+    #[test]
+    fn a_file_without_a_known_extension_gets_a_sniffed_type() {
+        let dir = TempDir::new();
+        dir.write("README", "just some notes");
+        dir.write("picture", b"\x89PNG\r\n\x1a\nrest of the image");
+        dir.write("data.bin", [0u8, 1, 2, 3]);
+        dir.write("page", "<html><script>alert(1)</script></html>");
+        let files = StaticFiles::new(dir.path());
+
+        let cases = [
+            ("/README", mime::TEXT_PLAIN),
+            ("/picture", "image/png"),
+            ("/data.bin", mime::OCTET_STREAM),
+            ("/page", mime::TEXT_PLAIN)
+        ];
+        for (path, content_type) in cases {
+            let response = files.handle(&request(path, ""));
+            assert_eq!(response.status(), 200, "{path}");
+            assert_eq!(response.header("Content-Type"), Some(content_type), "{path}");
+        }
+    }
+
+    // An app root with its index page, a script, and a route-looking directory of real files.
+    fn spa_root() -> TempDir {
+        let dir = TempDir::new();
+        dir.write("index.html", "<div id=app></div>");
+        dir.write("app.js", "boot()");
+        dir.write("books/cover.txt", "a real file");
+        dir
+    }
+
+    #[test]
+    fn real_files_are_served_and_routes_get_the_index() {
+        let dir = spa_root();
+        let spa = SpaFallback::new(dir.path(), "index.html");
+
+        let cases = [
+            ("/app.js", 200, "boot()"),
+            ("/books/cover.txt", 200, "a real file"),
+            ("/books", 200, "<div id=app></div>"),
+            ("/books/42", 200, "<div id=app></div>"),
+            ("/books/42/reviews", 200, "<div id=app></div>"),
+            ("/v1.2/books", 200, "<div id=app></div>")
+        ];
+        for (path, status, body) in cases {
+            let response = spa.handle(&request(path, ""));
+            assert_eq!(response.status(), status, "{path}");
+            assert_eq!(response.body(), body.as_bytes(), "{path}");
+        }
+    }
+
+    #[test]
+    fn a_missing_asset_stays_a_404() {
+        let dir = spa_root();
+        let spa = SpaFallback::new(dir.path(), "index.html");
+
+        for path in ["/main.js", "/books/missing.png", "/books/42/style.css"] {
+            assert_eq!(spa.handle(&request(path, "")).status(), 404, "{path}");
+        }
+    }
+
+    #[test]
+    fn without_its_index_a_route_is_a_404() {
+        let dir = spa_root();
+        let spa = SpaFallback::new(dir.path(), "missing.html");
+
+        assert_eq!(spa.handle(&request("/books/42", "")).status(), 404);
+        assert_eq!(spa.handle(&request("/app.js", "")).status(), 200);
+    }
+
+    #[test]
+    fn the_index_is_revalidated_like_any_file() {
+        let dir = spa_root();
+        let spa = SpaFallback::new(dir.path(), "index.html");
+        let etag = spa.handle(&request("/books/42", "")).header("ETag").unwrap().to_string();
+
+        let response = spa.handle(&request("/authors/7", &format!("If-None-Match: {etag}\r\n")));
+        assert_eq!(response.status(), 304);
+    }
+
+    #[test]
+    fn a_directory_path_gets_its_index_file() {
+        let dir = TempDir::new();
+        dir.write("home.html", "<h1>home</h1>");
+        dir.write("docs/home.html", "<h1>docs</h1>");
+        dir.write("empty/other.html", "not an index");
+        let files = StaticFiles::new(dir.path()).index_file("home.html");
+
+        let cases = [
+            ("/", 200, "<h1>home</h1>"),
+            ("/docs/", 200, "<h1>docs</h1>"),
+            ("/docs/home.html", 200, "<h1>docs</h1>"),
+            ("/empty/", 404, "Not Found")
+        ];
+        for (path, status, body) in cases {
+            let response = files.handle(&request(path, ""));
+            assert_eq!(response.status(), status, "{path}");
+            assert_eq!(response.body(), body.as_bytes(), "{path}");
+        }
+        // the directory itself, without the slash, isn't a file to serve
+        assert_eq!(files.handle(&request("/docs", "")).status(), 404);
+    }
+
+    #[test]
+    fn without_an_index_file_a_directory_is_a_404() {
+        let dir = TempDir::new();
+        dir.write("index.html", "<h1>home</h1>");
+        let files = StaticFiles::new(dir.path());
+
+        assert_eq!(files.handle(&request("/", "")).status(), 404);
+        assert_eq!(files.handle(&request("/index.html", "")).status(), 200);
+    }
+
+    fn head(path: &str, headers: &str) -> Request {
+        let raw = format!("HEAD {path} HTTP/1.1\r\nHost: test\r\n{headers}\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn head_describes_the_body_get_would_send() {
+        let dir = TempDir::new();
+        dir.write("small.html", "<h1>hello</h1>");
+        dir.write("large.bin", large_contents());
+        dir.write("README", "plain words");
+        let files = StaticFiles::new(dir.path()).stream_threshold(1024);
+
+        for path in ["/small.html", "/large.bin", "/README"] {
+            let got = files.handle(&request(path, ""));
+            let mut headed = files.handle(&head(path, ""));
+            assert_eq!(headed.status(), 200, "{path}");
+            for name in ["Content-Type", "ETag", "Last-Modified", "Accept-Ranges"] {
+                assert_eq!(headed.header(name), got.header(name), "{name} of {path}");
+            }
+            assert_eq!(headed.body_len(), got.body_len(), "{path}");
+            // as the server sends it, the head alone
+            headed.omit_body();
+            let mut sent = Vec::new();
+            headed.write_to(&mut sent, Version::Http11, true).unwrap();
+            assert!(sent.ends_with(b"\r\n\r\n"), "{path}");
+        }
+    }
+
+    #[test]
+    fn head_is_revalidated_but_never_ranged() {
+        let dir = TempDir::new();
+        dir.write("large.bin", large_contents());
+        let files = StaticFiles::new(dir.path()).stream_threshold(1024);
+        let etag = files.handle(&head("/large.bin", "")).header("ETag").unwrap().to_string();
+
+        assert_eq!(files.handle(&head("/large.bin", &format!("If-None-Match: {etag}\r\n"))).status(), 304);
+        let ranged = files.handle(&head("/large.bin", "Range: bytes=0-9\r\n"));
+        assert_eq!(ranged.status(), 200);
+        assert_eq!(ranged.body_len(), Some(4096));
+    }
+
+    #[test]
+    fn head_of_a_precompressed_file_describes_the_sibling() {
+        let dir = precompressed_root();
+        let files = StaticFiles::new(dir.path());
+
+        let mut response = files.handle(&head("/app.js", "Accept-Encoding: gzip\r\n"));
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.body_len(), Some("gzipped bytes".len() as u64));
+        response.omit_body();
+        assert!(varies_by_encoding(response));
+    }
+
+    // A root holding dotfiles, backups and a secret directory next to an ordinary page.
+    fn root_with_hidden_files() -> TempDir {
+        let dir = TempDir::new();
+        for file in ["page.html", "page.html.bak", "page.html~", ".env", ".git/config", ".well-known/security.txt",
+            "secret/keys.txt", "secret/deep/keys.txt", "public/secret/readme.txt"] {
+            dir.write(file, "contents");
+        }
+        dir
+    }
+
+    #[test]
+    fn dotfiles_are_hidden_unless_allowed() {
+        let dir = root_with_hidden_files();
+        let paths = ["/.env", "/.git/config", "/.well-known/security.txt", "/%2Eenv", "/public/../.env", "/./.git/config"];
+
+        let files = StaticFiles::new(dir.path());
+        for path in paths {
+            assert_eq!(files.handle(&request(path, "")).status(), 404, "{path}");
+        }
+        let files = StaticFiles::new(dir.path()).allow_dotfiles(true);
+        for path in paths {
+            assert_eq!(files.handle(&request(path, "")).status(), 200, "{path} with dotfiles allowed");
+        }
+    }
+
+    #[test]
+    fn denylisted_patterns_are_404s() {
+        let dir = root_with_hidden_files();
+        let files = StaticFiles::new(dir.path()).hide("*.bak").hide("*~").hide("/secret/**");
+        let cases = [
+            ("/page.html", 200),
+            ("/page.html.bak", 404),
+            ("/page%2Ehtml%2Ebak", 404),
+            ("/page.html~", 404),
+            ("/secret/keys.txt", 404),
+            ("/secret/deep/keys.txt", 404),
+            ("/public/secret/readme.txt", 200)
+        ];
+
+        for (path, status) in cases {
+            assert_eq!(files.handle(&request(path, "")).status(), status, "{path}");
+        }
+    }
+
+    #[test]
+    fn globs_match_components_and_paths() {
+        let cases = [
+            ("*.bak", "a.bak", true),
+            ("*.bak", "a.bak.txt", false),
+            ("*", "", true),
+            ("a*b*c", "aXbYc", true),
+            ("a*b*c", "acb", false),
+            ("exact", "exact", true),
+            ("exact", "exactly", false)
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob(pattern, text), expected, "{pattern} against {text}");
+        }
+
+        let cases = [
+            ("secret/**", "secret", true),
+            ("secret/**", "secret/a/b", true),
+            ("**/*.key", "a/b/c.key", true),
+            ("**/*.key", "c.key", true),
+            ("a/*/c", "a/b/c", true),
+            ("a/*/c", "a/b/b/c", false),
+            ("a/**/c", "a/b/b/c", true)
+        ];
+        for (pattern, path, expected) in cases {
+            let pattern: Vec<&str> = pattern.split('/').collect();
+            let components: Vec<&str> = path.split('/').collect();
+            assert_eq!(glob_path(&pattern, &components), expected, "{pattern:?} against {path}");
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use std::{
+    array,
+    collections::HashMap,
+    fmt::Write,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use crate::{
+    embedded,
+    histogram::{HistogramSnapshot, LatencyHistogram},
+};
+
+/*
+    Lifetime counters shared by the accept loop and every connection. Each counter is an
+    independent atomic, so updating one never blocks a worker; the price is that a snapshot taken
+    while traffic is flowing may be off by the few events that land between two loads. The one
+    lock is the registry of what each open connection is doing, held only to update an entry.
+ */
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    connections_accepted: AtomicU64,
+    closed_by_client: AtomicU64,
+    closed_by_server: AtomicU64,
+    accept_errors: AtomicU64,
+    requests_served: AtomicU64,
+    requests_shed: AtomicU64,
+    closed_on_request: AtomicU64,
+    closed_idle: AtomicU64,
+    closed_at_max_requests: AtomicU64,
+    // one slot per bound in REQUESTS_PER_CONNECTION_BOUNDS, plus one for anything above the last
+    requests_per_connection: [AtomicU64; REQUESTS_PER_CONNECTION_BOUNDS.len() + 1],
+    requests_on_closed_connections: AtomicU64,
+    log_lines_dropped: AtomicU64,
+    client_disconnects: AtomicU64,
+    handler_panics: AtomicU64,
+    abandoned_requests: AtomicU64,
+    request_timeouts: AtomicU64,
+    latency: LatencyHistogram,
+    next_connection_id: AtomicU64,
+    activity: Mutex<HashMap<u64, Activity>>
+}
+
+// What an open connection handed to the pool is doing.
+#[derive(Debug, Clone, Copy)]
+enum Activity {
+    // waiting for a worker
+    Queued,
+    // receiving a request, running its handler or sending the response
+    Active,
+    // kept alive since the instant it finished its last response, waiting for the next request
+    Idle(Instant)
+}
+
+/*
+    A connection's entry in the activity registry, from when it is queued until it closes. The
+    entry is removed on drop, whichever way the connection ends.
+ */
+pub(crate) struct TrackedConnection {
+    id: u64,
+    stats: Arc<ServerStats>
+}
+
+impl TrackedConnection {
+    pub(crate) fn active(&self) {
+        self.stats.set_activity(self.id, Activity::Active);
+    }
+
+    pub(crate) fn idle(&self) {
+        self.stats.set_activity(self.id, Activity::Idle(Instant::now()));
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        // recover the map even if poisoned; the drop must not panic during unwinding
+        self.stats.activity
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/// Upper bounds of the requests-per-connection histogram buckets.
+pub const REQUESTS_PER_CONNECTION_BOUNDS: [u64; 9] = [0, 1, 2, 4, 8, 16, 32, 64, 128];
+
+/// Why a connection ended. Each reason counts towards either the client or the server side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The peer hung up.
+    Client,
+    /// The client asked for the connection to close after its request: `Connection: close`,
+    /// or an HTTP/1.0 request without `Connection: keep-alive`.
+    ClientRequested,
+    /// The server closed it after the keep-alive timeout passed with no new request.
+    IdleTimeout,
+    /// The server closed it after `max_requests_per_connection` requests.
+    MaxRequests,
+    /// The server closed it for any other reason: protocol error, I/O failure or shutdown.
+    Server
+}
+
+impl ServerStats {
+    /// Stats whose request latency histogram uses the given bucket upper bounds.
+    pub fn with_latency_buckets(bounds: Vec<Duration>) -> Self {
+        Self { latency: LatencyHistogram::new(bounds), ..Self::default() }
+    }
+
+    pub(crate) fn connection_accepted(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Starts tracking a connection that is about to be queued for a worker.
+    pub(crate) fn track_connection(self: &Arc<Self>) -> TrackedConnection {
+        let id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        self.set_activity(id, Activity::Queued);
+        TrackedConnection { id, stats: Arc::clone(self) }
+    }
+
+    fn set_activity(&self, id: u64, activity: Activity) {
+        self.activity
+            .lock()
+            .expect("Mutex poisoned: Another thread panicked while holding the lock.")
+            .insert(id, activity);
+    }
+
+    pub(crate) fn connection_closed(&self, reason: CloseReason) {
+        let detail = match reason {
+            CloseReason::ClientRequested => Some(&self.closed_on_request),
+            CloseReason::IdleTimeout => Some(&self.closed_idle),
+            CloseReason::MaxRequests => Some(&self.closed_at_max_requests),
+            CloseReason::Client | CloseReason::Server => None
+        };
+        if let Some(counter) = detail {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+
+        match reason {
+            CloseReason::Client | CloseReason::ClientRequested => self.closed_by_client.fetch_add(1, Ordering::Relaxed),
+            CloseReason::IdleTimeout | CloseReason::MaxRequests | CloseReason::Server => {
+                self.closed_by_server.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+    }
+
+    // Records how many requests a connection served before it closed.
+    pub(crate) fn connection_served(&self, requests: u64) {
+        let index = REQUESTS_PER_CONNECTION_BOUNDS.partition_point(|&bound| bound < requests);
+        self.requests_per_connection[index].fetch_add(1, Ordering::Relaxed);
+        self.requests_on_closed_connections.fetch_add(requests, Ordering::Relaxed);
+    }
+
+    pub(crate) fn accept_error(&self) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_served(&self, duration: Duration) {
+        self.requests_served.fetch_add(1, Ordering::Relaxed);
+        self.latency.record(duration);
+    }
+
+    pub(crate) fn request_shed(&self) {
+        self.requests_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn client_disconnected(&self) {
+        self.client_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handler_panicked(&self) {
+        self.handler_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_abandoned(&self) {
+        self.abandoned_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_timed_out(&self) {
+        self.request_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn log_line_dropped(&self) {
+        self.log_lines_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Copies the current counter values without taking any locks.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let (mut queued, mut active, mut idle, mut oldest_idle) = (0, 0, 0, None);
+        let now = Instant::now();
+        for activity in self.activity.lock().expect("Mutex poisoned: Another thread panicked while holding the lock.").values() {
+            match activity {
+                Activity::Queued => queued += 1,
+                Activity::Active => active += 1,
+                Activity::Idle(since) => {
+                    idle += 1;
+                    oldest_idle = oldest_idle.max(Some(now.saturating_duration_since(*since)));
+                }
+            }
+        }
+
+        StatsSnapshot {
+            connections_accepted: self.connections_accepted.load(Ordering::Relaxed),
+            closed_by_client: self.closed_by_client.load(Ordering::Relaxed),
+            closed_by_server: self.closed_by_server.load(Ordering::Relaxed),
+            accept_errors: self.accept_errors.load(Ordering::Relaxed),
+            requests_served: self.requests_served.load(Ordering::Relaxed),
+            requests_shed: self.requests_shed.load(Ordering::Relaxed),
+            closed_on_request: self.closed_on_request.load(Ordering::Relaxed),
+            closed_idle: self.closed_idle.load(Ordering::Relaxed),
+            closed_at_max_requests: self.closed_at_max_requests.load(Ordering::Relaxed),
+            requests_per_connection: array::from_fn(|i| self.requests_per_connection[i].load(Ordering::Relaxed)),
+            requests_on_closed_connections: self.requests_on_closed_connections.load(Ordering::Relaxed),
+            log_lines_dropped: self.log_lines_dropped.load(Ordering::Relaxed),
+            client_disconnects: self.client_disconnects.load(Ordering::Relaxed),
+            handler_panics: self.handler_panics.load(Ordering::Relaxed),
+            abandoned_requests: self.abandoned_requests.load(Ordering::Relaxed),
+            request_timeouts: self.request_timeouts.load(Ordering::Relaxed),
+            connections_queued: queued,
+            connections_active: active,
+            connections_idle: idle,
+            oldest_idle,
+            latency: self.latency.snapshot()
+        }
+    }
+}
+
+/// A point-in-time copy of `ServerStats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub connections_accepted: u64,
+    pub closed_by_client: u64,
+    pub closed_by_server: u64,
+    pub accept_errors: u64,
+    pub requests_served: u64,
+    /// Connections answered with 503 by load shedding instead of being queued.
+    pub requests_shed: u64,
+    /// Connections closed because the client asked for it in its request.
+    pub closed_on_request: u64,
+    /// Keep-alive connections closed for sitting idle past the keep-alive timeout.
+    pub closed_idle: u64,
+    /// Connections closed for reaching the per-connection request limit.
+    pub closed_at_max_requests: u64,
+    /// Closed connections by how many requests each served, bucketed by
+    /// `REQUESTS_PER_CONNECTION_BOUNDS` (not cumulative); the last entry is the overflow bucket.
+    pub requests_per_connection: [u64; REQUESTS_PER_CONNECTION_BOUNDS.len() + 1],
+    /// Total requests served by the connections counted in `requests_per_connection`.
+    pub requests_on_closed_connections: u64,
+    /// Access log lines thrown away because the log queue was full.
+    pub log_lines_dropped: u64,
+    /// Connections the client broke off (broken pipe or reset) while we were still talking.
+    pub client_disconnects: u64,
+    /// Handler panics turned into a 500.
+    pub handler_panics: u64,
+    /// Requests whose client disconnected while they waited in the queue, skipped unhandled.
+    pub abandoned_requests: u64,
+    /// Connections closed with 408 because a request's head, or the next request on a
+    /// keep-alive connection, didn't arrive in time.
+    pub request_timeouts: u64,
+    /// Open connections waiting for a worker.
+    pub connections_queued: u64,
+    /// Open connections with a request in progress.
+    pub connections_active: u64,
+    /// Open keep-alive connections waiting for their next request.
+    pub connections_idle: u64,
+    /// How long the longest-idle keep-alive connection has been waiting; None if none is idle.
+    pub oldest_idle: Option<Duration>,
+    pub latency: HistogramSnapshot
+}
+
+const PERCENTILES: [(&str, f64); 4] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p999", 0.999)];
+
+impl StatsSnapshot {
+    pub fn connections_open(&self) -> u64 {
+        self.connections_accepted
+            .saturating_sub(self.closed_by_client + self.closed_by_server)
+    }
+
+    /// Requests that reused an already open connection instead of paying for a new one.
+    pub fn keep_alive_reuses(&self) -> u64 {
+        self.requests_served.saturating_sub(self.connections_accepted)
+    }
+
+    /// Mean number of requests per closed connection; None before any connection has closed.
+    pub fn mean_requests_per_connection(&self) -> Option<f64> {
+        let connections: u64 = self.requests_per_connection.iter().sum();
+        (connections > 0).then(|| self.requests_on_closed_connections as f64 / connections as f64)
+    }
+
+    /// Renders the human-readable status page.
+    pub fn render_status(&self) -> String {
+        self.render_status_with(&[])
+    }
+
+    /// Renders the status page with a table of `settings` below the counters, label and value.
+    pub fn render_status_with(&self, settings: &[(&str, String)]) -> String {
+        let rows = [
+            ("Connections accepted", self.connections_accepted),
+            ("Connections open", self.connections_open()),
+            ("Connections queued", self.connections_queued),
+            ("Connections active", self.connections_active),
+            ("Connections idle", self.connections_idle),
+            ("Closed by client", self.closed_by_client),
+            ("Closed by server", self.closed_by_server),
+            ("Accept errors", self.accept_errors),
+            ("Requests served", self.requests_served),
+            ("Requests shed", self.requests_shed),
+            ("Keep-alive reuses", self.keep_alive_reuses()),
+            ("Closed on client request", self.closed_on_request),
+            ("Closed idle", self.closed_idle),
+            ("Closed at max requests", self.closed_at_max_requests),
+            ("Log lines dropped", self.log_lines_dropped),
+            ("Client disconnects", self.client_disconnects),
+            ("Handler panics", self.handler_panics),
+            ("Abandoned requests", self.abandoned_requests),
+            ("Request timeouts (408)", self.request_timeouts)
+        ];
+
+        let mut page = String::from(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    \
+             <title>Server status</title>\n    <style>\n"
+        );
+        page.push_str(&String::from_utf8_lossy(embedded::STATUS_CSS.body));
+        page.push_str(
+            "    </style>\n  </head>\n  <body>\n    <h1>Server status</h1>\n    <table>\n"
+        );
+        for (label, value) in rows {
+            let _ = writeln!(page, "      <tr><th>{label}</th><td>{value}</td></tr>");
+        }
+        let oldest_idle = self.oldest_idle.map_or(String::from("-"), |age| format!("{:.1}s", age.as_secs_f64()));
+        let _ = writeln!(page, "      <tr><th>Oldest idle connection</th><td>{oldest_idle}</td></tr>");
+        let mean = self.mean_requests_per_connection().map_or(String::from("-"), |mean| format!("{mean:.2}"));
+        let _ = writeln!(page, "      <tr><th>Requests per connection</th><td>{mean}</td></tr>");
+        // the closed connections by how many requests each served, one row per bucket
+        let mut lower = 0;
+        for (i, count) in self.requests_per_connection.iter().enumerate() {
+            let label = match REQUESTS_PER_CONNECTION_BOUNDS.get(i) {
+                Some(&bound) if bound == lower => format!("{bound}"),
+                Some(&bound) => format!("{lower}–{bound}"),
+                None => format!("over {}", lower - 1)
+            };
+            lower = REQUESTS_PER_CONNECTION_BOUNDS.get(i).map_or(lower, |bound| bound + 1);
+            let _ = writeln!(page, "      <tr><th>Closed after {label} requests</th><td>{count}</td></tr>");
+        }
+        for (label, q) in PERCENTILES {
+            let value = self.latency
+                .percentile(q)
+                .map_or(String::from("-"), |latency| format!("{:.3}ms", latency.as_secs_f64() * 1000.0));
+            let _ = writeln!(page, "      <tr><th>Latency {label}</th><td>{value}</td></tr>");
+        }
+        page.push_str("    </table>\n");
+        if !settings.is_empty() {
+            page.push_str("    <h2>Settings</h2>\n    <table>\n");
+            for (label, value) in settings {
+                let _ = writeln!(page, "      <tr><th>{label}</th><td>{value}</td></tr>");
+            }
+            page.push_str("    </table>\n");
+        }
+        page.push_str("  </body>\n</html>\n");
+
+        page
+    }
+
+    /// Renders the counters in the Prometheus text exposition format.
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            ("connections_accepted_total", "Connections accepted.", self.connections_accepted),
+            ("connections_closed_by_client_total", "Connections closed by the client.", self.closed_by_client),
+            ("connections_closed_by_server_total", "Connections closed by the server.", self.closed_by_server),
+            ("accept_errors_total", "Failed accept calls.", self.accept_errors),
+            ("requests_served_total", "Requests answered.", self.requests_served),
+            ("requests_shed_total", "Requests refused with 503 by load shedding.", self.requests_shed),
+            ("keep_alive_reuses_total", "Requests served on a reused connection.", self.keep_alive_reuses()),
+            ("connections_closed_on_request_total", "Connections closed because the client asked.", self.closed_on_request),
+            ("connections_closed_idle_total", "Keep-alive connections closed by the idle timeout.", self.closed_idle),
+            ("connections_closed_max_requests_total", "Connections closed at the per-connection request limit.", self.closed_at_max_requests),
+            ("access_log_lines_dropped_total", "Access log lines dropped because the log queue was full.", self.log_lines_dropped),
+            ("client_disconnects_total", "Connections broken off by the client mid-exchange.", self.client_disconnects),
+            ("handler_panics_total", "Handler panics answered with a 500.", self.handler_panics),
+            ("abandoned_requests_total", "Queued requests skipped because the client had disconnected.", self.abandoned_requests),
+            ("request_timeouts_total", "Connections closed with 408 Request Timeout.", self.request_timeouts)
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}");
+        }
+        let _ = writeln!(
+            out,
+            "# HELP connections_open Connections currently open.\n# TYPE connections_open gauge\nconnections_open {}",
+            self.connections_open()
+        );
+        let gauges = [
+            ("connections_queued", "Open connections waiting for a worker.", self.connections_queued),
+            ("connections_active", "Open connections with a request in progress.", self.connections_active),
+            ("connections_idle", "Keep-alive connections waiting for their next request.", self.connections_idle)
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}");
+        }
+        let name = "oldest_idle_connection_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} How long the longest-idle keep-alive connection has waited.\n# TYPE {name} gauge\n{name} {}",
+            self.oldest_idle.unwrap_or_default().as_secs_f64()
+        );
+
+        let name = "request_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Time from first request byte to last response byte.\n# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in self.latency.bounds.iter().zip(&self.latency.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{}\"}} {cumulative}", bound.as_secs_f64());
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.latency.count());
+        let _ = writeln!(out, "{name}_sum {}", self.latency.sum.as_secs_f64());
+        let _ = writeln!(out, "{name}_count {}", self.latency.count());
+
+        let name = "requests_per_connection";
+        let _ = writeln!(out, "# HELP {name} Requests served by each closed connection.\n# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in REQUESTS_PER_CONNECTION_BOUNDS.iter().zip(&self.requests_per_connection) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let connections: u64 = self.requests_per_connection.iter().sum();
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {connections}");
+        let _ = writeln!(out, "{name}_sum {}", self.requests_on_closed_connections);
+        let _ = writeln!(out, "{name}_count {connections}");
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_close_reason_counts_towards_its_side_and_its_own_counter() {
+        let stats = ServerStats::default();
+        let reasons = [
+            CloseReason::Client,
+            CloseReason::ClientRequested,
+            CloseReason::ClientRequested,
+            CloseReason::IdleTimeout,
+            CloseReason::MaxRequests,
+            CloseReason::Server
+        ];
+        reasons.into_iter().for_each(|reason| stats.connection_closed(reason));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.closed_by_client, 3);
+        assert_eq!(snapshot.closed_by_server, 3);
+        assert_eq!(snapshot.closed_on_request, 2);
+        assert_eq!(snapshot.closed_idle, 1);
+        assert_eq!(snapshot.closed_at_max_requests, 1);
+    }
+
+    #[test]
+    fn connections_are_bucketed_by_the_requests_they_served() {
+        let stats = ServerStats::default();
+        assert_eq!(stats.snapshot().mean_requests_per_connection(), None);
+
+        for requests in [0, 1, 1, 3, 4, 5, 128, 129, 1000] {
+            stats.connection_served(requests);
+        }
+
+        let snapshot = stats.snapshot();
+        // bounds 0, 1, 2, 4, 8, 16, 32, 64, 128, then the overflow bucket
+        assert_eq!(snapshot.requests_per_connection, [1, 2, 0, 2, 1, 0, 0, 0, 1, 2]);
+        assert_eq!(snapshot.requests_on_closed_connections, 1271);
+        assert_eq!(snapshot.mean_requests_per_connection(), Some(1271.0 / 9.0));
+    }
+
+    #[test]
+    fn the_metrics_export_the_close_reasons_and_a_cumulative_histogram() {
+        let stats = ServerStats::default();
+        stats.connection_closed(CloseReason::IdleTimeout);
+        stats.connection_closed(CloseReason::MaxRequests);
+        for requests in [1, 3, 200] {
+            stats.connection_served(requests);
+        }
+
+        let metrics = stats.snapshot().render_metrics();
+        let expected = [
+            "connections_closed_idle_total 1",
+            "connections_closed_max_requests_total 1",
+            "connections_closed_on_request_total 0",
+            "requests_per_connection_bucket{le=\"1\"} 1",
+            "requests_per_connection_bucket{le=\"2\"} 1",
+            "requests_per_connection_bucket{le=\"4\"} 2",
+            "requests_per_connection_bucket{le=\"128\"} 2",
+            "requests_per_connection_bucket{le=\"+Inf\"} 3",
+            "requests_per_connection_sum 204",
+            "requests_per_connection_count 3"
+        ];
+        for line in expected {
+            assert!(metrics.lines().any(|metric| metric == line), "no {line:?} in\n{metrics}");
+        }
+    }
+
+    #[test]
+    fn handler_panics_are_counted_on_the_page_and_in_the_metrics() {
+        let stats = ServerStats::default();
+        stats.handler_panicked();
+        stats.handler_panicked();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.handler_panics, 2);
+        let metrics = snapshot.render_metrics();
+        assert!(metrics.lines().any(|metric| metric == "handler_panics_total 2"), "{metrics}");
+        assert!(metrics.contains("# TYPE handler_panics_total counter\n"), "{metrics}");
+        let page = snapshot.render_status();
+        assert!(page.contains("<tr><th>Handler panics</th><td>2</td></tr>"), "{page}");
+    }
+
+    #[test]
+    fn each_tracked_connection_counts_as_what_it_is_doing_until_dropped() {
+        let stats = Arc::new(ServerStats::default());
+        let first = stats.track_connection();
+        let second = stats.track_connection();
+        let third = stats.track_connection();
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.connections_queued, snapshot.connections_active, snapshot.connections_idle), (3, 0, 0));
+        assert_eq!(snapshot.oldest_idle, None);
+
+        first.active();
+        second.idle();
+        std::thread::sleep(Duration::from_millis(20));
+        third.idle();
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.connections_queued, snapshot.connections_active, snapshot.connections_idle), (0, 1, 2));
+        assert!(snapshot.oldest_idle.unwrap() >= Duration::from_millis(20), "{:?}", snapshot.oldest_idle);
+
+        drop(second);
+        drop(third);
+        first.idle();
+        first.active();
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.connections_queued, snapshot.connections_active, snapshot.connections_idle), (0, 1, 0));
+        drop(first);
+        assert_eq!(stats.snapshot().connections_active, 0);
+    }
+
+    #[test]
+    fn connection_activity_is_exported_as_gauges_and_on_the_page() {
+        let stats = Arc::new(ServerStats::default());
+        let _queued = stats.track_connection();
+        let idle = stats.track_connection();
+        idle.idle();
+        for requests in [1, 3, 2000] {
+            stats.connection_served(requests);
+        }
+
+        let snapshot = stats.snapshot();
+        let metrics = snapshot.render_metrics();
+        for line in ["# TYPE connections_queued gauge", "connections_queued 1", "connections_active 0", "connections_idle 1"] {
+            assert!(metrics.lines().any(|metric| metric == line), "no {line:?} in\n{metrics}");
+        }
+        assert!(metrics.lines().any(|metric| metric.starts_with("oldest_idle_connection_seconds ")), "{metrics}");
+
+        let page = snapshot.render_status();
+        let rows = [
+            "<tr><th>Connections queued</th><td>1</td></tr>",
+            "<tr><th>Connections idle</th><td>1</td></tr>",
+            "<tr><th>Closed after 1 requests</th><td>1</td></tr>",
+            "<tr><th>Closed after 3–4 requests</th><td>1</td></tr>",
+            "<tr><th>Closed after over 128 requests</th><td>1</td></tr>"
+        ];
+        for row in rows {
+            assert!(page.contains(row), "no {row:?} in\n{page}");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::io;
+
+/*
+    The sd_notify protocol: a service started with Type=notify tells systemd how it is doing
+    by sending newline-separated VAR=value assignments as one datagram to the unix socket named
+    in NOTIFY_SOCKET. A name starting with '@' is in Linux's abstract namespace. Without the
+    variable we weren't started that way and there is nobody to tell.
+ */
+#[cfg(unix)]
+pub(crate) fn notify(state: &str) -> io::Result<bool> {
+    use std::{env, os::unix::net::UnixDatagram};
+
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    match path.to_str().and_then(|path| path.strip_prefix('@')) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Some(name) => {
+            #[cfg(target_os = "android")]
+            use std::os::android::net::SocketAddrExt;
+            #[cfg(target_os = "linux")]
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), &path)?;
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn notify(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
+
+// Sends `state`, logging rather than failing: a missed notification mustn't stop the server.
+pub(crate) fn notify_or_log(state: &str) {
+    if let Err(e) = notify(state) {
+        eprintln!("Failed to notify systemd ({state}): {e}");
+    }
+}
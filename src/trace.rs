@@ -0,0 +1,145 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt::{Display, Formatter},
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+use crate::Request;
+
+/*
+    W3C Trace Context (https://www.w3.org/TR/trace-context/): the `traceparent` header that
+    carries a distributed trace across services. We read it so our access log lines can be tied
+    to the trace the caller started, and `child` makes the header to send on to anything we call.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    parent_id: [u8; 8],
+    flags: u8
+}
+
+impl TraceContext {
+    /// Parses a `traceparent` value like `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    ///
+    /// None for anything invalid, which the spec says to treat as if the header were absent.
+    pub fn parse(value: &str) -> Option<TraceContext> {
+        let mut fields = value.trim().split('-');
+        let version = hex::<1>(fields.next()?)?[0];
+        let trace_id = hex::<16>(fields.next()?)?;
+        let parent_id = hex::<8>(fields.next()?)?;
+        let flags = hex::<1>(fields.next()?)?[0];
+
+        // version ff is forbidden; a later version may append fields, version 00 may not
+        if version == 0xff || (version == 0 && fields.next().is_some()) {
+            return None;
+        }
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return None;
+        }
+
+        Some(TraceContext { trace_id, parent_id, flags })
+    }
+
+    /// The 32 hex digit id shared by every span in the trace.
+    pub fn trace_id(&self) -> String {
+        to_hex(&self.trace_id)
+    }
+
+    /// The caller's span, which becomes the parent of ours.
+    pub fn parent_id(&self) -> String {
+        to_hex(&self.parent_id)
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 1 == 1
+    }
+
+    /// The same trace with a fresh span id, to propagate on an outgoing request.
+    pub fn child(&self) -> TraceContext {
+        let mut parent_id = RandomState::new().build_hasher().finish().to_be_bytes();
+        // all zeroes is the one invalid span id
+        if parent_id == [0; 8] {
+            parent_id[7] = 1;
+        }
+        TraceContext { parent_id, ..*self }
+    }
+}
+
+impl Display for TraceContext {
+    /// Formats as a version 00 `traceparent` value.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", to_hex(&self.trace_id), to_hex(&self.parent_id), self.flags)
+    }
+}
+
+// Exactly 2 * N lowercase hex digits; the spec doesn't allow uppercase.
+fn hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != 2 * N || !text.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/*
+    The span a request is served in, with the `tracing` feature; without it, nothing at all.
+    It is named `request` like every request span, since tracing wants names fixed at compile
+    time; `otel.name`, the name OpenTelemetry exporters show, is the method and route pattern,
+    "GET /users/:id", rather than the raw path, which would make every id a span name of its
+    own. The route is only known once the handler has routed the request, so that, the status
+    and the duration are recorded as the span closes.
+
+    A valid incoming `traceparent` puts the span in the caller's trace: its trace id and the
+    caller's span id, the parent, are recorded for a subscriber to link the spans up by. An
+    invalid one is ignored, as the spec asks.
+ */
+pub(crate) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan
+}
+
+impl RequestSpan {
+    /// Opens the request's span and enters it, so whatever the handler logs lands inside it.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn enter(request: &Request) -> RequestSpan {
+        #[cfg(feature = "tracing")]
+        {
+            let trace = request.trace_context();
+            let span = tracing::info_span!(
+                "request",
+                otel.name = tracing::field::Empty,
+                http.method = request.method(),
+                http.route = tracing::field::Empty,
+                http.status_code = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+                request_id = request.id(),
+                trace_id = trace.map(|trace| trace.trace_id()),
+                parent_id = trace.map(|trace| trace.parent_id())
+            );
+            RequestSpan { span: span.entered() }
+        }
+        #[cfg(not(feature = "tracing"))]
+        RequestSpan {}
+    }
+
+    /// Records how the request went, and closes the span.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn close(self, request: &Request, status: u16, duration: Duration) {
+        #[cfg(feature = "tracing")]
+        {
+            let route = request.route().unwrap_or("unrouted");
+            self.span.record("otel.name", format!("{} {route}", request.method()));
+            self.span.record("http.route", route);
+            self.span.record("http.status_code", status);
+            self.span.record("duration_ms", duration.as_secs_f64() * 1000.0);
+        }
+    }
+}
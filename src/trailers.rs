@@ -0,0 +1,166 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+/*
+    Fields a recipient must not take from a trailer, because they describe or route the message
+    and are needed before the body: framing, routing, caching and the like (RFC 9110, 6.5.1).
+ */
+const FORBIDDEN: [&str; 15] = [
+    "Authorization", "Cache-Control", "Connection", "Content-Encoding", "Content-Length",
+    "Content-Range", "Content-Type", "Expires", "Host", "Keep-Alive", "Location", "Set-Cookie",
+    "TE", "Trailer", "Transfer-Encoding"
+];
+
+/// Why trailers couldn't be declared or set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrailerError {
+    /// The body isn't a stream of unknown length, so it won't be chunked and has nowhere to put
+    /// a trailer.
+    NotChunked,
+    /// HTTP/1.0 has no chunked encoding, and so no trailers.
+    Http10,
+    /// A name that isn't a valid field name, or one HTTP doesn't allow in a trailer.
+    Forbidden(String),
+    /// `set` was called with a name that wasn't declared up front.
+    Undeclared(String),
+    /// The value contains a line break or other control character.
+    InvalidValue(String)
+}
+
+impl Display for TrailerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrailerError::NotChunked => write!(f, "trailers can only follow a chunked body of unknown length"),
+            TrailerError::Http10 => write!(f, "trailers can't be sent to an HTTP/1.0 client"),
+            TrailerError::Forbidden(name) => write!(f, "\"{name}\" can't be sent as a trailer"),
+            TrailerError::Undeclared(name) => write!(f, "trailer \"{name}\" was not declared"),
+            TrailerError::InvalidValue(name) => write!(f, "the value of trailer \"{name}\" contains a control character")
+        }
+    }
+}
+
+impl std::error::Error for TrailerError {}
+
+/// The trailer fields of one response, from `Response::trailers`. The names are fixed when the
+/// handle is made, since they're announced in the `Trailer` header before the body; the values
+/// are filled in while the body is produced, typically by the reader streaming it, and are sent
+/// after the last chunk. A declared trailer that was never set is simply left out.
+///
+/// Clones share the same values, so one can go to the reader and another stay with the handler.
+#[derive(Debug, Clone)]
+pub struct Trailers {
+    names: Arc<[String]>,
+    values: Arc<Mutex<Vec<(String, String)>>>
+}
+
+impl Trailers {
+    pub(crate) fn declare(names: &[&str]) -> Result<Self, TrailerError> {
+        if let Some(name) = names.iter().find(|name| !allowed(name)) {
+            return Err(TrailerError::Forbidden(name.to_string()));
+        }
+
+        Ok(Self {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            values: Arc::new(Mutex::new(Vec::new()))
+        })
+    }
+
+    /// Sets (or replaces) the value of the declared trailer `name`.
+    pub fn set(&self, name: &str, value: &str) -> Result<(), TrailerError> {
+        let Some(declared) = self.names.iter().find(|declared| declared.eq_ignore_ascii_case(name)) else {
+            return Err(TrailerError::Undeclared(name.to_string()));
+        };
+        if value.bytes().any(|byte| byte.is_ascii_control() && byte != b'\t') {
+            return Err(TrailerError::InvalidValue(name.to_string()));
+        }
+
+        let mut values = self.values.lock().expect("Mutex poisoned: Another thread panicked while holding the lock.");
+        values.retain(|(key, _)| key != declared);
+        values.push((declared.clone(), value.trim_matches([' ', '\t']).to_string()));
+        Ok(())
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    // The `Trailer` header's value: every declared name.
+    pub(crate) fn header_value(&self) -> String {
+        self.names.join(", ")
+    }
+
+    // The trailer section, written after the zero-length chunk and before the final CRLF.
+    pub(crate) fn write_fields<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let values = self.values.lock().expect("Mutex poisoned: Another thread panicked while holding the lock.");
+        for (name, value) in values.iter() {
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+        Ok(())
+    }
+}
+
+fn allowed(name: &str) -> bool {
+    let token = !name.is_empty() && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte));
+    token && !FORBIDDEN.iter().any(|forbidden| name.eq_ignore_ascii_case(forbidden))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(trailers: &Trailers) -> String {
+        let mut out = Vec::new();
+        trailers.write_fields(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn only_valid_names_allowed_in_a_trailer_can_be_declared() {
+        let cases = [
+            ("X-Checksum", true),
+            ("Server-Timing", true),
+            ("content-length", false),
+            ("Set-Cookie", false),
+            ("Trailer", false),
+            ("", false),
+            ("Bad Name", false),
+            ("X-Checksum:", false)
+        ];
+        for (name, expected) in cases {
+            let declared = Trailers::declare(&["X-Ok", name]);
+            assert_eq!(declared.is_ok(), expected, "{name:?}");
+            if !expected {
+                assert_eq!(declared.unwrap_err(), TrailerError::Forbidden(name.to_string()), "{name:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn values_are_set_by_declared_name_and_sent_as_set() {
+        let trailers = Trailers::declare(&["X-Checksum", "X-Rows"]).unwrap();
+        assert_eq!(trailers.header_value(), "X-Checksum, X-Rows");
+        assert_eq!(written(&trailers), "");
+
+        trailers.set("x-checksum", " 1234\t").unwrap();
+        assert_eq!(written(&trailers), "X-Checksum: 1234\r\n");
+
+        // a clone shares the values, and setting again replaces
+        trailers.clone().set("X-Checksum", "5678").unwrap();
+        trailers.set("X-Rows", "3").unwrap();
+        assert_eq!(written(&trailers), "X-Checksum: 5678\r\nX-Rows: 3\r\n");
+    }
+
+    #[test]
+    fn undeclared_names_and_control_characters_are_refused() {
+        let trailers = Trailers::declare(&["X-Checksum"]).unwrap();
+        assert_eq!(trailers.set("X-Other", "1"), Err(TrailerError::Undeclared("X-Other".to_string())));
+        assert_eq!(
+            trailers.set("X-Checksum", "12\r\nInjected: yes"),
+            Err(TrailerError::InvalidValue("X-Checksum".to_string()))
+        );
+        assert!(trailers.set("X-Checksum", "a\tb").is_ok());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{Request, Response, Router};
+
+/// Picks a `Router` by the request's host, then lets it dispatch as usual.
+///
+/// Host patterns are either exact (`api.example.com`) or a wildcard for all subdomains
+/// (`*.example.com`, which doesn't match `example.com` itself). Exact patterns win over
+/// wildcards, and a longer wildcard over a shorter one; requests for any other host go to the
+/// default router.
+pub struct VirtualHostRouter {
+    hosts: Vec<(HostPattern, Router)>,
+    default: Router
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    Exact(String),
+    // the suffix including its leading dot, e.g. ".example.com"
+    Wildcard(String)
+}
+
+impl VirtualHostRouter {
+    pub fn new(default: Router) -> Self {
+        Self { hosts: Vec::new(), default }
+    }
+
+    /// Serves requests for hosts matching `pattern` with `router`. Matching ignores case and port.
+    pub fn host(&mut self, pattern: &str, router: Router) -> &mut Self {
+        let pattern = pattern.to_ascii_lowercase();
+        let pattern = match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => HostPattern::Wildcard(suffix.to_string()),
+            _ => HostPattern::Exact(pattern)
+        };
+
+        self.hosts.push((pattern, router));
+        self
+    }
+
+    pub fn handle(&self, request: &mut Request) -> Response {
+        let router = request
+            .host()
+            .map(hostname)
+            .and_then(|host| self.select(&host))
+            .unwrap_or(&self.default);
+
+        router.handle(request)
+    }
+
+    fn select(&self, host: &str) -> Option<&Router> {
+        let exact = self.hosts.iter().find(|(pattern, _)| matches!(pattern, HostPattern::Exact(name) if name == host));
+
+        exact
+            .or_else(|| {
+                self.hosts
+                    .iter()
+                    .filter(|(pattern, _)| match pattern {
+                        HostPattern::Wildcard(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
+                        HostPattern::Exact(_) => false
+                    })
+                    .max_by_key(|(pattern, _)| match pattern {
+                        HostPattern::Wildcard(suffix) => suffix.len(),
+                        HostPattern::Exact(_) => 0
+                    })
+            })
+            .map(|(_, router)| router)
+    }
+}
+
+// "WWW.Example.com:8080" → "www.example.com"; an IPv6 literal keeps its brackets: "[::1]:80" → "[::1]".
+fn hostname(host: &str) -> String {
+    let name = match host.strip_prefix('[') {
+        Some(_) => host.split_inclusive(']').next().unwrap_or(host),
+        None => host.split(':').next().unwrap_or(host)
+    };
+
+    // a fully qualified "example.com." names the same host
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A router that answers every GET with `name`, so a response shows which router served it.
+    fn named(name: &'static str) -> Router {
+        let mut router = Router::new();
+        router.get("/", move |_| Response::html(200, name));
+        router
+    }
+
+    // Without a host the request is HTTP/1.0, where Host is optional.
+    fn served_by(vhosts: &VirtualHostRouter, host: Option<&str>) -> String {
+        let raw = match host {
+            Some(host) => format!("GET / HTTP/1.1\r\nHost: {host}\r\n\r\n"),
+            None => "GET / HTTP/1.0\r\n\r\n".to_string()
+        };
+        let mut request = Request::parse(&mut raw.as_bytes()).unwrap().unwrap();
+        String::from_utf8(vhosts.handle(&mut request).body().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn each_host_goes_to_its_router() {
+        let mut vhosts = VirtualHostRouter::new(named("default"));
+        vhosts
+            .host("*.example.com", named("any subdomain"))
+            .host("API.example.com", named("api"))
+            .host("*.eu.example.com", named("eu subdomain"))
+            .host("[::1]", named("loopback"));
+
+        let cases = [
+            (Some("api.example.com"), "api"),
+            (Some("Api.Example.COM:8080"), "api"),
+            (Some("api.example.com."), "api"),
+            (Some("www.example.com"), "any subdomain"),
+            (Some("a.b.example.com"), "any subdomain"),
+            (Some("shop.eu.example.com"), "eu subdomain"),
+            (Some("eu.example.com"), "any subdomain"),
+            (Some("example.com"), "default"),
+            (Some("notexample.com"), "default"),
+            (Some("[::1]:8080"), "loopback"),
+            (Some("other.org"), "default"),
+            (None, "default")
+        ];
+        for (host, expected) in cases {
+            assert_eq!(served_by(&vhosts, host), expected, "{host:?}");
+        }
+    }
+
+    #[test]
+    fn hostnames_drop_the_port_and_trailing_dot() {
+        let cases = [
+            ("example.com", "example.com"),
+            ("WWW.Example.com:8080", "www.example.com"),
+            ("example.com.", "example.com"),
+            ("[::1]:80", "[::1]"),
+            ("[::1]", "[::1]"),
+            ("127.0.0.1:3000", "127.0.0.1")
+        ];
+
+        for (host, expected) in cases {
+            assert_eq!(hostname(host), expected, "{host}");
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fmt::{Debug, Formatter},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use crate::{request::Version, Response};
+
+const PENDING: u8 = 0;
+const RESPONDED: u8 = 1;
+const TIMED_OUT: u8 = 2;
+
+/*
+    Decides, exactly once per request, who gets to answer it: the worker running the handler, or
+    a timer that fires when a route's timeout elapses. Both sides race to move `state` out of
+    PENDING with a compare-exchange, so a 504 can never be written on top of (or after) a real
+    response, nor the other way round.
+
+    We can't stop a handler that overruns; Rust threads can't be killed. The timer therefore only
+    answers the client and shuts the socket down. The handler keeps running until it returns on
+    its own, and the connection loop then sees it lost the race and discards its response.
+ */
+pub(crate) struct Watchdog {
+    stream: Arc<TcpStream>,
+    version: Version,
+    state: AtomicU8,
+    // one per armed timer; dropping a sender wakes its timer thread early so it can exit
+    cancel: Mutex<Vec<mpsc::Sender<()>>>
+}
+
+impl Watchdog {
+    pub(crate) fn new(stream: Arc<TcpStream>, version: Version) -> Arc<Self> {
+        Arc::new(Self { stream, version, state: AtomicU8::new(PENDING), cancel: Mutex::new(Vec::new()) })
+    }
+
+    /// Starts a timer thread that answers with 504 if the request is still unanswered after
+    /// `timeout`. It may be armed more than once (the server's request timeout, then the
+    /// route's); whichever timer runs out first answers.
+    pub(crate) fn arm(self: &Arc<Self>, timeout: Duration) {
+        let (sender, receiver) = mpsc::channel::<()>();
+        self.cancel.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(sender);
+
+        let watchdog = Arc::clone(self);
+        thread::spawn(move || {
+            // Disconnected means the request was answered in time; only a timeout fires the 504
+            if receiver.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout) {
+                watchdog.fire();
+            }
+        });
+    }
+
+    /// Claims the right to respond for the handler. Returns false if the timer already answered.
+    pub(crate) fn claim(&self) -> bool {
+        let claimed = self.state
+            .compare_exchange(PENDING, RESPONDED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        self.cancel.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+
+        claimed
+    }
+
+    fn fire(&self) {
+        if self.state.compare_exchange(PENDING, TIMED_OUT, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return;
+        }
+
+        let mut writer = &*self.stream;
+        if let Err(e) = Response::status_only(504).write_to(&mut writer, self.version, false) {
+            eprintln!("Failed to write 504 response: {e}");
+        }
+        // the handler's late write, if it gets that far, now fails instead of reaching the client
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Debug for Watchdog {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watchdog").field("state", &self.state).finish()
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use crate::{static_files, Request, Response, StaticFiles};
+
+// Distinguishes temp files of concurrent uploads to the same directory.
+static UPLOAD_ID: AtomicU64 = AtomicU64::new(0);
+
+/*
+    PUT and DELETE on files below a directory, for `Router::mount_writable`. An upload is
+    streamed into a temp file next to its target and renamed over it only once complete, so
+    readers never see a half-written file and a failed upload leaves the old one in place.
+
+    What can be written is what `files`, the mount's reader, would serve: no dotfiles, and no
+    path through a symlink its policy refuses, or a PUT could write outside the directory.
+ */
+#[derive(Debug, Clone)]
+pub(crate) struct WritableDir {
+    files: StaticFiles
+}
+
+impl WritableDir {
+    pub(crate) fn new(files: StaticFiles) -> Self {
+        Self { files }
+    }
+
+    /// 201 for a new file, 204 when an existing one was replaced.
+    pub(crate) fn put(&self, request: &mut Request, request_path: &str) -> Response {
+        let Some(target) = self.target(request_path) else {
+            return Response::status_only(404);
+        };
+        if target.is_dir() {
+            return Response::status_only(409);
+        }
+
+        let existed = target.is_file();
+        match self.store(request, &target) {
+            Ok(()) if existed => Response::status_only(204),
+            Ok(()) => Response::status_only(201),
+            // the body reader enforces the size limit on chunked uploads
+            Err(e) if e.kind() == ErrorKind::InvalidData => Response::status_only(413),
+            Err(e) => {
+                eprintln!("Failed to store {}: {e}", target.display());
+                Response::status_only(500)
+            }
+        }
+    }
+
+    pub(crate) fn delete(&self, request_path: &str) -> Response {
+        let Some(target) = self.target(request_path).filter(|path| path.is_file()) else {
+            return Response::status_only(404);
+        };
+
+        match fs::remove_file(&target) {
+            Ok(()) => Response::status_only(204),
+            Err(e) if e.kind() == ErrorKind::NotFound => Response::status_only(404),
+            Err(e) => {
+                eprintln!("Failed to delete {}: {e}", target.display());
+                Response::status_only(500)
+            }
+        }
+    }
+
+    // The mount root itself is not a file anyone can write or delete.
+    fn target(&self, request_path: &str) -> Option<PathBuf> {
+        let root = self.files.root();
+        static_files::sanitize(root, request_path)
+            .filter(|path| path != root && !self.files.is_hidden(path) && self.files.allows_write(path))
+    }
+
+    fn store(&self, request: &mut Request, target: &Path) -> io::Result<()> {
+        let parent = target.parent().expect("a sanitized target lies below the mount root");
+        // sanitize only allows normal components, and target() refused any through a symlink
+        fs::create_dir_all(parent)?;
+
+        let file_name = target.file_name().expect("a sanitized target has a file name");
+        let temp = parent.join(format!(
+            ".{}.upload-{}-{}",
+            file_name.to_string_lossy(),
+            process::id(),
+            UPLOAD_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let result = write_body(request, &temp).and_then(|()| {
+            // a symlink swapped in while the body was uploading mustn't redirect the rename
+            if !self.files.allows_write(target) {
+                return Err(io::Error::new(ErrorKind::PermissionDenied, "the upload path now runs through a symlink"));
+            }
+            fs::rename(&temp, target)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+}
+
+fn write_body(request: &mut Request, temp: &Path) -> io::Result<()> {
+    let mut file = File::create(temp)?;
+    let streamed = match request.body_reader() {
+        Some(mut body) => io::copy(&mut body, &mut file).map(Some)?,
+        None => None
+    };
+    if streamed.is_none() {
+        // already buffered by someone else; still store what was sent
+        file.write_all(request.body())?;
+    }
+
+    file.sync_all()
+}
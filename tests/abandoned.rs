@@ -0,0 +1,143 @@
+// Resets connections with SO_LINGER, which is set through setsockopt as Linux defines it.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::{
+    ffi::{c_int, c_void},
+    io::{Read, Write},
+    net::{Shutdown, TcpStream},
+    os::fd::AsRawFd,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, log::LogFile, Response, ServerConfig};
+use common::TestServer;
+
+// Closes `stream` with a reset rather than a FIN, as a client giving up on a request may.
+fn reset(stream: TcpStream) {
+    #[repr(C)]
+    struct Linger {
+        l_onoff: c_int,
+        l_linger: c_int
+    }
+    extern "C" {
+        fn setsockopt(socket: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+    }
+    const SOL_SOCKET: c_int = 1;
+    const SO_LINGER: c_int = 13;
+
+    let linger = Linger { l_onoff: 1, l_linger: 0 };
+    // SAFETY: the fd is open while `stream` lives, and `linger` outlives the call
+    let result = unsafe {
+        setsockopt(stream.as_raw_fd(), SOL_SOCKET, SO_LINGER, (&linger as *const Linger).cast(), size_of::<Linger>() as u32)
+    };
+    assert_eq!(result, 0, "couldn't set SO_LINGER");
+    drop(stream);
+}
+
+fn send(server: &TestServer, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(format!("GET {path} HTTP/1.1\r\nHost: test\r\n\r\n").as_bytes()).unwrap();
+    stream
+}
+
+// The value of one counter on the metrics page.
+fn metric(server: &TestServer, name: &str) -> u64 {
+    let page = Client::get(&server.addr(), "/metrics").unwrap();
+    let page = String::from_utf8(page.body().to_vec()).unwrap();
+    page.lines()
+        .find_map(|line| line.strip_prefix(name)?.trim().parse().ok())
+        .unwrap_or_else(|| panic!("no {name} in {page}"))
+}
+
+/*
+    One worker, held by a slow request while the others queue up behind it, returned once the
+    slow handler has started; every handler that runs records its path.
+ */
+fn one_busy_worker() -> (TestServer, Arc<Mutex<Vec<String>>>, thread::JoinHandle<()>) {
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let (started, slow_started) = mpsc::channel();
+    let started = Mutex::new(started);
+    let recorded = Arc::clone(&handled);
+    let config = ServerConfig { workers: 1, ..common::config() };
+    let server = TestServer::start(config, move |request| {
+        recorded.lock().unwrap().push(request.path().to_string());
+        if request.path() == "/slow" {
+            let _ = started.lock().unwrap().send(());
+            thread::sleep(Duration::from_millis(500));
+        }
+        Response::html(200, "done")
+    });
+
+    let addr = server.addr();
+    let slow = thread::spawn(move || assert_eq!(Client::get(&addr, "/slow").unwrap().status(), 200));
+    slow_started.recv_timeout(Duration::from_secs(5)).expect("the slow handler never ran");
+    (server, handled, slow)
+}
+
+#[test]
+fn requests_whose_clients_reset_while_queued_are_never_handled() {
+    let (server, handled, slow) = one_busy_worker();
+
+    for n in 0..3 {
+        reset(send(&server, &format!("/gone-{n}")));
+    }
+    slow.join().unwrap();
+
+    // queued behind the abandoned ones, so by the time it's answered they've been looked at
+    assert_eq!(Client::get(&server.addr(), "/after").unwrap().status(), 200);
+    assert_eq!(*handled.lock().unwrap(), ["/slow", "/after"]);
+    assert_eq!(metric(&server, "abandoned_requests_total"), 3);
+}
+
+#[test]
+fn a_client_that_only_stopped_sending_is_still_answered() {
+    let (server, handled, slow) = one_busy_worker();
+
+    let mut half_closed = send(&server, "/half-closed");
+    half_closed.shutdown(Shutdown::Write).unwrap();
+    slow.join().unwrap();
+
+    half_closed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut response = String::new();
+    half_closed.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response:?}");
+    assert!(response.ends_with("done"));
+    assert_eq!(*handled.lock().unwrap(), ["/slow", "/half-closed"]);
+    assert_eq!(metric(&server, "abandoned_requests_total"), 0);
+}
+
+#[test]
+fn a_client_hanging_up_mid_response_is_counted_and_logged_as_aborted() {
+    let logs = common::TempDir::new();
+    let log_path = logs.path().join("access.log");
+    let config = ServerConfig { access_log: Some(LogFile::new(&log_path)), ..common::config() };
+    // far more than the socket buffers hold, so the server is still writing when the reset lands
+    let server = TestServer::start(config, |request| match request.path() {
+        "/big" => Response::new(200).with_reader(std::io::repeat(b'x').take(64 << 20), None),
+        _ => Response::html(200, "small")
+    });
+
+    let mut stream = send(&server, "/big");
+    let mut head = [0; 512];
+    stream.read_exact(&mut head).unwrap();
+    reset(stream);
+
+    let started = Instant::now();
+    let line = loop {
+        let log = std::fs::read_to_string(&log_path).unwrap_or_default();
+        if let Some(line) = log.lines().find(|line| line.contains("/big")) {
+            break line.to_string();
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "the response was never logged");
+        thread::sleep(Duration::from_millis(20));
+    };
+    assert!(line.ends_with(" aborted"), "{line}");
+    let sent: u64 = line.split(' ').nth(5).unwrap().parse().unwrap();
+    assert!(sent < 64 << 20, "{line}");
+    assert_eq!(metric(&server, "client_disconnects_total"), 1);
+    // and nothing else was affected
+    assert_eq!(Client::get(&server.addr(), "/next").unwrap().status(), 200);
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use std::thread;
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+#[test]
+fn several_acceptors_feed_one_pool_and_stop_together() {
+    let config = ServerConfig { acceptor_threads: 4, ..common::config() };
+    let server = TestServer::start(config, |request| Response::html(200, request.path().to_string()));
+
+    // one-shot connections from several clients at once, so the acceptors have a backlog to share
+    thread::scope(|scope| {
+        for client in 0..8 {
+            let addr = server.addr();
+            scope.spawn(move || {
+                for n in 0..10 {
+                    let path = format!("/{client}/{n}");
+                    let response = Client::get(&addr, &path).unwrap();
+                    assert_eq!(response.body(), path.as_bytes());
+                }
+            });
+        }
+    });
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use std::{
+    fs, thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, log::LogFile, log_format::LogFormat, Response, ServerConfig};
+use common::{TempDir, TestServer};
+
+#[test]
+fn lines_are_written_in_the_configured_format() {
+    let logs = TempDir::new();
+    let log_path = logs.path().join("access.log");
+    let config = ServerConfig {
+        access_log: Some(LogFile::new(&log_path)),
+        access_log_format: Some(LogFormat::parse("%m \"%r\" %s %b %{X-Client}i %{Content-Type}o %L").unwrap()),
+        ..common::config()
+    };
+    let server = TestServer::start(config, |_| Response::html(200, "hello"));
+
+    let response = Client::new(&server.addr()).request("GET", "/page?x=1", &[("X-Client", "tests")], b"").unwrap();
+    let id = response.header("X-Request-Id").unwrap().to_string();
+
+    let started = Instant::now();
+    let log = loop {
+        let log = fs::read_to_string(&log_path).unwrap_or_default();
+        if !log.is_empty() {
+            break log;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "nothing was logged");
+        thread::sleep(Duration::from_millis(20));
+    };
+    assert_eq!(log, format!("GET \"GET /page?x=1 HTTP/1.1\" 200 5 tests text/html; charset=utf-8 {id}\n"));
+}
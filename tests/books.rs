@@ -0,0 +1,184 @@
+mod common;
+
+use std::sync::Arc;
+use book_web_server::{
+    books::{self, Catalog},
+    client::Client,
+    json::{self, Value},
+    Response, Router,
+};
+use common::TestServer;
+
+fn serve() -> (TestServer, Arc<Catalog>) {
+    let mut router = Router::new();
+    let catalog = books::mount(&mut router);
+    (TestServer::start(common::config(), move |request| router.handle(request)), catalog)
+}
+
+fn send(server: &TestServer, method: &str, path: &str, body: &str) -> Response {
+    let headers = [("Content-Type", "application/json")];
+    Client::new(&server.addr()).request(method, path, &headers, body.as_bytes()).unwrap()
+}
+
+fn json(response: &Response) -> Value {
+    json::parse(std::str::from_utf8(response.body()).unwrap()).unwrap()
+}
+
+fn error(response: &Response) -> String {
+    json(response).get("error").and_then(Value::as_str).unwrap().to_string()
+}
+
+#[test]
+fn a_book_can_be_created_read_updated_and_deleted() {
+    let (server, catalog) = serve();
+
+    let created = send(&server, "POST", "/books", r#"{"title": "Dune", "author": "Frank Herbert"}"#);
+    assert_eq!(created.status(), 201);
+    assert_eq!(created.header("Location"), Some("/books/1"));
+    assert_eq!(created.header("Content-Type"), Some("application/json; charset=utf-8"));
+    assert_eq!(std::str::from_utf8(created.body()).unwrap(), r#"{"id":1,"title":"Dune","author":"Frank Herbert","year":null}"#);
+
+    let shown = Client::get(&server.addr(), "/books/1").unwrap();
+    assert_eq!(shown.status(), 200);
+    assert_eq!(json(&shown), json(&created));
+
+    let updated = send(&server, "PUT", "/books/1", r#"{"title": "Dune", "author": "Frank Herbert", "year": 1965}"#);
+    assert_eq!(updated.status(), 200);
+    assert_eq!(catalog.get(1).unwrap().year, Some(1965));
+
+    assert_eq!(send(&server, "DELETE", "/books/1", "").status(), 204);
+    assert_eq!(Client::get(&server.addr(), "/books/1").unwrap().status(), 404);
+    assert_eq!(catalog.books(), []);
+}
+
+#[test]
+fn books_inserted_directly_are_served() {
+    let (server, catalog) = serve();
+    let book = catalog.insert("Emma", "Jane Austen", Some(1815));
+
+    let response = Client::get(&server.addr(), &format!("/books/{}", book.id)).unwrap();
+    assert_eq!(json(&response), book.to_json());
+}
+
+#[test]
+fn unknown_ids_are_404s() {
+    let (server, _catalog) = serve();
+    let body = r#"{"title": "Dune", "author": "Frank Herbert"}"#;
+
+    for (method, path) in [("GET", "/books/7"), ("GET", "/books/seven"), ("PUT", "/books/7"), ("DELETE", "/books/7")] {
+        let response = send(&server, method, path, body);
+        assert_eq!(response.status(), 404, "{method} {path}");
+        assert_eq!(error(&response), "no such book", "{method} {path}");
+    }
+}
+
+#[test]
+fn invalid_bodies_are_refused() {
+    let (server, catalog) = serve();
+    let cases = [
+        (r#"{"title": "Dune""#, "invalid JSON: expected ',' or '}'"),
+        (r#"["Dune", "Frank Herbert"]"#, "title must be a non-empty string"),
+        (r#"{"title": " ", "author": "Frank Herbert"}"#, "title must be a non-empty string"),
+        (r#"{"title": "Dune", "author": 7}"#, "author must be a non-empty string"),
+        (r#"{"title": "Dune", "author": "Frank Herbert", "year": -1}"#, "year must be a non-negative integer"),
+        (r#"{"title": "Dune", "author": "Frank Herbert", "year": "1965"}"#, "year must be a non-negative integer")
+    ];
+
+    for (body, message) in cases {
+        let response = send(&server, "POST", "/books", body);
+        assert_eq!(response.status(), 400, "{body}");
+        assert_eq!(error(&response), message, "{body}");
+    }
+    assert_eq!(catalog.books(), []);
+}
+
+#[test]
+fn a_body_not_declared_as_json_is_a_415() {
+    let (server, _catalog) = serve();
+    let body = br#"{"title": "Dune", "author": "Frank Herbert"}"#;
+
+    let response = Client::new(&server.addr()).request("POST", "/books", &[("Content-Type", "text/plain")], body).unwrap();
+    assert_eq!(response.status(), 415);
+    let response = Client::new(&server.addr()).request("POST", "/books", &[], body).unwrap();
+    assert_eq!(response.status(), 415);
+}
+
+// The ids on one page of `GET /books`, with its next and prev offsets.
+fn page(server: &TestServer, query: &str) -> (Vec<u64>, Option<u64>, Option<u64>) {
+    let response = Client::get(&server.addr(), &format!("/books?{query}")).unwrap();
+    assert_eq!(response.status(), 200, "{query}");
+    let page = json(&response);
+    let Some(Value::Array(items)) = page.get("items") else {
+        panic!("no items in {page}");
+    };
+
+    let ids = items.iter().map(|book| book.get("id").and_then(Value::as_u64).unwrap()).collect();
+    let offset = |name| page.get(name).and_then(Value::as_u64);
+    (ids, offset("next"), offset("prev"))
+}
+
+#[test]
+fn the_list_is_paged_with_next_and_prev_offsets() {
+    let (server, catalog) = serve();
+    for n in 1..=5 {
+        catalog.insert(&format!("Book {n}"), "Anon", None);
+    }
+
+    let cases = [
+        ("", (vec![1, 2, 3, 4, 5], None, None)),
+        ("limit=2", (vec![1, 2], Some(2), None)),
+        ("limit=2&offset=2", (vec![3, 4], Some(4), Some(0))),
+        ("limit=2&offset=4", (vec![5], None, Some(2))),
+        ("limit=2&offset=1", (vec![2, 3], Some(3), Some(0))),
+        ("limit=2&offset=9", (vec![], None, Some(3)))
+    ];
+    for (query, expected) in cases {
+        assert_eq!(page(&server, query), expected, "{query}");
+    }
+
+    let response = Client::get(&server.addr(), "/books?limit=2").unwrap();
+    let listed = json(&response);
+    assert_eq!(listed.get("total").and_then(Value::as_u64), Some(5));
+    assert_eq!(listed.get("limit").and_then(Value::as_u64), Some(2));
+    assert_eq!(listed.get("offset").and_then(Value::as_u64), Some(0));
+}
+
+#[test]
+fn the_list_can_be_sorted_and_filtered() {
+    let (server, catalog) = serve();
+    for title in ["Persuasion", "Emma", "Dune", "emma, again"] {
+        catalog.insert(title, "Anon", None);
+    }
+
+    let cases = [
+        ("sort=title", vec![3, 2, 1, 4]),
+        ("sort=title&order=desc", vec![4, 1, 2, 3]),
+        ("order=desc", vec![4, 3, 2, 1]),
+        ("title_contains=EMMA", vec![2, 4]),
+        ("title_contains=emma%2C%20again", vec![4]),
+        ("title_contains=nothing", vec![])
+    ];
+    for (query, expected) in cases {
+        assert_eq!(page(&server, query).0, expected, "{query}");
+    }
+}
+
+#[test]
+fn invalid_list_parameters_are_400s() {
+    let (server, _catalog) = serve();
+    let cases = [
+        ("limit=0", "limit"),
+        ("limit=101", "limit"),
+        ("limit=ten", "limit"),
+        ("offset=-1", "offset"),
+        ("sort=year", "sort"),
+        ("order=up", "order"),
+        ("title_contains=%zz", "title_contains")
+    ];
+
+    for (query, parameter) in cases {
+        let response = Client::get(&server.addr(), &format!("/books?{query}")).unwrap();
+        assert_eq!(response.status(), 400, "{query}");
+        assert_eq!(error(&response), format!("invalid value for {parameter}"), "{query}");
+    }
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+// Answers with the Content-Type the request asks for in `X-Content-Type`.
+fn serve(config: ServerConfig) -> TestServer {
+    TestServer::start(config, |request| {
+        let content_type = request.header("X-Content-Type").unwrap_or_default().to_string();
+        Response::new(200).with_header("Content-Type", &content_type).with_body("body")
+    })
+}
+
+// The Content-Type a response set to `content_type` goes out with.
+fn content_type(server: &TestServer, content_type: &str) -> String {
+    let response = Client::new(&server.addr()).request("GET", "/", &[("X-Content-Type", content_type)], b"").unwrap();
+    response.header("Content-Type").unwrap().to_string()
+}
+
+#[test]
+fn textual_responses_get_the_default_charset() {
+    let server = serve(common::config());
+    let cases = [
+        ("text/html", "text/html; charset=utf-8"),
+        ("application/json", "application/json; charset=utf-8"),
+        ("text/html; charset=iso-8859-1", "text/html; charset=iso-8859-1"),
+        ("image/png", "image/png")
+    ];
+
+    for (sent, expected) in cases {
+        assert_eq!(content_type(&server, sent), expected, "{sent}");
+    }
+}
+
+#[test]
+fn the_charset_is_configurable_and_can_be_turned_off() {
+    let server = serve(ServerConfig { default_charset: Some(String::from("iso-8859-1")), ..common::config() });
+    assert_eq!(content_type(&server, "text/plain"), "text/plain; charset=iso-8859-1");
+
+    let server = serve(ServerConfig { default_charset: None, ..common::config() });
+    assert_eq!(content_type(&server, "text/plain"), "text/plain");
+}
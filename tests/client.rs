@@ -0,0 +1,70 @@
+mod common;
+
+use std::{thread, time::Duration};
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+// Answers every request with its method and the size of its body, so a test can see what arrived.
+fn echo_server(config: ServerConfig) -> TestServer {
+    TestServer::start(config, |request| {
+        Response::html(200, format!("{} {}", request.method(), request.body().len()))
+    })
+}
+
+#[test]
+fn keep_alive_reuses_one_connection() {
+    let server = echo_server(common::config());
+    let mut client = Client::new(&server.addr());
+
+    for _ in 0..3 {
+        assert_eq!(client.request("GET", "/", &[], &[]).unwrap().body(), b"GET 0");
+    }
+    assert_eq!(client.connections(), 1);
+}
+
+#[test]
+fn without_keep_alive_every_request_gets_a_connection() {
+    let server = echo_server(common::config());
+    let mut client = Client::new(&server.addr()).keep_alive(false);
+
+    for _ in 0..3 {
+        assert_eq!(client.request("GET", "/", &[], &[]).unwrap().status(), 200);
+    }
+    assert_eq!(client.connections(), 3);
+}
+
+#[test]
+fn a_body_is_sent_with_its_length() {
+    let server = echo_server(common::config());
+    let mut client = Client::new(&server.addr());
+
+    let response = client.request("POST", "/", &[("Content-Type", "text/plain")], b"twelve bytes").unwrap();
+    assert_eq!(response.body(), b"POST 12");
+}
+
+#[test]
+fn a_head_response_has_no_body_and_keeps_the_connection() {
+    let server = echo_server(common::config());
+    let mut client = Client::new(&server.addr());
+
+    let response = client.request("HEAD", "/", &[], &[]).unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.body().is_empty());
+    // had the body been read as the Content-Length says, this would hang or misparse
+    assert_eq!(client.request("GET", "/", &[], &[]).unwrap().body(), b"GET 0");
+    assert_eq!(client.connections(), 1);
+}
+
+#[test]
+fn a_connection_the_server_timed_out_is_replaced() {
+    let config = ServerConfig { keep_alive_timeout: Duration::from_millis(200), ..common::config() };
+    let server = echo_server(config);
+    let mut client = Client::new(&server.addr());
+
+    assert_eq!(client.request("GET", "/", &[], &[]).unwrap().status(), 200);
+    thread::sleep(Duration::from_millis(600));
+
+    // not even a POST is at risk: the stale connection is noticed before anything is sent on it
+    assert_eq!(client.request("POST", "/", &[], b"body").unwrap().body(), b"POST 4");
+    assert_eq!(client.connections(), 2);
+}
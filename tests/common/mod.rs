@@ -0,0 +1,123 @@
+// Shared by the integration tests: a server on an ephemeral port, run on a thread of its own.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use book_web_server::{Request, Response, Server, ServerConfig, ShutdownHandle};
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    running: Option<JoinHandle<anyhow::Result<()>>>
+}
+
+impl TestServer {
+    /// Binds `config` on 127.0.0.1 with a port of the OS's choosing, and starts `run`.
+    pub fn start<H>(config: ServerConfig, handler: H) -> TestServer
+    where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+    {
+        TestServer::run(bind(config, handler))
+    }
+
+    /// Runs a server bound with `bind` and set up further, e.g. with a redirect listener.
+    pub fn run(server: Server) -> TestServer {
+        let addr = server.local_addr().expect("the test server has no address");
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        TestServer { addr, shutdown, running: Some(running) }
+    }
+
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Asks the server to stop and waits for `run` to return.
+    pub fn stop(mut self) -> anyhow::Result<()> {
+        self.shutdown.shutdown();
+        self.running.take().expect("stopped twice").join().expect("the server thread panicked")
+    }
+}
+
+impl Drop for TestServer {
+    // a failing test still stops its server, so the next one isn't left sharing the process with it
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(running) = self.running.take() {
+            let _ = running.join();
+        }
+    }
+}
+
+/// Binds `config` on 127.0.0.1 with a port of the OS's choosing, without running it yet.
+pub fn bind<H>(config: ServerConfig, handler: H) -> Server
+where H: Fn(&mut Request) -> Response + Send + Sync + 'static
+{
+    let config = ServerConfig { addr: String::from("127.0.0.1:0"), ..config };
+    Server::bind(config, handler).expect("couldn't start the test server")
+}
+
+/// A config with short timeouts, so a test that goes wrong fails quickly instead of hanging.
+pub fn config() -> ServerConfig {
+    ServerConfig {
+        workers: 2,
+        shutdown_grace: Duration::from_secs(5),
+        keep_alive_timeout: Duration::from_secs(2),
+        header_timeout: Duration::from_secs(2),
+        ..ServerConfig::default()
+    }
+}
+
+/// Writes `raw` as is and reads until the server closes the connection.
+pub fn send_raw(addr: SocketAddr, raw: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).expect("couldn't connect to the test server");
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(raw).expect("couldn't send the request");
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+/// A fresh directory per test, removed when the test is done with it.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!("integration-test-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `contents` to `relative`, creating the directories on the way, and returns its path.
+    pub fn write(&self, relative: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
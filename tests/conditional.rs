@@ -0,0 +1,28 @@
+mod common;
+
+use book_web_server::{client::Client, Response};
+use common::TestServer;
+
+#[test]
+fn a_dynamic_response_with_an_etag_is_revalidated() {
+    let server = TestServer::start(common::config(), |_| {
+        Response::html(200, "<h1>books</h1>").with_header("ETag", "\"v1\"")
+    });
+
+    let fresh = Client::new(&server.addr()).request("GET", "/", &[], b"").unwrap();
+    assert_eq!(fresh.status(), 200);
+    assert_eq!(fresh.header("ETag"), Some("\"v1\""));
+
+    // pipelined: a 304 with a body would corrupt the response after it
+    let response = common::send_raw(
+        server.addr,
+        b"GET / HTTP/1.1\r\nHost: x\r\nIf-None-Match: \"v1\"\r\n\r\n\
+          GET / HTTP/1.1\r\nHost: x\r\nIf-None-Match: \"v0\"\r\nConnection: close\r\n\r\n"
+    );
+    let (not_modified, changed) = response.split_once("HTTP/1.1 200 OK\r\n").expect(&response);
+    assert!(not_modified.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{response}");
+    assert!(not_modified.contains("ETag: \"v1\"\r\n"), "{not_modified}");
+    assert!(!not_modified.contains("Content-Length"), "{not_modified}");
+    assert!(not_modified.ends_with("\r\n\r\n"), "{not_modified}");
+    assert!(changed.ends_with("\r\n\r\n<h1>books</h1>"), "{changed}");
+}
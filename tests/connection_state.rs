@@ -0,0 +1,44 @@
+mod common;
+
+use book_web_server::{client::Client, Response};
+use common::TestServer;
+
+// Counts the requests each connection has made, in the connection's state.
+fn serve() -> TestServer {
+    TestServer::start(common::config(), |request| {
+        let state = request.connection_state();
+        let count = state.get::<u32>().copied().unwrap_or(0) + 1;
+        state.set(count);
+        Response::html(200, count.to_string())
+    })
+}
+
+fn count(client: &mut Client) -> String {
+    let response = client.request("GET", "/", &[], b"").unwrap();
+    String::from_utf8(response.body().to_vec()).unwrap()
+}
+
+#[test]
+fn a_kept_alive_connection_sees_what_its_earlier_requests_stored() {
+    let server = serve();
+
+    let mut client = Client::new(&server.addr());
+    assert_eq!(count(&mut client), "1");
+    assert_eq!(count(&mut client), "2");
+    assert_eq!(count(&mut client), "3");
+}
+
+#[test]
+fn a_new_connection_starts_with_nothing() {
+    let server = serve();
+
+    let mut first = Client::new(&server.addr());
+    assert_eq!(count(&mut first), "1");
+    assert_eq!(count(&mut first), "2");
+    let mut second = Client::new(&server.addr());
+    assert_eq!(count(&mut second), "1");
+    // one-shot connections never get past their first request
+    let mut closing = Client::new(&server.addr()).keep_alive(false);
+    assert_eq!(count(&mut closing), "1");
+    assert_eq!(count(&mut closing), "1");
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use book_web_server::{client::Client, json::{self, Value}, Response};
+use common::TestServer;
+
+fn get(server: &TestServer, accept: &str) -> Response {
+    Client::new(&server.addr()).request("GET", "/missing", &[("Accept", accept)], b"").unwrap()
+}
+
+#[test]
+fn a_bare_error_is_answered_in_the_format_the_client_prefers() {
+    let server = TestServer::start(common::config(), |_| Response::status_only(404));
+
+    let response = get(&server, "application/json");
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.header("Vary"), Some("Accept"));
+    let body = json::parse(std::str::from_utf8(response.body()).unwrap()).unwrap();
+    assert_eq!(body.get("error").and_then(Value::as_str), Some("Not Found"));
+    // the id in the body is the one the response carries
+    assert_eq!(body.get("request_id").and_then(Value::as_str), response.header("X-Request-Id"));
+
+    let response = get(&server, "text/html");
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.header("Vary"), Some("Accept"));
+    assert!(!response.header("Content-Type").unwrap_or_default().contains("json"));
+}
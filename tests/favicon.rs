@@ -0,0 +1,22 @@
+mod common;
+
+use book_web_server::{client::Client, Response};
+use common::TestServer;
+
+#[test]
+fn the_embedded_icon_fills_in_for_a_handler_404_only() {
+    let server = TestServer::start(common::config(), |_| Response::status_only(404));
+
+    let icon = Client::get(&server.addr(), "/favicon.ico").unwrap();
+    assert_eq!(icon.status(), 200);
+    assert_eq!(icon.header("Content-Type"), Some("image/x-icon"));
+    assert!(!icon.body().is_empty());
+
+    assert_eq!(Client::get(&server.addr(), "/other").unwrap().status(), 404);
+}
+
+#[test]
+fn a_handler_that_serves_the_icon_wins() {
+    let server = TestServer::start(common::config(), |_| Response::new(200).with_body("custom icon"));
+    assert_eq!(Client::get(&server.addr(), "/favicon.ico").unwrap().body(), b"custom icon");
+}
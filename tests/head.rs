@@ -0,0 +1,34 @@
+mod common;
+
+use book_web_server::{client::Client, Response, StaticFiles};
+use common::{TempDir, TestServer};
+
+#[test]
+fn head_gets_the_framing_of_get_and_no_body() {
+    let dir = TempDir::new();
+    dir.write("page.html", "<h1>hello</h1>");
+    let files = StaticFiles::new(dir.path());
+    let server = TestServer::start(common::config(), move |request| match request.path() {
+        "/stream" => Response::new(200).with_reader(std::io::repeat(b'x'), None),
+        _ => files.handle(request)
+    });
+
+    // pipelined: anything HEAD sent past its head would be read as the start of the next response
+    let response = common::send_raw(
+        server.addr,
+        b"HEAD /page.html HTTP/1.1\r\nHost: x\r\n\r\n\
+          HEAD /stream HTTP/1.1\r\nHost: x\r\n\r\n\
+          GET /page.html HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n"
+    );
+    let responses: Vec<&str> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+    assert_eq!(responses.len(), 3, "{response}");
+    assert!(responses[0].contains("Content-Length: 14\r\n"), "{}", responses[0]);
+    assert!(responses[0].ends_with("\r\n\r\n"), "{}", responses[0]);
+    assert!(responses[1].contains("Transfer-Encoding: chunked\r\n"), "{}", responses[1]);
+    assert!(responses[1].ends_with("\r\n\r\n"), "{}", responses[1]);
+    assert!(responses[2].ends_with("\r\n\r\n<h1>hello</h1>"), "{}", responses[2]);
+
+    let response = Client::new(&server.addr()).request("HEAD", "/missing.html", &[], b"").unwrap();
+    assert_eq!(response.status(), 404);
+    assert!(response.body().is_empty());
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use std::{
+    io::Write,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, Response};
+use common::TestServer;
+
+// A server whose plaintext port redirects to https://example.com; returns it and that port.
+fn redirecting_server() -> (TestServer, String) {
+    let mut server = common::bind(common::config(), |_| Response::html(200, "over https"));
+    server.redirect_to_https("127.0.0.1:0", "example.com").unwrap();
+    let redirect_addr = server.redirect_addr().unwrap().to_string();
+    (TestServer::run(server), redirect_addr)
+}
+
+#[test]
+fn a_plaintext_request_is_redirected_to_the_same_target() {
+    let (_server, redirect_addr) = redirecting_server();
+
+    let response = Client::get(&redirect_addr, "/docs/page?lang=en").unwrap();
+    assert!(matches!(response.status(), 301 | 308), "{}", response.status());
+    assert_eq!(response.header("Location"), Some("https://example.com/docs/page?lang=en"));
+}
+
+#[test]
+fn a_redirected_post_is_told_to_keep_its_method() {
+    let (_server, redirect_addr) = redirecting_server();
+
+    let response = Client::new(&redirect_addr).keep_alive(false).request("GET", "/form", &[], b"").unwrap();
+    assert_eq!(response.status(), 301);
+    let response = Client::new(&redirect_addr).keep_alive(false).request("POST", "/form", &[], b"title=Dune").unwrap();
+    assert_eq!(response.status(), 308);
+    assert_eq!(response.header("Location"), Some("https://example.com/form"));
+}
+
+#[test]
+fn a_client_trickling_its_head_holds_up_the_redirects_only_briefly() {
+    let (_server, redirect_addr) = redirecting_server();
+
+    let mut trickler = TcpStream::connect(&redirect_addr).unwrap();
+    let trickling = thread::spawn(move || {
+        // a byte every 100ms never trips the per-read timeout, only the deadline on the whole head
+        for byte in b"GET / HTTP/1.1\r\nHost: example.com\r\nX-Slow: it takes a while to get to the end\r\n" {
+            if trickler.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    let response = Client::new(&redirect_addr).timeout(Duration::from_secs(10)).request("GET", "/", &[], &[]).unwrap();
+    assert!(matches!(response.status(), 301 | 308));
+    assert!(started.elapsed() < Duration::from_secs(2), "waited {:?} behind the trickler", started.elapsed());
+    trickling.join().unwrap();
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+// Polls the metrics, over one kept-alive connection, until `name` reaches `expected`.
+fn wait_for_metric(metrics: &mut Client, name: &str, expected: u64) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let page = metrics.request("GET", "/metrics", &[], b"").unwrap();
+        let page = String::from_utf8(page.body().to_vec()).unwrap();
+        let value: u64 = page.lines().find_map(|line| line.strip_prefix(name)?.trim().parse().ok()).unwrap();
+        if value == expected {
+            return;
+        }
+        assert!(Instant::now() < deadline, "{name} is {value}, not {expected}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn a_connection_closes_once_it_has_served_its_cap() {
+    let config = ServerConfig { max_requests_per_connection: Some(3), ..common::config() };
+    let server = TestServer::start(config, |request| Response::html(200, request.path().to_string()));
+
+    let pipelined: String = (1..=5).map(|n| format!("GET /{n} HTTP/1.1\r\nHost: test\r\n\r\n")).collect();
+    // send_raw only returns once the server has closed the connection
+    let sent = common::send_raw(server.addr, pipelined.as_bytes());
+
+    let responses: Vec<&str> = sent.split("HTTP/1.1 ").skip(1).collect();
+    assert_eq!(responses.len(), 3, "{sent}");
+    assert!(responses[0].contains("Keep-Alive: timeout=2, max=2\r\n"), "{}", responses[0]);
+    assert!(responses[1].contains("Keep-Alive: timeout=2, max=1\r\n"), "{}", responses[1]);
+    assert!(responses[2].contains("Connection: close\r\n"), "{}", responses[2]);
+    assert!(!responses[2].contains("Keep-Alive"));
+    for (n, response) in responses.iter().enumerate() {
+        assert!(response.ends_with(&format!("/{}", n + 1)), "{response}");
+    }
+}
+
+#[test]
+fn connections_the_client_asked_to_close_are_counted() {
+    let server = TestServer::start(common::config(), |_| Response::html(200, "hello"));
+    // kept alive, and polling well within the idle timeout, so it is never counted itself
+    let mut metrics = Client::new(&server.addr());
+
+    common::send_raw(server.addr, b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+    common::send_raw(server.addr, b"GET / HTTP/1.0\r\n\r\n");
+    wait_for_metric(&mut metrics, "connections_closed_on_request_total", 2);
+}
+
+#[test]
+fn connections_the_server_closed_are_counted_by_reason() {
+    let config = ServerConfig {
+        max_requests_per_connection: Some(2),
+        keep_alive_timeout: Duration::from_millis(200),
+        ..common::config()
+    };
+    let server = TestServer::start(config, |_| Response::html(200, "hello"));
+    // a connection per poll, so none of them is left to go idle
+    let mut metrics = Client::new(&server.addr()).keep_alive(false);
+
+    // answered, then left idle until the server gives up on it
+    common::send_raw(server.addr, b"GET / HTTP/1.1\r\nHost: test\r\n\r\n");
+    wait_for_metric(&mut metrics, "connections_closed_idle_total", 1);
+
+    common::send_raw(server.addr, b"GET / HTTP/1.1\r\nHost: test\r\n\r\nGET / HTTP/1.1\r\nHost: test\r\n\r\n");
+    wait_for_metric(&mut metrics, "connections_closed_max_requests_total", 1);
+    wait_for_metric(&mut metrics, "connections_closed_idle_total", 1);
+}
+
+#[test]
+fn open_connections_are_reported_as_active_or_idle() {
+    let config = ServerConfig { workers: 4, ..common::config() };
+    let server = TestServer::start(config, |request| {
+        if request.path() == "/slow" {
+            thread::sleep(Duration::from_millis(1500));
+        }
+        Response::html(200, "done")
+    });
+
+    let mut kept = Client::new(&server.addr());
+    assert_eq!(kept.request("GET", "/", &[], b"").unwrap().status(), 200);
+    let addr = server.addr();
+    let slow = thread::spawn(move || Client::get(&addr, "/slow").unwrap().status());
+
+    // the poller's own connection is active while it asks
+    let mut metrics = Client::new(&server.addr()).keep_alive(false);
+    wait_for_metric(&mut metrics, "connections_active", 2);
+    wait_for_metric(&mut metrics, "connections_idle", 1);
+    wait_for_metric(&mut metrics, "connections_queued", 0);
+    assert_eq!(slow.join().unwrap(), 200);
+
+    drop(kept);
+    wait_for_metric(&mut metrics, "connections_idle", 0);
+    wait_for_metric(&mut metrics, "connections_active", 1);
+}
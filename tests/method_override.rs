@@ -0,0 +1,29 @@
+mod common;
+
+use book_web_server::{client::Client, method_override::MethodOverride, Response, Router, ServerConfig};
+use common::TestServer;
+
+fn serve(method_override: Option<MethodOverride>) -> TestServer {
+    let mut router = Router::new();
+    router.delete("/books/:id", |request| {
+        Response::html(200, format!("deleted, asked with {}", request.original_method().unwrap_or("DELETE")))
+    });
+    router.post("/books/:id", |_| Response::html(200, "posted"));
+    TestServer::start(ServerConfig { method_override, ..common::config() }, move |request| router.handle(request))
+}
+
+fn post(server: &TestServer) -> String {
+    let headers = [("X-HTTP-Method-Override", "DELETE")];
+    let response = Client::new(&server.addr()).request("POST", "/books/1", &headers, b"").unwrap();
+    String::from_utf8(response.body().to_vec()).unwrap()
+}
+
+#[test]
+fn an_overridden_post_is_routed_by_the_method_it_asks_for() {
+    assert_eq!(post(&serve(Some(MethodOverride::default()))), "deleted, asked with POST");
+}
+
+#[test]
+fn the_header_is_ignored_unless_configured() {
+    assert_eq!(post(&serve(None)), "posted");
+}
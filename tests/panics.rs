@@ -0,0 +1,70 @@
+mod common;
+
+use std::sync::mpsc;
+use book_web_server::{
+    client::Client,
+    recovery::{fallible, HandlerPanic, InternalError},
+    Request, Response, Router,
+};
+use common::TestServer;
+
+#[test]
+fn a_panicking_handler_answers_500_and_the_workers_carry_on() {
+    let mut server = common::bind(common::config(), |request| match request.path() {
+        "/boom" => panic!("boom"),
+        _ => Response::status_only(200)
+    });
+    let (reports, heard) = mpsc::channel::<HandlerPanic>();
+    server.on_panic(move |report| reports.send(report.clone()).unwrap());
+    let server = TestServer::run(server);
+
+    // more panics than workers: none of them may take a worker down
+    for _ in 0..4 {
+        let response = common::send_raw(server.addr, b"GET /boom HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 "), "{response}");
+        assert!(response.contains("Connection: close"), "{response}");
+    }
+    assert_eq!(Client::get(&server.addr(), "/").unwrap().status(), 200);
+
+    let report = heard.recv().unwrap();
+    assert_eq!(report.request_line, "GET /boom HTTP/1.1");
+    assert_eq!(report.message, "boom");
+    assert_eq!(heard.try_iter().count(), 3);
+}
+
+#[test]
+fn the_id_a_client_sees_on_a_500_is_its_request_id() {
+    let server = TestServer::start(common::config(), fallible(|request: &mut Request| match request.path() {
+        "/boom" => panic!("boom"),
+        _ => Err(InternalError::new("the database is down"))
+    }));
+
+    for path in ["/boom", "/broken"] {
+        let response = Client::new(&server.addr()).request("GET", path, &[("Accept", "application/json")], b"").unwrap();
+        assert_eq!(response.status(), 500, "{path}");
+        let id = response.header("X-Request-Id").unwrap();
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.ends_with(&format!("\"request_id\":\"{id}\"}}")), "{path}: {body}");
+    }
+}
+
+#[test]
+fn a_panic_is_reported_with_its_route_and_counted() {
+    let mut router = Router::new();
+    router.get("/books/:id", |_| panic!("no such shelf"));
+    let mut server = common::bind(common::config(), move |request| router.handle(request));
+    let (reports, heard) = mpsc::channel::<HandlerPanic>();
+    server.on_panic(move |report| reports.send(report.clone()).unwrap());
+    let server = TestServer::run(server);
+
+    assert_eq!(Client::get(&server.addr(), "/books/7").unwrap().status(), 500);
+    assert_eq!(Client::get(&server.addr(), "/books/8").unwrap().status(), 500);
+    assert_eq!(Client::get(&server.addr(), "/books").unwrap().status(), 404);
+
+    let report = heard.recv().unwrap();
+    assert_eq!(report.route.as_deref(), Some("/books/:id"));
+    assert_eq!(report.path, "/books/7");
+    let metrics = Client::get(&server.addr(), "/metrics").unwrap();
+    let metrics = String::from_utf8(metrics.body().to_vec()).unwrap();
+    assert!(metrics.lines().any(|line| line == "handler_panics_total 2"), "{metrics}");
+}
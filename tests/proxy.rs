@@ -0,0 +1,51 @@
+mod common;
+
+use std::{
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, proxy::Proxy, Response};
+use common::TestServer;
+
+fn proxy_to(proxy: Proxy) -> TestServer {
+    TestServer::start(common::config(), move |request| proxy.handle(request))
+}
+
+#[test]
+fn requests_are_relayed_to_the_upstream() {
+    let upstream = TestServer::start(common::config(), |request| {
+        let echoed = format!("{} {} {}", request.method(), request.target(), String::from_utf8_lossy(request.body()));
+        Response::html(200, echoed).with_header("X-Upstream", "yes")
+    });
+    let proxy = proxy_to(Proxy::new(&upstream.addr()));
+
+    let response = Client::new(&proxy.addr()).request("PUT", "/books/1?x=y", &[("Content-Type", "text/plain")], b"body").unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("X-Upstream"), Some("yes"));
+    assert_eq!(response.body(), b"PUT /books/1?x=y body");
+}
+
+#[test]
+fn an_unreachable_upstream_is_a_502() {
+    // a port that was free a moment ago, with nothing listening on it now
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let proxy = proxy_to(Proxy::new(&addr.to_string()));
+
+    assert_eq!(Client::get(&proxy.addr(), "/").unwrap().status(), 502);
+}
+
+#[test]
+fn an_upstream_that_never_answers_is_a_504() {
+    let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = silent.local_addr().unwrap();
+    // accepts, reads nothing and answers nothing, until the test is over
+    thread::spawn(move || {
+        let _held: Vec<_> = silent.incoming().collect();
+    });
+    let proxy = proxy_to(Proxy::new(&addr.to_string()).read_timeout(Duration::from_millis(200)));
+
+    let asked = Instant::now();
+    assert_eq!(Client::get(&proxy.addr(), "/").unwrap().status(), 504);
+    assert!(asked.elapsed() < Duration::from_secs(2), "the 504 took {:?}", asked.elapsed());
+}
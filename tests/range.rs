@@ -0,0 +1,39 @@
+mod common;
+
+use book_web_server::{client::Client, Response, StaticFiles};
+use common::{TempDir, TestServer};
+
+// A generated page at /generated, and the files in `dir` everywhere else.
+fn serve(dir: &TempDir) -> TestServer {
+    let files = StaticFiles::new(dir.path());
+    TestServer::start(common::config(), move |request| match request.path() {
+        "/generated" => Response::html(200, "generated per request"),
+        _ => files.handle(request)
+    })
+}
+
+#[test]
+fn a_generated_response_ignores_range_and_says_so() {
+    let dir = TempDir::new();
+    let server = serve(&dir);
+
+    let response = Client::new(&server.addr()).request("GET", "/generated", &[("Range", "bytes=0-3")], &[]).unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("Accept-Ranges"), Some("none"));
+    assert_eq!(response.body(), b"generated per request");
+}
+
+#[test]
+fn a_static_file_still_serves_the_range() {
+    let dir = TempDir::new();
+    dir.write("page.txt", "0123456789");
+    let server = serve(&dir);
+
+    let response = Client::new(&server.addr()).request("GET", "/page.txt", &[("Range", "bytes=0-3")], &[]).unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.header("Content-Range"), Some("bytes 0-3/10"));
+    assert_eq!(response.body(), b"0123");
+
+    let whole = Client::get(&server.addr(), "/page.txt").unwrap();
+    assert_eq!(whole.header("Accept-Ranges"), Some("bytes"));
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use book_web_server::{client::Client, Response};
+use common::TestServer;
+
+#[test]
+fn repeated_headers_survive_the_trip_both_ways() {
+    let server = TestServer::start(common::config(), |request| {
+        let tags: Vec<&str> = request.header_all("X-Tag").collect();
+        let mut response = Response::html(200, tags.join("|"));
+        response.add_header("Set-Cookie", "session=1; HttpOnly");
+        response.add_header("Set-Cookie", "theme=dark");
+        response
+    });
+
+    let headers = [("X-Tag", "a"), ("X-Other", "z"), ("x-tag", "b, c")];
+    let response = Client::new(&server.addr()).keep_alive(false).request("GET", "/", &headers, b"").unwrap();
+    assert_eq!(response.body(), b"a|b, c");
+    assert_eq!(response.header_all("Set-Cookie").collect::<Vec<_>>(), ["session=1; HttpOnly", "theme=dark"]);
+}
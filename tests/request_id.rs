@@ -0,0 +1,45 @@
+mod common;
+
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+// Answers with the id the handler saw, to compare with the one in the response header.
+fn serve(config: ServerConfig) -> TestServer {
+    TestServer::start(config, |request| Response::html(200, request.id().unwrap_or("none").to_string()))
+}
+
+// The id the handler saw and the one sent back, for a request carrying `sent` as its X-Request-Id.
+fn ids(server: &TestServer, sent: Option<&str>) -> (String, String) {
+    let headers: Vec<(&str, &str)> = sent.map(|id| ("X-Request-Id", id)).into_iter().collect();
+    let response = Client::new(&server.addr()).request("GET", "/", &headers, b"").unwrap();
+    let seen = String::from_utf8(response.body().to_vec()).unwrap();
+    (seen, response.header("X-Request-Id").unwrap().to_string())
+}
+
+#[test]
+fn every_request_gets_a_fresh_id_that_is_sent_back() {
+    let server = serve(common::config());
+
+    let (first, echoed) = ids(&server, None);
+    assert_eq!(first, echoed);
+    let (second, echoed) = ids(&server, None);
+    assert_eq!(second, echoed);
+    assert_ne!(first, second);
+
+    // without trust, a client's id is replaced
+    let (seen, echoed) = ids(&server, Some("client-chosen"));
+    assert_eq!(seen, echoed);
+    assert_ne!(seen, "client-chosen");
+}
+
+#[test]
+fn a_trusted_client_id_is_adopted_if_it_is_well_formed() {
+    let server = serve(ServerConfig { trust_request_id: true, ..common::config() });
+
+    assert_eq!(ids(&server, Some("0f8fad5b-d9cb")), ("0f8fad5b-d9cb".to_string(), "0f8fad5b-d9cb".to_string()));
+    for sent in ["has space", &"a".repeat(129)] {
+        let (seen, echoed) = ids(&server, Some(sent));
+        assert_eq!(seen, echoed, "{sent}");
+        assert_ne!(seen, sent, "{sent}");
+    }
+}
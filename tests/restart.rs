@@ -0,0 +1,27 @@
+// Threads are counted through /proc.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::fs;
+use book_web_server::{client::Client, Response};
+use common::TestServer;
+
+// Alone in its test binary, so no other test's threads come and go while it counts.
+fn thread_count() -> usize {
+    fs::read_dir("/proc/self/task").expect("can't list this process's threads").count()
+}
+
+#[test]
+fn starting_and_stopping_five_times_leaks_no_threads() {
+    let before = thread_count();
+
+    for round in 0..5 {
+        let server = TestServer::start(common::config(), move |_| Response::html(200, format!("round {round}")));
+        let response = Client::get(&server.addr(), "/").expect("no response");
+        assert_eq!(response.body(), format!("round {round}").as_bytes());
+        server.stop().unwrap();
+    }
+
+    assert_eq!(thread_count(), before);
+}
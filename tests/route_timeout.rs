@@ -0,0 +1,37 @@
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, Response, Router};
+use common::TestServer;
+
+fn serve() -> TestServer {
+    let mut router = Router::new();
+    router.get("/slow", |_| {
+        thread::sleep(Duration::from_secs(1));
+        Response::html(200, "too late")
+    }).timeout(Duration::from_millis(100));
+    router.get("/quick", |_| Response::html(200, "in time")).timeout(Duration::from_secs(5));
+    TestServer::start(common::config(), move |request| router.handle(request))
+}
+
+#[test]
+fn a_route_over_its_timeout_is_answered_with_a_504() {
+    let server = serve();
+
+    let asked = Instant::now();
+    let response = Client::get(&server.addr(), "/slow").unwrap();
+    assert_eq!(response.status(), 504);
+    assert!(asked.elapsed() < Duration::from_millis(800), "the 504 took {:?}", asked.elapsed());
+}
+
+#[test]
+fn a_route_within_its_timeout_is_answered_normally() {
+    let server = serve();
+
+    let response = Client::get(&server.addr(), "/quick").unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.body(), b"in time");
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::{thread, time::Duration};
+use book_web_server::{client::Client, Response, Router, ServerConfig};
+use common::TestServer;
+
+fn serve(config: ServerConfig) -> TestServer {
+    let mut router = Router::new();
+    router.get("/slow", |_| {
+        thread::sleep(Duration::from_millis(50));
+        Response::html(200, "done")
+    }).server_timing();
+    router.get("/quick", |_| Response::html(200, "done"));
+    TestServer::start(config, move |request| router.handle(request))
+}
+
+// The Server-Timing metrics, by name.
+fn phases(response: &Response) -> Vec<(String, f64)> {
+    let Some(value) = response.header("Server-Timing") else {
+        return Vec::new();
+    };
+    value
+        .split(", ")
+        .map(|metric| {
+            let (name, duration) = metric.split_once(";dur=").unwrap();
+            (name.to_string(), duration.parse().unwrap())
+        })
+        .collect()
+}
+
+fn names(response: &Response) -> Vec<String> {
+    phases(response).into_iter().map(|(name, _)| name).collect()
+}
+
+#[test]
+fn the_previous_write_is_reported_from_a_connections_second_request() {
+    let server = serve(ServerConfig { server_timing: true, ..common::config() });
+
+    let mut client = Client::new(&server.addr());
+    let first = client.request("GET", "/slow", &[], b"").unwrap();
+    assert_eq!(names(&first), ["queue", "parse", "handler"]);
+    let handler = phases(&first)[2].1;
+    assert!(handler >= 50.0, "the handler slept 50ms but took {handler}ms");
+
+    let second = client.request("GET", "/quick", &[], b"").unwrap();
+    assert_eq!(names(&second), ["queue", "parse", "handler", "write"]);
+}
+
+#[test]
+fn without_the_server_setting_only_routes_that_ask_report_timings() {
+    let server = serve(common::config());
+
+    let mut client = Client::new(&server.addr());
+    assert_eq!(names(&client.request("GET", "/slow", &[], b"").unwrap()), ["queue", "parse", "handler"]);
+    assert!(names(&client.request("GET", "/quick", &[], b"").unwrap()).is_empty());
+}
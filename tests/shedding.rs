@@ -0,0 +1,57 @@
+mod common;
+
+use std::{
+    io::Write,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+// A queue depth of zero sheds every connection, so everything is answered on the accept thread.
+fn shedding_server() -> TestServer {
+    let config = ServerConfig { shed_queue_depth: Some(0), ..common::config() };
+    TestServer::start(config, |_| Response::html(200, "served by a worker"))
+}
+
+#[test]
+fn a_shed_request_gets_a_503_and_health_checks_still_pass() {
+    let server = shedding_server();
+
+    assert_eq!(Client::get(&server.addr(), "/").unwrap().status(), 503);
+    assert_eq!(Client::get(&server.addr(), "/health").unwrap().status(), 200);
+}
+
+#[test]
+fn a_trickled_head_holds_up_the_accept_thread_only_briefly() {
+    let server = shedding_server();
+
+    let mut trickler = TcpStream::connect(server.addr).unwrap();
+    let trickling = thread::spawn(move || {
+        // a byte every 50ms never trips a per-read timeout, only a deadline on the whole head
+        for byte in b"GET /health HTTP/1.1\r\nHost: example.com\r\nX-Slow: yes\r\n" {
+            if trickler.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    assert_eq!(Client::get(&server.addr(), "/health").unwrap().status(), 200);
+    assert!(started.elapsed() < Duration::from_secs(1), "waited {:?} behind the trickler", started.elapsed());
+    trickling.join().unwrap();
+}
+
+#[test]
+fn a_shed_request_body_is_not_waited_for() {
+    let server = shedding_server();
+
+    let started = Instant::now();
+    // a large body is announced but never sent
+    let response = common::send_raw(server.addr, b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 1000000\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+    assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+}
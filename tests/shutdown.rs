@@ -0,0 +1,73 @@
+mod common;
+
+use std::{
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+// Starts a server whose handler takes `handler_time` per request and reports each one it starts.
+fn slow_server(config: ServerConfig, handler_time: Duration) -> (TestServer, mpsc::Receiver<()>) {
+    let (started, handler_started) = mpsc::channel();
+    let started = Mutex::new(started);
+    let server = TestServer::start(config, move |_| {
+        let _ = started.lock().unwrap().send(());
+        thread::sleep(handler_time);
+        Response::html(200, "done")
+    });
+    (server, handler_started)
+}
+
+#[test]
+fn a_request_finishing_within_the_grace_period_is_answered() {
+    let config = ServerConfig { shutdown_grace: Duration::from_secs(5), ..common::config() };
+    let (server, handler_started) = slow_server(config, Duration::from_millis(300));
+
+    let addr = server.addr();
+    let request = thread::spawn(move || Client::get(&addr, "/slow"));
+    handler_started.recv_timeout(Duration::from_secs(5)).expect("the handler never ran");
+
+    server.stop().unwrap();
+    let response = request.join().unwrap().expect("the in-flight request was cut off");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.body(), b"done");
+}
+
+#[test]
+fn a_handler_outlasting_the_grace_period_is_force_closed() {
+    let grace = Duration::from_millis(300);
+    let config = ServerConfig { shutdown_grace: grace, ..common::config() };
+    let (server, handler_started) = slow_server(config, Duration::from_secs(5));
+
+    let addr = server.addr();
+    let request = thread::spawn(move || Client::new(&addr).timeout(Duration::from_secs(10)).request("GET", "/slow", &[], &[]));
+    handler_started.recv_timeout(Duration::from_secs(5)).expect("the handler never ran");
+
+    let stopping = Instant::now();
+    server.stop().unwrap();
+    // well short of the handler's five seconds, with room for a slow machine
+    assert!(stopping.elapsed() < Duration::from_secs(3), "stopping took {:?}", stopping.elapsed());
+
+    // the connection was shut down under the handler, so the client sees it closed, not a response
+    assert!(request.join().unwrap().is_err());
+}
+
+#[test]
+fn the_grace_period_bounds_the_whole_shutdown() {
+    let grace = Duration::from_secs(1);
+    let config = ServerConfig { shutdown_grace: grace, ..common::config() };
+    let (server, handler_started) = slow_server(config, Duration::from_secs(5));
+
+    let addr = server.addr();
+    let request = thread::spawn(move || Client::get(&addr, "/slow"));
+    handler_started.recv_timeout(Duration::from_secs(5)).expect("the handler never ran");
+
+    let stopping = Instant::now();
+    server.stop().unwrap();
+    // the workers share the connections' grace period rather than getting one of their own after it
+    let took = stopping.elapsed();
+    assert!(took >= grace && took < grace + Duration::from_millis(500), "stopping took {took:?}");
+    let _ = request.join();
+}
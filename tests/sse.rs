@@ -0,0 +1,57 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+use book_web_server::{client::Client, sse::{self, ClientGone, Event}};
+use common::TestServer;
+
+#[test]
+fn events_reach_the_client_until_the_producer_is_done() {
+    let server = TestServer::start(common::config(), |_| {
+        let (events, response) = sse::stream();
+        thread::spawn(move || {
+            for n in 1..=3 {
+                events.send(&Event::new(n.to_string()).id(&n.to_string())).unwrap();
+            }
+        });
+        response
+    });
+
+    let response = Client::get(&server.addr(), "/events").unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("Content-Type"), Some("text/event-stream; charset=utf-8"));
+    assert_eq!(response.body(), b"id: 1\ndata: 1\n\nid: 2\ndata: 2\n\nid: 3\ndata: 3\n\n");
+}
+
+#[test]
+fn the_producer_hears_that_the_client_has_gone() {
+    let (outcome, outcomes) = mpsc::channel::<ClientGone>();
+    let server = TestServer::start(common::config(), move |_| {
+        let (events, response) = sse::stream();
+        let outcome = outcome.clone();
+        thread::spawn(move || {
+            // an endless stream, kept up until a send fails
+            let gone = loop {
+                if let Err(gone) = events.send(&Event::new("tick")) {
+                    break gone;
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
+            let _ = outcome.send(gone);
+        });
+        response
+    });
+
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(b"GET /events HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    let mut first = [0; 64];
+    let _ = stream.read(&mut first).unwrap();
+    drop(stream);
+
+    assert_eq!(outcomes.recv_timeout(Duration::from_secs(10)), Ok(ClientGone));
+}
@@ -0,0 +1,58 @@
+// NOTIFY_SOCKET is a unix datagram socket.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    env, fs,
+    os::unix::net::UnixDatagram,
+    process,
+    sync::Mutex,
+    time::Duration,
+};
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::{TempDir, TestServer};
+
+// NOTIFY_SOCKET is read by every server in the process, so tests that start one take turns.
+static ONE_AT_A_TIME: Mutex<()> = Mutex::new(());
+
+// Returns once the server has answered a request, so it is past its startup.
+fn serve(config: ServerConfig) -> TestServer {
+    let server = TestServer::start(config, |_| Response::html(200, "hello"));
+    assert_eq!(Client::get(&server.addr(), "/").unwrap().status(), 200);
+    server
+}
+
+#[test]
+fn systemd_is_told_when_the_server_is_ready_and_when_it_stops() {
+    let _turn = ONE_AT_A_TIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = TempDir::new();
+    let path = dir.path().join("notify.sock");
+    let systemd = UnixDatagram::bind(&path).unwrap();
+    systemd.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let received = || {
+        let mut buffer = [0; 64];
+        let length = systemd.recv(&mut buffer).expect("no notification");
+        String::from_utf8_lossy(&buffer[..length]).into_owned()
+    };
+
+    env::set_var("NOTIFY_SOCKET", &path);
+    let server = serve(common::config());
+    assert_eq!(received(), "READY=1");
+    server.stop().unwrap();
+    env::remove_var("NOTIFY_SOCKET");
+    assert_eq!(received(), "STOPPING=1");
+}
+
+#[test]
+fn the_pid_file_lasts_as_long_as_the_server() {
+    let _turn = ONE_AT_A_TIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = TempDir::new();
+    let pid_file = dir.path().join("server.pid");
+
+    let server = serve(ServerConfig { pid_file: Some(pid_file.clone()), ..common::config() });
+    assert_eq!(fs::read_to_string(&pid_file).unwrap(), format!("{}\n", process::id()));
+
+    server.stop().unwrap();
+    assert!(!pid_file.exists());
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, Response, ServerConfig};
+use common::TestServer;
+
+// Reads the body through, and answers with how that went.
+fn serve(config: ServerConfig) -> TestServer {
+    TestServer::start(config, |request| {
+        if request.path() == "/slow" {
+            thread::sleep(Duration::from_secs(1));
+            return Response::html(200, "too late");
+        }
+        let mut body = Vec::new();
+        match request.body_reader().map(|mut reader| reader.read_to_end(&mut body)) {
+            Some(Err(e)) => Response::html(400, format!("body read failed: {:?}", e.kind())),
+            _ => Response::html(200, format!("read {} bytes", body.len()))
+        }
+    })
+}
+
+// Connects, sends `head`, and returns the stream with a generous read timeout.
+fn connect(server: &TestServer, head: &[u8]) -> TcpStream {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(head).unwrap();
+    stream
+}
+
+fn read_all(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[test]
+fn a_head_trickling_in_past_the_header_timeout_is_cut_off() {
+    let server = serve(ServerConfig { header_timeout: Duration::from_millis(500), ..common::config() });
+
+    let mut stream = connect(&server, b"");
+    let mut trickler = stream.try_clone().unwrap();
+    let started = Instant::now();
+    thread::spawn(move || {
+        // each byte comes well within any per-read limit; only the deadline on the whole head stops it
+        for byte in b"GET / HTTP/1.1\r\nHost: x\r\nX-Slow: it takes a while to get to the end\r\n" {
+            if trickler.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+    let response = read_all(&mut stream);
+    assert!(!response.contains(" 200 "), "{response}");
+    assert!(started.elapsed() < Duration::from_secs(2), "cut off after {:?}", started.elapsed());
+}
+
+#[test]
+fn a_stalled_upload_fails_the_body_read_after_the_body_timeout() {
+    let server = serve(ServerConfig { body_timeout: Duration::from_millis(300), ..common::config() });
+
+    let started = Instant::now();
+    let mut stream = connect(&server, b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 100\r\n\r\nonly ten b");
+    let response = read_all(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+    assert!(response.contains("body read failed"), "{response}");
+    assert!(started.elapsed() < Duration::from_secs(2), "answered after {:?}", started.elapsed());
+}
+
+#[test]
+fn an_upload_that_keeps_coming_is_not_cut_off_by_the_body_timeout() {
+    let server = serve(ServerConfig { body_timeout: Duration::from_millis(300), ..common::config() });
+
+    let mut stream = connect(&server, b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\nConnection: close\r\n\r\n");
+    // a second in all, but never idle for longer than the timeout
+    for byte in b"0123456789" {
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(&[*byte]).unwrap();
+    }
+    let response = read_all(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.ends_with("read 10 bytes"), "{response}");
+}
+
+#[test]
+fn a_handler_past_the_request_timeout_is_answered_with_a_504() {
+    let server = serve(ServerConfig { request_timeout: Some(Duration::from_millis(200)), ..common::config() });
+
+    let started = Instant::now();
+    let response = Client::get(&server.addr(), "/slow").unwrap();
+    assert_eq!(response.status(), 504);
+    assert!(started.elapsed() < Duration::from_millis(800), "the 504 took {:?}", started.elapsed());
+
+    // a request within the budget is unaffected
+    assert_eq!(Client::get(&server.addr(), "/").unwrap().status(), 200);
+}
+
+// Short enough that the clients below outlast them without the tests dragging on.
+fn impatient() -> ServerConfig {
+    ServerConfig {
+        header_timeout: Duration::from_millis(300),
+        keep_alive_timeout: Duration::from_millis(300),
+        ..common::config()
+    }
+}
+
+#[test]
+fn a_head_that_stops_halfway_gets_a_408() {
+    let server = serve(impatient());
+
+    let mut stream = connect(&server, b"GET / HTTP/1.1\r\nHost: x\r\n");
+    let response = read_all(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "{response}");
+    assert!(response.contains("Connection: close\r\n"), "{response}");
+}
+
+#[test]
+fn an_idle_keep_alive_connection_is_closed_with_a_408() {
+    let server = serve(impatient());
+
+    let mut stream = connect(&server, b"GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+    let response = read_all(&mut stream);
+    let (first, rest) = response.split_once("read 0 bytes").unwrap();
+    assert!(first.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(rest.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "{response}");
+}
+
+// The request_timeouts_total counter.
+fn timeouts_counted(server: &TestServer) -> String {
+    let metrics = Client::get(&server.addr(), "/metrics").unwrap();
+    let metrics = String::from_utf8(metrics.body().to_vec()).unwrap();
+    metrics.lines().find(|line| line.starts_with("request_timeouts_total ")).unwrap().to_string()
+}
+
+#[test]
+fn a_connection_that_never_sent_anything_is_closed_silently() {
+    let server = serve(impatient());
+
+    let mut stream = connect(&server, b"");
+    assert_eq!(read_all(&mut stream), "");
+    assert_eq!(timeouts_counted(&server), "request_timeouts_total 0");
+
+    let mut stream = connect(&server, b"GET / HTTP/1.1\r\nHost: x\r\n");
+    read_all(&mut stream);
+    assert_eq!(timeouts_counted(&server), "request_timeouts_total 1");
+}
@@ -0,0 +1,186 @@
+// Run with `cargo test --features tracing`.
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+use book_web_server::{client::Client, Response, Router, ServerConfig, ThreadPool};
+use common::TestServer;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Metadata, Subscriber,
+};
+
+// A span as the subscriber saw it, with every field recorded on it, formatted.
+#[derive(Debug, Clone)]
+struct CapturedSpan {
+    name: &'static str,
+    fields: HashMap<&'static str, String>
+}
+
+impl CapturedSpan {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+}
+
+// Keeps every span, open or closed, in memory; a test looks through the closed ones.
+#[derive(Default)]
+struct Capture {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, CapturedSpan>>,
+    closed: Mutex<Vec<CapturedSpan>>
+}
+
+struct Fields<'a>(&'a mut HashMap<&'static str, String>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut span = CapturedSpan { name: attributes.metadata().name(), fields: HashMap::new() };
+        attributes.record(&mut Fields(&mut span.fields));
+        self.open.lock().unwrap().insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, id: &Id, values: &Record<'_>) {
+        if let Some(span) = self.open.lock().unwrap().get_mut(&id.into_u64()) {
+            values.record(&mut Fields(&mut span.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+
+    // nothing here clones spans, so the first close is the last
+    fn try_close(&self, id: Id) -> bool {
+        if let Some(span) = self.open.lock().unwrap().remove(&id.into_u64()) {
+            self.closed.lock().unwrap().push(span);
+        }
+        true
+    }
+}
+
+// The process-wide subscriber; spans are opened on worker threads, so a scoped default won't do.
+fn capture() -> Arc<Capture> {
+    static CAPTURE: OnceLock<Arc<Capture>> = OnceLock::new();
+    Arc::clone(CAPTURE.get_or_init(|| {
+        let capture = Arc::new(Capture::default());
+        tracing::subscriber::set_global_default(Arc::clone(&capture)).expect("another subscriber is installed");
+        capture
+    }))
+}
+
+// Waits for a closed span matching `wanted`: a request's span closes only after its response is sent.
+fn closed_span(capture: &Capture, wanted: impl Fn(&CapturedSpan) -> bool) -> CapturedSpan {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Some(span) = capture.closed.lock().unwrap().iter().find(|span| wanted(span)) {
+            return span.clone();
+        }
+        assert!(Instant::now() < deadline, "no such span was closed");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn users_server() -> TestServer {
+    let mut router = Router::new();
+    router.get("/users/:id", |request| Response::html(200, format!("user {}", request.param("id").unwrap_or("?"))));
+    // each test names its requests, since the servers of tests running at once all count ids from 1
+    let config = ServerConfig { trust_request_id: true, ..common::config() };
+    TestServer::start(config, move |request| router.handle(request))
+}
+
+// Sends a GET with `headers` and the request id `id`, and returns the status.
+fn get(server: &TestServer, path: &str, id: &str, headers: &[(&str, &str)]) -> u16 {
+    let headers: Vec<(&str, &str)> = headers.iter().copied().chain([("X-Request-Id", id)]).collect();
+    Client::new(&server.addr()).request("GET", path, &headers, &[]).unwrap().status()
+}
+
+fn request_span(capture: &Capture, id: &str) -> CapturedSpan {
+    closed_span(capture, |span| span.name == "request" && span.field("request_id") == Some(id))
+}
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+#[test]
+fn a_request_span_is_named_by_route_and_joins_the_callers_trace() {
+    let capture = capture();
+    let server = users_server();
+
+    assert_eq!(get(&server, "/users/42", "traced", &[("traceparent", TRACEPARENT)]), 200);
+
+    let span = request_span(&capture, "traced");
+    assert_eq!(span.field("otel.name"), Some("GET /users/:id"));
+    assert_eq!(span.field("http.method"), Some("GET"));
+    assert_eq!(span.field("http.route"), Some("/users/:id"));
+    assert_eq!(span.field("http.status_code"), Some("200"));
+    assert!(span.field("duration_ms").is_some_and(|ms| ms.parse::<f64>().is_ok()));
+    assert_eq!(span.field("trace_id"), Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+    assert_eq!(span.field("parent_id"), Some("00f067aa0ba902b7"));
+}
+
+#[test]
+fn an_invalid_traceparent_is_ignored() {
+    let capture = capture();
+    let server = users_server();
+
+    // uppercase hex isn't allowed
+    let invalid = "00-4BF92F3577B34DA6A3CE929D0E0E4736-00F067AA0BA902B7-01";
+    assert_eq!(get(&server, "/users/7", "invalid-trace", &[("traceparent", invalid)]), 200);
+
+    let span = request_span(&capture, "invalid-trace");
+    assert_eq!(span.field("http.status_code"), Some("200"));
+    assert_eq!(span.field("trace_id"), None);
+    assert_eq!(span.field("parent_id"), None);
+}
+
+#[test]
+fn an_unrouted_request_is_named_without_its_path() {
+    let capture = capture();
+    let server = users_server();
+
+    assert_eq!(get(&server, "/no/such/page", "unrouted", &[]), 404);
+
+    let span = request_span(&capture, "unrouted");
+    assert_eq!(span.field("otel.name"), Some("GET unrouted"));
+    assert_eq!(span.field("http.status_code"), Some("404"));
+}
+
+#[test]
+fn a_pool_job_runs_in_a_span_of_its_own() {
+    let capture = capture();
+    let pool = ThreadPool::new(1);
+
+    pool.execute_with_name("nightly-report", || {});
+    drop(pool);
+
+    let span = closed_span(&capture, |span| span.name == "job" && span.field("job") == Some("nightly-report"));
+    assert_eq!(span.field("worker"), Some("0"));
+}
@@ -0,0 +1,22 @@
+mod common;
+
+use book_web_server::{client::Client, Response};
+use common::TestServer;
+
+#[test]
+fn the_client_gets_one_vary_line_with_every_field() {
+    let server = TestServer::start(common::config(), |request| match request.path() {
+        "/cors" => Response::html(200, "hi").with_header("Vary", "Origin").with_vary("accept-language").with_vary("Origin"),
+        // a bare error negotiated into JSON, which varies on Accept
+        _ => Response::status_only(404).with_header("Vary", "Cookie")
+    });
+
+    let cases = [
+        ("/cors", vec!["Origin, accept-language"]),
+        ("/missing", vec!["Cookie, Accept"])
+    ];
+    for (path, expected) in cases {
+        let response = Client::new(&server.addr()).request("GET", path, &[("Accept", "application/json")], b"").unwrap();
+        assert_eq!(response.header_all("Vary").collect::<Vec<_>>(), expected, "{path}");
+    }
+}
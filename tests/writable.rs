@@ -0,0 +1,94 @@
+mod common;
+
+use std::{fs, path::Path};
+use book_web_server::{client::Client, Router};
+use common::{TempDir, TestServer};
+
+fn serve(root: &Path) -> TestServer {
+    let mut router = Router::new();
+    router.mount_writable("/files", root);
+    TestServer::start(common::config(), move |request| router.handle(request))
+}
+
+fn put(server: &TestServer, path: &str, body: &[u8]) -> u16 {
+    Client::new(&server.addr()).request("PUT", path, &[], body).unwrap().status()
+}
+
+fn delete(server: &TestServer, path: &str) -> u16 {
+    Client::new(&server.addr()).request("DELETE", path, &[], &[]).unwrap().status()
+}
+
+#[test]
+fn a_put_stores_the_file_and_a_get_serves_it() {
+    let dir = TempDir::new();
+    let server = serve(dir.path());
+
+    assert_eq!(put(&server, "/files/notes/today.txt", b"first"), 201);
+    assert_eq!(put(&server, "/files/notes/today.txt", b"second"), 204);
+    assert_eq!(fs::read(dir.path().join("notes/today.txt")).unwrap(), b"second");
+    assert_eq!(Client::get(&server.addr(), "/files/notes/today.txt").unwrap().body(), b"second");
+}
+
+#[test]
+fn a_delete_removes_the_file() {
+    let dir = TempDir::new();
+    let server = serve(dir.path());
+
+    assert_eq!(put(&server, "/files/notes/today.txt", b"first"), 201);
+    assert_eq!(delete(&server, "/files/notes/today.txt"), 204);
+    assert!(!dir.path().join("notes/today.txt").exists());
+    assert_eq!(delete(&server, "/files/notes/today.txt"), 404);
+    assert_eq!(Client::get(&server.addr(), "/files/notes/today.txt").unwrap().status(), 404);
+}
+
+#[test]
+fn dotfiles_cant_be_written() {
+    let dir = TempDir::new();
+    let server = serve(dir.path());
+
+    assert_eq!(put(&server, "/files/.env", b"SECRET=1"), 404);
+    assert_eq!(put(&server, "/files/.git/config", b"[core]"), 404);
+    assert!(!dir.path().join(".env").exists());
+    assert!(!dir.path().join(".git").exists());
+}
+
+#[test]
+fn dotfiles_cant_be_deleted() {
+    let dir = TempDir::new();
+    fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+    let server = serve(dir.path());
+
+    assert_eq!(delete(&server, "/files/.env"), 404);
+    assert!(dir.path().join(".env").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn nothing_is_written_through_a_symlink() {
+    let dir = TempDir::new();
+    let outside = TempDir::new();
+    std::os::unix::fs::symlink(outside.path(), dir.path().join("escape")).unwrap();
+    std::os::unix::fs::symlink(outside.path().join("target.txt"), dir.path().join("link.txt")).unwrap();
+    let server = serve(dir.path());
+
+    assert_eq!(put(&server, "/files/escape/planted.txt", b"x"), 404);
+    assert_eq!(put(&server, "/files/escape/deeper/planted.txt", b"x"), 404);
+    assert_eq!(put(&server, "/files/link.txt", b"x"), 404);
+    assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn nothing_is_deleted_through_a_symlink() {
+    let dir = TempDir::new();
+    let outside = TempDir::new();
+    fs::write(outside.path().join("target.txt"), "keep").unwrap();
+    std::os::unix::fs::symlink(outside.path(), dir.path().join("escape")).unwrap();
+    std::os::unix::fs::symlink(outside.path().join("target.txt"), dir.path().join("link.txt")).unwrap();
+    let server = serve(dir.path());
+
+    assert_eq!(delete(&server, "/files/escape/target.txt"), 404);
+    assert_eq!(delete(&server, "/files/link.txt"), 404);
+    assert!(outside.path().join("target.txt").exists());
+    assert!(dir.path().join("link.txt").symlink_metadata().is_ok());
+}